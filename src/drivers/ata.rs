@@ -0,0 +1,292 @@
+// ATA/IDE disk driver using programmed I/O.
+//
+// Each of the two legacy channels (primary at 0x1F0/0x3F6, secondary at
+// 0x170/0x376) can hold a master and a slave drive. `probe` sends IDENTIFY to
+// all four positions and returns the ATA disks that answer; ATAPI devices
+// are skipped. Transfers poll the status register one sector at a time and
+// use LBA28 commands where they reach, switching to LBA48 above 128 GiB.
+//
+// IDENTIFY turns interrupts on at the drive (the BIOS leaves them off) and
+// unmasks IRQ 14/15, so an interrupt fires after each command;
+// `interrupts.rs` acknowledges them by reading the status register through
+// `handle_irq` and `irq_count` reports how many arrived.
+
+use crate::fs::block_device::{BlockDevice, IoError};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+// registers relative to the channel's I/O base
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 0x01;
+const STATUS_DRQ: u8 = 0x08;
+const STATUS_DF: u8 = 0x20;
+const STATUS_BSY: u8 = 0x80;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_CACHE_FLUSH_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+/// Status polls before a command is considered hung.
+const POLL_LIMIT: u32 = 1_000_000;
+
+/// Largest LBA (exclusive) that LBA28 commands can address.
+const LBA28_LIMIT: u64 = 1 << 28;
+
+static IRQ_COUNTS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Primary,
+    Secondary,
+}
+
+impl Channel {
+    fn io_base(self) -> u16 {
+        match self {
+            Channel::Primary => 0x1F0,
+            Channel::Secondary => 0x170,
+        }
+    }
+
+    fn ctrl_base(self) -> u16 {
+        match self {
+            Channel::Primary => 0x3F6,
+            Channel::Secondary => 0x376,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Channel::Primary => 0,
+            Channel::Secondary => 1,
+        }
+    }
+
+    fn irq(self) -> u8 {
+        match self {
+            Channel::Primary => 14,
+            Channel::Secondary => 15,
+        }
+    }
+}
+
+/// Called from the IRQ 14/15 handlers. Reading the status register
+/// acknowledges the interrupt on the drive.
+pub(crate) fn handle_irq(channel: Channel) {
+    let mut status: Port<u8> = Port::new(channel.io_base() + REG_STATUS);
+    unsafe { status.read(); }
+    IRQ_COUNTS[channel.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of interrupts seen on `channel` since boot.
+pub fn irq_count(channel: Channel) -> u64 {
+    IRQ_COUNTS[channel.index()].load(Ordering::Relaxed)
+}
+
+/// One ATA disk on a legacy IDE channel.
+pub struct AtaDrive {
+    channel: Channel,
+    slave: bool,
+    sectors: u64,
+    lba48: bool,
+    model: String,
+}
+
+impl AtaDrive {
+    /// Send IDENTIFY to one drive position. Returns `Ok(None)` if nothing
+    /// (or a non-ATA device) is attached there.
+    pub fn identify(channel: Channel, slave: bool) -> Result<Option<AtaDrive>, IoError> {
+        let mut drive = AtaDrive { channel, slave, sectors: 0, lba48: false, model: String::new() };
+        // a floating bus reads 0xFF
+        if drive.status() == 0xFF { return Ok(None); }
+        // device control: clear nIEN so the drive raises interrupts
+        let mut control: Port<u8> = Port::new(channel.ctrl_base());
+        unsafe { control.write(0) };
+        crate::interrupts::unmask_irq(channel.irq());
+        drive.write_reg(REG_DRIVE, 0xA0 | ((slave as u8) << 4));
+        drive.delay();
+        drive.write_reg(REG_SECTOR_COUNT, 0);
+        drive.write_reg(REG_LBA_LOW, 0);
+        drive.write_reg(REG_LBA_MID, 0);
+        drive.write_reg(REG_LBA_HIGH, 0);
+        drive.write_reg(REG_COMMAND, CMD_IDENTIFY);
+        if drive.status() == 0 { return Ok(None); }
+        let mut polls = 0;
+        while drive.status() & STATUS_BSY != 0 {
+            polls += 1;
+            if polls > POLL_LIMIT { return Ok(None); }
+        }
+        // ATAPI and SATA devices set these signature bytes and abort IDENTIFY
+        if drive.read_reg(REG_LBA_MID) != 0 || drive.read_reg(REG_LBA_HIGH) != 0 {
+            return Ok(None);
+        }
+        if drive.wait_drq().is_err() { return Ok(None); }
+        let mut words = [0u16; 256];
+        let mut data: Port<u16> = Port::new(channel.io_base() + REG_DATA);
+        for w in words.iter_mut() {
+            *w = unsafe { data.read() };
+        }
+        drive.lba48 = words[83] & (1 << 10) != 0;
+        drive.sectors = if drive.lba48 {
+            words[100] as u64 | (words[101] as u64) << 16 | (words[102] as u64) << 32 | (words[103] as u64) << 48
+        } else {
+            words[60] as u64 | (words[61] as u64) << 16
+        };
+        // the model string is stored with the bytes of each word swapped
+        let mut model = Vec::with_capacity(40);
+        for w in &words[27..47] {
+            model.push((w >> 8) as u8);
+            model.push(*w as u8);
+        }
+        drive.model = String::from(String::from_utf8_lossy(&model).trim_end());
+        Ok(Some(drive))
+    }
+
+    pub fn channel(&self) -> Channel { self.channel }
+
+    pub fn is_slave(&self) -> bool { self.slave }
+
+    pub fn supports_lba48(&self) -> bool { self.lba48 }
+
+    /// Model string reported by IDENTIFY.
+    pub fn model(&self) -> &str { &self.model }
+
+    fn read_reg(&self, reg: u16) -> u8 {
+        let mut port: Port<u8> = Port::new(self.channel.io_base() + reg);
+        unsafe { port.read() }
+    }
+
+    fn write_reg(&self, reg: u16, value: u8) {
+        let mut port: Port<u8> = Port::new(self.channel.io_base() + reg);
+        unsafe { port.write(value) }
+    }
+
+    /// Alternate status: same bits as the status register, but reading it
+    /// does not acknowledge an interrupt. Shares its port with device control.
+    fn status(&self) -> u8 {
+        let mut port: Port<u8> = Port::new(self.channel.ctrl_base());
+        unsafe { port.read() }
+    }
+
+    /// The ~400ns a drive needs after selection before its status is valid.
+    fn delay(&self) {
+        for _ in 0..4 { self.status(); }
+    }
+
+    fn wait_ready(&self) -> Result<(), IoError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.status();
+            if status & STATUS_BSY == 0 {
+                if status & (STATUS_ERR | STATUS_DF) != 0 { return Err(IoError::Device); }
+                return Ok(());
+            }
+        }
+        Err(IoError::Device)
+    }
+
+    fn wait_drq(&self) -> Result<(), IoError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.status();
+            if status & (STATUS_ERR | STATUS_DF) != 0 { return Err(IoError::Device); }
+            if status & STATUS_BSY == 0 && status & STATUS_DRQ != 0 { return Ok(()); }
+        }
+        Err(IoError::Device)
+    }
+
+    /// Select the drive and issue a one-sector command at `lba`.
+    fn command(&self, lba: u64, cmd28: u8, cmd48: u8) -> Result<(), IoError> {
+        self.wait_ready()?;
+        let slave = (self.slave as u8) << 4;
+        if lba < LBA28_LIMIT {
+            self.write_reg(REG_DRIVE, 0xE0 | slave | ((lba >> 24) as u8 & 0x0F));
+            self.delay();
+            self.write_reg(REG_SECTOR_COUNT, 1);
+            self.write_reg(REG_LBA_LOW, lba as u8);
+            self.write_reg(REG_LBA_MID, (lba >> 8) as u8);
+            self.write_reg(REG_LBA_HIGH, (lba >> 16) as u8);
+            self.write_reg(REG_COMMAND, cmd28);
+        } else {
+            self.write_reg(REG_DRIVE, 0x40 | slave);
+            self.delay();
+            // high-order bytes first, then the low-order ones
+            self.write_reg(REG_SECTOR_COUNT, 0);
+            self.write_reg(REG_LBA_LOW, (lba >> 24) as u8);
+            self.write_reg(REG_LBA_MID, (lba >> 32) as u8);
+            self.write_reg(REG_LBA_HIGH, (lba >> 40) as u8);
+            self.write_reg(REG_SECTOR_COUNT, 1);
+            self.write_reg(REG_LBA_LOW, lba as u8);
+            self.write_reg(REG_LBA_MID, (lba >> 8) as u8);
+            self.write_reg(REG_LBA_HIGH, (lba >> 16) as u8);
+            self.write_reg(REG_COMMAND, cmd48);
+        }
+        Ok(())
+    }
+
+    /// Error register contents after a failed command, for diagnostics.
+    pub fn last_error(&self) -> u8 { self.read_reg(REG_ERROR) }
+}
+
+impl BlockDevice for AtaDrive {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        if buf.len() != 512 { return Err(IoError::BadBuffer); }
+        if lba >= self.sectors { return Err(IoError::OutOfRange); }
+        self.command(lba, CMD_READ_SECTORS, CMD_READ_SECTORS_EXT)?;
+        self.wait_drq()?;
+        let mut data: Port<u16> = Port::new(self.channel.io_base() + REG_DATA);
+        for chunk in buf.chunks_exact_mut(2) {
+            let w = unsafe { data.read() };
+            chunk.copy_from_slice(&w.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if data.len() != 512 { return Err(IoError::BadBuffer); }
+        if lba >= self.sectors { return Err(IoError::OutOfRange); }
+        self.command(lba, CMD_WRITE_SECTORS, CMD_WRITE_SECTORS_EXT)?;
+        self.wait_drq()?;
+        let mut port: Port<u16> = Port::new(self.channel.io_base() + REG_DATA);
+        for chunk in data.chunks_exact(2) {
+            unsafe { port.write(u16::from_le_bytes([chunk[0], chunk[1]])); }
+        }
+        self.wait_ready()
+    }
+
+    fn sector_count(&self) -> u64 { self.sectors }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.wait_ready()?;
+        self.write_reg(REG_DRIVE, 0xA0 | ((self.slave as u8) << 4));
+        self.delay();
+        self.write_reg(REG_COMMAND, if self.lba48 { CMD_CACHE_FLUSH_EXT } else { CMD_CACHE_FLUSH });
+        self.wait_ready()
+    }
+}
+
+/// IDENTIFY every drive position on both channels.
+pub fn probe() -> Vec<AtaDrive> {
+    let mut drives = Vec::new();
+    for channel in [Channel::Primary, Channel::Secondary] {
+        for slave in [false, true] {
+            if let Ok(Some(drive)) = AtaDrive::identify(channel, slave) {
+                drives.push(drive);
+            }
+        }
+    }
+    drives
+}
@@ -0,0 +1,4 @@
+pub mod ata;
+pub mod pci;
+pub mod rtc;
+pub mod virtio_blk;
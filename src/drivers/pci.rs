@@ -0,0 +1,403 @@
+// PCI configuration space access through the legacy 0xCF8/0xCFC ports.
+//
+// `enumerate` walks the bus hierarchy from the host bridge through PCI-PCI
+// bridges and decodes each function's header: IDs, class, BARs with their
+// sizes, interrupt line and capability list. Drivers describe the functions
+// they handle with a `PciDriver` ID table; registered drivers show up next
+// to their devices in `bound_devices`, and `claim` hands a driver the first
+// matching function nobody else has taken.
+
+use alloc::vec::Vec;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+// configuration space offsets
+const REG_VENDOR_ID: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_STATUS: u8 = 0x06;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER_TYPE: u8 = 0x0C;
+const REG_BAR0: u8 = 0x10;
+const REG_SECONDARY_BUS: u8 = 0x19;
+const REG_CAPABILITIES: u8 = 0x34;
+const REG_INTERRUPT_LINE: u8 = 0x3C;
+const REG_INTERRUPT_PIN: u8 = 0x3D;
+
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+const HEADER_TYPE_MASK: u8 = 0x7F;
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+/// Header type of a PCI-to-PCI bridge.
+pub const HEADER_PCI_BRIDGE: u8 = 0x01;
+
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+/// Capability IDs found in `PciDevice::capabilities`.
+pub const CAP_POWER_MANAGEMENT: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR_SPECIFIC: u8 = 0x09;
+pub const CAP_PCI_EXPRESS: u8 = 0x10;
+pub const CAP_MSI_X: u8 = 0x11;
+
+/// Capability list entries followed before the list is assumed to loop:
+/// the 192 bytes after the header fit at most 48 of them.
+const MAX_CAPABILITIES: usize = 48;
+/// Drivers the registry holds, and functions that can be bound at once.
+const MAX_DRIVERS: usize = 8;
+const MAX_BOUND: usize = 16;
+
+/// Bus/device/function triple identifying one PCI function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    fn select(self, offset: u8) {
+        let address = 1u32 << 31
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xFC) as u32;
+        let mut port: Port<u32> = Port::new(CONFIG_ADDRESS);
+        unsafe { port.write(address) };
+    }
+
+    /// Read the aligned dword containing `offset`.
+    pub fn read_u32(self, offset: u8) -> u32 {
+        self.select(offset);
+        let mut port: Port<u32> = Port::new(CONFIG_DATA);
+        unsafe { port.read() }
+    }
+
+    pub fn write_u32(self, offset: u8, value: u32) {
+        self.select(offset);
+        let mut port: Port<u32> = Port::new(CONFIG_DATA);
+        unsafe { port.write(value) };
+    }
+
+    pub fn read_u16(self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+}
+
+/// A decoded base address register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    /// `size` ports starting at `port`.
+    Io { port: u16, size: u32 },
+    /// `size` bytes of memory at physical `addr`. A 64-bit BAR also uses
+    /// the following register for the upper half of the address.
+    Memory { addr: u64, size: u64, prefetchable: bool, is_64bit: bool },
+}
+
+/// An entry in a function's capability list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    /// Configuration space offset of the capability header.
+    pub offset: u8,
+}
+
+/// A function found on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// Layout of the rest of the header, without the multi-function bit.
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    /// INTA#-INTD# as 1-4, or 0 if the function uses no interrupt pin.
+    pub interrupt_pin: u8,
+}
+
+impl PciDevice {
+    /// Read the header of the function at `address`, or `None` if nothing
+    /// answers there.
+    pub fn probe(address: PciAddress) -> Option<PciDevice> {
+        let id = address.read_u32(REG_VENDOR_ID);
+        if id as u16 == 0xFFFF { return None; }
+        let class = address.read_u32(REG_CLASS);
+        Some(PciDevice {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: address.read_u8(REG_HEADER_TYPE + 2) & HEADER_TYPE_MASK,
+            interrupt_line: address.read_u8(REG_INTERRUPT_LINE),
+            interrupt_pin: address.read_u8(REG_INTERRUPT_PIN),
+        })
+    }
+
+    /// Number of BARs in this header layout: six for devices, two for
+    /// PCI-PCI bridges, none otherwise.
+    pub fn bar_count(&self) -> u8 {
+        match self.header_type {
+            0x00 => 6,
+            HEADER_PCI_BRIDGE => 2,
+            _ => 0,
+        }
+    }
+
+    /// Raw value of base address register `index` (0-5).
+    pub fn bar(&self, index: u8) -> u32 {
+        self.address.read_u32(REG_BAR0 + index * 4)
+    }
+
+    /// Decode BAR `index`, sizing it by writing all ones and reading back
+    /// which address bits stick. Decoding is switched off meanwhile so the
+    /// probe address never reaches the bus. `None` for an unimplemented BAR
+    /// or the upper half of a 64-bit one.
+    pub fn decode_bar(&self, index: u8) -> Option<Bar> {
+        if index >= self.bar_count() { return None; }
+        let offset = REG_BAR0 + index * 4;
+        let raw = self.bar(index);
+        let command = self.address.read_u16(REG_COMMAND);
+        self.address.write_u32(REG_COMMAND, (command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE)) as u32);
+        let size_mask = |offset: u8, value: u32| {
+            self.address.write_u32(offset, u32::MAX);
+            let mask = self.address.read_u32(offset);
+            self.address.write_u32(offset, value);
+            mask
+        };
+        let bar = if raw & 1 == 1 {
+            let mask = size_mask(offset, raw) & !0x3;
+            // only the low 16 bits of an I/O BAR decode
+            let size = (!(mask | 0xFFFF_0000)).wrapping_add(1) & 0xFFFF;
+            if size == 0 { None } else { Some(Bar::Io { port: (raw & !0x3) as u16, size }) }
+        } else {
+            let is_64bit = (raw >> 1) & 3 == 2 && index + 1 < self.bar_count();
+            let low_mask = size_mask(offset, raw) & !0xF;
+            let (addr, mask) = if is_64bit {
+                let high = self.bar(index + 1);
+                let high_mask = size_mask(offset + 4, high);
+                ((high as u64) << 32 | (raw & !0xF) as u64, (high_mask as u64) << 32 | low_mask as u64)
+            } else {
+                ((raw & !0xF) as u64, 0xFFFF_FFFF_0000_0000 | low_mask as u64)
+            };
+            // no writable address bits: the BAR is not implemented
+            if mask == 0 || mask == 0xFFFF_FFFF_0000_0000 {
+                None
+            } else {
+                Some(Bar::Memory { addr, size: (!mask).wrapping_add(1), prefetchable: raw & 0x8 != 0, is_64bit })
+            }
+        };
+        self.address.write_u32(REG_COMMAND, command as u32);
+        bar
+    }
+
+    /// Every implemented BAR with its index; the upper half of a 64-bit BAR
+    /// is not listed separately.
+    pub fn bars(&self) -> Vec<(u8, Bar)> {
+        let mut bars = Vec::new();
+        let mut index = 0;
+        while index < self.bar_count() {
+            let bar = self.decode_bar(index);
+            let next = if matches!(bar, Some(Bar::Memory { is_64bit: true, .. })) { index + 2 } else { index + 1 };
+            if let Some(bar) = bar { bars.push((index, bar)); }
+            index = next;
+        }
+        bars
+    }
+
+    /// I/O port base of BAR `index`, if it is an I/O BAR.
+    pub fn io_bar(&self, index: u8) -> Option<u16> {
+        let bar = self.bar(index);
+        if bar & 1 == 1 { Some((bar & !0x3) as u16) } else { None }
+    }
+
+    /// Physical base of BAR `index`, if it is a memory BAR. A 64-bit BAR
+    /// takes its upper half from the next register.
+    pub fn mmio_bar(&self, index: u8) -> Option<u64> {
+        let bar = self.bar(index);
+        if bar & 1 == 1 { return None; }
+        let high = if (bar >> 1) & 3 == 2 && index < 5 { self.bar(index + 1) as u64 } else { 0 };
+        Some(high << 32 | (bar & !0xF) as u64)
+    }
+
+    /// Walk the capability list, if the function has one.
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let mut caps = Vec::new();
+        if self.address.read_u16(REG_STATUS) & STATUS_CAPABILITIES == 0 { return caps; }
+        // the bottom two bits of every pointer are reserved
+        let mut offset = self.address.read_u8(REG_CAPABILITIES) & !0x3;
+        while offset >= 0x40 && caps.len() < MAX_CAPABILITIES {
+            let header = self.address.read_u16(offset);
+            caps.push(Capability { id: header as u8, offset });
+            offset = (header >> 8) as u8 & !0x3;
+        }
+        caps
+    }
+
+    /// Offset of the first capability with `id`.
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities().iter().find(|c| c.id == id).map(|c| c.offset)
+    }
+
+    /// True for PCI-PCI bridges, whose secondary bus holds more functions.
+    pub fn is_pci_bridge(&self) -> bool {
+        self.header_type == HEADER_PCI_BRIDGE && self.class == CLASS_BRIDGE && self.subclass == SUBCLASS_PCI_BRIDGE
+    }
+
+    /// Enable I/O and memory decoding and let the device master the bus.
+    pub fn enable_bus_master(&self) {
+        let command = self.address.read_u16(REG_COMMAND) | COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER;
+        // the upper half is the status register, whose bits clear when 1 is
+        // written, so leave it as zeros
+        self.address.write_u32(REG_COMMAND, command as u32);
+    }
+
+    /// True if the device may master the bus.
+    pub fn is_bus_master(&self) -> bool {
+        self.address.read_u16(REG_COMMAND) & COMMAND_BUS_MASTER != 0
+    }
+}
+
+/// Every function on the bus, in bus/device/function order of discovery.
+/// Buses are found by following PCI-PCI bridges from the host bridge, so
+/// unused bus numbers cost nothing.
+pub fn enumerate() -> Vec<PciDevice> {
+    let mut found = Vec::new();
+    let host = PciAddress { bus: 0, device: 0, function: 0 };
+    match PciDevice::probe(host) {
+        // a multi-function host bridge has one function per root bus
+        Some(_) if host.read_u8(REG_HEADER_TYPE + 2) & HEADER_MULTI_FUNCTION != 0 => {
+            for function in 0..8u8 {
+                if PciDevice::probe(PciAddress { bus: 0, device: 0, function }).is_some() {
+                    scan_bus(function, &mut found);
+                }
+            }
+        }
+        Some(_) => scan_bus(0, &mut found),
+        None => {}
+    }
+    found
+}
+
+fn scan_bus(bus: u8, found: &mut Vec<PciDevice>) {
+    for device in 0..32u8 {
+        let first = PciAddress { bus, device, function: 0 };
+        if PciDevice::probe(first).is_none() { continue; }
+        let functions = if first.read_u8(REG_HEADER_TYPE + 2) & HEADER_MULTI_FUNCTION != 0 { 8 } else { 1 };
+        for function in 0..functions {
+            let dev = match PciDevice::probe(PciAddress { bus, device, function }) {
+                Some(dev) => dev,
+                None => continue,
+            };
+            found.push(dev);
+            let secondary = dev.address.read_u8(REG_SECONDARY_BUS);
+            // an unconfigured bridge has secondary bus 0; never rescan a bus
+            if dev.is_pci_bridge() && secondary > bus {
+                scan_bus(secondary, found);
+            }
+        }
+    }
+}
+
+/// Scan every bus for the first function with the given IDs.
+pub fn find_device(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    enumerate().into_iter().find(|d| d.vendor_id == vendor_id && d.device_id == device_id)
+}
+
+/// Vendor/device pair a driver handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciId {
+    pub vendor_id: u16,
+    pub device_id: u16,
+}
+
+impl PciId {
+    pub const fn new(vendor_id: u16, device_id: u16) -> Self {
+        PciId { vendor_id, device_id }
+    }
+}
+
+/// A driver's name and the functions it can drive.
+#[derive(Debug)]
+pub struct PciDriver {
+    pub name: &'static str,
+    pub ids: &'static [PciId],
+}
+
+impl PciDriver {
+    pub fn matches(&self, device: &PciDevice) -> bool {
+        self.ids.iter().any(|id| id.vendor_id == device.vendor_id && id.device_id == device.device_id)
+    }
+}
+
+static DRIVERS: spin::Mutex<[Option<&'static PciDriver>; MAX_DRIVERS]> = spin::Mutex::new([None; MAX_DRIVERS]);
+static BOUND: spin::Mutex<[Option<(PciAddress, &'static str)>; MAX_BOUND]> = spin::Mutex::new([None; MAX_BOUND]);
+
+/// Add `driver` to the registry. Returns false if the registry is full;
+/// registering a driver twice is a no-op.
+pub fn register_driver(driver: &'static PciDriver) -> bool {
+    let mut drivers = DRIVERS.lock();
+    if drivers.iter().flatten().any(|d| core::ptr::eq(*d, driver)) { return true; }
+    match drivers.iter_mut().find(|d| d.is_none()) {
+        Some(slot) => {
+            *slot = Some(driver);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The first registered driver that handles `device`.
+pub fn driver_for(device: &PciDevice) -> Option<&'static PciDriver> {
+    DRIVERS.lock().iter().flatten().find(|d| d.matches(device)).copied()
+}
+
+/// Name of the driver that claimed the function at `address`.
+pub fn bound_driver(address: PciAddress) -> Option<&'static str> {
+    BOUND.lock().iter().flatten().find(|(a, _)| *a == address).map(|(_, name)| *name)
+}
+
+/// Every function together with the registered driver that handles it
+/// (if any) and whether that driver has claimed it.
+pub fn bound_devices() -> Vec<(PciDevice, Option<&'static PciDriver>, bool)> {
+    enumerate()
+        .into_iter()
+        .map(|d| (d, driver_for(&d), bound_driver(d.address).is_some()))
+        .collect()
+}
+
+/// Bind `driver` to the first function it matches that no driver has
+/// claimed yet, and return that function.
+pub fn claim(driver: &'static PciDriver) -> Option<PciDevice> {
+    let candidates = enumerate();
+    let mut bound = BOUND.lock();
+    let device = candidates
+        .into_iter()
+        .find(|d| driver.matches(d) && !bound.iter().flatten().any(|(a, _)| *a == d.address))?;
+    let slot = bound.iter_mut().find(|b| b.is_none())?;
+    *slot = Some((device.address, driver.name));
+    Some(device)
+}
+
+/// Forget the driver bound to `address`, e.g. after its init failed.
+pub fn release(address: PciAddress) {
+    for slot in BOUND.lock().iter_mut() {
+        if slot.is_some_and(|(a, _)| a == address) { *slot = None; }
+    }
+}
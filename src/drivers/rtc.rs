@@ -0,0 +1,107 @@
+// CMOS real-time clock.
+//
+// The RTC keeps the date and time in CMOS registers behind the index/data
+// port pair 0x70/0x71. Values are BCD unless status register B says binary,
+// and the hour may be in 12-hour form with the top bit meaning PM. A read can
+// straddle the once-a-second update, so `read` waits for the update flag to
+// clear and repeats until two consecutive reads agree. If the update flag
+// never clears, `read` falls back to the last time it read, or the FAT epoch.
+
+use crate::fs::time::DateTime;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+/// Century register on QEMU and most PCs (the ACPI FADT can name another).
+const REG_CENTURY: u8 = 0x32;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATING: u8 = 0x80;
+const STATUS_B_24_HOUR: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const HOUR_PM: u8 = 0x80;
+
+/// Reads attempted before settling for the last one.
+const READ_ATTEMPTS: usize = 8;
+/// Status polls before the update flag is considered stuck. An update
+/// takes under 2 ms.
+const POLL_LIMIT: u32 = 1_000_000;
+
+/// Last time successfully read, for when the RTC stops answering.
+static LAST_READ: Mutex<Option<DateTime>> = Mutex::new(None);
+
+fn read_register(reg: u8) -> u8 {
+    let mut index: Port<u8> = Port::new(CMOS_INDEX);
+    let mut data: Port<u8> = Port::new(CMOS_DATA);
+    // bit 7 of the index would disable NMIs; leave it clear
+    without_interrupts(|| unsafe {
+        index.write(reg & 0x7F);
+        data.read()
+    })
+}
+
+fn updating() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UPDATING != 0
+}
+
+/// Raw register values: seconds, minutes, hours, day, month, year, century.
+/// `None` if the update flag does not clear.
+fn read_raw() -> Option<[u8; 7]> {
+    let mut polls = 0;
+    while updating() {
+        polls += 1;
+        if polls > POLL_LIMIT { return None; }
+    }
+    Some([REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR, REG_CENTURY].map(read_register))
+}
+
+fn from_bcd(v: u8) -> u8 { (v >> 4) * 10 + (v & 0x0F) }
+
+/// Current date and time as kept by the RTC (normally UTC on QEMU).
+pub fn read() -> DateTime {
+    let stuck = || LAST_READ.lock().unwrap_or(DateTime::FAT_EPOCH);
+    let mut raw = match read_raw() {
+        Some(raw) => raw,
+        None => return stuck(),
+    };
+    for _ in 0..READ_ATTEMPTS {
+        let again = match read_raw() {
+            Some(again) => again,
+            None => return stuck(),
+        };
+        if again == raw { break; }
+        raw = again;
+    }
+    let status_b = read_register(REG_STATUS_B);
+    let [mut second, mut minute, hour_raw, mut day, mut month, mut year, mut century] = raw;
+    let pm = hour_raw & HOUR_PM != 0;
+    let mut hour = hour_raw & !HOUR_PM;
+    if status_b & STATUS_B_BINARY == 0 {
+        second = from_bcd(second);
+        minute = from_bcd(minute);
+        hour = from_bcd(hour);
+        day = from_bcd(day);
+        month = from_bcd(month);
+        year = from_bcd(year);
+        century = from_bcd(century);
+    }
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is hour 0, 12 PM stays 12
+        hour = (hour % 12) + if pm { 12 } else { 0 };
+    }
+    // no century register: assume this century
+    let century = if (19..=21).contains(&century) { century as u16 } else { 20 };
+    let now = DateTime { year: century * 100 + year as u16, month, day, hour, minute, second };
+    *LAST_READ.lock() = Some(now);
+    now
+}
@@ -0,0 +1,325 @@
+// virtio-blk disk driver over PCI (legacy interface).
+//
+// QEMU exposes `-drive if=virtio` disks as transitional virtio devices
+// (vendor 0x1AF4, device 0x1001) with the legacy registers in I/O BAR 0. The
+// driver negotiates features, places the single request virtqueue in DMA
+// memory and submits one descriptor chain per request: a header the device
+// reads, the sector buffer, and a status byte the device writes back.
+//
+// The device raises its PCI interrupt line when a request completes. The
+// handler acknowledges it by reading the ISR register; the driver sleeps
+// with `hlt` until the used ring moves, or spins when interrupts are off.
+
+use crate::drivers::pci::{self, PciDevice, PciDriver, PciId};
+use crate::fs::block_device::{BlockDevice, IoError};
+use crate::memory::DmaRegion;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{FrameAllocator, Size4KiB};
+use x86_64::VirtAddr;
+
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+const VIRTIO_BLK_DEVICE_ID: u16 = 0x1001;
+
+// legacy registers relative to BAR 0
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_ADDRESS: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_DEVICE_STATUS: u16 = 0x12;
+const REG_ISR_STATUS: u16 = 0x13;
+// device-specific configuration starts here while MSI-X is off
+const REG_CAPACITY: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 0x80;
+
+const FEATURE_RO: u32 = 1 << 5;
+const FEATURE_FLUSH: u32 = 1 << 9;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+const REQUEST_STATUS_OK: u8 = 0;
+
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+// layout of the request page: header, status byte, sector buffer
+const HEADER_OFFSET: usize = 0;
+const STATUS_OFFSET: usize = 16;
+const DATA_OFFSET: usize = 512;
+
+/// Spins (interrupts off) or wake-ups (interrupts on) before a request is
+/// considered lost.
+const POLL_LIMIT: u32 = 10_000_000;
+const WAKE_LIMIT: u32 = 10_000;
+
+/// ISR ports of initialized devices, read by the interrupt handler.
+static ISR_PORTS: spin::Mutex<Vec<u16>> = spin::Mutex::new(Vec::new());
+static IRQ_COUNT: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+fn handle_irq() {
+    for &port in ISR_PORTS.lock().iter() {
+        // reading the ISR acknowledges the interrupt; bit 0 means the used
+        // ring was updated
+        let mut isr: Port<u8> = Port::new(port);
+        if unsafe { isr.read() } & 1 != 0 {
+            IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Number of completion interrupts seen since boot, across all devices.
+pub fn irq_count() -> u64 { IRQ_COUNT.load(Ordering::Relaxed) }
+
+/// Find the first virtio-blk function on the PCI bus.
+pub fn find_device() -> Option<PciDevice> {
+    pci::find_device(VIRTIO_VENDOR_ID, VIRTIO_BLK_DEVICE_ID)
+}
+
+/// Registry entry: the functions this driver binds to.
+pub static PCI_DRIVER: PciDriver = PciDriver { name: "virtio-blk", ids: &[PciId::new(VIRTIO_VENDOR_ID, VIRTIO_BLK_DEVICE_ID)] };
+
+pub struct VirtioBlk {
+    io_base: u16,
+    sectors: u64,
+    read_only: bool,
+    can_flush: bool,
+    queue: DmaRegion,
+    queue_size: u16,
+    used_offset: usize,
+    request: DmaRegion,
+    // next free slot in the available ring, and last used entry consumed
+    avail_idx: u16,
+    used_idx: u16,
+    interrupt_driven: bool,
+    // a request timed out and may still be in flight
+    failed: bool,
+}
+
+impl VirtioBlk {
+    /// Claim and initialize the first unclaimed virtio-blk device. Returns
+    /// `Ok(None)` if there is none.
+    pub fn probe(
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+        physical_memory_offset: VirtAddr,
+    ) -> Result<Option<VirtioBlk>, IoError> {
+        let device = match pci::claim(&PCI_DRIVER) {
+            Some(device) => device,
+            None => return Ok(None),
+        };
+        // leave the function for another attempt if it cannot be set up
+        VirtioBlk::init(device, frame_allocator, physical_memory_offset).map(Some).inspect_err(|_| pci::release(device.address))
+    }
+
+    /// Reset and initialize the device at `device`.
+    pub fn init(
+        device: PciDevice,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+        physical_memory_offset: VirtAddr,
+    ) -> Result<VirtioBlk, IoError> {
+        let io_base = device.io_bar(0).ok_or(IoError::Device)?;
+        device.enable_bus_master();
+        let write8 = |reg: u16, value: u8| unsafe { Port::<u8>::new(io_base + reg).write(value) };
+        write8(REG_DEVICE_STATUS, 0);
+        write8(REG_DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+        write8(REG_DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let offered = unsafe { Port::<u32>::new(io_base + REG_DEVICE_FEATURES).read() };
+        let features = offered & (FEATURE_RO | FEATURE_FLUSH);
+        unsafe { Port::<u32>::new(io_base + REG_GUEST_FEATURES).write(features) };
+
+        unsafe { Port::<u16>::new(io_base + REG_QUEUE_SELECT).write(0) };
+        let queue_size = unsafe { Port::<u16>::new(io_base + REG_QUEUE_SIZE).read() };
+        if queue_size < 3 {
+            write8(REG_DEVICE_STATUS, STATUS_FAILED);
+            return Err(IoError::Device);
+        }
+        // descriptor table and available ring, then the used ring on its own
+        // page boundary (legacy layout)
+        let n = queue_size as usize;
+        let used_offset = (16 * n + 6 + 2 * n).next_multiple_of(4096);
+        let queue_bytes = used_offset + (6 + 8 * n).next_multiple_of(4096);
+        let queue = DmaRegion::alloc(frame_allocator, physical_memory_offset, queue_bytes / 4096);
+        let request = DmaRegion::alloc(frame_allocator, physical_memory_offset, 1);
+        let (queue, request) = match queue.zip(request) {
+            Some(regions) => regions,
+            None => {
+                write8(REG_DEVICE_STATUS, STATUS_FAILED);
+                return Err(IoError::Device);
+            }
+        };
+        let pfn = (queue.phys_addr().as_u64() >> 12) as u32;
+        unsafe { Port::<u32>::new(io_base + REG_QUEUE_ADDRESS).write(pfn) };
+
+        let sectors = {
+            let low = unsafe { Port::<u32>::new(io_base + REG_CAPACITY).read() };
+            let high = unsafe { Port::<u32>::new(io_base + REG_CAPACITY + 4).read() };
+            (high as u64) << 32 | low as u64
+        };
+
+        interrupts::without_interrupts(|| ISR_PORTS.lock().push(io_base + REG_ISR_STATUS));
+        let interrupt_driven = crate::interrupts::register_irq_handler(device.interrupt_line, handle_irq);
+        write8(REG_DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+
+        Ok(VirtioBlk {
+            io_base,
+            sectors,
+            read_only: features & FEATURE_RO != 0,
+            can_flush: features & FEATURE_FLUSH != 0,
+            queue,
+            queue_size,
+            used_offset,
+            request,
+            avail_idx: 0,
+            used_idx: 0,
+            failed: false,
+            interrupt_driven,
+        })
+    }
+
+    fn descriptor(&self, index: usize) -> *mut Descriptor {
+        self.queue.ptr_at(index * 16)
+    }
+
+    /// Send one request and wait for the device to finish it. The header
+    /// always goes first and the status byte last. `data` puts the sector
+    /// buffer in between: `Some(true)` if the device fills it (reads),
+    /// `Some(false)` if the device consumes it (writes).
+    fn submit(&mut self, kind: u32, sector: u64, data: Option<bool>) -> Result<(), IoError> {
+        if self.failed { return Err(IoError::Device); }
+        let phys = self.request.phys_addr().as_u64();
+        unsafe {
+            write_volatile(self.request.ptr_at(HEADER_OFFSET), RequestHeader { kind, reserved: 0, sector });
+            write_volatile(self.request.ptr_at::<u8>(STATUS_OFFSET), 0xFF);
+            write_volatile(self.descriptor(0), Descriptor {
+                addr: phys + HEADER_OFFSET as u64,
+                len: 16,
+                flags: DESC_NEXT,
+                next: if data.is_some() { 1 } else { 2 },
+            });
+            if let Some(device_writes) = data {
+                write_volatile(self.descriptor(1), Descriptor {
+                    addr: phys + DATA_OFFSET as u64,
+                    len: 512,
+                    flags: DESC_NEXT | if device_writes { DESC_WRITE } else { 0 },
+                    next: 2,
+                });
+            }
+            write_volatile(self.descriptor(2), Descriptor {
+                addr: phys + STATUS_OFFSET as u64,
+                len: 1,
+                flags: DESC_WRITE,
+                next: 0,
+            });
+
+            // publish descriptor 0 in the available ring, then the new index
+            let avail = 16 * self.queue_size as usize;
+            let slot = avail + 4 + 2 * (self.avail_idx % self.queue_size) as usize;
+            write_volatile(self.queue.ptr_at::<u16>(slot), 0);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(self.queue.ptr_at::<u16>(avail + 2), self.avail_idx);
+            fence(Ordering::SeqCst);
+            Port::<u16>::new(self.io_base + REG_QUEUE_NOTIFY).write(0);
+        }
+        self.wait_used()?;
+        let status = unsafe { read_volatile(self.request.ptr_at::<u8>(STATUS_OFFSET)) };
+        if status == REQUEST_STATUS_OK { Ok(()) } else { Err(IoError::Device) }
+    }
+
+    fn device_used_idx(&self) -> u16 {
+        let idx = unsafe { read_volatile(self.queue.ptr_at::<u16>(self.used_offset + 2)) };
+        fence(Ordering::SeqCst);
+        idx
+    }
+
+    /// Wait for the used ring to move past the last consumed entry. On a
+    /// timeout the device still owns the request buffers and may complete
+    /// the request later, which would make the used index match the next
+    /// request too early, so the device is failed for good.
+    fn wait_used(&mut self) -> Result<(), IoError> {
+        let target = self.used_idx.wrapping_add(1);
+        if self.interrupt_driven && interrupts::are_enabled() {
+            for _ in 0..WAKE_LIMIT {
+                // disable first so the completion cannot slip in between the
+                // check and `hlt`; `sti; hlt` then wakes on it
+                interrupts::disable();
+                if self.device_used_idx() == target {
+                    interrupts::enable();
+                    self.used_idx = target;
+                    return Ok(());
+                }
+                interrupts::enable_and_hlt();
+            }
+        } else {
+            for _ in 0..POLL_LIMIT {
+                if self.device_used_idx() == target {
+                    self.used_idx = target;
+                    return Ok(());
+                }
+                core::hint::spin_loop();
+            }
+        }
+        self.failed = true;
+        Err(IoError::Device)
+    }
+
+    /// True once a request has timed out; every later request fails.
+    pub fn is_failed(&self) -> bool { self.failed }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        if buf.len() != 512 { return Err(IoError::BadBuffer); }
+        if lba >= self.sectors { return Err(IoError::OutOfRange); }
+        self.submit(REQUEST_IN, lba, Some(true))?;
+        let sector = unsafe { read_volatile(self.request.ptr_at::<[u8; 512]>(DATA_OFFSET)) };
+        buf.copy_from_slice(&sector);
+        Ok(())
+    }
+
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if data.len() != 512 { return Err(IoError::BadBuffer); }
+        if lba >= self.sectors { return Err(IoError::OutOfRange); }
+        if self.read_only { return Err(IoError::ReadOnly); }
+        let mut sector = [0u8; 512];
+        sector.copy_from_slice(data);
+        unsafe { write_volatile(self.request.ptr_at::<[u8; 512]>(DATA_OFFSET), sector) };
+        self.submit(REQUEST_OUT, lba, Some(false))
+    }
+
+    fn sector_count(&self) -> u64 { self.sectors }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        if !self.can_flush { return Ok(()); }
+        self.submit(REQUEST_FLUSH, 0, None)
+    }
+
+    fn is_read_only(&self) -> bool { self.read_only }
+}
@@ -1,9 +1,59 @@
+use core::fmt;
+
+/// Errors reported by a `BlockDevice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoError {
+    /// The LBA is past the end of the device.
+    OutOfRange,
+    /// The buffer is not exactly one sector long.
+    BadBuffer,
+    /// The device does not accept writes.
+    ReadOnly,
+    /// The device reported a failure.
+    Device,
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoError::OutOfRange => write!(f, "sector out of range"),
+            IoError::BadBuffer => write!(f, "buffer is not one sector"),
+            IoError::ReadOnly => write!(f, "device is read-only"),
+            IoError::Device => write!(f, "device error"),
+        }
+    }
+}
+
 /// Minimal BlockDevice trait used by the FAT modules.
+///
+/// Reads take `&mut self` so that drivers and caches can update their own
+/// state (I/O ports, cached sectors) while reading.
 pub trait BlockDevice {
     /// Read exactly 512 bytes from LBA into `buf`.
-    fn read_sector(&self, lba: u64, buf: &mut [u8]);
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError>;
     /// Write exactly 512 bytes from `data` into LBA.
-    fn write_sector(&mut self, lba: u64, data: &[u8]);
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError>;
     /// Number of 512-byte sectors on this device.
     fn sector_count(&self) -> u64;
+    /// Make sure all completed writes have reached the medium.
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+    /// True if writes will be rejected.
+    fn is_read_only(&self) -> bool { false }
+}
+
+/// Lets wrappers such as `Partition` borrow a device instead of owning it.
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> { (**self).read_sector(lba, buf) }
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> { (**self).write_sector(lba, data) }
+    fn sector_count(&self) -> u64 { (**self).sector_count() }
+    fn flush(&mut self) -> Result<(), IoError> { (**self).flush() }
+    fn is_read_only(&self) -> bool { (**self).is_read_only() }
+}
+
+/// Check `lba` and the buffer length for a `BlockDevice` with `sectors`
+/// sectors, returning the byte offset of the sector.
+pub fn sector_offset(lba: u64, len: usize, sectors: u64) -> Result<usize, IoError> {
+    if len != 512 { return Err(IoError::BadBuffer); }
+    if lba >= sectors { return Err(IoError::OutOfRange); }
+    Ok(lba as usize * 512)
 }
@@ -1,10 +1,14 @@
-use crate::fs::fat_constants::{BOOT_SIG_LEAD, BOOT_SIG_TRAIL};
+use crate::fs::fat_constants::{
+    BOOT_SIG_LEAD, BOOT_SIG_TRAIL, BYTES_PER_SECTOR, EXTENDED_BOOT_SIG, FAT12_MAX_CLUSTERS, FAT16_MAX_CLUSTERS,
+    FAT32_BACKUP_BOOT_SECTOR, MEDIA_FIXED, NO_LABEL,
+};
 use core::fmt;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum FatError {
     InvalidLength,
     InvalidSignature,
+    InvalidGeometry,
 }
 
 impl fmt::Display for FatError {
@@ -12,6 +16,59 @@ impl fmt::Display for FatError {
         match self {
             FatError::InvalidLength => write!(f, "invalid boot sector length"),
             FatError::InvalidSignature => write!(f, "invalid boot signature"),
+            FatError::InvalidGeometry => write!(f, "invalid BPB geometry"),
+        }
+    }
+}
+
+/// FAT variant, determined solely by the number of data clusters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    pub fn from_cluster_count(clusters: u32) -> Self {
+        if clusters <= FAT12_MAX_CLUSTERS {
+            FatType::Fat12
+        } else if clusters <= FAT16_MAX_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    /// Smallest FAT entry value that marks the end of a chain.
+    pub fn eoc_min(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+
+    /// FAT entry value that marks a bad cluster.
+    pub fn bad_cluster(self) -> u32 {
+        self.eoc_min() - 1
+    }
+
+    /// Value written to terminate a chain.
+    pub fn eoc(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    /// File system type string stored in the extended BPB.
+    pub fn label(self) -> &'static [u8; 8] {
+        match self {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+            FatType::Fat32 => b"FAT32   ",
         }
     }
 }
@@ -23,11 +80,29 @@ pub struct BootSector {
     pub reserved_sectors: u16,
     pub num_fats: u8,
     pub max_root_dir_entries: u16,
-    pub total_sectors: u16,
-    pub sectors_per_fat: u16,
+    /// From `total_sectors_small` (offset 19), or `total_sectors_large`
+    /// (offset 32) when the small field is 0.
+    pub total_sectors: u32,
+    /// From offset 22, or the FAT32 field at offset 36 when that is 0.
+    pub sectors_per_fat: u32,
+    pub fat_type: FatType,
+    /// First cluster of the root directory (FAT32 only, 0 otherwise).
+    pub root_cluster: u32,
+    /// Sector number of the FSInfo sector (FAT32 only, 0 otherwise).
+    pub fs_info_sector: u16,
     pub fat_start_lba: u32,
+    /// Start of the fixed root region. For FAT32, which has none, this is the
+    /// same as `data_start_lba`.
     pub root_dir_start_lba: u32,
     pub data_start_lba: u32,
+    /// Name of the formatting system, offset 3.
+    pub oem_name: [u8; 8],
+    /// Media descriptor, offset 21; also the low byte of FAT entry 0.
+    pub media: u8,
+    /// Volume serial number from the extended BPB (0 if there is none).
+    pub volume_id: u32,
+    /// Volume label from the extended BPB, space padded.
+    pub volume_label: [u8; 11],
 }
 
 impl BootSector {
@@ -40,13 +115,50 @@ impl BootSector {
         let reserved_sectors = u16::from_le_bytes([buf[14], buf[15]]);
         let num_fats = buf[16];
         let max_root_dir_entries = u16::from_le_bytes([buf[17], buf[18]]);
-        let total_sectors = u16::from_le_bytes([buf[19], buf[20]]);
-        let sectors_per_fat = u16::from_le_bytes([buf[22], buf[23]]);
+        let total_sectors_small = u16::from_le_bytes([buf[19], buf[20]]);
+        let sectors_per_fat_small = u16::from_le_bytes([buf[22], buf[23]]);
+        let total_sectors_large = u32::from_le_bytes([buf[32], buf[33], buf[34], buf[35]]);
+        let sectors_per_fat_large = u32::from_le_bytes([buf[36], buf[37], buf[38], buf[39]]);
+
+        // block devices move 512-byte sectors and the rest of the stack
+        // counts in them, so other sector sizes cannot be mounted
+        if bytes_per_sector != BYTES_PER_SECTOR || sectors_per_cluster == 0 || num_fats == 0 {
+            return Err(FatError::InvalidGeometry);
+        }
+
+        let total_sectors = if total_sectors_small != 0 { total_sectors_small as u32 } else { total_sectors_large };
+        let sectors_per_fat = if sectors_per_fat_small != 0 { sectors_per_fat_small as u32 } else { sectors_per_fat_large };
 
         let fat_start_lba = reserved_sectors as u32;
-        let root_dir_start_lba = fat_start_lba + (num_fats as u32 * sectors_per_fat as u32);
-        let root_dir_sectors = ((max_root_dir_entries as u32 * 32) + (bytes_per_sector as u32 - 1)) / bytes_per_sector as u32;
-        let data_start_lba = root_dir_start_lba + root_dir_sectors;
+        // a corrupt BPB (or MBR boot code read as one) must not overflow
+        let root_dir_start_lba = (num_fats as u32)
+            .checked_mul(sectors_per_fat)
+            .and_then(|fats| fat_start_lba.checked_add(fats))
+            .ok_or(FatError::InvalidGeometry)?;
+        let root_dir_sectors = (max_root_dir_entries as u32 * 32).div_ceil(bytes_per_sector as u32);
+        let data_start_lba = root_dir_start_lba.checked_add(root_dir_sectors).ok_or(FatError::InvalidGeometry)?;
+        let clusters = total_sectors.saturating_sub(data_start_lba) / sectors_per_cluster as u32;
+        let fat_type = FatType::from_cluster_count(clusters);
+
+        let mut oem_name = [0u8; 8];
+        oem_name.copy_from_slice(&buf[3..11]);
+        // the extended BPB follows the FAT32 fields, if present
+        let ext = if fat_type == FatType::Fat32 { 64 } else { 36 };
+        let mut volume_label = *NO_LABEL;
+        let mut volume_id = 0;
+        if buf[ext + 2] == EXTENDED_BOOT_SIG {
+            volume_id = u32::from_le_bytes([buf[ext + 3], buf[ext + 4], buf[ext + 5], buf[ext + 6]]);
+            volume_label.copy_from_slice(&buf[ext + 7..ext + 18]);
+        }
+
+        let (root_cluster, fs_info_sector) = if fat_type == FatType::Fat32 {
+            (
+                u32::from_le_bytes([buf[44], buf[45], buf[46], buf[47]]),
+                u16::from_le_bytes([buf[48], buf[49]]),
+            )
+        } else {
+            (0, 0)
+        };
 
         Ok(BootSector {
             bytes_per_sector,
@@ -56,26 +168,72 @@ impl BootSector {
             max_root_dir_entries,
             total_sectors,
             sectors_per_fat,
+            fat_type,
+            root_cluster,
+            fs_info_sector,
             fat_start_lba,
             root_dir_start_lba,
             data_start_lba,
+            oem_name,
+            media: buf[21],
+            volume_id,
+            volume_label,
         })
     }
 
     pub fn serialize(&self, buf: &mut [u8]) -> Result<(), FatError> {
         if buf.len() < 512 { return Err(FatError::InvalidLength); }
+        let fat32 = self.fat_type == FatType::Fat32;
+        // jump over the BPB to a halt loop: the volume is not bootable
+        let ext = if fat32 { 64 } else { 36 };
+        let stub = ext + 26;
+        buf[0..3].copy_from_slice(&[0xEB, stub as u8 - 2, 0x90]);
+        buf[stub..stub + 3].copy_from_slice(&[0xF4, 0xEB, 0xFD]);
+        buf[3..11].copy_from_slice(&self.oem_name);
         buf[11..13].copy_from_slice(&self.bytes_per_sector.to_le_bytes());
         buf[13] = self.sectors_per_cluster;
         buf[14..16].copy_from_slice(&self.reserved_sectors.to_le_bytes());
         buf[16] = self.num_fats;
         buf[17..19].copy_from_slice(&self.max_root_dir_entries.to_le_bytes());
-        buf[19..21].copy_from_slice(&self.total_sectors.to_le_bytes());
-        buf[22..24].copy_from_slice(&self.sectors_per_fat.to_le_bytes());
+        buf[21] = self.media;
+        // CHS geometry only matters to BIOS booting; use the usual values
+        let (sectors_per_track, heads): (u16, u16) = if self.media == MEDIA_FIXED { (63, 255) } else { (18, 2) };
+        buf[24..26].copy_from_slice(&sectors_per_track.to_le_bytes());
+        buf[26..28].copy_from_slice(&heads.to_le_bytes());
+        if !fat32 && self.total_sectors <= u16::MAX as u32 {
+            buf[19..21].copy_from_slice(&(self.total_sectors as u16).to_le_bytes());
+            buf[32..36].copy_from_slice(&0u32.to_le_bytes());
+        } else {
+            buf[19..21].copy_from_slice(&0u16.to_le_bytes());
+            buf[32..36].copy_from_slice(&self.total_sectors.to_le_bytes());
+        }
+        if fat32 {
+            buf[22..24].copy_from_slice(&0u16.to_le_bytes());
+            buf[36..40].copy_from_slice(&self.sectors_per_fat.to_le_bytes());
+            buf[44..48].copy_from_slice(&self.root_cluster.to_le_bytes());
+            buf[48..50].copy_from_slice(&self.fs_info_sector.to_le_bytes());
+            let backup: u16 = if self.reserved_sectors > FAT32_BACKUP_BOOT_SECTOR { FAT32_BACKUP_BOOT_SECTOR } else { 0 };
+            buf[50..52].copy_from_slice(&backup.to_le_bytes());
+        } else {
+            if self.sectors_per_fat > u16::MAX as u32 { return Err(FatError::InvalidGeometry); }
+            buf[22..24].copy_from_slice(&(self.sectors_per_fat as u16).to_le_bytes());
+        }
+        // extended BPB: drive number, boot signature, serial, label, type
+        buf[ext] = if self.media == MEDIA_FIXED { 0x80 } else { 0x00 };
+        buf[ext + 2] = EXTENDED_BOOT_SIG;
+        buf[ext + 3..ext + 7].copy_from_slice(&self.volume_id.to_le_bytes());
+        buf[ext + 7..ext + 18].copy_from_slice(&self.volume_label);
+        buf[ext + 18..ext + 26].copy_from_slice(self.fat_type.label());
         // boot sig
         buf[510] = BOOT_SIG_LEAD;
         buf[511] = BOOT_SIG_TRAIL;
         Ok(())
     }
+
+    /// Number of data clusters on the volume.
+    pub fn cluster_count(&self) -> u32 {
+        self.total_sectors.saturating_sub(self.data_start_lba) / self.sectors_per_cluster as u32
+    }
 }
 
 // Unit tests for BootSector moved to tests/fs_integration.rs
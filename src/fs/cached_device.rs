@@ -0,0 +1,156 @@
+// Sector cache for block devices.
+//
+// `CachedDevice` wraps any `BlockDevice` and keeps the most recently used
+// sectors in memory. Writes only touch the cache and mark the slot dirty;
+// dirty sectors reach the wrapped device when they are evicted or when
+// `sync` (or `flush`) is called. Eviction picks the least recently used slot.
+// Lookups are a linear scan, which is fine for the few dozen slots a kernel
+// heap can spare.
+
+use crate::fs::block_device::{BlockDevice, IoError};
+use alloc::vec::Vec;
+
+/// Hit/miss counters for a `CachedDevice`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads and writes served from a cached sector.
+    pub hits: u64,
+    /// Reads and writes that needed a free or evicted slot.
+    pub misses: u64,
+    /// Dirty sectors written to the wrapped device.
+    pub writebacks: u64,
+}
+
+struct Slot {
+    lba: u64,
+    data: [u8; 512],
+    dirty: bool,
+    // value of `CachedDevice::tick` at the last access
+    last_used: u64,
+}
+
+pub struct CachedDevice<D: BlockDevice> {
+    inner: D,
+    slots: Vec<Slot>,
+    capacity: usize,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl<D: BlockDevice> CachedDevice<D> {
+    /// Wrap `inner` with a cache of `capacity` sectors (at least one).
+    pub fn new(inner: D, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        CachedDevice { inner, slots: Vec::with_capacity(capacity), capacity, tick: 0, stats: CacheStats::default() }
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    pub fn stats(&self) -> CacheStats { self.stats }
+
+    pub fn reset_stats(&mut self) { self.stats = CacheStats::default(); }
+
+    /// Number of cached sectors not yet written to the wrapped device.
+    pub fn dirty_count(&self) -> usize { self.slots.iter().filter(|s| s.dirty).count() }
+
+    /// The wrapped device. Reading it directly skips dirty cached sectors.
+    pub fn inner(&self) -> &D { &self.inner }
+
+    /// The wrapped device. Writes made through it are not seen by the cache;
+    /// call `invalidate` afterwards.
+    pub fn inner_mut(&mut self) -> &mut D { &mut self.inner }
+
+    /// Write every dirty sector to the wrapped device, in LBA order.
+    pub fn sync(&mut self) -> Result<(), IoError> {
+        self.slots.sort_unstable_by_key(|s| s.lba);
+        for slot in self.slots.iter_mut().filter(|s| s.dirty) {
+            self.inner.write_sector(slot.lba, &slot.data)?;
+            slot.dirty = false;
+            self.stats.writebacks += 1;
+        }
+        Ok(())
+    }
+
+    /// Write back dirty sectors and empty the cache.
+    pub fn invalidate(&mut self) -> Result<(), IoError> {
+        self.sync()?;
+        self.slots.clear();
+        Ok(())
+    }
+
+    /// Index of the slot holding `lba`, marking it as just used.
+    fn lookup(&mut self, lba: u64) -> Option<usize> {
+        self.tick += 1;
+        let i = self.slots.iter().position(|s| s.lba == lba)?;
+        self.slots[i].last_used = self.tick;
+        self.stats.hits += 1;
+        Some(i)
+    }
+
+    /// Claim a slot for `lba`, evicting (and writing back) the least
+    /// recently used one when the cache is full. The slot's data is stale.
+    fn claim(&mut self, lba: u64) -> Result<usize, IoError> {
+        self.stats.misses += 1;
+        let slot = Slot { lba, data: [0u8; 512], dirty: false, last_used: self.tick };
+        if self.slots.len() < self.capacity {
+            self.slots.push(slot);
+            return Ok(self.slots.len() - 1);
+        }
+        let (i, _) = self.slots.iter().enumerate().min_by_key(|(_, s)| s.last_used).expect("cache has no slots");
+        if self.slots[i].dirty {
+            self.inner.write_sector(self.slots[i].lba, &self.slots[i].data)?;
+            self.stats.writebacks += 1;
+        }
+        self.slots[i] = slot;
+        Ok(i)
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for CachedDevice<D> {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        if buf.len() != 512 { return Err(IoError::BadBuffer); }
+        if lba >= self.inner.sector_count() { return Err(IoError::OutOfRange); }
+        let i = match self.lookup(lba) {
+            Some(i) => i,
+            None => {
+                let i = self.claim(lba)?;
+                if let Err(e) = self.inner.read_sector(lba, &mut self.slots[i].data) {
+                    self.slots.swap_remove(i);
+                    return Err(e);
+                }
+                i
+            }
+        };
+        buf.copy_from_slice(&self.slots[i].data);
+        Ok(())
+    }
+
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if data.len() != 512 { return Err(IoError::BadBuffer); }
+        if lba >= self.inner.sector_count() { return Err(IoError::OutOfRange); }
+        if self.inner.is_read_only() { return Err(IoError::ReadOnly); }
+        // whole-sector writes never need the old contents
+        let i = match self.lookup(lba) {
+            Some(i) => i,
+            None => self.claim(lba)?,
+        };
+        self.slots[i].data.copy_from_slice(data);
+        self.slots[i].dirty = true;
+        Ok(())
+    }
+
+    fn sector_count(&self) -> u64 { self.inner.sector_count() }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.sync()?;
+        self.inner.flush()
+    }
+
+    fn is_read_only(&self) -> bool { self.inner.is_read_only() }
+}
+
+impl<D: BlockDevice> Drop for CachedDevice<D> {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
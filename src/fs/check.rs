@@ -0,0 +1,333 @@
+// Consistency checker (fsck) for mounted FAT volumes.
+//
+// `check` validates the BPB, then walks the directory tree from the root and
+// follows every cluster chain, claiming each cluster in a bitmap. A cluster
+// that is claimed twice is cross-linked; a link that leaves the volume (or
+// points at a free cluster) is invalid. Chains are compared against the
+// file size, and any allocated cluster that no chain reached is lost.
+//
+// With `repair` set, bad chains are cut at the last good cluster, file sizes
+// and chain lengths are made to agree, unrecoverable directory entries are
+// removed, lost clusters are freed and the FAT32 free count is rewritten.
+
+use crate::fs::block_device::BlockDevice;
+use crate::fs::boot_sector::FatType;
+use crate::fs::fat_constants::FSINFO_UNKNOWN;
+use crate::fs::fs::{FileSystem, FsError};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A BPB field is out of range or inconsistent.
+    BadBootSector(&'static str),
+    /// A chain starts at, or links to, a value that is not a data cluster.
+    InvalidCluster { path: String, cluster: u32 },
+    /// A chain runs into a cluster that already belongs to another chain
+    /// (or loops back on itself).
+    CrossLinked { path: String, cluster: u32 },
+    /// The chain has fewer clusters than `size` needs.
+    ChainTooShort { path: String, clusters: u32, size: u32 },
+    /// The chain has more clusters than `size` needs.
+    ChainTooLong { path: String, clusters: u32, size: u32 },
+    /// A subdirectory's `.` or `..` entry points at the wrong cluster.
+    BadDotEntry { path: String },
+    /// Allocated clusters that no chain reaches.
+    LostClusters { count: u32 },
+    /// The FSInfo free cluster count does not match the FAT.
+    FreeCountMismatch { recorded: u32, actual: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadBootSector(what) => write!(f, "boot sector: {}", what),
+            Problem::InvalidCluster { path, cluster } => write!(f, "{}: invalid cluster {:#x}", path, cluster),
+            Problem::CrossLinked { path, cluster } => write!(f, "{}: cross-linked at cluster {}", path, cluster),
+            Problem::ChainTooShort { path, clusters, size } => {
+                write!(f, "{}: {} clusters too short for {} bytes", path, clusters, size)
+            }
+            Problem::ChainTooLong { path, clusters, size } => {
+                write!(f, "{}: {} clusters too long for {} bytes", path, clusters, size)
+            }
+            Problem::BadDotEntry { path } => write!(f, "{}: bad . or .. entry", path),
+            Problem::LostClusters { count } => write!(f, "{} lost clusters", count),
+            Problem::FreeCountMismatch { recorded, actual } => {
+                write!(f, "FSInfo free count {} (actual {})", recorded, actual)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    pub problems: Vec<Problem>,
+    pub files: u32,
+    pub directories: u32,
+    /// Free clusters after the check (and repair, if requested).
+    pub free_clusters: u32,
+    /// True if repairs were requested.
+    pub repaired: bool,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool { self.problems.is_empty() }
+}
+
+/// One bit per cluster, set once a chain has claimed it.
+struct ClusterBitmap {
+    words: Vec<u32>,
+}
+
+impl ClusterBitmap {
+    fn new(clusters: u32) -> Self {
+        ClusterBitmap { words: vec![0u32; (clusters as usize).div_ceil(32)] }
+    }
+
+    fn get(&self, c: u32) -> bool { self.words[c as usize / 32] & (1 << (c % 32)) != 0 }
+
+    fn set(&mut self, c: u32) { self.words[c as usize / 32] |= 1 << (c % 32); }
+}
+
+/// Why a chain walk stopped early.
+enum Fault {
+    Invalid(u32),
+    CrossLinked(u32),
+}
+
+/// Result of following one chain.
+struct Walk {
+    /// Clusters claimed for this chain.
+    count: u32,
+    /// Last good cluster, if any.
+    last: Option<u32>,
+    fault: Option<Fault>,
+}
+
+fn walk_chain<D: BlockDevice>(
+    fs: &mut FileSystem<'_, D>,
+    used: &mut ClusterBitmap,
+    start: u32,
+) -> Result<Walk, FsError> {
+    let mut fat = fs.fat();
+    let mut walk = Walk { count: 0, last: None, fault: None };
+    let mut cur = start;
+    loop {
+        if !fat.is_valid_cluster(cur) {
+            walk.fault = Some(Fault::Invalid(cur));
+            break;
+        }
+        if used.get(cur) {
+            walk.fault = Some(Fault::CrossLinked(cur));
+            break;
+        }
+        used.set(cur);
+        walk.count += 1;
+        walk.last = Some(cur);
+        let next = fat.read_entry(cur)?;
+        if fat.is_eoc(next) { break; }
+        cur = next;
+    }
+    Ok(walk)
+}
+
+fn check_boot_sector<D: BlockDevice>(fs: &mut FileSystem<'_, D>, report: &mut CheckReport) {
+    let bs = fs.boot_sector;
+    let mut bad = |what| report.problems.push(Problem::BadBootSector(what));
+    if bs.bytes_per_sector != 512 { bad("bytes per sector is not 512"); }
+    if !bs.sectors_per_cluster.is_power_of_two() { bad("sectors per cluster is not a power of two"); }
+    if bs.reserved_sectors == 0 { bad("no reserved sectors"); }
+    if bs.data_start_lba >= bs.total_sectors { bad("no room for a data region"); }
+    let entries_per_fat = match bs.fat_type {
+        FatType::Fat12 => bs.sectors_per_fat as u64 * 512 * 2 / 3,
+        FatType::Fat16 => bs.sectors_per_fat as u64 * 512 / 2,
+        FatType::Fat32 => bs.sectors_per_fat as u64 * 512 / 4,
+    };
+    if entries_per_fat < bs.cluster_count() as u64 + 2 { bad("FAT too small for the cluster count"); }
+    match bs.fat_type {
+        FatType::Fat32 => {
+            if bs.root_cluster < 2 || bs.root_cluster > bs.cluster_count() + 1 { bad("root cluster out of range"); }
+        }
+        _ => {
+            if bs.max_root_dir_entries == 0 { bad("no root directory entries"); }
+        }
+    }
+    if bs.total_sectors as u64 > fs.device.sector_count() { bad("volume extends past the end of the device"); }
+}
+
+/// Check the volume and, with `repair`, fix what can be fixed. Device errors
+/// abort the check.
+pub fn check<D: BlockDevice>(fs: &mut FileSystem<'_, D>, repair: bool) -> Result<CheckReport, FsError> {
+    let mut report = CheckReport { repaired: repair, ..CheckReport::default() };
+    check_boot_sector(fs, &mut report);
+    let cluster_bytes = fs.boot_sector.sectors_per_cluster as u32 * 512;
+    let max_cluster = fs.fat().max_cluster();
+    let mut used = ClusterBitmap::new(max_cluster + 1);
+    // the FSInfo count is compared against the FAT as found, before repairs
+    let free_before = {
+        let mut fat = fs.fat();
+        let mut free = 0u32;
+        for c in 2..=max_cluster {
+            if fat.read_entry(c)? == 0 { free += 1; }
+        }
+        free
+    };
+
+    if let Some(root) = fs.root_cluster() {
+        let walk = walk_chain(fs, &mut used, root)?;
+        if let Some(fault) = walk.fault {
+            report_fault(&mut report, String::from("/"), fault);
+            if repair && let Some(last) = walk.last { cut_chain(fs, last)?; }
+        }
+    }
+
+    // directories still to visit: (first cluster, path)
+    let mut pending: Vec<(u32, String)> = vec![(0, String::new())];
+    while let Some((dir, dir_path)) = pending.pop() {
+        for entry in fs.open_dir(dir)?.list()? {
+            if entry.is_dot() { continue; }
+            let name = entry.display_name();
+            let path = format!("{}/{}", dir_path, name);
+            if entry.is_dir() {
+                report.directories += 1;
+            } else {
+                report.files += 1;
+            }
+            // empty files have no chain
+            let mut walk = if entry.start_cluster == 0 && !entry.is_dir() {
+                Walk { count: 0, last: None, fault: None }
+            } else {
+                walk_chain(fs, &mut used, entry.start_cluster)?
+            };
+            let faulted = walk.fault.is_some();
+            if let Some(fault) = walk.fault.take() {
+                report_fault(&mut report, path.clone(), fault);
+                if repair {
+                    match walk.last {
+                        Some(last) => cut_chain(fs, last)?,
+                        // nothing of the chain is usable
+                        None if entry.is_dir() => fs.open_dir(dir)?.delete(&name)?,
+                        None => { fs.open_dir(dir)?.set_cluster_and_size(&name, 0, 0)?; }
+                    }
+                }
+            }
+            if entry.is_dir() {
+                if walk.count > 0 {
+                    check_dot_entries(fs, &mut report, entry.start_cluster, dir, &path, repair)?;
+                    pending.push((entry.start_cluster, path));
+                }
+                continue;
+            }
+            if walk.count == 0 && faulted {
+                // already reported; repair truncated the file to nothing
+                continue;
+            }
+            let needed = entry.file_size.div_ceil(cluster_bytes);
+            if walk.count < needed {
+                report.problems.push(Problem::ChainTooShort { path, clusters: walk.count, size: entry.file_size });
+                if repair {
+                    let start = if walk.count == 0 { 0 } else { entry.start_cluster };
+                    fs.open_dir(dir)?.set_cluster_and_size(&name, start, walk.count * cluster_bytes)?;
+                }
+            } else if walk.count > needed {
+                report.problems.push(Problem::ChainTooLong { path, clusters: walk.count, size: entry.file_size });
+                if repair {
+                    if needed == 0 {
+                        let start = entry.start_cluster;
+                        fs.with_fat(|fat| Ok(fat.free_cluster(start)?))?;
+                        fs.open_dir(dir)?.set_cluster_and_size(&name, 0, entry.file_size)?;
+                    } else {
+                        let last = fs.fat().get_chain(entry.start_cluster)?[needed as usize - 1];
+                        // the tail was claimed by this chain alone, so it can
+                        // be freed directly
+                        fs.with_fat(|fat| {
+                            let tail = fat.read_entry(last)?;
+                            let eoc = fat.eoc();
+                            fat.write_entry(last, eoc)?;
+                            fat.free_cluster(tail)?;
+                            Ok(())
+                        })?;
+                    }
+                }
+            }
+        }
+    }
+
+    // allocated clusters that no chain claimed
+    let bad = fs.boot_sector.fat_type.bad_cluster();
+    let mut lost = 0u32;
+    let mut free = 0u32;
+    fs.with_fat(|fat| {
+        for c in 2..=max_cluster {
+            let value = fat.read_entry(c)?;
+            if value == 0 {
+                free += 1;
+            } else if value != bad && !used.get(c) {
+                lost += 1;
+                if repair {
+                    fat.write_entry(c, 0)?;
+                    free += 1;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    if lost > 0 { report.problems.push(Problem::LostClusters { count: lost }); }
+    report.free_clusters = free;
+
+    if let Some(mut info) = fs.fs_info {
+        if info.free_count != FSINFO_UNKNOWN && info.free_count != free_before {
+            report.problems.push(Problem::FreeCountMismatch { recorded: info.free_count, actual: free_before });
+        }
+        if repair && info.free_count != free {
+            info.free_count = free;
+            fs.fs_info = Some(info);
+            fs.write_fs_info()?;
+        }
+    }
+    Ok(report)
+}
+
+fn report_fault(report: &mut CheckReport, path: String, fault: Fault) {
+    report.problems.push(match fault {
+        Fault::Invalid(cluster) => Problem::InvalidCluster { path, cluster },
+        Fault::CrossLinked(cluster) => Problem::CrossLinked { path, cluster },
+    });
+}
+
+/// End the chain at `last`. The clusters it used to link to are no longer
+/// claimed by anything and are freed by the lost-cluster pass.
+fn cut_chain<D: BlockDevice>(fs: &mut FileSystem<'_, D>, last: u32) -> Result<(), FsError> {
+    fs.with_fat(|fat| {
+        let eoc = fat.eoc();
+        Ok(fat.write_entry(last, eoc)?)
+    })
+}
+
+fn check_dot_entries<D: BlockDevice>(
+    fs: &mut FileSystem<'_, D>,
+    report: &mut CheckReport,
+    cluster: u32,
+    parent: u32,
+    path: &str,
+    repair: bool,
+) -> Result<(), FsError> {
+    let root = fs.root_cluster();
+    let (dot, dotdot) = {
+        let mut dir = fs.open_dir(cluster)?;
+        (dir.find(".")?.map(|e| e.start_cluster), dir.find("..")?.map(|e| e.start_cluster))
+    };
+    // FAT32 volumes written elsewhere may store the root cluster in `..`
+    let parent_ok = |c: u32| c == parent || (parent == 0 && Some(c) == root);
+    if dot == Some(cluster) && dotdot.is_some_and(parent_ok) { return Ok(()); }
+    report.problems.push(Problem::BadDotEntry { path: String::from(path) });
+    if repair {
+        let mut dir = fs.open_dir(cluster)?;
+        if dot.is_some() { dir.set_cluster_and_size(".", cluster, 0)?; }
+        if dotdot.is_some() { dir.set_cluster_and_size("..", parent, 0)?; }
+    }
+    Ok(())
+}
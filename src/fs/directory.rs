@@ -1,13 +1,33 @@
-use crate::fs::block_device::BlockDevice;
+use crate::fs::block_device::{BlockDevice, IoError};
+use crate::fs::lfn::{self, LfnAccumulator, ATTR_LONG_NAME, LFN_LAST_FLAG};
+use crate::fs::time::{self, DateTime};
+use alloc::string::String;
+use alloc::vec::Vec;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// Attribute bits stored at offset 11 of a directory entry.
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Bits a caller may change with `FileSystem::set_attributes`.
+pub const ATTR_USER_MASK: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_ARCHIVE;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DirectoryEntry {
     pub name: [u8; 8],
     pub ext: [u8; 3],
     pub attr: u8,
-    pub reserved: [u8; 10],
-    pub start_cluster: u16,
+    pub created: DateTime,
+    pub modified: DateTime,
+    /// Last access date; FAT keeps no access time, so the time is midnight.
+    pub accessed: DateTime,
+    /// First cluster; the high 16 bits live at offset 20 (FAT32 only).
+    pub start_cluster: u32,
     pub file_size: u32,
+    /// VFAT long name stored in the LFN entries preceding this one, if any.
+    pub long_name: Option<String>,
 }
 
 impl DirectoryEntry {
@@ -16,129 +36,384 @@ impl DirectoryEntry {
             name: [0u8; 8],
             ext: [0u8; 3],
             attr: 0,
-            reserved: [0u8; 10],
+            created: DateTime::default(),
+            modified: DateTime::default(),
+            accessed: DateTime::default(),
             start_cluster: 0,
             file_size: 0,
+            long_name: None,
+        }
+    }
+
+    /// Raw 11-byte 8.3 name as stored on disk.
+    pub fn short_name(&self) -> [u8; 11] {
+        let mut out = [b' '; 11];
+        out[0..8].copy_from_slice(&self.name);
+        out[8..11].copy_from_slice(&self.ext);
+        out
+    }
+
+    /// Name to show to users: the long name if present, otherwise `BASE.EXT`.
+    pub fn display_name(&self) -> String {
+        if let Some(long) = &self.long_name {
+            return long.clone();
+        }
+        let base = core::str::from_utf8(&self.name).unwrap_or("").trim_end_matches(' ');
+        let ext = core::str::from_utf8(&self.ext).unwrap_or("").trim_end_matches(' ');
+        let mut s = String::from(base);
+        if !ext.is_empty() {
+            s.push('.');
+            s.push_str(ext);
         }
+        s
     }
+
+    /// True if `name` refers to this entry, by 8.3 name or (case-insensitively)
+    /// by long name.
+    pub fn matches(&self, name: &str) -> bool {
+        if to_short_name(name) == Some(self.short_name()) { return true; }
+        match &self.long_name {
+            Some(long) => long.to_lowercase() == name.to_lowercase(),
+            None => false,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool { self.attr & ATTR_DIRECTORY != 0 }
+
+    pub fn is_read_only(&self) -> bool { self.attr & ATTR_READ_ONLY != 0 }
+
+    pub fn is_hidden(&self) -> bool { self.attr & ATTR_HIDDEN != 0 }
+
+    pub fn is_system(&self) -> bool { self.attr & ATTR_SYSTEM != 0 }
+
+    pub fn is_archive(&self) -> bool { self.attr & ATTR_ARCHIVE != 0 }
+
+    /// True for the `.` and `..` entries at the start of every subdirectory.
+    pub fn is_dot(&self) -> bool { self.name[0] == b'.' }
+
+    fn parse(raw: &[u8; 32]) -> Self {
+        let mut name = [b' '; 8];
+        name.copy_from_slice(&raw[0..8]);
+        let mut ext = [b' '; 3];
+        ext.copy_from_slice(&raw[8..11]);
+        let attr = raw[11];
+        let start_cluster = u16::from_le_bytes([raw[26], raw[27]]) as u32
+            | (u16::from_le_bytes([raw[20], raw[21]]) as u32) << 16;
+        let file_size = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]);
+        let word = |off: usize| u16::from_le_bytes([raw[off], raw[off + 1]]);
+        DirectoryEntry {
+            name,
+            ext,
+            attr,
+            created: DateTime::from_fat(word(16), word(14), raw[13]),
+            modified: DateTime::from_fat(word(24), word(22), 0),
+            accessed: DateTime::from_fat(word(18), 0, 0),
+            start_cluster,
+            file_size,
+            long_name: None,
+        }
+    }
+}
+
+// Timestamp fields of a raw entry: creation (hundredths at 13, time at 14,
+// date at 16), last access date at 18 and last modification (time at 22,
+// date at 24).
+fn stamp_created(raw: &mut [u8; 32], t: DateTime) {
+    raw[13] = t.fat_hundredths();
+    raw[14..16].copy_from_slice(&t.fat_time().to_le_bytes());
+    raw[16..18].copy_from_slice(&t.fat_date().to_le_bytes());
+}
+
+fn stamp_modified(raw: &mut [u8; 32], t: DateTime) {
+    raw[18..20].copy_from_slice(&t.fat_date().to_le_bytes());
+    raw[22..24].copy_from_slice(&t.fat_time().to_le_bytes());
+    raw[24..26].copy_from_slice(&t.fat_date().to_le_bytes());
+}
+
+/// Characters allowed in a short name besides `A-Z` and `0-9`.
+const SHORT_NAME_SPECIALS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// True if `b` may appear in an (upper-case) 8.3 name.
+pub fn is_short_name_char(b: u8) -> bool {
+    b.is_ascii_uppercase() || b.is_ascii_digit() || SHORT_NAME_SPECIALS.contains(&b)
+}
+
+fn valid_short_part(part: &str, max: usize) -> bool {
+    part.len() <= max && part.bytes().all(is_short_name_char)
+}
+
+/// Convert a user supplied name into the raw 11-byte 8.3 form stored on disk.
+///
+/// Accepts either a dotted name (`"foo.bin"`, `"README"`) or an already
+/// padded 11-character name (`"FOO     BIN"`). `.` and `..` map to the
+/// special dot entries. Returns `None` when the name does not fit 8.3 or
+/// contains characters FAT does not allow in short names.
+pub fn to_short_name(name: &str) -> Option<[u8; 11]> {
+    let mut out = [b' '; 11];
+    if name == "." || name == ".." {
+        out[..name.len()].copy_from_slice(name.as_bytes());
+        return Some(out);
+    }
+    let up = name.to_ascii_uppercase();
+    let (base, ext) = if up.len() == 11 && !up.contains('.') {
+        (up[0..8].trim_end_matches(' '), up[8..11].trim_end_matches(' '))
+    } else {
+        match up.split_once('.') {
+            Some((b, e)) => (b, e),
+            None => (up.as_str(), ""),
+        }
+    };
+    if base.is_empty() || !valid_short_part(base, 8) || !valid_short_part(ext, 3) {
+        return None;
+    }
+    out[..base.len()].copy_from_slice(base.as_bytes());
+    out[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(out)
+}
+
+/// A live entry and the directory slots it occupies: `first..=idx`, where
+/// `first` is the first LFN fragment (or `idx` when there is no long name).
+struct Slot {
+    first: usize,
+    idx: usize,
+    entry: DirectoryEntry,
 }
 
+/// A directory backed by a list of sectors: either the fixed root region or
+/// the sectors of a subdirectory's cluster chain.
 pub struct Directory<'a, D: BlockDevice> {
     device: &'a mut D,
-    start_lba: u64,
+    sectors: Vec<u64>,
     num_entries: u16,
 }
 
 impl<'a, D: BlockDevice> Directory<'a, D> {
+    /// Directory stored in a contiguous region (the FAT12 root directory).
     pub fn new(device: &'a mut D, start_lba: u64, num_entries: u16) -> Self {
-        Directory { device, start_lba, num_entries }
+        let sector_count = (num_entries as u64 * 32).div_ceil(512);
+        let sectors = (start_lba..start_lba + sector_count).collect();
+        Directory { device, sectors, num_entries }
     }
-    
-    fn read_entry_raw(&mut self, idx: usize, out: &mut [u8; 32]) {
+
+    /// Directory stored in an arbitrary list of sectors (a cluster chain).
+    pub fn from_sectors(device: &'a mut D, sectors: Vec<u64>) -> Self {
+        let num_entries = (sectors.len() * 512 / 32) as u16;
+        Directory { device, sectors, num_entries }
+    }
+
+    fn read_entry_raw(&mut self, idx: usize, out: &mut [u8; 32]) -> Result<(), IoError> {
         let entries_per_sector = 512 / 32;
         let sector_idx = idx / entries_per_sector;
         let sector_off = idx % entries_per_sector;
         let mut sector = [0u8; 512];
-        self.device.read_sector(self.start_lba + sector_idx as u64, &mut sector);
+        self.device.read_sector(self.sectors[sector_idx], &mut sector)?;
         let start = sector_off * 32;
         out.copy_from_slice(&sector[start..start+32]);
+        Ok(())
     }
 
-    fn write_entry_raw(&mut self, idx: usize, data: &[u8; 32]) {
+    fn write_entry_raw(&mut self, idx: usize, data: &[u8; 32]) -> Result<(), IoError> {
         let entries_per_sector = 512 / 32;
         let sector_idx = idx / entries_per_sector;
         let sector_off = idx % entries_per_sector;
         let mut sector = [0u8; 512];
-        self.device.read_sector(self.start_lba + sector_idx as u64, &mut sector);
+        self.device.read_sector(self.sectors[sector_idx], &mut sector)?;
         let start = sector_off * 32;
         sector[start..start+32].copy_from_slice(data);
-        self.device.write_sector(self.start_lba + sector_idx as u64, &sector);
+        self.device.write_sector(self.sectors[sector_idx], &sector)
     }
 
-    pub fn list(&mut self) -> alloc::vec::Vec<DirectoryEntry> {
-        let mut out = alloc::vec::Vec::new();
+    /// Walk the directory and return every live entry together with the
+    /// slot range it occupies (LFN fragments included). Volume labels and
+    /// orphaned LFN fragments are skipped.
+    fn scan(&mut self) -> Result<Vec<Slot>, IoError> {
+        let mut out = Vec::new();
+        let mut lfn = LfnAccumulator::new();
+        let mut first = 0usize;
         for i in 0..self.num_entries as usize {
             let mut raw = [0u8; 32];
-            self.read_entry_raw(i, &mut raw);
+            self.read_entry_raw(i, &mut raw)?;
             if raw[0] == 0x00 { break; }
-            if raw[0] == 0xE5 { continue; }
-            // parse
-            let mut name = [b' '; 8];
-            name.copy_from_slice(&raw[0..8]);
-            let mut ext = [b' '; 3];
-            ext.copy_from_slice(&raw[8..11]);
-            let attr = raw[11];
-            let start_cluster = u16::from_le_bytes([raw[26], raw[27]]);
-            let file_size = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]);
-            out.push(DirectoryEntry { name, ext, attr, reserved: [0u8;10], start_cluster, file_size });
+            if raw[0] == 0xE5 { lfn.reset(); continue; }
+            if raw[11] == ATTR_LONG_NAME {
+                if raw[0] & LFN_LAST_FLAG != 0 { first = i; }
+                lfn.push(&raw);
+                continue;
+            }
+            let mut entry = DirectoryEntry::parse(&raw);
+            entry.long_name = lfn.take(&entry.short_name());
+            if raw[11] & ATTR_VOLUME_ID != 0 { continue; }
+            let start = if entry.long_name.is_some() { first } else { i };
+            out.push(Slot { first: start, idx: i, entry });
         }
-        out
+        Ok(out)
     }
 
-    pub fn find(&mut self, name: &str) -> Option<DirectoryEntry> {
-        for i in 0..self.num_entries as usize {
-            let mut raw = [0u8; 32];
-            self.read_entry_raw(i, &mut raw);
-            if raw[0] == 0x00 { break; }
-            if raw[0] == 0xE5 { continue; }
-            let fname = core::str::from_utf8(&raw[0..11]).unwrap_or("");
-            let fname_trim = fname.trim_end_matches(' ');
-            if fname_trim == name {                
-                let mut nameb = [b' '; 8];
-                nameb.copy_from_slice(&raw[0..8]);
-                let mut ext = [b' ';3]; ext.copy_from_slice(&raw[8..11]);
-                let attr = raw[11];
-                let start_cluster = u16::from_le_bytes([raw[26], raw[27]]);
-                let file_size = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]);
-                return Some(DirectoryEntry { name: nameb, ext, attr, reserved: [0u8;10], start_cluster, file_size });
+    pub fn list(&mut self) -> Result<Vec<DirectoryEntry>, IoError> {
+        Ok(self.scan()?.into_iter().map(|s| s.entry).collect())
+    }
+
+    /// Slot of the live entry called `name`, if any.
+    fn position(&mut self, name: &str) -> Result<Option<Slot>, IoError> {
+        Ok(self.scan()?.into_iter().find(|s| s.entry.matches(name)))
+    }
+
+    pub fn find(&mut self, name: &str) -> Result<Option<DirectoryEntry>, IoError> {
+        Ok(self.position(name)?.map(|s| s.entry))
+    }
+
+    pub fn create(&mut self, name: &str, start_cluster: u32, size: u32) -> Result<bool, IoError> {
+        self.create_with_attr(name, 0, start_cluster, size)
+    }
+
+    /// Write a new entry into the first run of free slots large enough for
+    /// it. Names that do not fit 8.3 get a generated `~N` short name and LFN
+    /// entries. Returns false when the name is invalid or the directory has
+    /// no room left.
+    pub fn create_with_attr(&mut self, name: &str, attr: u8, start_cluster: u32, size: u32) -> Result<bool, IoError> {
+        let (short, lfn_entries) = match to_short_name(name) {
+            Some(s) => (s, Vec::new()),
+            None if lfn::is_valid_long_name(name) => {
+                let taken: Vec<[u8; 11]> = self.scan()?.iter().map(|s| s.entry.short_name()).collect();
+                match lfn::generate_short_name(name, |c| taken.contains(c)) {
+                    Some(s) => (s, lfn::build_entries(name, &s)),
+                    None => return Ok(false),
+                }
             }
+            None => return Ok(false),
+        };
+        let needed = lfn_entries.len() + 1;
+        let start = match self.find_free_run(needed)? {
+            Some(i) => i,
+            None => return Ok(false),
+        };
+        for (k, raw) in lfn_entries.iter().enumerate() {
+            self.write_entry_raw(start + k, raw)?;
         }
-        None
+        let mut entry = [0u8; 32];
+        entry[0..11].copy_from_slice(&short);
+        entry[11] = attr;
+        entry[20..22].copy_from_slice(&((start_cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(start_cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        let now = time::now();
+        stamp_created(&mut entry, now);
+        stamp_modified(&mut entry, now);
+        self.write_entry_raw(start + needed - 1, &entry)?;
+        Ok(true)
     }
 
-    pub fn create(&mut self, name: &str, start_cluster: u16, size: u32) {
-        // find first free entry
+    /// First index of `needed` consecutive free slots. Everything after the
+    /// 0x00 end marker counts as free; if the run reaches into that area the
+    /// slot following it is cleared so it keeps terminating the directory.
+    fn find_free_run(&mut self, needed: usize) -> Result<Option<usize>, IoError> {
+        let mut run = 0usize;
+        let mut ended = false;
         for i in 0..self.num_entries as usize {
             let mut raw = [0u8; 32];
-            self.read_entry_raw(i, &mut raw);
-            if raw[0] == 0x00 || raw[0] == 0xE5 {
-                // write entry
-                let mut entry = [0u8; 32];
-                let mut name_buf = [b' '; 11];
-                for (j, b) in name.as_bytes().iter().take(11).enumerate() { name_buf[j] = *b; }
-                entry[0..11].copy_from_slice(&name_buf);
-                entry[11] = 0; // attr
-                entry[26..28].copy_from_slice(&start_cluster.to_le_bytes());
-                entry[28..32].copy_from_slice(&size.to_le_bytes());
-                self.write_entry_raw(i, &entry);
-                return;
+            if !ended {
+                self.read_entry_raw(i, &mut raw)?;
+                ended = raw[0] == 0x00;
+            }
+            if ended || raw[0] == 0xE5 { run += 1; } else { run = 0; }
+            if run == needed {
+                let next = i + 1;
+                if ended && next < self.num_entries as usize {
+                    self.write_entry_raw(next, &[0u8; 32])?;
+                }
+                return Ok(Some(next - needed));
             }
         }
+        Ok(None)
     }
 
-    pub fn delete(&mut self, name: &str) {
-        for i in 0..self.num_entries as usize {
-            let mut raw = [0u8; 32];
-            self.read_entry_raw(i, &mut raw);
-            if raw[0] == 0x00 { break; }
-            if raw[0] == 0xE5 { continue; }
-            let fname = core::str::from_utf8(&raw[0..11]).unwrap_or("");
-            let fname_trim = fname.trim_end_matches(' ');
-            if fname_trim == name {
+    /// Replace the attribute byte of the entry called `name`. Returns false
+    /// if no such entry exists.
+    pub fn set_attr(&mut self, name: &str, attr: u8) -> Result<bool, IoError> {
+        match self.position(name)? {
+            Some(slot) => {
+                let mut raw = [0u8; 32];
+                self.read_entry_raw(slot.idx, &mut raw)?;
+                raw[11] = attr;
+                self.write_entry_raw(slot.idx, &raw)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Point the entry called `name` at a new cluster chain and size.
+    pub fn set_cluster_and_size(&mut self, name: &str, start_cluster: u32, size: u32) -> Result<bool, IoError> {
+        match self.position(name)? {
+            Some(slot) => {
+                let mut raw = [0u8; 32];
+                self.read_entry_raw(slot.idx, &mut raw)?;
+                raw[20..22].copy_from_slice(&((start_cluster >> 16) as u16).to_le_bytes());
+                raw[26..28].copy_from_slice(&(start_cluster as u16).to_le_bytes());
+                raw[28..32].copy_from_slice(&size.to_le_bytes());
+                self.write_entry_raw(slot.idx, &raw)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Like `set_cluster_and_size`, for new file contents: also stamps the
+    /// modification time and access date.
+    pub fn set_contents(&mut self, name: &str, start_cluster: u32, size: u32) -> Result<bool, IoError> {
+        match self.position(name)? {
+            Some(slot) => {
+                let mut raw = [0u8; 32];
+                self.read_entry_raw(slot.idx, &mut raw)?;
+                raw[20..22].copy_from_slice(&((start_cluster >> 16) as u16).to_le_bytes());
+                raw[26..28].copy_from_slice(&(start_cluster as u16).to_le_bytes());
+                raw[28..32].copy_from_slice(&size.to_le_bytes());
+                stamp_modified(&mut raw, time::now());
+                self.write_entry_raw(slot.idx, &raw)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Copy the creation, modification and access stamps of `from` onto the
+    /// entry called `name`, e.g. after a rename re-created it.
+    pub fn set_times(&mut self, name: &str, from: &DirectoryEntry) -> Result<bool, IoError> {
+        match self.position(name)? {
+            Some(slot) => {
+                let mut raw = [0u8; 32];
+                self.read_entry_raw(slot.idx, &mut raw)?;
+                stamp_created(&mut raw, from.created);
+                stamp_modified(&mut raw, from.modified);
+                raw[18..20].copy_from_slice(&from.accessed.fat_date().to_le_bytes());
+                self.write_entry_raw(slot.idx, &raw)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Mark the entry called `name` and its LFN fragments as deleted.
+    pub fn delete(&mut self, name: &str) -> Result<(), IoError> {
+        if let Some(slot) = self.position(name)? {
+            for i in slot.first..=slot.idx {
+                let mut raw = [0u8; 32];
+                self.read_entry_raw(i, &mut raw)?;
                 raw[0] = 0xE5; // mark deleted
-                self.write_entry_raw(i, &raw);
-                return;
+                self.write_entry_raw(i, &raw)?;
             }
         }
+        Ok(())
     }
 
-    pub fn serialize(&mut self, buf: &mut [u8]) {
+    pub fn serialize(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
         // writes current directory region into buf
         for i in 0..self.num_entries as usize {
             let mut raw = [0u8; 32];
-            self.read_entry_raw(i, &mut raw);
+            self.read_entry_raw(i, &mut raw)?;
             buf[i*32..i*32+32].copy_from_slice(&raw);
         }
+        Ok(())
     }
 }
 
@@ -6,8 +6,28 @@ pub const SECTORS_PER_CLUSTER_DEFAULT: u8 = 1;
 pub const NUM_FATS: u8 = 1; // single FAT copy for simplicity
 pub const FAT12_MAX_ROOT_DIR_ENTRIES: u16 = 224; // common floppy default
 
-// FAT12 detection thresholds
+pub const FAT16_ROOT_DIR_ENTRIES: u16 = 512;
+pub const FAT32_RESERVED_SECTORS: u16 = 32;
+/// Sector holding the FAT32 boot sector backup (FSInfo backup follows it).
+pub const FAT32_BACKUP_BOOT_SECTOR: u16 = 6;
+
+// BPB defaults written by format
+pub const OEM_NAME: &[u8; 8] = b"RZOS    ";
+pub const MEDIA_FIXED: u8 = 0xF8;
+pub const MEDIA_FLOPPY: u8 = 0xF0;
+pub const EXTENDED_BOOT_SIG: u8 = 0x29;
+pub const NO_LABEL: &[u8; 11] = b"NO NAME    ";
+
+// FAT type detection thresholds (by count of data clusters)
 pub const FAT12_MAX_CLUSTERS: u32 = 4084; // < 4085 means FAT12
+pub const FAT16_MAX_CLUSTERS: u32 = 65524; // < 65525 means FAT16, otherwise FAT32
+
+// FAT32 FSInfo sector signatures
+pub const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+pub const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+pub const FSINFO_TRAIL_SIG: u32 = 0xAA55_0000;
+/// FSInfo value meaning "unknown" for the free count and next-free hint.
+pub const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
 
 // Boot sector signature offset
 pub const BOOT_SIG_OFFSET: usize = 510;
@@ -1,85 +1,241 @@
-use crate::fs::block_device::BlockDevice;
+use crate::fs::block_device::{BlockDevice, IoError};
+use crate::fs::boot_sector::{BootSector, FatType};
+use crate::fs::free_map::FreeMap;
 
 pub struct FatTable<'a, D: BlockDevice> {
     device: &'a mut D,
     start_lba: u64,
-    sectors_per_fat: u16,
+    sectors_per_fat: u32,
+    // number of FAT copies; every write goes to all of them
+    num_fats: u8,
+    fat_type: FatType,
+    // highest valid cluster number (cluster_count + 1)
+    max_cluster: u32,
+    // where the next allocation scan starts
+    next_free: u32,
+    // clusters allocated / freed through this table, for FSInfo bookkeeping
+    pub allocated: u32,
+    pub freed: u32,
     // simple cache: one sector buffer
     cache_sector: u32,
     cache: [u8; 512],
     cache_dirty: bool,
+    // free-cluster bitmap kept in step with written entries, if any
+    free_map: Option<&'a mut FreeMap>,
 }
 
 impl<'a, D: BlockDevice> FatTable<'a, D> {
+    /// FAT12 table sized from `sectors_per_fat` alone.
     pub fn new(device: &'a mut D, start_lba: u64, sectors_per_fat: u16) -> Self {
+        let max_bytes = sectors_per_fat as u32 * 512;
+        let cluster_count = (max_bytes * 2) / 3 - 2; // approx
+        Self::with_type(device, start_lba, sectors_per_fat as u32, FatType::Fat12, cluster_count)
+    }
+
+    /// Table of the given FAT type covering `cluster_count` data clusters.
+    pub fn with_type(device: &'a mut D, start_lba: u64, sectors_per_fat: u32, fat_type: FatType, cluster_count: u32) -> Self {
+        // never index past the end of the FAT itself
+        let entries_per_fat = match fat_type {
+            FatType::Fat12 => sectors_per_fat * 512 * 2 / 3,
+            FatType::Fat16 => sectors_per_fat * 512 / 2,
+            FatType::Fat32 => sectors_per_fat * 512 / 4,
+        };
+        let max_cluster = core::cmp::min(cluster_count + 1, entries_per_fat.saturating_sub(1));
         FatTable {
             device,
             start_lba,
             sectors_per_fat,
+            num_fats: 1,
+            fat_type,
+            max_cluster,
+            next_free: 2,
+            allocated: 0,
+            freed: 0,
             cache_sector: u32::MAX,
             cache: [0u8; 512],
             cache_dirty: false,
+            free_map: None,
         }
     }
 
-    fn load_sector(&mut self, sector_idx: u32) {
-        if self.cache_sector == sector_idx { return; }
-        if self.cache_dirty { self.flush().unwrap_or(()); }
-        self.device.read_sector(self.start_lba + sector_idx as u64, &mut self.cache);
+    /// Table for the volume described by `bs`, writing through to all
+    /// `num_fats` copies.
+    pub fn for_volume(device: &'a mut D, bs: &BootSector) -> Self {
+        let mut fat = Self::with_type(device, bs.fat_start_lba as u64, bs.sectors_per_fat, bs.fat_type, bs.cluster_count());
+        fat.num_fats = bs.num_fats;
+        fat
+    }
+
+    /// Keep `map` up to date with every entry written through this table
+    /// and allocate from it instead of scanning.
+    pub fn with_free_map(mut self, map: &'a mut FreeMap) -> Self {
+        self.free_map = Some(map);
+        self
+    }
+
+    /// Free clusters according to the bitmap, if the table has one.
+    pub fn free_count(&self) -> Option<u32> {
+        self.free_map.as_ref().map(|m| m.free_count())
+    }
+
+    pub fn fat_type(&self) -> FatType { self.fat_type }
+
+    pub fn num_fats(&self) -> u8 { self.num_fats }
+
+    /// Value that terminates a chain for this FAT type.
+    pub fn eoc(&self) -> u32 { self.fat_type.eoc() }
+
+    /// True if `value` marks the end of a chain.
+    pub fn is_eoc(&self, value: u32) -> bool { value >= self.fat_type.eoc_min() }
+
+    /// True if `cluster` is a data cluster on this volume.
+    pub fn is_valid_cluster(&self, cluster: u32) -> bool { cluster >= 2 && cluster <= self.max_cluster }
+
+    pub fn max_cluster(&self) -> u32 { self.max_cluster }
+
+    /// Cluster where the next allocation search starts.
+    pub fn next_free(&self) -> u32 { self.next_free }
+
+    /// Start allocation searches at `cluster` (e.g. the FSInfo hint).
+    pub fn set_next_free(&mut self, cluster: u32) {
+        if self.is_valid_cluster(cluster) { self.next_free = cluster; }
+    }
+
+    fn load_sector(&mut self, sector_idx: u32) -> Result<(), IoError> {
+        if self.cache_sector == sector_idx { return Ok(()); }
+        self.flush()?;
+        // mark the cache empty first so a failed read is not mistaken for data
+        self.cache_sector = u32::MAX;
+        self.device.read_sector(self.start_lba + sector_idx as u64, &mut self.cache)?;
         self.cache_sector = sector_idx;
-        self.cache_dirty = false;
+        Ok(())
     }
 
-    pub fn flush(&mut self) -> Result<(), ()> {
+    pub fn flush(&mut self) -> Result<(), IoError> {
         if !self.cache_dirty { return Ok(()); }
-        self.device.write_sector(self.start_lba + self.cache_sector as u64, &self.cache);
+        for copy in 0..self.num_fats as u32 {
+            let lba = self.copy_lba(copy, self.cache_sector);
+            self.device.write_sector(lba, &self.cache)?;
+        }
         self.cache_dirty = false;
         Ok(())
     }
 
+    /// LBA of sector `sector_idx` within FAT copy `copy`.
+    fn copy_lba(&self, copy: u32, sector_idx: u32) -> u64 {
+        self.start_lba + (copy * self.sectors_per_fat + sector_idx) as u64
+    }
+
+    /// Number of sectors in FAT copies 1.. that differ from the first copy.
+    /// With `repair`, those sectors are overwritten with the first copy.
+    pub fn sync_mirrors(&mut self, repair: bool) -> Result<u32, IoError> {
+        self.flush()?;
+        let mut mismatches = 0;
+        let mut primary = [0u8; 512];
+        let mut mirror = [0u8; 512];
+        for sector in 0..self.sectors_per_fat {
+            self.device.read_sector(self.copy_lba(0, sector), &mut primary)?;
+            for copy in 1..self.num_fats as u32 {
+                let lba = self.copy_lba(copy, sector);
+                self.device.read_sector(lba, &mut mirror)?;
+                if mirror != primary {
+                    mismatches += 1;
+                    if repair { self.device.write_sector(lba, &primary)?; }
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+
     /// Helper to write a data-sector using the underlying device borrowed by the FatTable.
-    pub fn write_data_sector(&mut self, lba: u64, data: &[u8]) {
-        self.device.write_sector(lba, data);
+    pub fn write_data_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        self.device.write_sector(lba, data)
     }
 
     /// Helper to read a data-sector using the underlying device borrowed by the FatTable.
-    pub fn read_data_sector(&mut self, lba: u64, buf: &mut [u8]) {
-        self.device.read_sector(lba, buf);
+    pub fn read_data_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        self.device.read_sector(lba, buf)
+    }
+
+    /// Read the FAT entry for cluster `n`.
+    pub fn read_entry(&mut self, cluster: u32) -> Result<u32, IoError> {
+        match self.fat_type {
+            FatType::Fat12 => Ok(self.read_entry12(cluster)? as u32),
+            FatType::Fat16 => {
+                let idx = cluster as usize * 2;
+                self.load_sector((idx / 512) as u32)?;
+                let off = idx % 512;
+                Ok(u16::from_le_bytes([self.cache[off], self.cache[off + 1]]) as u32)
+            }
+            FatType::Fat32 => {
+                let idx = cluster as usize * 4;
+                self.load_sector((idx / 512) as u32)?;
+                let off = idx % 512;
+                let raw = u32::from_le_bytes([self.cache[off], self.cache[off + 1], self.cache[off + 2], self.cache[off + 3]]);
+                Ok(raw & 0x0FFF_FFFF)
+            }
+        }
+    }
+
+    /// Write the FAT entry for cluster `n`.
+    pub fn write_entry(&mut self, cluster: u32, value: u32) -> Result<(), IoError> {
+        match self.fat_type {
+            FatType::Fat12 => self.write_entry12(cluster, value as u16)?,
+            FatType::Fat16 => {
+                let idx = cluster as usize * 2;
+                self.load_sector((idx / 512) as u32)?;
+                let off = idx % 512;
+                self.cache[off..off + 2].copy_from_slice(&(value as u16).to_le_bytes());
+                self.cache_dirty = true;
+            }
+            FatType::Fat32 => {
+                let idx = cluster as usize * 4;
+                self.load_sector((idx / 512) as u32)?;
+                let off = idx % 512;
+                // the top 4 bits are reserved and must be preserved
+                let old = u32::from_le_bytes([self.cache[off], self.cache[off + 1], self.cache[off + 2], self.cache[off + 3]]);
+                let new = (old & 0xF000_0000) | (value & 0x0FFF_FFFF);
+                self.cache[off..off + 4].copy_from_slice(&new.to_le_bytes());
+                self.cache_dirty = true;
+            }
+        }
+        if let Some(map) = self.free_map.as_mut() { map.mark(cluster, value == 0); }
+        Ok(())
     }
 
     /// Read a FAT12 entry (12-bit) for cluster `n`.
-    pub fn read_entry(&mut self, cluster: u16) -> u16 {
+    fn read_entry12(&mut self, cluster: u32) -> Result<u16, IoError> {
         // index into FAT bytes
         let idx = (cluster as usize * 3) / 2;
         let sector_idx = (idx / 512) as u32;
         let offset = idx % 512;
-        self.load_sector(sector_idx);
+        self.load_sector(sector_idx)?;
         // need potentially two bytes across sector boundary
         let b0 = self.cache[offset] as u16;
         let b1 = if offset + 1 < 512 { self.cache[offset+1] as u16 } else {
             // read next sector
-            self.load_sector(sector_idx + 1);
+            self.load_sector(sector_idx + 1)?;
             self.cache[0] as u16
         };
         let word = (b1 << 8) | b0;
         if cluster % 2 == 0 {
-            word & 0x0FFF
+            Ok(word & 0x0FFF)
         } else {
-            (word >> 4) & 0x0FFF
+            Ok((word >> 4) & 0x0FFF)
         }
     }
 
     /// Write a FAT12 entry.
-    pub fn write_entry(&mut self, cluster: u16, value: u16) {
+    fn write_entry12(&mut self, cluster: u32, value: u16) -> Result<(), IoError> {
         let idx = (cluster as usize * 3) / 2;
         let sector_idx = (idx / 512) as u32;
         let offset = idx % 512;
-        self.load_sector(sector_idx);
+        self.load_sector(sector_idx)?;
         // get next byte possibly in next sector
         let next_byte = if offset + 1 < 512 { self.cache[offset+1] } else {
             // read next sector into temp
             let mut tmp = [0u8; 512];
-            self.device.read_sector(self.start_lba + (sector_idx + 1) as u64, &mut tmp);
+            self.device.read_sector(self.start_lba + (sector_idx + 1) as u64, &mut tmp)?;
             tmp[0]
         } as u16;
         let cur = self.cache[offset] as u16;
@@ -99,65 +255,97 @@ impl<'a, D: BlockDevice> FatTable<'a, D> {
         if offset + 1 < 512 {
             self.cache[offset+1] = new_b1;
         } else {
-            // write next sector's first byte
+            // write next sector's first byte, in every copy
             let mut tmp = [0u8; 512];
-            self.device.read_sector(self.start_lba + (sector_idx + 1) as u64, &mut tmp);
+            self.device.read_sector(self.start_lba + (sector_idx + 1) as u64, &mut tmp)?;
             tmp[0] = new_b1;
-            self.device.write_sector(self.start_lba + (sector_idx + 1) as u64, &tmp);
+            for copy in 0..self.num_fats as u32 {
+                let lba = self.copy_lba(copy, sector_idx + 1);
+                self.device.write_sector(lba, &tmp)?;
+            }
         }
+        Ok(())
     }
 
-    /// Find a free cluster (value 0) and allocate it (set to 0xFFF end)
-    pub fn alloc_cluster(&mut self) -> Option<u16> {
-        // naive scan clusters starting at 2
-        let max_bytes = (self.sectors_per_fat as usize) * 512;
-        let max_clusters = (max_bytes * 2) / 3; // approx
-        for n in 2..(max_clusters as u16) {
-            if self.read_entry(n) == 0 {
-                self.write_entry(n, 0xFFF);
-                return Some(n);
-            }
+    /// Find a free cluster (value 0) and allocate it (mark it end-of-chain).
+    /// The search starts at the allocation hint and wraps around once; with
+    /// a free map it goes straight to the next free bit, otherwise it scans
+    /// the FAT. Returns `None` when the volume is full.
+    pub fn alloc_cluster(&mut self) -> Result<Option<u32>, IoError> {
+        let found = if self.free_map.is_some() { self.find_free_mapped()? } else { self.find_free_scan()? };
+        let n = match found { Some(n) => n, None => return Ok(None) };
+        let eoc = self.eoc();
+        self.write_entry(n, eoc)?;
+        self.next_free = if n < self.max_cluster { n + 1 } else { 2 };
+        self.allocated += 1;
+        Ok(Some(n))
+    }
+
+    fn find_free_scan(&mut self) -> Result<Option<u32>, IoError> {
+        let span = self.max_cluster.saturating_sub(1);
+        for i in 0..span {
+            let n = 2 + (self.next_free - 2 + i) % span;
+            if self.read_entry(n)? == 0 { return Ok(Some(n)); }
+        }
+        Ok(None)
+    }
+
+    fn find_free_mapped(&mut self) -> Result<Option<u32>, IoError> {
+        loop {
+            let n = match self.free_map.as_ref().and_then(|m| m.find_free(self.next_free)) {
+                Some(n) => n,
+                None => return Ok(None),
+            };
+            // the map only goes stale if the FAT was changed behind its
+            // back; trust the FAT and keep looking
+            if self.read_entry(n)? == 0 { return Ok(Some(n)); }
+            if let Some(map) = self.free_map.as_mut() { map.mark(n, false); }
         }
-        None
     }
 
     /// Free a cluster chain starting at `cluster`.
-    pub fn free_cluster(&mut self, cluster: u16) {
+    pub fn free_cluster(&mut self, cluster: u32) -> Result<(), IoError> {
         let mut cur = cluster;
-        loop {
-            let next = self.read_entry(cur);
-            self.write_entry(cur, 0);
-            if next >= 0xFF8 { break; }
+        // a chain can never be longer than the volume; this stops cycles
+        for _ in 0..self.max_cluster {
+            if !self.is_valid_cluster(cur) { break; }
+            let next = self.read_entry(cur)?;
+            if next == 0 { break; }
+            self.write_entry(cur, 0)?;
+            self.freed += 1;
+            if self.is_eoc(next) { break; }
             cur = next;
         }
+        Ok(())
     }
 
     /// Follow chain starting at `start` until EOF and return vector of clusters.
-    pub fn get_chain(&mut self, start: u16) -> alloc::vec::Vec<u16> {
+    pub fn get_chain(&mut self, start: u32) -> Result<alloc::vec::Vec<u32>, IoError> {
         let mut out = alloc::vec::Vec::new();
         let mut cur = start;
-        loop {
+        for _ in 0..self.max_cluster {
+            if !self.is_valid_cluster(cur) { break; }
             out.push(cur);
-            let next = self.read_entry(cur);
-            if next >= 0xFF8 { break; }
+            let next = self.read_entry(cur)?;
+            if self.is_eoc(next) { break; }
             cur = next;
         }
-        out
+        Ok(out)
     }
 
     /// Non-alloc version: fill provided slice with the cluster chain and return the length.
-    pub fn get_chain_nonalloc(&mut self, start: u16, out: &mut [u16]) -> usize {
+    pub fn get_chain_nonalloc(&mut self, start: u32, out: &mut [u32]) -> Result<usize, IoError> {
         let mut idx = 0usize;
         let mut cur = start;
         loop {
-            if idx >= out.len() { break; }
+            if idx >= out.len() || !self.is_valid_cluster(cur) { break; }
             out[idx] = cur;
             idx += 1;
-            let next = self.read_entry(cur);
-            if next >= 0xFF8 { break; }
+            let next = self.read_entry(cur)?;
+            if self.is_eoc(next) { break; }
             cur = next;
         }
-        idx
+        Ok(idx)
     }
 }
 
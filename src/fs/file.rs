@@ -0,0 +1,252 @@
+// Open file handles.
+//
+// A `File` borrows the `FileSystem` it was opened from and reads or writes
+// one sector at a time, walking (and extending) the cluster chain as the
+// position moves. Nothing is buffered beyond a single sector, so files can be
+// much larger than the heap. The directory entry (start cluster and size) is
+// rewritten by `flush`, which also runs when the handle is dropped.
+
+use crate::fs::block_device::BlockDevice;
+use crate::fs::fs::{FileSystem, FsError};
+use alloc::string::String;
+
+/// Position argument for `File::seek`, relative to the start, the end, or the
+/// current position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u32),
+    End(i64),
+    Current(i64),
+}
+
+pub struct File<'f, 'a, D: BlockDevice> {
+    fs: &'f mut FileSystem<'a, D>,
+    // parent directory cluster and name, used to update the entry
+    dir: u32,
+    name: String,
+    start_cluster: u32,
+    size: u32,
+    pos: u32,
+    read_only: bool,
+    // last cluster visited: (index within the file, cluster number)
+    cur: Option<(u32, u32)>,
+    // written to since the entry was last updated (start cluster, size and
+    // modification time)
+    dirty: bool,
+}
+
+impl<'f, 'a, D: BlockDevice> File<'f, 'a, D> {
+    pub(crate) fn new(
+        fs: &'f mut FileSystem<'a, D>,
+        dir: u32,
+        name: &str,
+        start_cluster: u32,
+        size: u32,
+        read_only: bool,
+    ) -> Self {
+        File {
+            fs,
+            dir,
+            name: String::from(name),
+            start_cluster,
+            size,
+            pos: 0,
+            read_only,
+            cur: None,
+            dirty: false,
+        }
+    }
+
+    /// Current file size in bytes.
+    pub fn len(&self) -> u32 { self.size }
+
+    pub fn is_empty(&self) -> bool { self.size == 0 }
+
+    /// Current position in bytes from the start of the file.
+    pub fn position(&self) -> u32 { self.pos }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.fs.boot_sector.sectors_per_cluster as u32 * 512
+    }
+
+    /// Cluster holding byte `index * cluster_bytes`. With `extend`, clusters
+    /// are allocated and linked until the chain is long enough; without it,
+    /// `None` is returned past the end of the chain.
+    fn cluster_at(&mut self, index: u32, extend: bool) -> Result<Option<u32>, FsError> {
+        if self.start_cluster == 0 {
+            if !extend { return Ok(None); }
+            let first = self.fs.with_fat(|fat| fat.alloc_cluster()?.ok_or(FsError::NoSpace))?;
+            self.start_cluster = first;
+            self.dirty = true;
+            self.cur = None;
+        }
+        let (mut i, mut cluster) = match self.cur {
+            Some((ci, c)) if ci <= index => (ci, c),
+            _ => (0, self.start_cluster),
+        };
+        while i < index {
+            let next = {
+                let mut fat = self.fs.fat();
+                let next = fat.read_entry(cluster)?;
+                if fat.is_valid_cluster(next) { Some(next) } else { None }
+            };
+            cluster = match next {
+                Some(next) => next,
+                None if extend => {
+                    let prev = cluster;
+                    self.fs.with_fat(|fat| {
+                        let new = fat.alloc_cluster()?.ok_or(FsError::NoSpace)?;
+                        fat.write_entry(prev, new)?;
+                        Ok(new)
+                    })?
+                }
+                None => return Ok(None),
+            };
+            i += 1;
+        }
+        self.cur = Some((i, cluster));
+        Ok(Some(cluster))
+    }
+
+    /// LBA of the sector holding byte `pos`, and the offset inside it.
+    fn sector_at(&mut self, pos: u32, extend: bool) -> Result<Option<(u64, usize)>, FsError> {
+        let cluster_bytes = self.cluster_bytes();
+        let cluster = match self.cluster_at(pos / cluster_bytes, extend)? {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let offset = pos % cluster_bytes;
+        let lba = self.fs.cluster_lba(cluster) + (offset / 512) as u64;
+        Ok(Some((lba, (offset % 512) as usize)))
+    }
+
+    /// Read up to `buf.len()` bytes from the current position. Returns the
+    /// number of bytes read, which is 0 at or past the end of the file.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, FsError> {
+        let mut done = 0usize;
+        while done < buf.len() && self.pos < self.size {
+            let (lba, off) = match self.sector_at(self.pos, false)? {
+                Some(s) => s,
+                None => break, // chain shorter than the recorded size
+            };
+            let n = (512 - off).min(buf.len() - done).min((self.size - self.pos) as usize);
+            let mut sector = [0u8; 512];
+            self.fs.device.read_sector(lba, &mut sector)?;
+            buf[done..done + n].copy_from_slice(&sector[off..off + n]);
+            done += n;
+            self.pos += n as u32;
+        }
+        Ok(done)
+    }
+
+    /// Write `data` at the current position, growing the file as needed. A
+    /// position past the end is filled with zeros first. If the volume fills
+    /// up, the bytes written so far are kept and `NoSpace` is returned.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        if self.read_only { return Err(FsError::ReadOnly); }
+        if self.pos as u64 + data.len() as u64 > u32::MAX as u64 { return Err(FsError::FileTooLarge); }
+        if self.pos > self.size {
+            let target = self.pos;
+            self.zero_fill(target)?;
+        }
+        self.write_at_pos(data)?;
+        Ok(data.len())
+    }
+
+    /// Write `data` at the end of the file, leaving the position after it.
+    pub fn append(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        self.pos = self.size;
+        self.write(data)
+    }
+
+    fn write_at_pos(&mut self, data: &[u8]) -> Result<(), FsError> {
+        let mut done = 0usize;
+        while done < data.len() {
+            let (lba, off) = self.sector_at(self.pos, true)?.ok_or(FsError::NoSpace)?;
+            let n = (512 - off).min(data.len() - done);
+            let mut sector = [0u8; 512];
+            if n < 512 {
+                self.fs.device.read_sector(lba, &mut sector)?;
+            }
+            sector[off..off + n].copy_from_slice(&data[done..done + n]);
+            self.fs.device.write_data(lba, &sector)?;
+            done += n;
+            self.pos += n as u32;
+            self.size = self.size.max(self.pos);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Extend the file with zeros up to `target`, keeping the position.
+    fn zero_fill(&mut self, target: u32) -> Result<(), FsError> {
+        let zero = [0u8; 512];
+        let resume = self.pos;
+        self.pos = self.size;
+        while self.pos < target {
+            let n = ((target - self.pos) as usize).min(512);
+            self.write_at_pos(&zero[..n])?;
+        }
+        self.pos = resume;
+        Ok(())
+    }
+
+    /// Move the position. Seeking past the end is allowed; a later write
+    /// fills the gap with zeros.
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u32, FsError> {
+        let new = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(d) => self.size as i64 + d,
+            SeekFrom::Current(d) => self.pos as i64 + d,
+        };
+        if new < 0 || new > u32::MAX as i64 { return Err(FsError::InvalidSeek); }
+        self.pos = new as u32;
+        Ok(self.pos)
+    }
+
+    /// Truncate or zero-extend the file to `len` bytes. Clusters past the new
+    /// end are freed. The position is left unchanged.
+    pub fn set_len(&mut self, len: u32) -> Result<(), FsError> {
+        if self.read_only { return Err(FsError::ReadOnly); }
+        if len > self.size { return self.zero_fill(len); }
+        if len == self.size { return Ok(()); }
+        let cluster_bytes = self.cluster_bytes();
+        let keep = len.div_ceil(cluster_bytes);
+        if keep == 0 {
+            let start = self.start_cluster;
+            self.fs.with_fat(|fat| Ok(fat.free_cluster(start)?))?;
+            self.start_cluster = 0;
+        } else if let Some(last) = self.cluster_at(keep - 1, false)? {
+            self.fs.with_fat(|fat| {
+                let next = fat.read_entry(last)?;
+                let eoc = fat.eoc();
+                fat.write_entry(last, eoc)?;
+                if fat.is_valid_cluster(next) { fat.free_cluster(next)?; }
+                Ok(())
+            })?;
+        }
+        self.cur = None;
+        self.size = len;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Write the start cluster, size and modification time back to the
+    /// directory entry and flush the device.
+    pub fn flush(&mut self) -> Result<(), FsError> {
+        if self.dirty {
+            let (start, size) = (self.start_cluster, self.size);
+            if !self.fs.open_dir(self.dir)?.set_contents(&self.name, start, size)? {
+                return Err(FsError::FileNotFound);
+            }
+            self.dirty = false;
+        }
+        self.fs.flush()
+    }
+}
+
+impl<'f, 'a, D: BlockDevice> Drop for File<'f, 'a, D> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,277 @@
+// Volume layout for `FileSystem::format`.
+//
+// `FormatOptions` collects the choices a user can make (FAT type, cluster
+// size, root directory size, label, serial, media byte, number of FATs,
+// journal size) and
+// `layout` turns them into a `BootSector` for a device of a given size.
+// Anything left unset is picked from the size: the FAT type by volume size,
+// the cluster size as the smallest that keeps the cluster count within the
+// type's range, and the FAT size by iterating until the FAT covers every
+// cluster the remaining sectors hold.
+
+use crate::fs::block_device::BlockDevice;
+use crate::fs::boot_sector::{BootSector, FatError, FatType};
+use crate::fs::fat_constants::*;
+use crate::fs::fat_table::FatTable;
+use crate::fs::fs::FsError;
+use crate::fs::fs_info::FsInfo;
+use crate::fs::journal;
+
+/// Volumes up to this many sectors (4 MiB) default to FAT12.
+const FAT12_AUTO_MAX_SECTORS: u64 = 8192;
+/// Volumes up to this many sectors (512 MiB) default to FAT16.
+const FAT16_AUTO_MAX_SECTORS: u64 = 1 << 20;
+
+/// Options for `FileSystem::format_with_options`. Unset fields are derived
+/// from the device size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    fat_type: Option<FatType>,
+    sectors_per_cluster: Option<u8>,
+    root_entries: Option<u16>,
+    num_fats: u8,
+    media: Option<u8>,
+    volume_id: u32,
+    volume_label: [u8; 11],
+    journal_sectors: u16,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self { Self::new() }
+}
+
+impl FormatOptions {
+    pub fn new() -> Self {
+        FormatOptions {
+            fat_type: None,
+            sectors_per_cluster: None,
+            root_entries: None,
+            num_fats: NUM_FATS,
+            media: None,
+            volume_id: 0,
+            volume_label: *NO_LABEL,
+            journal_sectors: 0,
+        }
+    }
+
+    /// Force FAT12, FAT16 or FAT32. Formatting fails if the device size
+    /// cannot give that type a valid cluster count.
+    pub fn fat_type(mut self, fat_type: FatType) -> Self {
+        self.fat_type = Some(fat_type);
+        self
+    }
+
+    /// Cluster size in sectors; must be a power of two.
+    pub fn sectors_per_cluster(mut self, sectors: u8) -> Self {
+        self.sectors_per_cluster = Some(sectors);
+        self
+    }
+
+    /// Entries in the fixed root directory (FAT12/16), rounded up to fill
+    /// whole sectors. Ignored for FAT32.
+    pub fn root_entries(mut self, entries: u16) -> Self {
+        self.root_entries = Some(entries);
+        self
+    }
+
+    pub fn num_fats(mut self, num_fats: u8) -> Self {
+        self.num_fats = num_fats;
+        self
+    }
+
+    /// Media descriptor byte (0xF0 or 0xF8-0xFF).
+    pub fn media(mut self, media: u8) -> Self {
+        self.media = Some(media);
+        self
+    }
+
+    /// Volume serial number.
+    pub fn serial(mut self, volume_id: u32) -> Self {
+        self.volume_id = volume_id;
+        self
+    }
+
+    /// Volume label, up to 11 characters. It is stored upper-cased; an
+    /// empty label becomes "NO NAME".
+    pub fn volume_label(mut self, label: &str) -> Result<Self, FsError> {
+        if label.len() > 11 || !label.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+            return Err(FsError::InvalidName);
+        }
+        if label.bytes().any(|b| b"\"*+,./:;<=>?[\\]|".contains(&b)) {
+            return Err(FsError::InvalidName);
+        }
+        self.volume_label = if label.is_empty() { *NO_LABEL } else { [b' '; 11] };
+        for (dst, b) in self.volume_label.iter_mut().zip(label.bytes()) {
+            *dst = b.to_ascii_uppercase();
+        }
+        Ok(self)
+    }
+
+    /// Reserve `sectors` sectors (at least 2: a header and one record) for a
+    /// write-ahead metadata journal; 0 formats without one. The reserved
+    /// region grows to fit it. `journal::JOURNAL_DEFAULT_SECTORS` is a
+    /// reasonable size.
+    pub fn journal(mut self, sectors: u16) -> Self {
+        self.journal_sectors = sectors;
+        self
+    }
+
+    /// Compute the boot sector for a volume of `total_sectors` sectors.
+    pub fn layout(&self, total_sectors: u64) -> Result<BootSector, FsError> {
+        let bad = || FsError::Boot(FatError::InvalidGeometry);
+        let total = total_sectors.min(u32::MAX as u64) as u32;
+        let fat_type = self.fat_type.unwrap_or(if total_sectors <= FAT12_AUTO_MAX_SECTORS {
+            FatType::Fat12
+        } else if total_sectors <= FAT16_AUTO_MAX_SECTORS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        });
+        let fat32 = fat_type == FatType::Fat32;
+        let media = self.media.unwrap_or(if total_sectors == 2880 { MEDIA_FLOPPY } else { MEDIA_FIXED });
+        if self.num_fats == 0 || !(media == MEDIA_FLOPPY || media >= 0xF8) { return Err(bad()); }
+        if self.journal_sectors == 1 { return Err(bad()); }
+        let journal_end = journal::journal_start(fat_type) as u32 + self.journal_sectors as u32;
+        let reserved = match (fat32, self.journal_sectors) {
+            (true, 0) => FAT32_RESERVED_SECTORS,
+            (true, _) => u16::try_from(journal_end).map_err(|_| bad())?.max(FAT32_RESERVED_SECTORS),
+            (false, 0) => 1,
+            (false, _) => u16::try_from(journal_end).map_err(|_| bad())?,
+        };
+        let root_entries = if fat32 {
+            0
+        } else {
+            let entries = self.root_entries.unwrap_or(match fat_type {
+                FatType::Fat12 => FAT12_MAX_ROOT_DIR_ENTRIES,
+                _ => FAT16_ROOT_DIR_ENTRIES,
+            });
+            // 16 entries to a sector
+            entries.checked_next_multiple_of(16).filter(|&e| e > 0).ok_or_else(bad)?
+        };
+
+        let spc_candidates: &[u8] = match self.sectors_per_cluster {
+            Some(spc) if spc.is_power_of_two() => &[spc][..],
+            Some(_) => return Err(bad()),
+            None if fat32 => &[default_fat32_cluster(total_sectors)][..],
+            None => &[1, 2, 4, 8, 16, 32, 64, 128][..],
+        };
+        // with an explicit cluster size the first candidate is the answer;
+        // otherwise take the smallest one whose cluster count suits the type
+        let mut last_err = bad();
+        for &spc in spc_candidates {
+            match self.geometry(fat_type, total, reserved, root_entries, spc, media) {
+                Ok(bs) => return Ok(bs),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn geometry(
+        &self,
+        fat_type: FatType,
+        total: u32,
+        reserved: u16,
+        root_entries: u16,
+        spc: u8,
+        media: u8,
+    ) -> Result<BootSector, FsError> {
+        let bad = || FsError::Boot(FatError::InvalidGeometry);
+        let root_sectors = (root_entries as u32 * 32).div_ceil(512);
+        let fixed = reserved as u32 + root_sectors;
+        // grow the FAT until it covers every cluster left beside it; its size
+        // only increases, so this terminates
+        let mut sectors_per_fat = 1u32;
+        let clusters = loop {
+            let fats = self.num_fats as u32 * sectors_per_fat;
+            let data = total.checked_sub(fixed + fats).ok_or_else(bad)?;
+            let clusters = data / spc as u32;
+            let bytes = match fat_type {
+                FatType::Fat12 => ((clusters as u64 + 2) * 3).div_ceil(2),
+                FatType::Fat16 => (clusters as u64 + 2) * 2,
+                FatType::Fat32 => (clusters as u64 + 2) * 4,
+            };
+            let needed = bytes.div_ceil(512) as u32;
+            if needed <= sectors_per_fat { break clusters; }
+            sectors_per_fat = needed;
+        };
+        if clusters == 0 || FatType::from_cluster_count(clusters) != fat_type { return Err(bad()); }
+
+        let fat32 = fat_type == FatType::Fat32;
+        let fat_start_lba = reserved as u32;
+        let root_dir_start_lba = fat_start_lba + self.num_fats as u32 * sectors_per_fat;
+        Ok(BootSector {
+            bytes_per_sector: BYTES_PER_SECTOR,
+            sectors_per_cluster: spc,
+            reserved_sectors: reserved,
+            num_fats: self.num_fats,
+            max_root_dir_entries: root_entries,
+            total_sectors: total,
+            sectors_per_fat,
+            fat_type,
+            root_cluster: if fat32 { 2 } else { 0 },
+            fs_info_sector: if fat32 { 1 } else { 0 },
+            fat_start_lba,
+            root_dir_start_lba,
+            data_start_lba: root_dir_start_lba + root_sectors,
+            oem_name: *OEM_NAME,
+            media,
+            volume_id: self.volume_id,
+            volume_label: self.volume_label,
+        })
+    }
+}
+
+/// FAT32 cluster size for a volume of `sectors` sectors, from the table in
+/// Microsoft's FAT specification.
+fn default_fat32_cluster(sectors: u64) -> u8 {
+    const TABLE: [(u64, u8); 4] = [(532_480, 1), (16_777_216, 8), (33_554_432, 16), (67_108_864, 32)];
+    TABLE.iter().find(|&&(limit, _)| sectors <= limit).map_or(64, |&(_, spc)| spc)
+}
+
+/// Write an empty volume laid out by `options` to `device`. Only the
+/// metadata regions are cleared; old file data stays in the (now free)
+/// clusters.
+pub(crate) fn write_volume<D: BlockDevice>(device: &mut D, options: &FormatOptions) -> Result<BootSector, FsError> {
+    if device.is_read_only() { return Err(FsError::ReadOnly); }
+    let bs = options.layout(device.sector_count())?;
+    let zero = [0u8; 512];
+    // reserved sectors, every FAT copy and the fixed root directory
+    for lba in 0..bs.data_start_lba as u64 {
+        device.write_sector(lba, &zero)?;
+    }
+    let mut buf = [0u8; 512];
+    bs.serialize(&mut buf)?;
+    device.write_sector(0, &buf)?;
+    if options.journal_sectors != 0 {
+        journal::write_empty(device, journal::journal_start(bs.fat_type), options.journal_sectors)?;
+    }
+
+    {
+        let mut fat = FatTable::for_volume(device, &bs);
+        // entry 0 carries the media byte, entry 1 is an end-of-chain marker
+        fat.write_entry(0, (bs.fat_type.eoc() & !0xFF) | bs.media as u32)?;
+        fat.write_entry(1, bs.fat_type.eoc())?;
+        if bs.fat_type == FatType::Fat32 {
+            fat.write_entry(bs.root_cluster, bs.fat_type.eoc())?;
+        }
+        fat.flush()?;
+    }
+
+    if bs.fat_type == FatType::Fat32 {
+        // one-cluster root directory
+        let root_lba = bs.data_start_lba as u64 + (bs.root_cluster as u64 - 2) * bs.sectors_per_cluster as u64;
+        for s in 0..bs.sectors_per_cluster as u64 {
+            device.write_sector(root_lba + s, &zero)?;
+        }
+        let mut info = [0u8; 512];
+        FsInfo { free_count: bs.cluster_count() - 1, next_free: bs.root_cluster + 1 }.serialize(&mut info)?;
+        device.write_sector(bs.fs_info_sector as u64, &info)?;
+        // backup boot sector and FSInfo
+        let backup = FAT32_BACKUP_BOOT_SECTOR as u64;
+        device.write_sector(backup, &buf)?;
+        device.write_sector(backup + 1, &info)?;
+    }
+    device.flush()?;
+    Ok(bs)
+}
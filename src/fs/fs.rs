@@ -1,7 +1,15 @@
-use crate::fs::block_device::BlockDevice;
-use crate::fs::boot_sector::{BootSector, FatError};
+use crate::fs::block_device::{BlockDevice, IoError};
+use crate::fs::boot_sector::{BootSector, FatError, FatType};
 use crate::fs::fat_table::FatTable;
-use crate::fs::directory::{Directory, DirectoryEntry};
+use crate::fs::file::File;
+use crate::fs::format::{self, FormatOptions};
+use crate::fs::free_map::FreeMap;
+use crate::fs::fs_info::FsInfo;
+use crate::fs::journal::JournalDevice;
+use crate::fs::lfn;
+use crate::fs::directory::{
+    to_short_name, Directory, DirectoryEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_USER_MASK, ATTR_VOLUME_ID,
+};
 use crate::println;
 use alloc::vec::Vec;
 use alloc::vec;
@@ -10,25 +18,96 @@ use crate::fs::fat_constants::*;
 #[derive(Debug)]
 pub enum FsError {
     Boot(FatError),
+    Io(IoError),
     FileAlreadyExists,
     FileNotFound,
     InvalidName,
     NoSpace,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    ReadOnly,
+    FileTooLarge,
+    InvalidSeek,
+    /// A mount point is in the way (`Vfs` mount, unmount, delete or rename).
+    Busy,
+    /// The operation cannot span two mounted file systems.
+    CrossDevice,
+    /// The file system does not implement the operation.
+    Unsupported,
+    /// The kernel heap cannot hold the volume's in-memory state.
+    NoMemory,
 }
 
 impl From<FatError> for FsError {
     fn from(e: FatError) -> Self { FsError::Boot(e) }
 }
 
+impl From<IoError> for FsError {
+    fn from(e: IoError) -> Self { FsError::Io(e) }
+}
+
 pub struct FileSystem<'a, D: BlockDevice> {
-    device: &'a mut D,
+    pub(crate) device: JournalDevice<'a, D>,
     pub boot_sector: BootSector,
+    /// FSInfo contents for FAT32 volumes; kept up to date as clusters are
+    /// allocated and freed.
+    pub fs_info: Option<FsInfo>,
+    /// Number of FAT sectors that differed between copies at mount time
+    /// (only counted when `MountOptions::check_fat_mirrors` is set).
+    pub fat_mismatches: u32,
+    /// Number of journal records replayed at mount time; non-zero after an
+    /// operation on a journaled volume was cut short.
+    pub journal_replayed: u32,
+    // where the next cluster allocation scan starts
+    next_free: u32,
+    // free clusters, built at mount; `None` for volumes too large to map
+    free_map: Option<FreeMap>,
+}
+
+/// Space usage of a mounted volume, from `FileSystem::stat`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsStat {
+    pub cluster_size: u32,
+    pub total_clusters: u32,
+    pub free_clusters: u32,
+    /// Clusters that are allocated or marked bad.
+    pub used_clusters: u32,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub used_bytes: u64,
+    pub files: u32,
+    /// Subdirectories, not counting the root.
+    pub directories: u32,
+    /// Files whose clusters are not one contiguous run.
+    pub fragmented_files: u32,
+}
+
+impl FsStat {
+    /// Fragmented files as a percentage of all files.
+    pub fn fragmentation(&self) -> u32 {
+        (self.fragmented_files * 100).checked_div(self.files).unwrap_or(0)
+    }
+}
+
+/// Options for `FileSystem::mount_with_options`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    /// Compare every FAT copy against the first one.
+    pub check_fat_mirrors: bool,
+    /// Overwrite FAT copies that differ with the first copy. Implies
+    /// `check_fat_mirrors`.
+    pub repair_fat_mirrors: bool,
 }
 
 impl<'a, D: BlockDevice> FileSystem<'a, D> {
     pub fn mount(device: &'a mut D) -> Result<Self, FsError> {
+        Self::mount_with_options(device, MountOptions::default())
+    }
+
+    pub fn mount_with_options(device: &'a mut D, options: MountOptions) -> Result<Self, FsError> {
         let mut buf = [0u8; 512];
-        device.read_sector(0, &mut buf);
+        device.read_sector(0, &mut buf)?;
         let bs = match BootSector::parse(&buf) {
             Ok(b) => b,
             Err(e) => {
@@ -36,185 +115,588 @@ impl<'a, D: BlockDevice> FileSystem<'a, D> {
                 return Err(FsError::Boot(e));
             }
         };
-        // build fat and dir using computed LBAs
-        Ok(FileSystem { device, boot_sector: bs })
+        let mut device = JournalDevice::new(device);
+        // replay before anything else is read: a committed transaction may
+        // have been cut off before FSInfo, the FAT or a directory was updated
+        let journal_replayed = device.open(&bs)?;
+        // FAT32 keeps an advisory free count and allocation hint in FSInfo;
+        // an unreadable FSInfo sector just means both are unknown
+        let fs_info = if bs.fat_type == FatType::Fat32 {
+            let mut info = FsInfo::unknown();
+            if bs.fs_info_sector != 0
+                && bs.fs_info_sector < bs.reserved_sectors
+                && device.read_sector(bs.fs_info_sector as u64, &mut buf).is_ok()
+            {
+                info = FsInfo::parse(&buf).unwrap_or(info);
+            }
+            Some(info)
+        } else {
+            None
+        };
+        let next_free = fs_info.map_or(2, |i| i.next_free);
+        let mut fs = FileSystem {
+            device,
+            boot_sector: bs,
+            fs_info,
+            fat_mismatches: 0,
+            journal_replayed,
+            next_free: 2,
+            free_map: None,
+        };
+        fs.next_free = if fs.fat().is_valid_cluster(next_free) { next_free } else { 2 };
+        if options.check_fat_mirrors || options.repair_fat_mirrors {
+            fs.fat_mismatches = fs.fat().sync_mirrors(options.repair_fat_mirrors)?;
+        }
+        fs.free_map = Some(FreeMap::build(&mut fs.fat())?);
+        Ok(fs)
     }
 
-    pub fn list_root(&mut self) -> Vec<DirectoryEntry> {
-        let mut dir = Directory::new(
-            self.device, 
-            self.boot_sector.root_dir_start_lba as u64, 
-            self.boot_sector.max_root_dir_entries);
-        dir.list()
+    /// First LBA of data cluster `cluster`.
+    pub(crate) fn cluster_lba(&self, cluster: u32) -> u64 {
+        self.boot_sector.data_start_lba as u64 + ((cluster as u64 - 2) * self.boot_sector.sectors_per_cluster as u64)
     }
 
-    pub fn read_file(&mut self, name: &str) -> Result<Vec<u8>, FsError> {
-        // create Directory, find entry
-        let entry = {
-            let mut dir = Directory::new(self.device, self.boot_sector.root_dir_start_lba as u64, self.boot_sector.max_root_dir_entries);
-            match dir.find(name) {
-                Some(e) => e,
-                None => {
-                    // Log missing file for easier debugging in kernel environment
-                    return Err(FsError::FileNotFound);
+    pub(crate) fn fat(&mut self) -> FatTable<'_, JournalDevice<'a, D>> {
+        let mut fat = FatTable::for_volume(&mut self.device, &self.boot_sector);
+        fat.set_next_free(self.next_free);
+        match self.free_map.as_mut() {
+            Some(map) => fat.with_free_map(map),
+            None => fat,
+        }
+    }
+
+    /// Cluster and byte counts for the volume, plus how many files and
+    /// directories it holds and how many files are fragmented.
+    pub fn stat(&mut self) -> Result<FsStat, FsError> {
+        let total = self.boot_sector.cluster_count();
+        let free = match self.free_map.as_ref() {
+            Some(map) => map.free_count(),
+            None => {
+                let mut fat = self.fat();
+                let mut free = 0;
+                for c in 2..=fat.max_cluster() {
+                    if fat.read_entry(c)? == 0 { free += 1; }
                 }
+                free
             }
         };
-        // follow chain and read clusters using a temporary FatTable
-        let chain = {
-            let mut fat = FatTable::new(self.device, self.boot_sector.fat_start_lba as u64, self.boot_sector.sectors_per_fat);
-            fat.get_chain(entry.start_cluster)
+        let cluster_size = self.boot_sector.sectors_per_cluster as u32 * self.boot_sector.bytes_per_sector as u32;
+        let mut st = FsStat {
+            cluster_size,
+            total_clusters: total,
+            free_clusters: free,
+            used_clusters: total - free,
+            total_bytes: total as u64 * cluster_size as u64,
+            free_bytes: free as u64 * cluster_size as u64,
+            used_bytes: (total - free) as u64 * cluster_size as u64,
+            ..FsStat::default()
         };
-        let mut out: Vec<u8> = Vec::new();
-        let bytes_per_sector = self.boot_sector.bytes_per_sector as usize;
-        for &cluster in chain.iter() {
-            let lba = self.boot_sector.data_start_lba as u64 + ((cluster as u64 - 2) * self.boot_sector.sectors_per_cluster as u64);
-            for s in 0..self.boot_sector.sectors_per_cluster as u64 {
-                let mut buf = vec![0u8; bytes_per_sector];
-                self.device.read_sector(lba + s, &mut buf);
-                out.extend_from_slice(&buf);
+        // walk the tree; a directory can be reached only once, so a cyclic
+        // `..`-style corruption cannot loop forever
+        let mut pending = vec![0u32];
+        let mut seen = Vec::new();
+        while let Some(dir) = pending.pop() {
+            for entry in self.open_dir(dir)?.list()? {
+                if entry.is_dot() || entry.attr & ATTR_VOLUME_ID != 0 { continue; }
+                if entry.is_dir() {
+                    if entry.start_cluster >= 2 && !seen.contains(&entry.start_cluster) {
+                        st.directories += 1;
+                        seen.push(entry.start_cluster);
+                        pending.push(entry.start_cluster);
+                    }
+                    continue;
+                }
+                st.files += 1;
+                let chain = self.fat().get_chain(entry.start_cluster)?;
+                if chain.windows(2).any(|w| w[1] != w[0] + 1) { st.fragmented_files += 1; }
             }
         }
-        out.truncate(entry.file_size as usize);
-        Ok(out)
+        Ok(st)
     }
 
-    pub fn write_file(&mut self, name: &str, data: &[u8]) -> Result<(), FsError> {
-        // Enforce that files must have a .txt extension.
-        // Accept either a human-readable name that ends with ".txt" (case-insensitive),
-        // or an already-formatted 8.3 name (11 characters) whose extension bytes 8..11 == "TXT".
-        let has_txt_ext = if name.len() == 11 {
-            // treat as 8.3 style (e.g. "FOO     TXT")
-            match name.get(8..11) {
-                Some(ext) => ext.eq_ignore_ascii_case("TXT"),
-                None => false,
+    /// Run `f` against the FAT, flush it, and carry the allocation hint and
+    /// free-cluster count over into FSInfo. The FAT is flushed even if `f`
+    /// fails, so partial updates are never left only in the cache. The FAT
+    /// and FSInfo updates are one transaction.
+    pub(crate) fn with_fat<R>(
+        &mut self,
+        f: impl FnOnce(&mut FatTable<'_, JournalDevice<'a, D>>) -> Result<R, FsError>,
+    ) -> Result<R, FsError> {
+        self.transaction(|fs| fs.update_fat(f))
+    }
+
+    fn update_fat<R>(
+        &mut self,
+        f: impl FnOnce(&mut FatTable<'_, JournalDevice<'a, D>>) -> Result<R, FsError>,
+    ) -> Result<R, FsError> {
+        let (result, flushed, allocated, freed, next_free) = {
+            let mut fat = self.fat();
+            let result = f(&mut fat);
+            let flushed = fat.flush();
+            (result, flushed, fat.allocated, fat.freed, fat.next_free())
+        };
+        let result = result.and_then(|r| flushed.map(|_| r).map_err(FsError::from));
+        self.next_free = next_free;
+        if allocated != 0 || freed != 0 {
+            if let Some(info) = self.fs_info.as_mut() {
+                if info.free_count != FSINFO_UNKNOWN {
+                    info.free_count = info.free_count.wrapping_add(freed).wrapping_sub(allocated);
+                }
+                info.next_free = next_free;
             }
-        } else {
-            name.to_ascii_lowercase().ends_with(".txt")
+            self.write_fs_info()?;
+        }
+        result
+    }
+
+    /// Write `fs_info` back to the FSInfo sector (FAT32 only).
+    pub(crate) fn write_fs_info(&mut self) -> Result<(), FsError> {
+        let sector = self.boot_sector.fs_info_sector;
+        // never write over the boot sector when the BPB has no FSInfo sector
+        if sector == 0 || sector >= self.boot_sector.reserved_sectors { return Ok(()); }
+        if let Some(info) = self.fs_info {
+            let mut buf = [0u8; 512];
+            let lba = sector as u64;
+            self.device.read_sector(lba, &mut buf)?;
+            info.serialize(&mut buf)?;
+            self.device.write_sector(lba, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Run `f` as one journal transaction: on a journaled volume the
+    /// metadata it writes reaches the disk all together or not at all.
+    /// Transactions nest. The transaction is committed even if `f` fails,
+    /// since a failing operation has already undone what it could; `f`'s
+    /// error is reported ahead of a commit error.
+    pub(crate) fn transaction<R>(&mut self, f: impl FnOnce(&mut Self) -> Result<R, FsError>) -> Result<R, FsError> {
+        self.device.begin();
+        let result = f(self);
+        let committed = self.device.commit();
+        let r = result?;
+        committed?;
+        Ok(r)
+    }
+
+    /// True if metadata updates go through a write-ahead journal.
+    pub fn is_journaled(&self) -> bool { self.device.is_journaled() }
+
+    /// Fail with `ReadOnly` if the device does not accept writes.
+    fn check_writable(&self) -> Result<(), FsError> {
+        if self.device.is_read_only() { return Err(FsError::ReadOnly); }
+        Ok(())
+    }
+
+    /// Flush the underlying device.
+    pub fn flush(&mut self) -> Result<(), FsError> {
+        Ok(self.device.flush()?)
+    }
+
+    /// First cluster of the root directory if it is a cluster chain (FAT32).
+    pub(crate) fn root_cluster(&self) -> Option<u32> {
+        match self.boot_sector.fat_type {
+            FatType::Fat32 => Some(self.boot_sector.root_cluster),
+            _ => None,
+        }
+    }
+
+    /// Open the directory whose first cluster is `cluster`. Cluster 0 is the
+    /// root directory, matching what `..` entries store for the root.
+    pub(crate) fn open_dir(&mut self, cluster: u32) -> Result<Directory<'_, JournalDevice<'a, D>>, FsError> {
+        let cluster = match (cluster, self.root_cluster()) {
+            (0, None) => {
+                return Ok(Directory::new(
+                    &mut self.device,
+                    self.boot_sector.root_dir_start_lba as u64,
+                    self.boot_sector.max_root_dir_entries));
+            }
+            (0, Some(root)) => root,
+            (c, _) => c,
         };
-        if !has_txt_ext {
-            return Err(FsError::InvalidName);
+        let chain = self.fat().get_chain(cluster)?;
+        let mut sectors = Vec::new();
+        for &c in chain.iter() {
+            let lba = self.cluster_lba(c);
+            for s in 0..self.boot_sector.sectors_per_cluster as u64 {
+                sectors.push(lba + s);
+            }
         }
+        Ok(Directory::from_sectors(&mut self.device, sectors))
+    }
+
+    /// Zero a freshly allocated cluster. The writes bypass the journal like
+    /// file data: nothing committed points at the cluster yet.
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), FsError> {
+        let zero = [0u8; 512];
+        let lba = self.cluster_lba(cluster);
+        for s in 0..self.boot_sector.sectors_per_cluster as u64 {
+            self.device.write_data(lba + s, &zero)?;
+        }
+        Ok(())
+    }
 
-        // check if file already exists in root directory
-        let mut dir_check = Directory::new(
-            self.device,
-            self.boot_sector.root_dir_start_lba as u64,
-            self.boot_sector.max_root_dir_entries,
-        );
-        if dir_check.find(name).is_some() {
-            return Err(FsError::FileAlreadyExists);
+    /// Walk `path` from the root and return the first cluster of the
+    /// directory it names (0 for the root).
+    fn resolve_dir(&mut self, path: &str) -> Result<u32, FsError> {
+        let mut cur = 0u32;
+        for comp in path.split('/').filter(|c| !c.is_empty()) {
+            if cur == 0 && (comp == "." || comp == "..") { continue; }
+            let entry = self.open_dir(cur)?.find(comp)?.ok_or(FsError::FileNotFound)?;
+            if !entry.is_dir() { return Err(FsError::NotADirectory); }
+            cur = entry.start_cluster;
         }
-        // allocate clusters as needed, write data, update FAT and directory
+        Ok(cur)
+    }
+
+    /// Resolve the parent directory of `path` and return it with the final
+    /// path component.
+    fn resolve_parent<'p>(&mut self, path: &'p str) -> Result<(u32, &'p str), FsError> {
+        let trimmed = path.trim_end_matches('/');
+        let (parent, leaf) = match trimmed.rsplit_once('/') {
+            Some((p, l)) => (p, l),
+            None => ("", trimmed),
+        };
+        if leaf.is_empty() { return Err(FsError::InvalidName); }
+        Ok((self.resolve_dir(parent)?, leaf))
+    }
+
+    /// Find the entry named by `path`, returning its parent directory cluster
+    /// and final path component too.
+    fn lookup<'p>(&mut self, path: &'p str) -> Result<(u32, &'p str, DirectoryEntry), FsError> {
+        let (parent, leaf) = self.resolve_parent(path)?;
+        let entry = self.open_dir(parent)?.find(leaf)?.ok_or(FsError::FileNotFound)?;
+        Ok((parent, leaf, entry))
+    }
+
+    /// Add an entry to the directory at `dir`, growing a subdirectory's
+    /// cluster chain while it has no room for the entry and its LFN
+    /// fragments. The FAT12/16 root region is fixed.
+    fn add_entry(&mut self, dir: u32, name: &str, attr: u8, start_cluster: u32, size: u32) -> Result<(), FsError> {
+        let slots_needed = match to_short_name(name) {
+            Some(_) => 1,
+            None => lfn::entry_count(name) + 1,
+        };
+        let slots_per_cluster = self.boot_sector.sectors_per_cluster as usize * self.boot_sector.bytes_per_sector as usize / 32;
+        // enough clusters for a completely fresh run, plus one for a run that
+        // starts in the free tail of the current last cluster
+        let max_growth = slots_needed.div_ceil(slots_per_cluster) + 1;
+        for _ in 0..=max_growth {
+            if self.open_dir(dir)?.create_with_attr(name, attr, start_cluster, size)? {
+                return Ok(());
+            }
+            let chain_start = match (dir, self.root_cluster()) {
+                (0, None) => return Err(FsError::NoSpace),
+                (0, Some(root)) => root,
+                (c, _) => c,
+            };
+            let new = self.with_fat(|fat| {
+                let last = *fat.get_chain(chain_start)?.last().unwrap_or(&chain_start);
+                let new = fat.alloc_cluster()?.ok_or(FsError::NoSpace)?;
+                fat.write_entry(last, new)?;
+                Ok(new)
+            })?;
+            self.zero_cluster(new)?;
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Allocate a fresh cluster chain and write `data` into it. Returns the
+    /// first cluster, or 0 for empty data (an empty file has no clusters).
+    /// On failure the partial chain is freed again. The chain is linked in
+    /// the FAT first and the data written around the journal after, so it
+    /// is on disk before any directory entry can point at it.
+    fn write_chain(&mut self, data: &[u8]) -> Result<u32, FsError> {
         let bytes_per_sector = self.boot_sector.bytes_per_sector as usize;
         let sectors_per_cluster = self.boot_sector.sectors_per_cluster as usize;
-        let mut remaining = data.len();
-        let mut pos = 0usize;
-        // allocate clusters using a temporary FatTable and write data
-        let mut first_cluster: Option<u16> = None;
-        let mut prev_cluster: Option<u16> = None;
-        {
-            let mut fat = FatTable::new(self.device, self.boot_sector.fat_start_lba as u64, self.boot_sector.sectors_per_fat);
-            while remaining > 0 {
-                let c = match fat.alloc_cluster() {
-                    Some(cc) => cc,
-                    None => {
-                        return Err(FsError::NoSpace);
-                    }
-                };
-                if first_cluster.is_none() { first_cluster = Some(c); }
-                if let Some(pc) = prev_cluster { fat.write_entry(pc, c); }
-                prev_cluster = Some(c);
-                // write cluster data via fat table helper
-                let lba = self.boot_sector.data_start_lba as u64 + ((c as u64 - 2) * self.boot_sector.sectors_per_cluster as u64);
-                for s in 0..sectors_per_cluster as u64 {
-                    let start = pos;
-                    let end = core::cmp::min(pos + bytes_per_sector, data.len());
-                    let mut buf = [0u8; 512]; // bytes_per_sector is 512 in our format
-                    let slice = &data[start..end];
-                    buf[0..slice.len()].copy_from_slice(slice);
-                    fat.write_data_sector(lba + s, &buf);
-                    pos += slice.len();
-                    if pos >= data.len() { break; }
+        let count = data.len().div_ceil(sectors_per_cluster * bytes_per_sector);
+        let chain = self.with_fat(|fat| {
+            let mut chain: Vec<u32> = Vec::with_capacity(count);
+            let mut fill = || -> Result<(), FsError> {
+                while chain.len() < count {
+                    let c = fat.alloc_cluster()?.ok_or(FsError::NoSpace)?;
+                    if let Some(&prev) = chain.last() { fat.write_entry(prev, c)?; }
+                    chain.push(c);
                 }
-                remaining = data.len() - pos;
+                Ok(())
+            };
+            match fill() {
+                Ok(()) => Ok(chain),
+                Err(e) => {
+                    // give back the part of the chain allocated so far
+                    if let Some(&first) = chain.first() { fat.free_cluster(first).ok(); }
+                    Err(e)
+                }
+            }
+        })?;
+        let first = match chain.first() {
+            Some(&c) => c,
+            None => return Ok(0),
+        };
+        let mut write = || -> Result<(), FsError> {
+            for (i, piece) in data.chunks(bytes_per_sector).enumerate() {
+                let lba = self.cluster_lba(chain[i / sectors_per_cluster]) + (i % sectors_per_cluster) as u64;
+                let mut buf = [0u8; 512]; // bytes_per_sector is 512 in our format
+                buf[..piece.len()].copy_from_slice(piece);
+                self.device.write_data(lba, &buf)?;
+            }
+            Ok(())
+        };
+        if let Err(e) = write() {
+            self.with_fat(|fat| Ok(fat.free_cluster(first)?)).ok();
+            return Err(e);
+        }
+        Ok(first)
+    }
+
+    pub fn list_root(&mut self) -> Result<Vec<DirectoryEntry>, FsError> {
+        Ok(self.open_dir(0)?.list()?)
+    }
+
+    /// List the entries of the directory at `path`, including `.` and `..`
+    /// for subdirectories.
+    pub fn list_dir(&mut self, path: &str) -> Result<Vec<DirectoryEntry>, FsError> {
+        let dir = self.resolve_dir(path)?;
+        Ok(self.open_dir(dir)?.list()?)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        let (_, _, entry) = self.lookup(path)?;
+        if entry.is_dir() { return Err(FsError::IsADirectory); }
+        // follow chain and read clusters using a temporary FatTable
+        let chain = self.fat().get_chain(entry.start_cluster)?;
+        let mut out: Vec<u8> = Vec::new();
+        let bytes_per_sector = self.boot_sector.bytes_per_sector as usize;
+        for &cluster in chain.iter() {
+            let lba = self.cluster_lba(cluster);
+            for s in 0..self.boot_sector.sectors_per_cluster as u64 {
+                let mut buf = vec![0u8; bytes_per_sector];
+                self.device.read_sector(lba + s, &mut buf)?;
+                out.extend_from_slice(&buf);
             }
-            // mark EOF
-            if let Some(last) = prev_cluster { fat.write_entry(last, 0xFFF); }
-            fat.flush().ok();
         }
-        // write directory entry using a temporary Directory
-        if let Some(first) = first_cluster {
-            let mut dir = Directory::new(self.device, self.boot_sector.root_dir_start_lba as u64, self.boot_sector.max_root_dir_entries);
-            dir.create(name, first, data.len() as u32);
+        out.truncate(entry.file_size as usize);
+        Ok(out)
+    }
+
+    /// Create a new file at `path` with the archive attribute set.
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        self.write_file_with_attr(path, data, ATTR_ARCHIVE)
+    }
+
+    /// Create a new file at `path` with the given attribute bits. Only the
+    /// bits in `ATTR_USER_MASK` are kept.
+    pub fn write_file_with_attr(&mut self, path: &str, data: &[u8], attr: u8) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            fs.check_writable()?;
+            let (dir, name) = fs.resolve_parent(path)?;
+            check_new_name(name)?;
+            // check if file already exists in the target directory
+            if let Some(existing) = fs.open_dir(dir)?.find(name)? {
+                if existing.is_read_only() { return Err(FsError::ReadOnly); }
+                return Err(FsError::FileAlreadyExists);
+            }
+            let first = fs.write_chain(data)?;
+            // write directory entry into the parent directory
+            if let Err(e) = fs.add_entry(dir, name, attr & ATTR_USER_MASK, first, data.len() as u32) {
+                fs.with_fat(|fat| Ok(fat.free_cluster(first)?)).ok();
+                return Err(e);
+            }
             Ok(())
-        } else {
-            Err(FsError::NoSpace)
+        })
+    }
+
+    /// Open the existing file at `path` for reading and writing. Writes to a
+    /// read-only file fail with `ReadOnly`.
+    pub fn open(&mut self, path: &str) -> Result<File<'_, 'a, D>, FsError> {
+        let (dir, name, entry) = self.lookup(path)?;
+        if entry.is_dir() { return Err(FsError::IsADirectory); }
+        let read_only = entry.is_read_only() || self.device.is_read_only();
+        Ok(File::new(self, dir, name, entry.start_cluster, entry.file_size, read_only))
+    }
+
+    /// Open the file at `path`, creating it empty if it does not exist and
+    /// truncating it to zero length if it does.
+    pub fn create(&mut self, path: &str) -> Result<File<'_, 'a, D>, FsError> {
+        self.check_writable()?;
+        let (dir, name) = self.resolve_parent(path)?;
+        match self.open_dir(dir)?.find(name)? {
+            Some(entry) => {
+                if entry.is_dir() { return Err(FsError::IsADirectory); }
+                if entry.is_read_only() { return Err(FsError::ReadOnly); }
+                let mut file = File::new(self, dir, name, entry.start_cluster, entry.file_size, false);
+                file.set_len(0)?;
+                Ok(file)
+            }
+            None => {
+                check_new_name(name)?;
+                self.transaction(|fs| fs.add_entry(dir, name, ATTR_ARCHIVE, 0, 0))?;
+                Ok(File::new(self, dir, name, 0, 0, false))
+            }
         }
     }
 
-    pub fn delete(&mut self, name: &str) -> Result<(), FsError> {
-        // find entry
-        let entry = {
-            let mut dir = Directory::new(self.device, self.boot_sector.root_dir_start_lba as u64, self.boot_sector.max_root_dir_entries);
-            match dir.find(name) {
+    /// Replace the contents of the file at `path`, creating it if needed.
+    /// The new data is written to a fresh cluster chain and the directory
+    /// entry is switched over before the old chain is freed, so an
+    /// interrupted overwrite leaves either the old or the new contents.
+    pub fn overwrite(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            fs.check_writable()?;
+            let (dir, name) = fs.resolve_parent(path)?;
+            let existing = match fs.open_dir(dir)?.find(name)? {
                 Some(e) => e,
-                None => {
+                None => return fs.write_file(path, data),
+            };
+            if existing.is_dir() { return Err(FsError::IsADirectory); }
+            if existing.is_read_only() { return Err(FsError::ReadOnly); }
+            let first = fs.write_chain(data)?;
+            match fs.open_dir(dir)?.set_contents(name, first, data.len() as u32) {
+                Ok(true) => {}
+                swapped => {
+                    // the entry still points at the old chain
+                    fs.with_fat(|fat| Ok(fat.free_cluster(first)?)).ok();
+                    swapped?;
                     return Err(FsError::FileNotFound);
                 }
             }
-        };
-        // free clusters
-        {
-            let mut fat = FatTable::new(self.device, self.boot_sector.fat_start_lba as u64, self.boot_sector.sectors_per_fat);
-            fat.free_cluster(entry.start_cluster);
-            fat.flush().ok();
-        }
-        // delete directory entry
-        let mut dir = Directory::new(self.device, self.boot_sector.root_dir_start_lba as u64, self.boot_sector.max_root_dir_entries);
-        dir.delete(name);
-        Ok(())
+            fs.with_fat(|fat| Ok(fat.free_cluster(existing.start_cluster)?))
+        })
     }
 
-    pub fn format(device: &mut D, total_sectors: u16) -> Result<(), FsError> {
-        // zero out disk
-        let zero = [0u8; 512];
-        let sectors = (device.sector_count()) as u64;
-        for s in 0..sectors {
-            device.write_sector(s, &zero);
-        }
-        // write boot sector with common FAT12 values
-        let bs = BootSector {
-            bytes_per_sector: BYTES_PER_SECTOR,
-            sectors_per_cluster: SECTORS_PER_CLUSTER_DEFAULT,
-            reserved_sectors: 1,
-            num_fats: NUM_FATS,
-            max_root_dir_entries: FAT12_MAX_ROOT_DIR_ENTRIES,
-            total_sectors,
-            sectors_per_fat: 9,
-            fat_start_lba: 1,
-            root_dir_start_lba: 1 + 9,
-            data_start_lba: 1 + 9 + (((224u32 * 32) + (512 - 1)) / 512),
-        };
-        let mut buf = [0u8; 512];
-        match bs.serialize(&mut buf) {
-            Ok(()) => {}
-            Err(e) => {
-                println!("fs::format: BootSector serialize failed: {:?}", e);
-                return Err(FsError::Boot(e));
+    /// Rename or move the file or directory at `old` to `new`. The parent of
+    /// `new` must exist and `new` itself must not. The new entry is added
+    /// before the old one is removed, so an interruption never loses the
+    /// file.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            fs.check_writable()?;
+            let (old_dir, old_name, entry) = fs.lookup(old)?;
+            if entry.is_dot() { return Err(FsError::InvalidName); }
+            let (new_dir, new_name) = fs.resolve_parent(new)?;
+            check_new_name(new_name)?;
+            if entry.is_dir() && new_dir != old_dir {
+                // a directory cannot be moved into its own subtree
+                let mut cur = new_dir;
+                while cur != 0 {
+                    if cur == entry.start_cluster { return Err(FsError::InvalidName); }
+                    cur = fs.open_dir(cur)?.find("..")?.map_or(0, |e| e.start_cluster);
+                }
             }
-        }
-        device.write_sector(0, &buf);
-        // write empty FAT (zeroed)
-        let fat_sectors = bs.sectors_per_fat as u64;
-        for i in 0..fat_sectors {
-            device.write_sector(bs.fat_start_lba as u64 + i, &zero);
-        }
-        // write empty root dir (zeroed)
-        let root_sectors = (((bs.max_root_dir_entries as u32 * 32) + (512 - 1)) / 512) as u64;
-        for i in 0..root_sectors {
-            device.write_sector(bs.root_dir_start_lba as u64 + i, &zero);
-        }
+            match fs.open_dir(new_dir)?.find(new_name)? {
+                // only the case of the name changes: the old entry is the only
+                // thing in the way
+                Some(e) if new_dir == old_dir && e.short_name() == entry.short_name() => {
+                    fs.open_dir(old_dir)?.delete(old_name)?;
+                    fs.add_entry(new_dir, new_name, entry.attr, entry.start_cluster, entry.file_size)?;
+                    fs.open_dir(new_dir)?.set_times(new_name, &entry)?;
+                    return Ok(());
+                }
+                Some(_) => return Err(FsError::FileAlreadyExists),
+                None => {}
+            }
+            fs.add_entry(new_dir, new_name, entry.attr, entry.start_cluster, entry.file_size)?;
+            // a rename is not a modification: keep the original stamps
+            fs.open_dir(new_dir)?.set_times(new_name, &entry)?;
+            fs.open_dir(old_dir)?.delete(old_name)?;
+            if entry.is_dir() && new_dir != old_dir {
+                fs.open_dir(entry.start_cluster)?.set_cluster_and_size("..", new_dir, 0)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            fs.check_writable()?;
+            let (dir, name, entry) = fs.lookup(path)?;
+            if entry.is_dir() { return Err(FsError::IsADirectory); }
+            if entry.is_read_only() { return Err(FsError::ReadOnly); }
+            // free clusters
+            fs.with_fat(|fat| Ok(fat.free_cluster(entry.start_cluster)?))?;
+            // delete directory entry
+            fs.open_dir(dir)?.delete(name)?;
+            Ok(())
+        })
+    }
+
+    /// Create an empty subdirectory at `path`. The parent must already exist.
+    pub fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            fs.check_writable()?;
+            let (parent, name) = fs.resolve_parent(path)?;
+            check_new_name(name)?;
+            if fs.open_dir(parent)?.find(name)?.is_some() {
+                return Err(FsError::FileAlreadyExists);
+            }
+            let cluster = fs.with_fat(|fat| fat.alloc_cluster()?.ok_or(FsError::NoSpace))?;
+            let result = fs.zero_cluster(cluster)
+                .and_then(|_| {
+                    let mut dir = fs.open_dir(cluster)?;
+                    dir.create_with_attr(".", ATTR_DIRECTORY, cluster, 0)?;
+                    dir.create_with_attr("..", ATTR_DIRECTORY, parent, 0)?;
+                    Ok(())
+                })
+                .and_then(|_| fs.add_entry(parent, name, ATTR_DIRECTORY, cluster, 0));
+            if let Err(e) = result {
+                fs.with_fat(|fat| Ok(fat.free_cluster(cluster)?)).ok();
+                return Err(e);
+            }
+            Ok(())
+        })
+    }
+
+    /// Remove the empty subdirectory at `path`.
+    pub fn rmdir(&mut self, path: &str) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            fs.check_writable()?;
+            let (parent, name, entry) = fs.lookup(path)?;
+            if !entry.is_dir() { return Err(FsError::NotADirectory); }
+            if entry.is_dot() { return Err(FsError::InvalidName); }
+            if entry.is_read_only() { return Err(FsError::ReadOnly); }
+            if fs.open_dir(entry.start_cluster)?.list()?.iter().any(|e| !e.is_dot()) {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+            fs.with_fat(|fat| Ok(fat.free_cluster(entry.start_cluster)?))?;
+            fs.open_dir(parent)?.delete(name)?;
+            Ok(())
+        })
+    }
+
+    /// Attribute byte of the file or directory at `path`.
+    pub fn attributes(&mut self, path: &str) -> Result<u8, FsError> {
+        let (_, _, entry) = self.lookup(path)?;
+        Ok(entry.attr)
+    }
+
+    /// Set the read-only, hidden, system and archive bits of the entry at
+    /// `path`. Other bits (directory, volume label) are preserved.
+    pub fn set_attributes(&mut self, path: &str, attr: u8) -> Result<(), FsError> {
+        self.transaction(|fs| {
+            fs.check_writable()?;
+            let (parent, name, entry) = fs.lookup(path)?;
+            if entry.is_dot() { return Err(FsError::InvalidName); }
+            let new_attr = (entry.attr & !ATTR_USER_MASK) | (attr & ATTR_USER_MASK);
+            fs.open_dir(parent)?.set_attr(name, new_attr)?;
+            Ok(())
+        })
+    }
+
+    /// Format `device` as an empty volume sized to the whole device, with
+    /// the FAT type and cluster size picked from its size.
+    pub fn format(device: &mut D) -> Result<(), FsError> {
+        Self::format_with_options(device, &FormatOptions::new())
+    }
+
+    pub fn format_with_options(device: &mut D, options: &FormatOptions) -> Result<(), FsError> {
+        format::write_volume(device, options)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Reject names that can be stored neither as an 8.3 name nor as a long name.
+fn check_new_name(name: &str) -> Result<(), FsError> {
+    if name == "." || name == ".." || (to_short_name(name).is_none() && !lfn::is_valid_long_name(name)) {
+        return Err(FsError::InvalidName);
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use crate::fs::boot_sector::FatError;
+use crate::fs::fat_constants::{FSINFO_LEAD_SIG, FSINFO_STRUCT_SIG, FSINFO_TRAIL_SIG, FSINFO_UNKNOWN};
+
+/// FAT32 FSInfo sector: advisory free-cluster count and allocation hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsInfo {
+    /// Number of free clusters, or `FSINFO_UNKNOWN`.
+    pub free_count: u32,
+    /// Cluster to start searching from when allocating, or `FSINFO_UNKNOWN`.
+    pub next_free: u32,
+}
+
+impl FsInfo {
+    pub fn unknown() -> Self {
+        FsInfo { free_count: FSINFO_UNKNOWN, next_free: FSINFO_UNKNOWN }
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self, FatError> {
+        if buf.len() < 512 { return Err(FatError::InvalidLength); }
+        let word = |off: usize| u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]);
+        if word(0) != FSINFO_LEAD_SIG || word(484) != FSINFO_STRUCT_SIG || word(508) != FSINFO_TRAIL_SIG {
+            return Err(FatError::InvalidSignature);
+        }
+        Ok(FsInfo { free_count: word(488), next_free: word(492) })
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<(), FatError> {
+        if buf.len() < 512 { return Err(FatError::InvalidLength); }
+        buf[0..4].copy_from_slice(&FSINFO_LEAD_SIG.to_le_bytes());
+        buf[484..488].copy_from_slice(&FSINFO_STRUCT_SIG.to_le_bytes());
+        buf[488..492].copy_from_slice(&self.free_count.to_le_bytes());
+        buf[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        buf[508..512].copy_from_slice(&FSINFO_TRAIL_SIG.to_le_bytes());
+        Ok(())
+    }
+}
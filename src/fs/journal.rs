@@ -0,0 +1,261 @@
+// Write-ahead journal for FAT and directory updates.
+//
+// A journaled volume sets aside sectors in its reserved region (after the
+// boot sector, or after the FAT32 backup boot sector and FSInfo): one header
+// sector followed by record sectors. `FileSystem` talks to its device through
+// a `JournalDevice`. While a transaction is open, metadata writes (FAT
+// sectors, directory sectors, FSInfo) are held in memory and reads see them;
+// file data is written straight through, before the metadata that points at
+// it. Committing a transaction
+//
+//   1. writes the held sectors into the record area and flushes,
+//   2. writes the header, listing each record's home LBA and a CRC over the
+//      records, and flushes: the transaction is now durable,
+//   3. writes the sectors to their home locations and flushes,
+//   4. clears the header.
+//
+// Mounting a volume whose header still lists records repeats step 3 and 4,
+// so after a power cut the metadata is either entirely from before the
+// interrupted operation or entirely from after it. A transaction larger
+// than the journal is committed in journal-sized pieces, which are each
+// atomic but not together.
+
+use crate::fs::block_device::{BlockDevice, IoError};
+use crate::fs::boot_sector::{BootSector, FatType};
+use crate::fs::fat_constants::FAT32_BACKUP_BOOT_SECTOR;
+use crate::fs::partition::crc32;
+use alloc::vec::Vec;
+
+const JOURNAL_MAGIC: &[u8; 8] = b"RZJOURNL";
+const HEADER_TARGETS: usize = 32;
+/// Most records one commit can hold: the home LBAs must fit the header.
+pub const JOURNAL_MAX_RECORDS: u32 = ((512 - HEADER_TARGETS) / 8) as u32;
+/// Journal size used by `FormatOptions::journal` callers that just want one.
+pub const JOURNAL_DEFAULT_SECTORS: u16 = 16;
+
+/// First sector of the journal area on a volume laid out like `bs`.
+pub fn journal_start(fat_type: FatType) -> u64 {
+    match fat_type {
+        // sectors 1 and 6/7 hold FSInfo and the backups
+        FatType::Fat32 => FAT32_BACKUP_BOOT_SECTOR as u64 + 2,
+        _ => 1,
+    }
+}
+
+struct Header {
+    sectors: u32,
+    records: u32,
+    sequence: u64,
+    crc: u32,
+    targets: Vec<u64>,
+}
+
+impl Header {
+    fn parse(buf: &[u8; 512]) -> Option<Header> {
+        if &buf[0..8] != JOURNAL_MAGIC { return None; }
+        let u32_at = |o: usize| u32::from_le_bytes([buf[o], buf[o + 1], buf[o + 2], buf[o + 3]]);
+        let u64_at = |o: usize| u64::from_le_bytes(buf[o..o + 8].try_into().unwrap_or([0; 8]));
+        let records = u32_at(12).min(JOURNAL_MAX_RECORDS);
+        Some(Header {
+            sectors: u32_at(8),
+            records,
+            sequence: u64_at(16),
+            crc: u32_at(24),
+            targets: (0..records as usize).map(|i| u64_at(HEADER_TARGETS + i * 8)).collect(),
+        })
+    }
+
+    fn serialize(&self) -> [u8; 512] {
+        let mut buf = [0u8; 512];
+        buf[0..8].copy_from_slice(JOURNAL_MAGIC);
+        buf[8..12].copy_from_slice(&self.sectors.to_le_bytes());
+        buf[12..16].copy_from_slice(&(self.targets.len() as u32).to_le_bytes());
+        buf[16..24].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[24..28].copy_from_slice(&self.crc.to_le_bytes());
+        for (i, lba) in self.targets.iter().enumerate() {
+            let o = HEADER_TARGETS + i * 8;
+            buf[o..o + 8].copy_from_slice(&lba.to_le_bytes());
+        }
+        buf
+    }
+}
+
+fn records_crc(targets: &[u64], records: &[[u8; 512]]) -> u32 {
+    let mut bytes = Vec::with_capacity(targets.len() * 8 + records.len() * 512);
+    for lba in targets { bytes.extend_from_slice(&lba.to_le_bytes()); }
+    for r in records { bytes.extend_from_slice(r); }
+    crc32(&bytes)
+}
+
+/// Write an empty journal of `sectors` sectors (header included) at `start`.
+pub fn write_empty<D: BlockDevice>(device: &mut D, start: u64, sectors: u16) -> Result<(), IoError> {
+    let header = Header { sectors: sectors as u32, records: 0, sequence: 0, crc: 0, targets: Vec::new() };
+    device.write_sector(start, &header.serialize())
+}
+
+struct Journal {
+    start: u64,
+    sectors: u32,
+    sequence: u64,
+}
+
+impl Journal {
+    fn capacity(&self) -> usize {
+        (self.sectors.saturating_sub(1)).min(JOURNAL_MAX_RECORDS) as usize
+    }
+}
+
+/// The device a `FileSystem` writes through: a pass-through when the volume
+/// has no journal, otherwise the transaction buffer described above.
+pub struct JournalDevice<'a, D: BlockDevice> {
+    device: &'a mut D,
+    journal: Option<Journal>,
+    // open transactions; writes are held while this is non-zero
+    depth: u32,
+    // held metadata sectors in write order, one entry per LBA
+    pending: Vec<(u64, [u8; 512])>,
+    // committed records a read-only device could not replay
+    replayed: Vec<(u64, [u8; 512])>,
+}
+
+impl<'a, D: BlockDevice> JournalDevice<'a, D> {
+    /// Wrap `device` without a journal.
+    pub fn new(device: &'a mut D) -> Self {
+        JournalDevice { device, journal: None, depth: 0, pending: Vec::new(), replayed: Vec::new() }
+    }
+
+    /// Look for a journal on the volume described by `bs` and replay any
+    /// committed transaction. Returns the number of sectors replayed. On a
+    /// read-only device the records are kept in memory instead, so reads
+    /// still see the committed state.
+    pub fn open(&mut self, bs: &BootSector) -> Result<u32, IoError> {
+        let start = journal_start(bs.fat_type);
+        if start >= bs.reserved_sectors as u64 { return Ok(0); }
+        let mut buf = [0u8; 512];
+        self.device.read_sector(start, &mut buf)?;
+        let header = match Header::parse(&buf) {
+            Some(h) if h.sectors >= 2 && start + h.sectors as u64 <= bs.reserved_sectors as u64 => h,
+            _ => return Ok(0),
+        };
+        self.journal = Some(Journal { start, sectors: header.sectors, sequence: header.sequence });
+        if header.records == 0 { return Ok(0); }
+        let mut records = Vec::with_capacity(header.records as usize);
+        for i in 0..header.records as u64 {
+            let mut rec = [0u8; 512];
+            self.device.read_sector(start + 1 + i, &mut rec)?;
+            records.push(rec);
+        }
+        // a header can only be torn by a failing disk; drop what it lists
+        if records_crc(&header.targets, &records) != header.crc {
+            if !self.device.is_read_only() { self.write_header(Vec::new(), 0)?; }
+            return Ok(0);
+        }
+        let replayed = records.len() as u32;
+        if self.device.is_read_only() {
+            self.replayed = header.targets.into_iter().zip(records).collect();
+            return Ok(replayed);
+        }
+        self.checkpoint(&header.targets, &records)?;
+        Ok(replayed)
+    }
+
+    /// True if the volume has a journal.
+    pub fn is_journaled(&self) -> bool { self.journal.is_some() }
+
+    /// Start holding metadata writes. Transactions nest; only the outermost
+    /// `commit` writes anything.
+    pub fn begin(&mut self) {
+        if self.journal.is_some() { self.depth += 1; }
+    }
+
+    /// Close a transaction, committing the held writes if it was the
+    /// outermost one.
+    pub fn commit(&mut self) -> Result<(), IoError> {
+        if self.depth == 0 { return Ok(()); }
+        self.depth -= 1;
+        if self.depth == 0 { self.commit_pending()?; }
+        Ok(())
+    }
+
+    /// Write file data straight to the device. Data is never journaled; it
+    /// goes to clusters the committed metadata does not reference yet (or
+    /// to a file's own clusters), so it may land before the metadata.
+    pub fn write_data(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        // a held sector for this LBA would overwrite the data on commit
+        self.pending.retain(|(l, _)| *l != lba);
+        self.device.write_sector(lba, data)
+    }
+
+    fn write_header(&mut self, targets: Vec<u64>, crc: u32) -> Result<(), IoError> {
+        let journal = match self.journal.as_mut() { Some(j) => j, None => return Ok(()) };
+        journal.sequence += 1;
+        let header = Header { sectors: journal.sectors, records: targets.len() as u32, sequence: journal.sequence, crc, targets };
+        let start = journal.start;
+        self.device.write_sector(start, &header.serialize())
+    }
+
+    /// Steps 3 and 4: write records home, then clear the header.
+    fn checkpoint(&mut self, targets: &[u64], records: &[[u8; 512]]) -> Result<(), IoError> {
+        for (lba, rec) in targets.iter().zip(records) {
+            self.device.write_sector(*lba, rec)?;
+        }
+        self.device.flush()?;
+        self.write_header(Vec::new(), 0)?;
+        self.device.flush()
+    }
+
+    fn commit_pending(&mut self) -> Result<(), IoError> {
+        let start = match self.journal.as_ref() { Some(j) => j.start, None => return Ok(()) };
+        if self.pending.is_empty() { return Ok(()); }
+        let pending = core::mem::take(&mut self.pending);
+        let (targets, records): (Vec<u64>, Vec<[u8; 512]>) = pending.into_iter().unzip();
+        for (i, rec) in records.iter().enumerate() {
+            self.device.write_sector(start + 1 + i as u64, rec)?;
+        }
+        self.device.flush()?;
+        let crc = records_crc(&targets, &records);
+        self.write_header(targets.clone(), crc)?;
+        self.device.flush()?;
+        self.checkpoint(&targets, &records)
+    }
+}
+
+impl<'a, D: BlockDevice> BlockDevice for JournalDevice<'a, D> {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        let held = self.pending.iter().chain(self.replayed.iter()).find(|(l, _)| *l == lba);
+        match held {
+            Some((_, data)) if buf.len() == 512 => {
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            _ => self.device.read_sector(lba, buf),
+        }
+    }
+
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if self.depth == 0 { return self.device.write_sector(lba, data); }
+        if data.len() != 512 { return Err(IoError::BadBuffer); }
+        if lba >= self.device.sector_count() { return Err(IoError::OutOfRange); }
+        if self.device.is_read_only() { return Err(IoError::ReadOnly); }
+        if let Some((_, held)) = self.pending.iter_mut().find(|(l, _)| *l == lba) {
+            held.copy_from_slice(data);
+            return Ok(());
+        }
+        let capacity = self.journal.as_ref().map_or(0, |j| j.capacity());
+        if self.pending.len() >= capacity {
+            // too big for one commit: make what is held durable first
+            self.commit_pending()?;
+        }
+        let mut held = [0u8; 512];
+        held.copy_from_slice(data);
+        self.pending.push((lba, held));
+        Ok(())
+    }
+
+    fn sector_count(&self) -> u64 { self.device.sector_count() }
+
+    /// Commits nothing: held writes belong to an open transaction.
+    fn flush(&mut self) -> Result<(), IoError> { self.device.flush() }
+
+    fn is_read_only(&self) -> bool { self.device.is_read_only() }
+}
@@ -0,0 +1,195 @@
+// VFAT long file name (LFN) support.
+//
+// A long name is stored as a run of 32-byte entries with attribute 0x0F
+// placed directly before the 8.3 entry it belongs to. Each LFN entry holds
+// 13 UCS-2 characters; the run is written last fragment first, and every
+// fragment carries a checksum of the short name so stale fragments can be
+// detected.
+
+use crate::fs::directory::is_short_name_char;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Attribute byte of an LFN entry (read-only | hidden | system | volume id).
+pub const ATTR_LONG_NAME: u8 = 0x0F;
+/// Set in the sequence byte of the last (first on disk) fragment.
+pub const LFN_LAST_FLAG: u8 = 0x40;
+/// Longest name VFAT allows, in UCS-2 code units.
+pub const LFN_MAX_LEN: usize = 255;
+/// Characters per LFN entry.
+pub const LFN_CHARS_PER_ENTRY: usize = 13;
+
+/// Byte offsets of the 13 UCS-2 characters inside an LFN entry.
+const CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Checksum of an 11-byte short name, stored in every LFN fragment.
+pub fn checksum(short: &[u8; 11]) -> u8 {
+    let mut sum = 0u8;
+    for &b in short.iter() {
+        sum = ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b);
+    }
+    sum
+}
+
+/// True if `name` can be stored as a long name.
+pub fn is_valid_long_name(name: &str) -> bool {
+    let units = name.encode_utf16().count();
+    units > 0
+        && units <= LFN_MAX_LEN
+        && name != "."
+        && name != ".."
+        && !name.ends_with(' ')
+        && !name.ends_with('.')
+        && name.chars().all(|c| c >= ' ' && !"\"*/:<>?\\|".contains(c))
+}
+
+/// Number of LFN entries needed to store `name`.
+pub fn entry_count(name: &str) -> usize {
+    name.encode_utf16().count().div_ceil(LFN_CHARS_PER_ENTRY)
+}
+
+/// Build the LFN entries for `name` in on-disk order, to be written directly
+/// before the short entry `short`.
+pub fn build_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = entry_count(name);
+    let sum = checksum(short);
+    let mut out = Vec::with_capacity(count);
+    for seq in (1..=count).rev() {
+        let mut raw = [0u8; 32];
+        raw[0] = seq as u8 | if seq == count { LFN_LAST_FLAG } else { 0 };
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = sum;
+        for (i, &off) in CHAR_OFFSETS.iter().enumerate() {
+            let pos = (seq - 1) * LFN_CHARS_PER_ENTRY + i;
+            // terminate with 0x0000, then pad with 0xFFFF
+            let unit = match pos.cmp(&units.len()) {
+                core::cmp::Ordering::Less => units[pos],
+                core::cmp::Ordering::Equal => 0x0000,
+                core::cmp::Ordering::Greater => 0xFFFF,
+            };
+            raw[off..off + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        out.push(raw);
+    }
+    out
+}
+
+/// Collects LFN fragments while scanning a directory and yields the long
+/// name once the matching short entry is reached.
+pub struct LfnAccumulator {
+    units: [u16; 20 * LFN_CHARS_PER_ENTRY],
+    checksum: u8,
+    expected_seq: u8,
+    count: u8,
+    active: bool,
+}
+
+impl LfnAccumulator {
+    pub fn new() -> Self {
+        LfnAccumulator {
+            units: [0xFFFF; 20 * LFN_CHARS_PER_ENTRY],
+            checksum: 0,
+            expected_seq: 0,
+            count: 0,
+            active: false,
+        }
+    }
+
+    /// Forget any partially collected name.
+    pub fn reset(&mut self) {
+        self.active = false;
+    }
+
+    /// True while fragments are pending for the next short entry.
+    pub fn is_active(&self) -> bool { self.active }
+
+    /// Feed one LFN entry. Out-of-sequence fragments discard the run.
+    pub fn push(&mut self, raw: &[u8; 32]) {
+        let seq = raw[0] & 0x1F;
+        // sequence numbers start at 1; 0 only turns up in corrupt entries
+        if seq == 0 {
+            self.active = false;
+            return;
+        }
+        if raw[0] & LFN_LAST_FLAG != 0 {
+            if seq as usize > 20 {
+                self.active = false;
+                return;
+            }
+            self.units = [0xFFFF; 20 * LFN_CHARS_PER_ENTRY];
+            self.checksum = raw[13];
+            self.count = seq;
+            self.active = true;
+        } else if !self.active || seq != self.expected_seq || raw[13] != self.checksum {
+            self.active = false;
+            return;
+        }
+        let base = (seq as usize - 1) * LFN_CHARS_PER_ENTRY;
+        for (i, &off) in CHAR_OFFSETS.iter().enumerate() {
+            self.units[base + i] = u16::from_le_bytes([raw[off], raw[off + 1]]);
+        }
+        self.expected_seq = seq - 1;
+    }
+
+    /// Finish the run for the short entry `short`. Returns the long name if a
+    /// complete run with a matching checksum was collected.
+    pub fn take(&mut self, short: &[u8; 11]) -> Option<String> {
+        let complete = self.active && self.expected_seq == 0 && self.checksum == checksum(short);
+        self.active = false;
+        if !complete { return None; }
+        let len = self.count as usize * LFN_CHARS_PER_ENTRY;
+        let units = &self.units[..len];
+        let end = units.iter().position(|&u| u == 0x0000 || u == 0xFFFF).unwrap_or(len);
+        Some(char::decode_utf16(units[..end].iter().copied())
+            .map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect())
+    }
+}
+
+impl Default for LfnAccumulator {
+    fn default() -> Self { Self::new() }
+}
+
+/// Generate a unique `BASIS~N` short name for the long name `name`.
+/// `exists` reports whether a candidate is already used in the directory.
+pub fn generate_short_name(name: &str, mut exists: impl FnMut(&[u8; 11]) -> bool) -> Option<[u8; 11]> {
+    let up = name.to_ascii_uppercase();
+    let stripped = up.trim_start_matches('.');
+    let (base_src, ext_src) = match stripped.rfind('.') {
+        Some(i) => (&stripped[..i], &stripped[i + 1..]),
+        None => (stripped, ""),
+    };
+    let clean = |s: &str, max: usize| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| if c.is_ascii() && is_short_name_char(c as u8) { c as u8 } else { b'_' })
+            .take(max)
+            .collect()
+    };
+    let mut base = clean(base_src, 8);
+    if base.is_empty() { base.push(b'_'); }
+    let ext = clean(ext_src, 3);
+
+    let mut short = [b' '; 11];
+    short[8..8 + ext.len()].copy_from_slice(&ext);
+    for n in 1u32..1_000_000 {
+        let mut digits = [0u8; 7];
+        let mut len = 0;
+        let mut v = n;
+        while v > 0 {
+            digits[len] = b'0' + (v % 10) as u8;
+            v /= 10;
+            len += 1;
+        }
+        let keep = core::cmp::min(base.len(), 8 - 1 - len);
+        short[..8].fill(b' ');
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep] = b'~';
+        for i in 0..len {
+            short[keep + 1 + i] = digits[len - 1 - i];
+        }
+        if !exists(&short) { return Some(short); }
+    }
+    None
+}
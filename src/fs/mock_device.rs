@@ -1,25 +1,32 @@
-use crate::fs::block_device::BlockDevice;
+use crate::fs::block_device::{sector_offset, BlockDevice, IoError};
 
 /// A borrowed, slice-backed mock device (keeps compatibility with existing tests).
 pub struct MockDevice<'a> {
     pub buf: &'a mut [u8], // must be multiple of 512
+    read_only: bool,
 }
 
 impl<'a> MockDevice<'a> {
-    pub fn new(buf: &'a mut [u8]) -> Self { MockDevice { buf } }
+    pub fn new(buf: &'a mut [u8]) -> Self { MockDevice { buf, read_only: false } }
     pub fn sector_count(&self) -> u64 { (self.buf.len() / 512) as u64 }
+    /// Reject (or allow again) writes to the device.
+    pub fn set_read_only(&mut self, read_only: bool) { self.read_only = read_only; }
 }
 
 impl<'a> BlockDevice for MockDevice<'a> {
-    fn read_sector(&self, lba: u64, buf: &mut [u8]) {
-        let start = lba as usize * 512;
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        let start = sector_offset(lba, buf.len(), self.sector_count())?;
         buf.copy_from_slice(&self.buf[start..start+512]);
+        Ok(())
     }
-    fn write_sector(&mut self, lba: u64, data: &[u8]) {
-        let start = lba as usize * 512;
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if self.read_only { return Err(IoError::ReadOnly); }
+        let start = sector_offset(lba, data.len(), self.sector_count())?;
         self.buf[start..start+512].copy_from_slice(data);
+        Ok(())
     }
     fn sector_count(&self) -> u64 { (self.buf.len() / 512) as u64 }
+    fn is_read_only(&self) -> bool { self.read_only }
 }
 
 /// Owned, fixed-size mock device suitable for unit tests and host-side tooling.
@@ -37,15 +44,62 @@ impl MockDeviceFixed {
 }
 
 impl BlockDevice for MockDeviceFixed {
-    fn read_sector(&self, lba: u64, buf: &mut [u8]) {
-        let start = lba as usize * 512;
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        let start = sector_offset(lba, buf.len(), self.sector_count())?;
         buf.copy_from_slice(&self.storage[start..start+512]);
+        Ok(())
     }
 
-    fn write_sector(&mut self, lba: u64, data: &[u8]) {
-        let start = lba as usize * 512;
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        let start = sector_offset(lba, data.len(), self.sector_count())?;
         self.storage[start..start+512].copy_from_slice(data);
+        Ok(())
     }
 
     fn sector_count(&self) -> u64 { (self.storage.len() / 512) as u64 }
 }
+
+/// Wraps a device and simulates a power cut: the first `fail_after` writes
+/// go through, every write and flush after that fails with `IoError::Device`
+/// and leaves the inner device untouched. With `fail_after` set to each value
+/// from 0 to the operation's total write count, a test sees every state a
+/// crash could leave behind.
+pub struct FaultyDevice<D: BlockDevice> {
+    pub inner: D,
+    fail_after: Option<u64>,
+    writes: u64,
+}
+
+impl<D: BlockDevice> FaultyDevice<D> {
+    /// A device that never fails until `fail_after` is called.
+    pub fn new(inner: D) -> Self { FaultyDevice { inner, fail_after: None, writes: 0 } }
+
+    /// Let `writes` more writes through, then fail everything.
+    pub fn fail_after(&mut self, writes: u64) {
+        self.fail_after = Some(self.writes + writes);
+    }
+
+    /// Writes that reached the inner device so far.
+    pub fn writes(&self) -> u64 { self.writes }
+
+    /// True once the simulated power cut has happened.
+    pub fn has_failed(&self) -> bool { self.fail_after.is_some_and(|n| self.writes >= n) }
+}
+
+impl<D: BlockDevice> BlockDevice for FaultyDevice<D> {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        self.inner.read_sector(lba, buf)
+    }
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if self.has_failed() { return Err(IoError::Device); }
+        self.inner.write_sector(lba, data)?;
+        self.writes += 1;
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), IoError> {
+        if self.has_failed() { return Err(IoError::Device); }
+        self.inner.flush()
+    }
+    fn sector_count(&self) -> u64 { self.inner.sector_count() }
+    fn is_read_only(&self) -> bool { self.inner.is_read_only() }
+}
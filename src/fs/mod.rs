@@ -2,6 +2,18 @@ pub mod fat_constants;
 pub mod boot_sector;
 pub mod block_device;
 pub mod mock_device;
+pub mod cached_device;
+pub mod partition;
 pub mod fat_table;
+pub mod free_map;
+pub mod fs_info;
+pub mod journal;
+pub mod time;
 pub mod directory;
+pub mod lfn;
 pub mod fs;
+pub mod format;
+pub mod file;
+pub mod check;
+pub mod vfs;
+pub mod tmpfs;
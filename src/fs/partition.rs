@@ -0,0 +1,287 @@
+// MBR and GPT partition tables.
+//
+// `read_partitions` looks at LBA 0 of a device. A protective MBR (type 0xEE)
+// means the real table is a GPT at LBA 1, checked by CRC and replaced by the
+// backup copy at the last LBA if damaged. Otherwise the four MBR slots are
+// read, following the EBR chain of an extended partition for logical
+// partitions. A sector 0 that is itself a FAT boot sector is an unpartitioned
+// volume and has no table.
+//
+// `Partition` turns one entry back into a `BlockDevice` whose LBA 0 is the
+// partition's first sector, so `FileSystem::mount` works on it unchanged.
+
+use crate::fs::block_device::{BlockDevice, IoError};
+use crate::fs::boot_sector::BootSector;
+use crate::fs::fat_constants::{BOOT_SIG_LEAD, BOOT_SIG_OFFSET, BOOT_SIG_TRAIL};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+const MBR_TYPES_FAT: [u8; 8] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E, 0x1B, 0xEF];
+/// Logical partitions followed before the EBR chain is considered looped.
+const MAX_LOGICAL: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Entries array bigger than this is rejected rather than read.
+const GPT_MAX_ENTRY_BYTES: u64 = 128 * 1024;
+
+/// Microsoft basic data partition, as stored on disk (mixed-endian).
+pub const GUID_BASIC_DATA: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+/// EFI system partition, as stored on disk (mixed-endian).
+pub const GUID_EFI_SYSTEM: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError {
+    Io(IoError),
+    /// Sector 0 has no MBR signature, or is an unpartitioned FAT volume.
+    NoTable,
+    /// A GPT header or entry array failed validation (both copies).
+    BadGpt(&'static str),
+    /// The partition does not fit on the device.
+    OutOfBounds,
+}
+
+impl From<IoError> for PartitionError {
+    fn from(e: IoError) -> Self { PartitionError::Io(e) }
+}
+
+impl fmt::Display for PartitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionError::Io(e) => write!(f, "{}", e),
+            PartitionError::NoTable => write!(f, "no partition table"),
+            PartitionError::BadGpt(what) => write!(f, "bad GPT: {}", what),
+            PartitionError::OutOfBounds => write!(f, "partition extends past the end of the device"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// MBR system ID byte.
+    Mbr(u8),
+    /// GPT partition type GUID, in on-disk byte order.
+    Gpt { type_guid: [u8; 16], name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// MBR slot (0-3, logical partitions from 4) or GPT entry index.
+    pub index: usize,
+    pub start_lba: u64,
+    pub sector_count: u64,
+    pub kind: PartitionKind,
+}
+
+impl PartitionInfo {
+    /// True for partition types that normally hold a FAT volume.
+    pub fn is_fat(&self) -> bool {
+        match &self.kind {
+            PartitionKind::Mbr(t) => MBR_TYPES_FAT.contains(t),
+            PartitionKind::Gpt { type_guid, .. } => *type_guid == GUID_BASIC_DATA || *type_guid == GUID_EFI_SYSTEM,
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3, reflected), as used for GPT headers and entries.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn le32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([b[off], b[off + 1], b[off + 2], b[off + 3]])
+}
+
+fn le64(b: &[u8], off: usize) -> u64 {
+    le32(b, off) as u64 | (le32(b, off + 4) as u64) << 32
+}
+
+/// True if `sector` is a FAT boot sector rather than an MBR. MBR boot code
+/// often puts nonzero bytes where the BPB would be, so the jump, the sector
+/// and cluster sizes and the media byte must all look like FAT.
+fn is_fat_boot_sector(sector: &[u8]) -> bool {
+    let jump = (sector[0] == 0xEB && sector[2] == 0x90) || sector[0] == 0xE9;
+    let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
+    let sectors_per_cluster = sector[13];
+    let media = sector[21];
+    jump
+        && bytes_per_sector.is_power_of_two()
+        && (512..=4096).contains(&bytes_per_sector)
+        && sectors_per_cluster.is_power_of_two()
+        && (media == 0xF0 || media >= 0xF8)
+        && BootSector::parse(sector).is_ok()
+}
+
+/// Read the partition table of `device`.
+pub fn read_partitions<D: BlockDevice>(device: &mut D) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let mut mbr = [0u8; 512];
+    device.read_sector(0, &mut mbr)?;
+    if mbr[BOOT_SIG_OFFSET] != BOOT_SIG_LEAD || mbr[BOOT_SIG_OFFSET + 1] != BOOT_SIG_TRAIL {
+        return Err(PartitionError::NoTable);
+    }
+    let slots: Vec<&[u8]> = (0..4).map(|i| &mbr[MBR_TABLE_OFFSET + i * 16..MBR_TABLE_OFFSET + (i + 1) * 16]).collect();
+    if slots.iter().any(|s| s[4] == MBR_TYPE_GPT_PROTECTIVE) {
+        return read_gpt(device);
+    }
+    if is_fat_boot_sector(&mbr) {
+        return Err(PartitionError::NoTable);
+    }
+    let mut parts = Vec::new();
+    for (i, slot) in slots.iter().enumerate() {
+        let kind = slot[4];
+        let (start, count) = (le32(slot, 8) as u64, le32(slot, 12) as u64);
+        if kind == 0 || count == 0 { continue; }
+        if slot[0] != 0x00 && slot[0] != 0x80 { return Err(PartitionError::NoTable); }
+        if MBR_TYPES_EXTENDED.contains(&kind) {
+            read_logical(device, start, &mut parts)?;
+        } else {
+            parts.push(PartitionInfo { index: i, start_lba: start, sector_count: count, kind: PartitionKind::Mbr(kind) });
+        }
+    }
+    Ok(parts)
+}
+
+/// Follow the EBR chain of the extended partition at `ext_start`. Each EBR
+/// describes one logical partition relative to itself, and the next EBR
+/// relative to the start of the extended partition.
+fn read_logical<D: BlockDevice>(
+    device: &mut D,
+    ext_start: u64,
+    parts: &mut Vec<PartitionInfo>,
+) -> Result<(), PartitionError> {
+    let mut ebr_lba = ext_start;
+    let mut buf = [0u8; 512];
+    for n in 0..MAX_LOGICAL {
+        device.read_sector(ebr_lba, &mut buf)?;
+        if buf[BOOT_SIG_OFFSET] != BOOT_SIG_LEAD || buf[BOOT_SIG_OFFSET + 1] != BOOT_SIG_TRAIL { break; }
+        let entry = &buf[MBR_TABLE_OFFSET..MBR_TABLE_OFFSET + 16];
+        if entry[4] != 0 && le32(entry, 12) != 0 {
+            parts.push(PartitionInfo {
+                index: 4 + n,
+                start_lba: ebr_lba + le32(entry, 8) as u64,
+                sector_count: le32(entry, 12) as u64,
+                kind: PartitionKind::Mbr(entry[4]),
+            });
+        }
+        let next = &buf[MBR_TABLE_OFFSET + 16..MBR_TABLE_OFFSET + 32];
+        if next[4] == 0 || le32(next, 8) == 0 { break; }
+        ebr_lba = ext_start + le32(next, 8) as u64;
+    }
+    Ok(())
+}
+
+fn read_gpt<D: BlockDevice>(device: &mut D) -> Result<Vec<PartitionInfo>, PartitionError> {
+    match read_gpt_at(device, 1) {
+        Ok(parts) => Ok(parts),
+        Err(PartitionError::BadGpt(what)) => {
+            let last = device.sector_count().saturating_sub(1);
+            // report the primary's problem if the backup is no better
+            read_gpt_at(device, last).map_err(|_| PartitionError::BadGpt(what))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Parse the GPT header at `lba` and its entry array.
+fn read_gpt_at<D: BlockDevice>(device: &mut D, lba: u64) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let mut header = [0u8; 512];
+    device.read_sector(lba, &mut header)?;
+    if &header[0..8] != GPT_SIGNATURE { return Err(PartitionError::BadGpt("missing signature")); }
+    let header_size = le32(&header, 12) as usize;
+    if !(92..=512).contains(&header_size) { return Err(PartitionError::BadGpt("bad header size")); }
+    let stored_crc = le32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != stored_crc { return Err(PartitionError::BadGpt("header CRC mismatch")); }
+    if le64(&header, 24) != lba { return Err(PartitionError::BadGpt("header LBA mismatch")); }
+
+    let entries_lba = le64(&header, 72);
+    let entry_count = le32(&header, 80) as u64;
+    let entry_size = le32(&header, 84) as u64;
+    if entry_size < 128 || entry_size & 7 != 0 { return Err(PartitionError::BadGpt("bad entry size")); }
+    let total = entry_count * entry_size;
+    if total > GPT_MAX_ENTRY_BYTES { return Err(PartitionError::BadGpt("entry array too large")); }
+    let mut entries = vec![0u8; total.div_ceil(512) as usize * 512];
+    for (i, sector) in entries.chunks_exact_mut(512).enumerate() {
+        device.read_sector(entries_lba + i as u64, sector)?;
+    }
+    if crc32(&entries[..total as usize]) != le32(&header, 88) {
+        return Err(PartitionError::BadGpt("entry array CRC mismatch"));
+    }
+
+    let mut parts = Vec::new();
+    for (i, entry) in entries[..total as usize].chunks_exact(entry_size as usize).enumerate() {
+        let mut type_guid = [0u8; 16];
+        type_guid.copy_from_slice(&entry[0..16]);
+        if type_guid == [0u8; 16] { continue; }
+        let (first, last) = (le64(entry, 32), le64(entry, 40));
+        if last < first { return Err(PartitionError::BadGpt("entry ends before it starts")); }
+        // UTF-16LE name, NUL padded
+        let units = entry[56..128].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).take_while(|&u| u != 0);
+        let name = char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect();
+        parts.push(PartitionInfo {
+            index: i,
+            start_lba: first,
+            sector_count: last - first + 1,
+            kind: PartitionKind::Gpt { type_guid, name },
+        });
+    }
+    Ok(parts)
+}
+
+/// First partition whose type normally holds a FAT volume.
+pub fn find_fat_partition<D: BlockDevice>(device: &mut D) -> Result<Option<PartitionInfo>, PartitionError> {
+    Ok(read_partitions(device)?.into_iter().find(|p| p.is_fat()))
+}
+
+/// One partition of `D`, addressed from its own first sector.
+pub struct Partition<D: BlockDevice> {
+    device: D,
+    start_lba: u64,
+    sectors: u64,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    /// View `info` on `device`, checking that it fits.
+    pub fn new(device: D, info: &PartitionInfo) -> Result<Self, PartitionError> {
+        let end = info.start_lba.checked_add(info.sector_count).ok_or(PartitionError::OutOfBounds)?;
+        if end > device.sector_count() { return Err(PartitionError::OutOfBounds); }
+        Ok(Partition { device, start_lba: info.start_lba, sectors: info.sector_count })
+    }
+
+    pub fn start_lba(&self) -> u64 { self.start_lba }
+
+    pub fn into_inner(self) -> D { self.device }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        if lba >= self.sectors { return Err(IoError::OutOfRange); }
+        self.device.read_sector(self.start_lba + lba, buf)
+    }
+
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if lba >= self.sectors { return Err(IoError::OutOfRange); }
+        self.device.write_sector(self.start_lba + lba, data)
+    }
+
+    fn sector_count(&self) -> u64 { self.sectors }
+
+    fn flush(&mut self) -> Result<(), IoError> { self.device.flush() }
+
+    fn is_read_only(&self) -> bool { self.device.is_read_only() }
+}
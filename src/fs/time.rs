@@ -0,0 +1,91 @@
+// Dates and times as FAT stores them, and the clock the file system stamps
+// directory entries with.
+//
+// FAT packs a date into 16 bits (years since 1980, month, day) and a time
+// into 16 bits with two-second resolution; the creation time has an extra
+// byte of hundredths to recover the odd second. The kernel installs the RTC
+// as the clock during `init`; until then (and in tests that do not set one)
+// every stamp is the FAT epoch, 1980-01-01 00:00:00.
+
+use core::fmt;
+use spin::Mutex;
+
+const FAT_YEAR_MIN: u16 = 1980;
+const FAT_YEAR_MAX: u16 = 2107;
+
+/// Calendar date and time of day. A `month` of 0 means "not set", which is
+/// how a zero FAT date field decodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub const FAT_EPOCH: DateTime = DateTime { year: 1980, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+
+    pub fn is_set(&self) -> bool { self.month != 0 }
+
+    /// Packed FAT date, or 0 if unset. Years outside 1980-2107 are clamped.
+    pub fn fat_date(&self) -> u16 {
+        if !self.is_set() { return 0; }
+        let year = self.year.clamp(FAT_YEAR_MIN, FAT_YEAR_MAX) - FAT_YEAR_MIN;
+        year << 9 | (self.month as u16 & 0x0F) << 5 | (self.day as u16 & 0x1F)
+    }
+
+    /// Packed FAT time (two-second resolution).
+    pub fn fat_time(&self) -> u16 {
+        (self.hour as u16 & 0x1F) << 11 | (self.minute as u16 & 0x3F) << 5 | (self.second as u16 / 2)
+    }
+
+    /// Hundredths of a second past `fat_time`, for the creation time field.
+    pub fn fat_hundredths(&self) -> u8 { (self.second % 2) * 100 }
+
+    /// Decode FAT fields. A zero date gives an unset `DateTime`.
+    pub fn from_fat(date: u16, time: u16, hundredths: u8) -> Self {
+        if date == 0 { return DateTime::default(); }
+        DateTime {
+            year: FAT_YEAR_MIN + (date >> 9),
+            month: (date >> 5 & 0x0F) as u8,
+            day: (date & 0x1F) as u8,
+            hour: (time >> 11) as u8,
+            minute: (time >> 5 & 0x3F) as u8,
+            second: (time & 0x1F) as u8 * 2 + (hundredths >= 100) as u8,
+        }
+    }
+
+    /// Same day, midnight: what an access date (which has no time) holds.
+    pub fn date_only(&self) -> Self {
+        DateTime { hour: 0, minute: 0, second: 0, ..*self }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_set() { return write!(f, "---------- --:--:--"); }
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn epoch_clock() -> DateTime { DateTime::FAT_EPOCH }
+
+static CLOCK: Mutex<fn() -> DateTime> = Mutex::new(epoch_clock);
+
+/// Use `clock` for every timestamp written from now on.
+pub fn set_clock(clock: fn() -> DateTime) {
+    *CLOCK.lock() = clock;
+}
+
+/// Current time according to the installed clock.
+pub fn now() -> DateTime {
+    let clock = *CLOCK.lock();
+    clock()
+}
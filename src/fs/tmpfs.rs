@@ -0,0 +1,301 @@
+// In-memory file system.
+//
+// Files and directories live on the heap as nodes in a single table, each
+// holding its name, parent, attributes, timestamps and either its bytes or
+// the indices of its children. Names are case-sensitive and may be anything
+// up to 255 bytes except `.`, `..` and names containing `/` or NUL. Nothing
+// is ever written to a device, so a `Tmpfs` suits scratch space and a
+// boot-time root; it plugs into a `Vfs` like a FAT `FileSystem` does.
+
+use crate::fs::directory::{DirectoryEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, ATTR_USER_MASK};
+use crate::fs::fs::{FsError, FsStat};
+use crate::fs::time::{self, DateTime};
+use crate::fs::vfs::{self, FileSystemOps};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const MAX_NAME_LEN: usize = 255;
+const ROOT: usize = 0;
+
+enum NodeKind {
+    File(Vec<u8>),
+    Dir(Vec<usize>),
+}
+
+struct Node {
+    name: String,
+    parent: usize,
+    attr: u8,
+    created: DateTime,
+    modified: DateTime,
+    kind: NodeKind,
+}
+
+pub struct Tmpfs {
+    // index 0 is the root; `None` marks a slot freed by a delete
+    nodes: Vec<Option<Node>>,
+    capacity: u64,
+    used: u64,
+}
+
+impl Tmpfs {
+    /// An empty file system that holds at most `capacity` bytes of file data.
+    pub fn new(capacity: u64) -> Self {
+        let now = time::now();
+        let root = Node {
+            name: String::new(),
+            parent: ROOT,
+            attr: ATTR_DIRECTORY,
+            created: now,
+            modified: now,
+            kind: NodeKind::Dir(Vec::new()),
+        };
+        Tmpfs { nodes: alloc::vec![Some(root)], capacity, used: 0 }
+    }
+
+    /// Bytes of file data stored.
+    pub fn used_bytes(&self) -> u64 { self.used }
+
+    fn node(&self, index: usize) -> &Node {
+        self.nodes[index].as_ref().expect("tmpfs: dangling node")
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut Node {
+        self.nodes[index].as_mut().expect("tmpfs: dangling node")
+    }
+
+    fn children(&self, dir: usize) -> Result<&[usize], FsError> {
+        match &self.node(dir).kind {
+            NodeKind::Dir(children) => Ok(children),
+            NodeKind::File(_) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn child(&self, dir: usize, name: &str) -> Result<Option<usize>, FsError> {
+        Ok(self.children(dir)?.iter().copied().find(|&c| self.node(c).name == name))
+    }
+
+    /// Node at `path`.
+    fn lookup(&self, path: &str) -> Result<usize, FsError> {
+        let mut cur = ROOT;
+        for part in vfs::normalize(path) {
+            cur = self.child(cur, part)?.ok_or(FsError::FileNotFound)?;
+        }
+        Ok(cur)
+    }
+
+    /// Directory that holds (or would hold) `path`, and the last component.
+    fn lookup_parent<'p>(&self, path: &'p str) -> Result<(usize, &'p str), FsError> {
+        let mut parts = vfs::normalize(path);
+        let name = parts.pop().ok_or(FsError::InvalidName)?;
+        let mut dir = ROOT;
+        for part in parts {
+            dir = self.child(dir, part)?.ok_or(FsError::FileNotFound)?;
+        }
+        self.children(dir)?;
+        Ok((dir, name))
+    }
+
+    fn file_data(&self, index: usize) -> Result<&Vec<u8>, FsError> {
+        match &self.node(index).kind {
+            NodeKind::File(data) => Ok(data),
+            NodeKind::Dir(_) => Err(FsError::IsADirectory),
+        }
+    }
+
+    /// Account for a file growing from `old` to `new` bytes.
+    fn reserve(&mut self, old: usize, new: usize) -> Result<(), FsError> {
+        let used = self.used - old as u64 + new as u64;
+        if used > self.capacity { return Err(FsError::NoSpace); }
+        self.used = used;
+        Ok(())
+    }
+
+    /// Fail unless `name` is a valid name that is not yet used in `dir`.
+    fn check_new(&self, dir: usize, name: &str) -> Result<(), FsError> {
+        check_name(name)?;
+        match self.child(dir, name)? {
+            Some(existing) if self.node(existing).attr & ATTR_READ_ONLY != 0 => Err(FsError::ReadOnly),
+            Some(_) => Err(FsError::FileAlreadyExists),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a node under `parent`; the name must have passed `check_new`.
+    fn insert(&mut self, parent: usize, name: &str, attr: u8, kind: NodeKind) {
+        let now = time::now();
+        let node = Node { name: String::from(name), parent, attr, created: now, modified: now, kind };
+        let index = match self.nodes.iter().position(|n| n.is_none()) {
+            Some(free) => {
+                self.nodes[free] = Some(node);
+                free
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        if let NodeKind::Dir(children) = &mut self.node_mut(parent).kind { children.push(index); }
+        self.node_mut(parent).modified = now;
+    }
+
+    /// Unlink `index` from its parent and free its slot.
+    fn remove(&mut self, index: usize) {
+        let parent = self.node(index).parent;
+        let parent_node = self.node_mut(parent);
+        if let NodeKind::Dir(children) = &mut parent_node.kind { children.retain(|&c| c != index); }
+        parent_node.modified = time::now();
+        self.nodes[index] = None;
+    }
+
+    fn entry(&self, index: usize) -> DirectoryEntry {
+        let node = self.node(index);
+        let mut entry = DirectoryEntry::empty();
+        entry.name = [b' '; 8];
+        entry.ext = [b' '; 3];
+        entry.attr = node.attr;
+        entry.created = node.created;
+        entry.modified = node.modified;
+        entry.accessed = node.modified.date_only();
+        entry.file_size = match &node.kind {
+            NodeKind::File(data) => data.len() as u32,
+            NodeKind::Dir(_) => 0,
+        };
+        entry.long_name = Some(node.name.clone());
+        entry
+    }
+}
+
+impl FileSystemOps for Tmpfs {
+    fn fs_type(&self) -> &'static str { "tmpfs" }
+
+    /// Entries in creation order. Unlike FAT there are no `.` and `..`
+    /// entries.
+    fn list_dir(&mut self, path: &str) -> Result<Vec<DirectoryEntry>, FsError> {
+        let dir = self.lookup(path)?;
+        Ok(self.children(dir)?.iter().map(|&c| self.entry(c)).collect())
+    }
+
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        let index = self.lookup(path)?;
+        Ok(self.file_data(index)?.clone())
+    }
+
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let (dir, name) = self.lookup_parent(path)?;
+        self.check_new(dir, name)?;
+        self.reserve(0, data.len())?;
+        self.insert(dir, name, ATTR_ARCHIVE, NodeKind::File(Vec::from(data)));
+        Ok(())
+    }
+
+    fn overwrite(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let index = self.lookup(path)?;
+        let old = self.file_data(index)?.len();
+        if self.node(index).attr & ATTR_READ_ONLY != 0 { return Err(FsError::ReadOnly); }
+        self.reserve(old, data.len())?;
+        let node = self.node_mut(index);
+        node.kind = NodeKind::File(Vec::from(data));
+        node.attr |= ATTR_ARCHIVE;
+        node.modified = time::now();
+        Ok(())
+    }
+
+    fn delete(&mut self, path: &str) -> Result<(), FsError> {
+        let index = self.lookup(path)?;
+        let size = self.file_data(index)?.len();
+        if self.node(index).attr & ATTR_READ_ONLY != 0 { return Err(FsError::ReadOnly); }
+        self.remove(index);
+        self.used -= size as u64;
+        Ok(())
+    }
+
+    fn rename(&mut self, old: &str, new: &str) -> Result<(), FsError> {
+        let index = self.lookup(old)?;
+        if index == ROOT { return Err(FsError::InvalidName); }
+        let (new_dir, new_name) = self.lookup_parent(new)?;
+        check_name(new_name)?;
+        // a directory cannot be moved into its own subtree
+        let mut cur = new_dir;
+        while cur != ROOT {
+            if cur == index { return Err(FsError::InvalidName); }
+            cur = self.node(cur).parent;
+        }
+        match self.child(new_dir, new_name)? {
+            Some(existing) if existing == index => {}
+            Some(_) => return Err(FsError::FileAlreadyExists),
+            None => {}
+        }
+        let old_dir = self.node(index).parent;
+        if old_dir != new_dir {
+            let now = time::now();
+            if let NodeKind::Dir(children) = &mut self.node_mut(old_dir).kind { children.retain(|&c| c != index); }
+            self.node_mut(old_dir).modified = now;
+            if let NodeKind::Dir(children) = &mut self.node_mut(new_dir).kind { children.push(index); }
+            self.node_mut(new_dir).modified = now;
+        }
+        // a rename is not a modification: the node keeps its stamps
+        let node = self.node_mut(index);
+        node.parent = new_dir;
+        node.name = String::from(new_name);
+        Ok(())
+    }
+
+    fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        let (dir, name) = self.lookup_parent(path)?;
+        self.check_new(dir, name)?;
+        self.insert(dir, name, ATTR_DIRECTORY, NodeKind::Dir(Vec::new()));
+        Ok(())
+    }
+
+    fn rmdir(&mut self, path: &str) -> Result<(), FsError> {
+        let index = self.lookup(path)?;
+        if index == ROOT { return Err(FsError::InvalidName); }
+        if !self.children(index)?.is_empty() { return Err(FsError::DirectoryNotEmpty); }
+        self.remove(index);
+        Ok(())
+    }
+
+    /// The full attribute byte, `ATTR_DIRECTORY` included, as on FAT.
+    fn attributes(&mut self, path: &str) -> Result<u8, FsError> {
+        let index = self.lookup(path)?;
+        Ok(self.node(index).attr)
+    }
+
+    /// Only the bits in `ATTR_USER_MASK` are kept.
+    fn set_attributes(&mut self, path: &str, attr: u8) -> Result<(), FsError> {
+        let index = self.lookup(path)?;
+        let node = self.node_mut(index);
+        node.attr = (node.attr & ATTR_DIRECTORY) | (attr & ATTR_USER_MASK);
+        Ok(())
+    }
+
+    /// Usage in bytes: a "cluster" here is a single byte.
+    fn stat(&mut self) -> Result<FsStat, FsError> {
+        let mut st = FsStat {
+            cluster_size: 1,
+            total_clusters: self.capacity.min(u32::MAX as u64) as u32,
+            free_clusters: (self.capacity - self.used).min(u32::MAX as u64) as u32,
+            used_clusters: self.used.min(u32::MAX as u64) as u32,
+            total_bytes: self.capacity,
+            free_bytes: self.capacity - self.used,
+            used_bytes: self.used,
+            ..FsStat::default()
+        };
+        for (index, node) in self.nodes.iter().enumerate() {
+            match node {
+                Some(Node { kind: NodeKind::File(_), .. }) => st.files += 1,
+                Some(Node { kind: NodeKind::Dir(_), .. }) if index != ROOT => st.directories += 1,
+                _ => {}
+            }
+        }
+        Ok(st)
+    }
+}
+
+fn check_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." || name.len() > MAX_NAME_LEN || name.contains(['/', '\0']) {
+        return Err(FsError::InvalidName);
+    }
+    Ok(())
+}
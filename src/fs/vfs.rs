@@ -0,0 +1,284 @@
+// Virtual file system: a mount table over any number of file systems.
+//
+// Each backend implements `FileSystemOps`, the whole-file operations the
+// shell and kernel use. `Vfs` maps absolute mount points (`/`, `/mnt/disk1`)
+// to backends and resolves a path by normalising it (`.`, `..` and repeated
+// slashes) and picking the mount with the longest matching prefix; the rest
+// of the path goes to that backend relative to its own root. Directory
+// listings show mount points as directories even when the parent file
+// system has no entry for them, and a file renamed across mounts is copied
+// and then deleted.
+
+use crate::fs::block_device::BlockDevice;
+use crate::fs::check::{self, CheckReport};
+use crate::fs::directory::{DirectoryEntry, ATTR_DIRECTORY};
+use crate::fs::fs::{FileSystem, FsError, FsStat};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Operations every mountable file system provides. Paths are relative to
+/// the backend's own root; a leading `/` is allowed.
+pub trait FileSystemOps {
+    /// Short type name shown by `mount`, e.g. "fat".
+    fn fs_type(&self) -> &'static str;
+    fn list_dir(&mut self, path: &str) -> Result<Vec<DirectoryEntry>, FsError>;
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsError>;
+    /// Create a new file; fails with `FileAlreadyExists` if `path` exists.
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError>;
+    /// Replace the contents of an existing file.
+    fn overwrite(&mut self, path: &str, data: &[u8]) -> Result<(), FsError>;
+    fn delete(&mut self, path: &str) -> Result<(), FsError>;
+    fn rename(&mut self, old: &str, new: &str) -> Result<(), FsError>;
+    fn mkdir(&mut self, path: &str) -> Result<(), FsError>;
+    fn rmdir(&mut self, path: &str) -> Result<(), FsError>;
+    fn attributes(&mut self, path: &str) -> Result<u8, FsError>;
+    fn set_attributes(&mut self, path: &str, attr: u8) -> Result<(), FsError>;
+    fn stat(&mut self) -> Result<FsStat, FsError>;
+    /// Write out anything cached. Called before the backend is unmounted.
+    fn flush(&mut self) -> Result<(), FsError> { Ok(()) }
+    /// Consistency check, for file systems that have one.
+    fn check(&mut self, _repair: bool) -> Result<CheckReport, FsError> { Err(FsError::Unsupported) }
+}
+
+impl<'a, D: BlockDevice> FileSystemOps for FileSystem<'a, D> {
+    fn fs_type(&self) -> &'static str { "fat" }
+    fn list_dir(&mut self, path: &str) -> Result<Vec<DirectoryEntry>, FsError> { FileSystem::list_dir(self, path) }
+    fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsError> { FileSystem::read_file(self, path) }
+    fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> { FileSystem::write_file(self, path, data) }
+    fn overwrite(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> { FileSystem::overwrite(self, path, data) }
+    fn delete(&mut self, path: &str) -> Result<(), FsError> { FileSystem::delete(self, path) }
+    fn rename(&mut self, old: &str, new: &str) -> Result<(), FsError> { FileSystem::rename(self, old, new) }
+    fn mkdir(&mut self, path: &str) -> Result<(), FsError> { FileSystem::mkdir(self, path) }
+    fn rmdir(&mut self, path: &str) -> Result<(), FsError> { FileSystem::rmdir(self, path) }
+    fn attributes(&mut self, path: &str) -> Result<u8, FsError> { FileSystem::attributes(self, path) }
+    fn set_attributes(&mut self, path: &str, attr: u8) -> Result<(), FsError> { FileSystem::set_attributes(self, path, attr) }
+    fn stat(&mut self) -> Result<FsStat, FsError> { FileSystem::stat(self) }
+    fn flush(&mut self) -> Result<(), FsError> { FileSystem::flush(self) }
+    fn check(&mut self, repair: bool) -> Result<CheckReport, FsError> { check::check(self, repair) }
+}
+
+struct Mount<'a> {
+    /// Normalised absolute path, e.g. "/" or "/mnt/disk1".
+    path: String,
+    fs: Box<dyn FileSystemOps + Send + 'a>,
+}
+
+/// A mounted file system as reported by `Vfs::mounts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub path: String,
+    pub fs_type: &'static str,
+}
+
+pub struct Vfs<'a> {
+    mounts: Vec<Mount<'a>>,
+}
+
+impl<'a> Default for Vfs<'a> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<'a> Vfs<'a> {
+    pub const fn new() -> Self {
+        Vfs { mounts: Vec::new() }
+    }
+
+    /// Attach `fs` at `path`. The mount point does not have to exist in the
+    /// parent file system; it shows up in listings either way.
+    pub fn mount(&mut self, path: &str, fs: Box<dyn FileSystemOps + Send + 'a>) -> Result<(), FsError> {
+        let path = join(&normalize(path));
+        if self.mounts.iter().any(|m| m.path == path) { return Err(FsError::Busy); }
+        self.mounts.push(Mount { path, fs });
+        Ok(())
+    }
+
+    /// Detach and return the file system mounted at `path`, flushing it
+    /// first. Fails with `Busy` while another file system is mounted below it.
+    pub fn unmount(&mut self, path: &str) -> Result<Box<dyn FileSystemOps + Send + 'a>, FsError> {
+        let parts = normalize(path);
+        let path = join(&parts);
+        let index = self.mounts.iter().position(|m| m.path == path).ok_or(FsError::FileNotFound)?;
+        if self.mounts.iter().any(|m| m.path != path && is_prefix(&parts, &normalize(&m.path))) {
+            return Err(FsError::Busy);
+        }
+        self.mounts[index].fs.flush()?;
+        Ok(self.mounts.remove(index).fs)
+    }
+
+    /// Mount points in the order they were mounted.
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        self.mounts.iter().map(|m| MountInfo { path: m.path.clone(), fs_type: m.fs.fs_type() }).collect()
+    }
+
+    /// The backend that owns `path` and the path within it.
+    fn resolve(&mut self, path: &str) -> Result<(&mut (dyn FileSystemOps + Send + 'a), String), FsError> {
+        let (index, rest) = self.locate(path)?;
+        Ok((&mut *self.mounts[index].fs, rest))
+    }
+
+    fn locate(&self, path: &str) -> Result<(usize, String), FsError> {
+        let parts = normalize(path);
+        let mut best: Option<(usize, usize)> = None;
+        for (i, m) in self.mounts.iter().enumerate() {
+            let mount_parts = normalize(&m.path);
+            if is_prefix(&mount_parts, &parts) && best.is_none_or(|(_, len)| mount_parts.len() > len) {
+                best = Some((i, mount_parts.len()));
+            }
+        }
+        let (index, depth) = best.ok_or(FsError::FileNotFound)?;
+        Ok((index, join(&parts[depth..])))
+    }
+
+    /// Fail with `Busy` if `path` is a mount point or contains one.
+    fn check_not_mount_point(&self, path: &str) -> Result<(), FsError> {
+        let parts = normalize(path);
+        if self.mounts.iter().any(|m| is_prefix(&parts, &normalize(&m.path))) {
+            return Err(FsError::Busy);
+        }
+        Ok(())
+    }
+
+    /// List the directory at `path`, adding the mount points below it (or the
+    /// directories leading to them) as directory entries.
+    pub fn list_dir(&mut self, path: &str) -> Result<Vec<DirectoryEntry>, FsError> {
+        let parts = normalize(path);
+        // first component below `path` of every mount point under it
+        let mut children: Vec<String> = Vec::new();
+        for m in self.mounts.iter() {
+            let mount_parts = normalize(&m.path);
+            if mount_parts.len() > parts.len() && is_prefix(&parts, &mount_parts) {
+                let name = mount_parts[parts.len()];
+                if !children.iter().any(|c| c == name) { children.push(String::from(name)); }
+            }
+        }
+        let (fs, rest) = self.resolve(path)?;
+        let mut list = match fs.list_dir(&rest) {
+            Ok(list) => list,
+            // a directory that only exists to hold mount points
+            Err(FsError::FileNotFound) if !children.is_empty() => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        for name in children {
+            if !list.iter().any(|e| e.matches(&name)) {
+                let mut entry = DirectoryEntry::empty();
+                entry.attr = ATTR_DIRECTORY;
+                entry.long_name = Some(name);
+                list.push(entry);
+            }
+        }
+        Ok(list)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        let (fs, rest) = self.resolve(path)?;
+        fs.read_file(&rest)
+    }
+
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let (fs, rest) = self.resolve(path)?;
+        fs.write_file(&rest, data)
+    }
+
+    pub fn overwrite(&mut self, path: &str, data: &[u8]) -> Result<(), FsError> {
+        let (fs, rest) = self.resolve(path)?;
+        fs.overwrite(&rest, data)
+    }
+
+    pub fn delete(&mut self, path: &str) -> Result<(), FsError> {
+        self.check_not_mount_point(path)?;
+        let (fs, rest) = self.resolve(path)?;
+        fs.delete(&rest)
+    }
+
+    /// Rename or move `old` to `new`. Within one file system this is the
+    /// backend's rename; across mounts only files can move, by copying the
+    /// contents and attributes and then deleting the original.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<(), FsError> {
+        self.check_not_mount_point(old)?;
+        let (from, old_rest) = self.locate(old)?;
+        let (to, new_rest) = self.locate(new)?;
+        if from == to {
+            return self.mounts[from].fs.rename(&old_rest, &new_rest);
+        }
+        let src = &mut self.mounts[from].fs;
+        let attr = src.attributes(&old_rest)?;
+        if attr & ATTR_DIRECTORY != 0 { return Err(FsError::CrossDevice); }
+        let data = src.read_file(&old_rest)?;
+        self.mounts[to].fs.write_file(&new_rest, &data)?;
+        if let Err(e) = self.mounts[from].fs.delete(&old_rest) {
+            self.mounts[to].fs.delete(&new_rest).ok();
+            return Err(e);
+        }
+        self.mounts[to].fs.set_attributes(&new_rest, attr)
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        let (fs, rest) = self.resolve(path)?;
+        fs.mkdir(&rest)
+    }
+
+    pub fn rmdir(&mut self, path: &str) -> Result<(), FsError> {
+        self.check_not_mount_point(path)?;
+        let (fs, rest) = self.resolve(path)?;
+        fs.rmdir(&rest)
+    }
+
+    pub fn attributes(&mut self, path: &str) -> Result<u8, FsError> {
+        let (fs, rest) = self.resolve(path)?;
+        fs.attributes(&rest)
+    }
+
+    pub fn set_attributes(&mut self, path: &str, attr: u8) -> Result<(), FsError> {
+        let (fs, rest) = self.resolve(path)?;
+        fs.set_attributes(&rest, attr)
+    }
+
+    /// Usage of the file system that holds `path`.
+    pub fn stat(&mut self, path: &str) -> Result<FsStat, FsError> {
+        self.resolve(path)?.0.stat()
+    }
+
+    /// Check the file system that holds `path`.
+    pub fn check(&mut self, path: &str, repair: bool) -> Result<CheckReport, FsError> {
+        self.resolve(path)?.0.check(repair)
+    }
+
+    /// Flush every mounted file system, returning the first error.
+    pub fn flush(&mut self) -> Result<(), FsError> {
+        let mut result = Ok(());
+        for m in self.mounts.iter_mut() {
+            let flushed = m.fs.flush();
+            if result.is_ok() { result = flushed; }
+        }
+        result
+    }
+}
+
+/// Split `path` into components, resolving `.` and `..`. Relative paths are
+/// taken from the root; `..` at the root stays there.
+pub fn normalize(path: &str) -> Vec<&str> {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => { parts.pop(); }
+            _ => parts.push(part),
+        }
+    }
+    parts
+}
+
+fn join(parts: &[&str]) -> String {
+    if parts.is_empty() { return String::from("/"); }
+    let mut s = String::new();
+    for part in parts {
+        s.push('/');
+        s.push_str(part);
+    }
+    s
+}
+
+fn is_prefix(prefix: &[&str], parts: &[&str]) -> bool {
+    prefix.len() <= parts.len() && prefix.iter().zip(parts).all(|(a, b)| a == b)
+}
@@ -3,6 +3,7 @@ use lazy_static::lazy_static;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use pic8259::ChainedPics;
 use spin;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -15,8 +16,29 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    PrimaryAta = PIC_1_OFFSET + 14,
+    SecondaryAta,
 }
 
+/// Rate of the timer interrupt: the PIT left at its power-on divisor runs at
+/// 1193182 / 65536 Hz.
+pub const TIMER_HZ: u64 = 18;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Timer interrupts since boot.
+pub fn ticks() -> u64 { TICKS.load(Ordering::Relaxed) }
+
+/// IRQ lines that PCI devices are routed to. Their owners are only known at
+/// runtime, so drivers attach to them with `register_irq_handler`.
+const SHARED_IRQS: [u8; 4] = [5, 9, 10, 11];
+
+/// Handlers for one line. PCI interrupts are level-triggered and can be
+/// shared, so every handler on a line runs and checks its own device.
+type IrqLine = [Option<fn()>; 4];
+
+static SHARED_IRQ_HANDLERS: spin::Mutex<[IrqLine; 16]> = spin::Mutex::new([[None; 4]; 16]);
+
 impl InterruptIndex {
     fn as_u8(self) -> u8 {
         self as u8
@@ -40,6 +62,14 @@ lazy_static! {
             .set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::PrimaryAta.as_usize()]
+            .set_handler_fn(primary_ata_interrupt_handler);
+        idt[InterruptIndex::SecondaryAta.as_usize()]
+            .set_handler_fn(secondary_ata_interrupt_handler);
+        idt[usize::from(PIC_1_OFFSET + 5)].set_handler_fn(shared_interrupt_handler::<5>);
+        idt[usize::from(PIC_1_OFFSET + 9)].set_handler_fn(shared_interrupt_handler::<9>);
+        idt[usize::from(PIC_1_OFFSET + 10)].set_handler_fn(shared_interrupt_handler::<10>);
+        idt[usize::from(PIC_1_OFFSET + 11)].set_handler_fn(shared_interrupt_handler::<11>);
         idt.page_fault.set_handler_fn(page_fault_handler);
         idt
     };
@@ -49,6 +79,42 @@ pub fn init_idt() {
     IDT.load();
 }
 
+/// Unmask a legacy IRQ line (0-15) on the PICs. Lines on the secondary PIC
+/// also need the cascade line (IRQ 2) open.
+pub fn unmask_irq(irq: u8) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        let mut pics = PICS.lock();
+        let [mut mask1, mut mask2] = pics.read_masks();
+        if irq < 8 {
+            mask1 &= !(1 << irq);
+        } else {
+            mask1 &= !(1 << 2);
+            mask2 &= !(1 << (irq - 8));
+        }
+        pics.write_masks(mask1, mask2);
+    });
+}
+
+/// Run `handler` whenever `irq` fires and unmask the line. Returns false if
+/// the line cannot be shared this way or all its slots are taken.
+pub fn register_irq_handler(irq: u8, handler: fn()) -> bool {
+    if !SHARED_IRQS.contains(&irq) { return false; }
+    let registered = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut handlers = SHARED_IRQ_HANDLERS.lock();
+        let line = &mut handlers[irq as usize];
+        if line.contains(&Some(handler)) { return true; }
+        match line.iter_mut().find(|h| h.is_none()) {
+            Some(slot) => {
+                *slot = Some(handler);
+                true
+            }
+            None => false,
+        }
+    });
+    if registered { unmask_irq(irq); }
+    registered
+}
+
 extern "x86-interrupt" fn breakpoint_handler(
     stack_frame: InterruptStackFrame)
 {
@@ -66,6 +132,10 @@ extern "x86-interrupt" fn timer_interrupt_handler(
 {
     // print!(".");
     // uncomment if you want to see timer interrupts
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    // the network stack also polls on the tick, for frames that raised no
+    // interrupt and for reassembly timeouts
+    crate::network::wake();
 
     unsafe {
         PICS.lock()
@@ -88,6 +158,42 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     }
 }
 
+extern "x86-interrupt" fn primary_ata_interrupt_handler(
+    _stack_frame: InterruptStackFrame
+) {
+    crate::drivers::ata::handle_irq(crate::drivers::ata::Channel::Primary);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::PrimaryAta.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(
+    _stack_frame: InterruptStackFrame
+) {
+    crate::drivers::ata::handle_irq(crate::drivers::ata::Channel::Secondary);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn shared_interrupt_handler<const IRQ: u8>(
+    _stack_frame: InterruptStackFrame
+) {
+    let handlers = SHARED_IRQ_HANDLERS.lock()[IRQ as usize];
+    for handler in handlers.iter().flatten() {
+        handler();
+    }
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(PIC_1_OFFSET + IRQ);
+    }
+}
+
 extern "x86-interrupt" fn page_fault_handler (
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
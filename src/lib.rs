@@ -14,14 +14,21 @@ pub mod memory;
 pub mod serial;
 pub mod vga_buffer;
 pub mod fs;
+pub mod drivers;
 pub mod allocator;
 pub mod task;
+pub mod network;
 
 pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
     x86_64::instructions::interrupts::enable();
+    // PCI drivers the kernel knows, so devices can be listed with theirs
+    drivers::pci::register_driver(&drivers::virtio_blk::PCI_DRIVER);
+    drivers::pci::register_driver(&network::device::e1000::PCI_DRIVER);
+    // stamp directory entries with the CMOS clock
+    fs::time::set_clock(drivers::rtc::read);
 }
 pub trait Testable {
     fn run(&self) -> ();
@@ -38,6 +38,38 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     }
     println!("vec at {:p}", vec.as_slice());
 
+    // List the PCI functions and which registered driver handles each.
+    for (dev, driver, _) in rz_rust_os::drivers::pci::bound_devices() {
+        let a = dev.address;
+        println!(
+            "pci {:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}:{:02x} {}",
+            a.bus, a.device, a.function, dev.vendor_id, dev.device_id, dev.class, dev.subclass,
+            driver.map_or("-", |d| d.name)
+        );
+    }
+
+    // Bring up the e1000 QEMU attaches by default and hand it to the network
+    // stack with the user-mode network's addresses.
+    {
+        use rz_rust_os::network::{self, config::NetConfig, device::e1000::E1000, device::NetworkDevice};
+
+        match E1000::probe(&mut mapper, &mut frame_allocator, phys_mem_offset) {
+            Ok(Some(nic)) => {
+                let mac = nic.mac_addr();
+                println!(
+                    "e1000: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}, link {}",
+                    mac[0], mac[1], mac[2], mac[3], mac[4], mac[5],
+                    if nic.link_up() { "up" } else { "down" }
+                );
+                let config = NetConfig::qemu_user();
+                network::init(Box::new(nic), Some(config));
+                println!("network: {}", config);
+            }
+            Ok(None) => {}
+            Err(e) => println!("e1000 init failed: {:?}", e),
+        }
+    }
+
     // --- demo: use in-repo FS on an in-memory mock device ---
     {
         use rz_rust_os::fs::fs::FileSystem;
@@ -48,8 +80,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
             let buf = &mut FS_BUF[..];
             let mut dev = MockDevice::new(buf);
             // format the mock device as a FAT12 volume
-            let sectors = dev.sector_count() as u16;
-            FileSystem::format(&mut dev, sectors).expect("format failed");
+            FileSystem::format(&mut dev).expect("format failed");
             // mount
             let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
             // write two files
@@ -65,7 +96,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
                 println!("BAR => {}", s);
             }
             // list root
-            let list = fs.list_root();
+            let list = fs.list_root().expect("list root failed");
             println!("Root dir entries: {}", list.len());
             for e in list.iter() {
                 let mut nm = [0u8; 11];
@@ -92,36 +123,42 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut executor = Executor::new();
     executor.spawn(Task::new(example_task()));
     executor.spawn(Task::new(keyboard::print_keypresses()));
+    // answer ARP and collect IPv4 datagrams as frames arrive
+    if rz_rust_os::network::is_initialized() {
+        executor.spawn(Task::new(rz_rust_os::network::poll_task()));
+    }
     {
-        // Demo: create a leaked mock device + filesystem, register it with the
+        // Demo: mount an in-memory tmpfs at `/` (and a FAT-formatted IDE disk
+        // at `/mnt/disk1` if one is attached), hand the mount table to the
         // shell, and run a few shell commands programmatically to demonstrate
         // ls/read/write/delete.
         use rz_rust_os::task::shell;
-        use rz_rust_os::fs::mock_device::MockDevice;
+        use rz_rust_os::drivers::ata::{self, Channel};
         use rz_rust_os::fs::fs::FileSystem;
+        use rz_rust_os::fs::tmpfs::Tmpfs;
+        use rz_rust_os::fs::vfs::Vfs;
+
+        // The root lives on the heap; leave most of it for everything else.
+        let mut vfs = Vfs::new();
+        vfs.mount("/", Box::new(Tmpfs::new(32 * 1024))).expect("mount failed");
+
+        // The primary master holds the boot image; any other IDE disk with a
+        // FAT volume on it is mounted persistently.
+        for drive in ata::probe() {
+            if drive.channel() == Channel::Primary && !drive.is_slave() { continue; }
+            let disk = Box::leak(Box::new(drive));
+            if let Ok(fs) = FileSystem::mount(disk) {
+                if vfs.mount("/mnt/disk1", Box::new(fs)).is_ok() {
+                    println!("mounted FAT disk at /mnt/disk1");
+                }
+                break;
+            }
+        }
 
-        // Allocate a boxed buffer for the device and leak it to get a 'static
-        // slice for the MockDevice.
-        let boxed_buf = Box::new([0u8; 512 * 64]);
-        let leaked_buf: &'static mut [u8; 512 * 64] = Box::leak(boxed_buf);
-        let leaked_slice: &'static mut [u8] = &mut leaked_buf[..];
-
-        // Create a boxed MockDevice that borrows the leaked slice and leak it so
-        // we have a 'static device to hand to FileSystem.
-        let dev_box = Box::new(MockDevice::new(leaked_slice));
-        let shell_dev: &'static mut MockDevice = Box::leak(dev_box);
-
-        // Format and mount the filesystem on the leaked device.
-        let sectors = shell_dev.sector_count() as u16;
-        FileSystem::format(shell_dev, sectors).expect("format failed");
-        let fs_box = Box::new(FileSystem::mount(shell_dev).expect("mount failed"));
-        let shell_fs: &'static mut FileSystem<'static, MockDevice<'static>> = Box::leak(fs_box);
-
-
-        // Register the filesystem with the shell.
-        shell::new(shell_fs);
+        // Register the mount table with the shell.
+        shell::new(vfs);
         // Create a couple of files to demonstrate read/list/delete
-        shell::shell_input("write foo.txt Hello from leaked FS");
+        shell::shell_input("write foo.txt Hello from tmpfs");
         shell::shell_input("write bar.txt Second file contents");
         shell::shell_input("ls");
         shell::shell_input("read foo.txt");
@@ -2,9 +2,15 @@ use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 use x86_64::{
     PhysAddr, VirtAddr,
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        mapper::MapToError, FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB,
     },
 };
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Virtual window that device register BARs are mapped into, above the heap.
+pub const MMIO_START: u64 = 0x_6666_0000_0000;
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
 
 /// Initialize a new OffsetPageTable.
 ///
@@ -55,6 +61,80 @@ pub fn create_example_mapping(
     map_to_result.expect("map_to failed").flush();
 }
 
+/// Physically contiguous, zeroed frames that a device can DMA into, accessed
+/// through the bootloader's mapping of all physical memory. Regions are
+/// never freed.
+pub struct DmaRegion {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    len: usize,
+}
+
+impl DmaRegion {
+    /// Allocate `frames` contiguous frames. Frames skipped while looking for
+    /// a contiguous run are lost.
+    pub fn alloc(
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+        physical_memory_offset: VirtAddr,
+        frames: usize,
+    ) -> Option<DmaRegion> {
+        let mut start = frame_allocator.allocate_frame()?;
+        let mut run = 1;
+        while run < frames {
+            let frame = frame_allocator.allocate_frame()?;
+            if frame == start + run as u64 {
+                run += 1;
+            } else {
+                start = frame;
+                run = 1;
+            }
+        }
+        let phys = start.start_address();
+        let len = frames * 4096;
+        let virt = physical_memory_offset + phys.as_u64();
+        unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, len) };
+        Some(DmaRegion { phys, virt, len })
+    }
+
+    /// Physical address of the first byte, as handed to devices.
+    pub fn phys_addr(&self) -> PhysAddr { self.phys }
+
+    pub fn virt_addr(&self) -> VirtAddr { self.virt }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Pointer to a `T` at byte `offset` into the region.
+    pub fn ptr_at<T>(&self, offset: usize) -> *mut T {
+        assert!(offset + core::mem::size_of::<T>() <= self.len, "DMA offset out of range");
+        (self.virt + offset as u64).as_mut_ptr()
+    }
+}
+
+/// Map `len` bytes of device registers starting at `phys` into the MMIO
+/// window, uncached, and return the virtual address of `phys`. The
+/// bootloader only maps RAM, so register BARs above it need their own pages.
+/// Mappings are never removed.
+pub fn map_mmio(
+    phys: PhysAddr,
+    len: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + (len.max(1) - 1) as u64);
+    let frames = PhysFrame::range_inclusive(first, last);
+    let pages = frames.count() as u64;
+    let start = NEXT_MMIO.fetch_add(pages * 4096, Ordering::Relaxed);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+    for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+        let page = Page::containing_address(VirtAddr::new(start + i as u64 * 4096));
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+    Ok(VirtAddr::new(start + phys.as_u64() % 4096))
+}
+
 /// A FrameAllocator that always returns `None`.
 pub struct EmptyFrameAllocator;
 
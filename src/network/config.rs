@@ -0,0 +1,75 @@
+// Static IPv4 interface configuration.
+
+use core::fmt;
+
+pub type Ipv4Addr = [u8; 4];
+
+/// Address, subnet and routers for the one interface the stack drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetConfig {
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    /// Router for destinations outside the subnet.
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+}
+
+impl NetConfig {
+    /// `ip` on a subnet of `prefix_len` bits (0-32), with no gateway or DNS.
+    pub const fn new(ip: Ipv4Addr, prefix_len: u8) -> Self {
+        NetConfig { ip, netmask: netmask(prefix_len), gateway: None, dns: None }
+    }
+
+    pub const fn with_gateway(mut self, gateway: Ipv4Addr) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    pub const fn with_dns(mut self, dns: Ipv4Addr) -> Self {
+        self.dns = Some(dns);
+        self
+    }
+
+    /// The fixed addresses QEMU's user-mode network (`-netdev user`) hands
+    /// out: 10.0.2.15/24, router 10.0.2.2, DNS 10.0.2.3.
+    pub const fn qemu_user() -> Self {
+        NetConfig::new([10, 0, 2, 15], 24).with_gateway([10, 0, 2, 2]).with_dns([10, 0, 2, 3])
+    }
+
+    /// Length of the subnet prefix.
+    pub fn prefix_len(&self) -> u8 { u32::from_be_bytes(self.netmask).leading_ones() as u8 }
+
+    /// Subnet-directed broadcast address.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        (u32::from_be_bytes(self.ip) | !u32::from_be_bytes(self.netmask)).to_be_bytes()
+    }
+
+    /// True if `addr` is on the directly attached subnet.
+    pub fn is_local(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::from_be_bytes(self.netmask);
+        u32::from_be_bytes(addr) & mask == u32::from_be_bytes(self.ip) & mask
+    }
+
+    /// Address to resolve with ARP when sending to `dst`: `dst` itself on
+    /// the subnet, else the gateway. `None` if `dst` is unreachable.
+    pub fn next_hop(&self, dst: Ipv4Addr) -> Option<Ipv4Addr> {
+        if self.is_local(dst) || dst == [255; 4] { Some(dst) } else { self.gateway }
+    }
+}
+
+impl fmt::Display for NetConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ip = self.ip;
+        write!(f, "{}.{}.{}.{}/{}", ip[0], ip[1], ip[2], ip[3], self.prefix_len())?;
+        if let Some(gw) = self.gateway {
+            write!(f, " via {}.{}.{}.{}", gw[0], gw[1], gw[2], gw[3])?;
+        }
+        Ok(())
+    }
+}
+
+/// Netmask with the top `prefix_len` bits set.
+pub const fn netmask(prefix_len: u8) -> Ipv4Addr {
+    let bits = if prefix_len == 0 { 0 } else { u32::MAX << (32 - if prefix_len > 32 { 32 } else { prefix_len }) };
+    bits.to_be_bytes()
+}
@@ -1,37 +1,393 @@
-use crate::network::device::{NetworkDevice, MacAddr, Result, NetError};
+// Intel 82540EM (e1000) network card driver.
+//
+// QEMU's default NIC (`-nic user,model=e1000`) is an 82540EM: vendor 0x8086,
+// device 0x100E, with its registers in 128 KiB of memory BAR 0. The driver
+// maps that BAR, resets the card, reads the MAC address from the EEPROM and
+// hands it two descriptor rings in DMA frames. Each descriptor points at its
+// own 2 KiB buffer, so a frame is always one descriptor.
+//
+// Receive: the card fills buffers from RDH up to (not including) RDT and sets
+// DD in the descriptor status; `receive` copies the frame out and gives the
+// descriptor back by moving RDT onto it.
+//
+// Transmit: `transmit` copies the frame into the buffer at TDT, asks for a
+// status write-back (RS) and advances TDT. A slot is free again once the card
+// has set DD in it.
+//
+// The card raises its PCI interrupt line for received frames, finished
+// transmits and link changes. The handler acknowledges it by reading ICR
+// (reading clears it) and keeps the causes for `interrupt_handler`.
+
+use crate::drivers::pci::{self, PciDevice, PciDriver, PciId};
+use crate::memory::{self, DmaRegion};
+use crate::network::device::{MacAddr, NetError, NetworkDevice, Result};
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+const INTEL_VENDOR_ID: u16 = 0x8086;
+const E1000_82540EM_DEVICE_ID: u16 = 0x100E;
+const MMIO_LEN: usize = 128 * 1024;
+
+// registers, as byte offsets into BAR 0
+const REG_CTRL: usize = 0x0000;
+const REG_STATUS: usize = 0x0008;
+const REG_EERD: usize = 0x0014;
+const REG_ICR: usize = 0x00C0;
+const REG_IMS: usize = 0x00D0;
+const REG_IMC: usize = 0x00D8;
+const REG_RCTL: usize = 0x0100;
+const REG_TCTL: usize = 0x0400;
+const REG_TIPG: usize = 0x0410;
+const REG_RDBAL: usize = 0x2800;
+const REG_RDBAH: usize = 0x2804;
+const REG_RDLEN: usize = 0x2808;
+const REG_RDH: usize = 0x2810;
+const REG_RDT: usize = 0x2818;
+const REG_TDBAL: usize = 0x3800;
+const REG_TDBAH: usize = 0x3804;
+const REG_TDLEN: usize = 0x3808;
+const REG_TDH: usize = 0x3810;
+const REG_TDT: usize = 0x3818;
+const REG_MTA: usize = 0x5200;
+const REG_RAL0: usize = 0x5400;
+const REG_RAH0: usize = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+
+const STATUS_LU: u32 = 1 << 1;
+const STATUS_SPEED_SHIFT: u32 = 6;
+
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+// BSIZE 00 with BSEX clear: 2048-byte buffers
+const RCTL_SECRC: u32 = 1 << 26;
+
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x10 << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+// inter-packet gap recommended for copper: IPGT 10, IPGR1 8, IPGR2 6
+const TIPG_COPPER: u32 = 10 | 8 << 10 | 6 << 20;
+
+const RAH_AV: u32 = 1 << 31;
+
+/// Interrupt causes in ICR and IMS.
+pub const ICR_TXDW: u32 = 1 << 0;
+pub const ICR_LSC: u32 = 1 << 2;
+pub const ICR_RXDMT0: u32 = 1 << 4;
+pub const ICR_RXO: u32 = 1 << 6;
+pub const ICR_RXT0: u32 = 1 << 7;
+
+const DESC_DD: u8 = 1 << 0;
+const RX_EOP: u8 = 1 << 1;
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+
+// ring sizes must make the ring length a multiple of 128 bytes
+const RX_DESCRIPTORS: usize = 32;
+const TX_DESCRIPTORS: usize = 16;
+const BUFFER_SIZE: usize = 2048;
+const RX_RING_OFFSET: usize = 0;
+const TX_RING_OFFSET: usize = RX_DESCRIPTORS * 16;
+
+/// Largest frame handed to `transmit`: MTU plus the Ethernet header (the
+/// card appends the FCS).
+const MAX_TX_FRAME: usize = 1514;
+/// Register polls before a reset or EEPROM read is considered stuck.
+const POLL_LIMIT: u32 = 1_000_000;
+
+/// ICR addresses of initialized cards, read by the interrupt handler.
+static ICR_REGS: spin::Mutex<Vec<usize>> = spin::Mutex::new(Vec::new());
+/// Causes acknowledged by the handler but not yet seen by a driver.
+static PENDING_CAUSES: AtomicU32 = AtomicU32::new(0);
+static IRQ_COUNT: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RxDescriptor {
+    addr: u64,
+    len: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TxDescriptor {
+    addr: u64,
+    len: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+fn handle_irq() {
+    for &icr in ICR_REGS.lock().iter() {
+        // the line is shared, so a zero ICR means another device raised it
+        let causes = unsafe { read_volatile(icr as *const u32) };
+        if causes != 0 {
+            PENDING_CAUSES.fetch_or(causes, Ordering::Relaxed);
+            IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+            crate::network::wake();
+        }
+    }
+}
+
+/// Number of interrupts seen since boot, across all cards.
+pub fn irq_count() -> u64 { IRQ_COUNT.load(Ordering::Relaxed) }
+
+/// Find the first 82540EM on the PCI bus.
+pub fn find_device() -> Option<PciDevice> {
+    pci::find_device(INTEL_VENDOR_ID, E1000_82540EM_DEVICE_ID)
+}
+
+/// Registry entry: the functions this driver binds to.
+pub static PCI_DRIVER: PciDriver = PciDriver { name: "e1000", ids: &[PciId::new(INTEL_VENDOR_ID, E1000_82540EM_DEVICE_ID)] };
 
 pub struct E1000 {
     mmio_base: usize,
     mac: MacAddr,
-    // TODO: descriptor rings, tx/rx indices would go here
+    rings: DmaRegion,
+    rx_buffers: DmaRegion,
+    tx_buffers: DmaRegion,
+    // next descriptor the card will complete, and next free transmit slot
+    rx_next: usize,
+    tx_next: usize,
+    interrupt_driven: bool,
 }
 
 impl E1000 {
-    pub const fn new(mmio_base: usize) -> Self {
-        Self { mmio_base, mac: [0u8;6] }
+    /// Claim and initialize the first unclaimed 82540EM. Returns `Ok(None)`
+    /// if there is none.
+    pub fn probe(
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+        physical_memory_offset: VirtAddr,
+    ) -> Result<Option<E1000>> {
+        let device = match pci::claim(&PCI_DRIVER) {
+            Some(device) => device,
+            None => return Ok(None),
+        };
+        // leave the function for another attempt if it cannot be set up
+        E1000::init(device, mapper, frame_allocator, physical_memory_offset).map(Some).inspect_err(|_| pci::release(device.address))
     }
 
-    /// Initialize hardware (placeholder)
-    pub fn init(&mut self) -> Result<()> {
-        // TODO: PCI/PCIe mapping, reset, configure Rx/Tx rings, read MAC
+    /// Map, reset and initialize the card at `device`.
+    pub fn init(
+        device: PciDevice,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+        physical_memory_offset: VirtAddr,
+    ) -> Result<E1000> {
+        let bar = device.mmio_bar(0).ok_or(NetError::DeviceFailure)?;
+        let mmio = memory::map_mmio(PhysAddr::new(bar), MMIO_LEN, mapper, frame_allocator)
+            .map_err(|_| NetError::DeviceFailure)?;
+        device.enable_bus_master();
+
+        let rings = DmaRegion::alloc(frame_allocator, physical_memory_offset, 1);
+        let rx_buffers = DmaRegion::alloc(frame_allocator, physical_memory_offset, RX_DESCRIPTORS * BUFFER_SIZE / 4096);
+        let tx_buffers = DmaRegion::alloc(frame_allocator, physical_memory_offset, TX_DESCRIPTORS * BUFFER_SIZE / 4096);
+        let (rings, (rx_buffers, tx_buffers)) = match rings.zip(rx_buffers.zip(tx_buffers)) {
+            Some(regions) => regions,
+            None => return Err(NetError::DeviceFailure),
+        };
+        let mut nic = E1000 {
+            mmio_base: mmio.as_u64() as usize,
+            mac: [0; 6],
+            rings,
+            rx_buffers,
+            tx_buffers,
+            rx_next: 0,
+            tx_next: 0,
+            interrupt_driven: false,
+        };
+        nic.reset()?;
+        nic.mac = nic.read_mac();
+        nic.setup_rx();
+        nic.setup_tx();
+
+        interrupts::without_interrupts(|| ICR_REGS.lock().push(nic.mmio_base + REG_ICR));
+        nic.interrupt_driven = crate::interrupts::register_irq_handler(device.interrupt_line, handle_irq);
+        if nic.interrupt_driven {
+            nic.write(REG_IMS, ICR_TXDW | ICR_LSC | ICR_RXDMT0 | ICR_RXO | ICR_RXT0);
+        }
+        Ok(nic)
+    }
+
+    /// Virtual address the register window is mapped at.
+    pub fn mmio_base(&self) -> usize { self.mmio_base }
+
+    /// True if the PHY reports a link.
+    pub fn link_up(&self) -> bool { self.read(REG_STATUS) & STATUS_LU != 0 }
+
+    /// Negotiated speed in Mbit/s, or 0 without a link.
+    pub fn link_speed(&self) -> u32 {
+        if !self.link_up() { return 0; }
+        match (self.read(REG_STATUS) >> STATUS_SPEED_SHIFT) & 3 {
+            0 => 10,
+            1 => 100,
+            _ => 1000,
+        }
+    }
+
+    /// True if the card's interrupt line has a handler.
+    pub fn is_interrupt_driven(&self) -> bool { self.interrupt_driven }
+
+    /// Acknowledge pending interrupt causes (`ICR_*`) and return them,
+    /// including any the IRQ handler already took. Received frames stay in
+    /// the ring until `receive` takes them.
+    pub fn interrupt_handler(&mut self) -> u32 {
+        PENDING_CAUSES.swap(0, Ordering::Relaxed) | self.read(REG_ICR)
+    }
+
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.mmio_base + reg) as *const u32) }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.mmio_base + reg) as *mut u32, value) }
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.write(REG_IMC, u32::MAX);
+        self.write(REG_CTRL, self.read(REG_CTRL) | CTRL_RST);
+        if !(0..POLL_LIMIT).any(|_| self.read(REG_CTRL) & CTRL_RST == 0) {
+            return Err(NetError::DeviceFailure);
+        }
+        // the reset re-enables nothing, but clear what fired before it
+        self.write(REG_IMC, u32::MAX);
+        self.read(REG_ICR);
+        self.write(REG_CTRL, self.read(REG_CTRL) | CTRL_SLU | CTRL_ASDE);
+        for i in 0..128 {
+            self.write(REG_MTA + i * 4, 0);
+        }
         Ok(())
     }
 
-    /// Called by IRQ wrapper (external) — schedule work / refill queues
-    pub fn interrupt_handler(&mut self) {
-        // TODO: acknowledge interrupt and mark RX/TX available
+    /// Read word `address` of the EEPROM, or `None` if the read never
+    /// finishes.
+    fn read_eeprom(&self, address: u8) -> Option<u16> {
+        self.write(REG_EERD, EERD_START | (address as u32) << 8);
+        (0..POLL_LIMIT).map(|_| self.read(REG_EERD)).find(|v| v & EERD_DONE != 0).map(|v| (v >> 16) as u16)
+    }
+
+    /// MAC address from EEPROM words 0-2, falling back to the receive
+    /// address the card loaded at reset. Either way it is (re)written to
+    /// receive address 0 so unicast frames to it are accepted.
+    fn read_mac(&self) -> MacAddr {
+        let mut mac = [0u8; 6];
+        let words = [self.read_eeprom(0), self.read_eeprom(1), self.read_eeprom(2)];
+        if words.iter().all(|w| w.is_some()) {
+            for (i, w) in words.iter().enumerate() {
+                mac[i * 2..i * 2 + 2].copy_from_slice(&w.unwrap_or(0).to_le_bytes());
+            }
+        } else {
+            mac[0..4].copy_from_slice(&self.read(REG_RAL0).to_le_bytes());
+            mac[4..6].copy_from_slice(&self.read(REG_RAH0).to_le_bytes()[..2]);
+        }
+        self.write(REG_RAL0, u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]));
+        self.write(REG_RAH0, u16::from_le_bytes([mac[4], mac[5]]) as u32 | RAH_AV);
+        mac
+    }
+
+    fn rx_descriptor(&self, index: usize) -> *mut RxDescriptor {
+        self.rings.ptr_at(RX_RING_OFFSET + index * 16)
+    }
+
+    fn tx_descriptor(&self, index: usize) -> *mut TxDescriptor {
+        self.rings.ptr_at(TX_RING_OFFSET + index * 16)
+    }
+
+    fn setup_rx(&mut self) {
+        let buffers = self.rx_buffers.phys_addr().as_u64();
+        for i in 0..RX_DESCRIPTORS {
+            let desc = RxDescriptor { addr: buffers + (i * BUFFER_SIZE) as u64, len: 0, checksum: 0, status: 0, errors: 0, special: 0 };
+            unsafe { write_volatile(self.rx_descriptor(i), desc) };
+        }
+        let ring = self.rings.phys_addr().as_u64() + RX_RING_OFFSET as u64;
+        self.write(REG_RDBAL, ring as u32);
+        self.write(REG_RDBAH, (ring >> 32) as u32);
+        self.write(REG_RDLEN, (RX_DESCRIPTORS * 16) as u32);
+        self.write(REG_RDH, 0);
+        // RDT == RDH means an empty ring, so one descriptor always stays back
+        self.write(REG_RDT, (RX_DESCRIPTORS - 1) as u32);
+        self.write(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+    }
+
+    fn setup_tx(&mut self) {
+        let buffers = self.tx_buffers.phys_addr().as_u64();
+        for i in 0..TX_DESCRIPTORS {
+            // DD set: every slot starts out free
+            let desc = TxDescriptor { addr: buffers + (i * BUFFER_SIZE) as u64, len: 0, cso: 0, cmd: 0, status: DESC_DD, css: 0, special: 0 };
+            unsafe { write_volatile(self.tx_descriptor(i), desc) };
+        }
+        let ring = self.rings.phys_addr().as_u64() + TX_RING_OFFSET as u64;
+        self.write(REG_TDBAL, ring as u32);
+        self.write(REG_TDBAH, (ring >> 32) as u32);
+        self.write(REG_TDLEN, (TX_DESCRIPTORS * 16) as u32);
+        self.write(REG_TDH, 0);
+        self.write(REG_TDT, 0);
+        self.write(REG_TIPG, TIPG_COPPER);
+        self.write(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
     }
 }
 
 impl NetworkDevice for E1000 {
-    fn transmit(&mut self, _frame: &[u8]) -> Result<()> {
-        // TODO: copy into Tx descriptor, kick the device
-        Err(NetError::WouldBlock)
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        if frame.len() > MAX_TX_FRAME { return Err(NetError::BufferTooSmall); }
+        let slot = self.tx_descriptor(self.tx_next);
+        let mut desc = unsafe { read_volatile(slot) };
+        if desc.status & DESC_DD == 0 { return Err(NetError::WouldBlock); }
+        let buffer = self.tx_buffers.ptr_at::<u8>(self.tx_next * BUFFER_SIZE);
+        unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), buffer, frame.len()) };
+        desc.len = frame.len() as u16;
+        desc.cmd = TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS;
+        desc.status = 0;
+        unsafe { write_volatile(slot, desc) };
+        fence(Ordering::SeqCst);
+        self.tx_next = (self.tx_next + 1) % TX_DESCRIPTORS;
+        self.write(REG_TDT, self.tx_next as u32);
+        Ok(())
     }
 
-    fn receive(&mut self, _buf: &mut [u8]) -> Result<usize> {
-        // TODO: copy from Rx descriptor into provided buffer
-        Err(NetError::WouldBlock)
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let slot = self.rx_descriptor(self.rx_next);
+        let mut desc = unsafe { read_volatile(slot) };
+        if desc.status & DESC_DD == 0 { return Err(NetError::WouldBlock); }
+        fence(Ordering::SeqCst);
+        let len = desc.len as usize;
+        // buffers hold whole frames, so EOP is always set; anything else or
+        // a receive error is dropped
+        let result = if desc.status & RX_EOP == 0 || desc.errors != 0 {
+            Err(NetError::DeviceFailure)
+        } else if len > buf.len() {
+            Err(NetError::BufferTooSmall)
+        } else {
+            let buffer = self.rx_buffers.ptr_at::<u8>(self.rx_next * BUFFER_SIZE);
+            unsafe { core::ptr::copy_nonoverlapping(buffer, buf.as_mut_ptr(), len) };
+            Ok(len)
+        };
+        // hand the descriptor back either way
+        desc.status = 0;
+        unsafe { write_volatile(slot, desc) };
+        fence(Ordering::SeqCst);
+        self.write(REG_RDT, self.rx_next as u32);
+        self.rx_next = (self.rx_next + 1) % RX_DESCRIPTORS;
+        result
     }
 
     fn mac_addr(&self) -> MacAddr { self.mac }
@@ -42,14 +398,3 @@ impl NetworkDevice for E1000 {
         self.interrupt_handler();
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn construct_e1000() {
-        let mut d = E1000::new(0xfee0_0000);
-        assert_eq!(d.mtu(), 1500);
-    }
-}
@@ -1,21 +1,51 @@
-/// Compute IPv4 header checksum (stub)
-pub fn ipv4_checksum(_header: &[u8]) -> u16 {
-    // TODO: implement real checksum
-    0
-}
+// RFC 1071 Internet checksum: the one's complement of the one's complement
+// sum of 16-bit big-endian words. Summing a block that already holds its
+// checksum gives 0 when the block is intact.
+
+use crate::network::internet::ipv4::PROTO_UDP;
 
-/// Compute UDP checksum (stub)
-pub fn udp_checksum(_src: [u8;4], _dst: [u8;4], _udp: &[u8]) -> u16 {
-    // TODO: implement pseudo-header + UDP checksum
-    0
+/// Add `data` to a running 32-bit sum, as big-endian words; an odd last
+/// byte is padded with zero.
+fn sum_words(data: &[u8], mut sum: u32) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for w in &mut words {
+        sum += u16::from_be_bytes([w[0], w[1]]) as u32;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    // an IPv4 packet has at most 32768 words, so the sum cannot overflow
+    sum
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn checksum_types() {
-        let _ = ipv4_checksum(&[0u8; 20]);
-        let _ = udp_checksum([0,0,0,0], [0,0,0,0], &[]);
+/// Fold the carries back in and complement.
+fn finish(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
     }
+    !(sum as u16)
+}
+
+/// Checksum of arbitrary data (ICMP messages use it directly).
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    finish(sum_words(data, 0))
+}
+
+/// IPv4 header checksum. With the checksum field zeroed this is the value
+/// to store; over a received header it is 0 if the header is intact.
+pub fn ipv4_checksum(header: &[u8]) -> u16 {
+    internet_checksum(header)
+}
+
+/// UDP checksum over the pseudo-header (addresses, protocol, length) and
+/// the datagram `udp`. With the checksum field zeroed this is the value to
+/// store (sent as 0xFFFF if it comes out 0, since 0 means "no checksum");
+/// over a received datagram it is 0 if the datagram is intact.
+pub fn udp_checksum(src: [u8;4], dst: [u8;4], udp: &[u8]) -> u16 {
+    let mut pseudo = [0u8; 12];
+    pseudo[0..4].copy_from_slice(&src);
+    pseudo[4..8].copy_from_slice(&dst);
+    pseudo[9] = PROTO_UDP;
+    pseudo[10..12].copy_from_slice(&(udp.len() as u16).to_be_bytes());
+    finish(sum_words(udp, sum_words(&pseudo, 0)))
 }
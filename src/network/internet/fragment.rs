@@ -0,0 +1,211 @@
+// IPv4 fragmentation and reassembly.
+//
+// `fragment` splits a packet that is larger than the link MTU into pieces
+// whose payloads (except the last) are multiples of 8 bytes, as RFC 791
+// requires.
+//
+// `Reassembler` collects incoming fragments per (src, dst, proto, id). A
+// datagram is handed back once its last fragment has fixed the length and
+// the received ranges cover it without gaps. Fragments that overlap data
+// already received drop the whole datagram (overlaps are how fragment
+// attacks rewrite headers), exact duplicates are ignored. Partial datagrams
+// are dropped when they are older than the timeout, and the oldest ones
+// are evicted when buffered data would exceed the memory limit.
+
+use crate::network::internet::ipv4::{Ipv4Header, IPV4_MIN_HEADER_LEN};
+use alloc::vec::Vec;
+
+/// Seconds a partial datagram may wait for its missing fragments.
+pub const REASSEMBLY_TIMEOUT_SECS: u64 = 30;
+/// Buffered fragment data the stack allows by default.
+pub const DEFAULT_MEMORY_LIMIT: usize = 32 * 1024;
+/// Datagrams reassembled at once; a new one evicts the oldest.
+const MAX_PARTIALS: usize = 8;
+/// Largest IPv4 payload: total length is 16 bits.
+const MAX_PAYLOAD: usize = u16::MAX as usize - IPV4_MIN_HEADER_LEN;
+
+/// Split `payload` into IPv4 packets (headers included) of at most `mtu`
+/// bytes, each carrying a copy of `header` with the offset and MF flag set.
+/// A packet that fits is returned as a single unfragmented packet. `None`
+/// if it does not fit and `header.dont_fragment` is set, if `mtu` cannot
+/// carry 8 bytes of payload, or if the payload is too large for IPv4.
+pub fn fragment(header: &Ipv4Header, payload: &[u8], mtu: usize) -> Option<Vec<Vec<u8>>> {
+    if payload.len() > MAX_PAYLOAD { return None; }
+    if IPV4_MIN_HEADER_LEN + payload.len() <= mtu {
+        let mut packet = alloc::vec![0u8; IPV4_MIN_HEADER_LEN + payload.len()];
+        header.write(payload, &mut packet)?;
+        return Some(alloc::vec![packet]);
+    }
+    let chunk = mtu.saturating_sub(IPV4_MIN_HEADER_LEN) & !7;
+    if header.dont_fragment || chunk == 0 { return None; }
+    let mut packets = Vec::with_capacity(payload.len().div_ceil(chunk));
+    for (i, piece) in payload.chunks(chunk).enumerate() {
+        let last = (i + 1) * chunk >= payload.len();
+        let mut frag = *header;
+        // a packet that is itself a fragment keeps its place in the original
+        frag.fragment_offset = header.fragment_offset.checked_add((i * chunk) as u16)?;
+        frag.more_fragments = !last || header.more_fragments;
+        let mut packet = alloc::vec![0u8; IPV4_MIN_HEADER_LEN + piece.len()];
+        frag.write(piece, &mut packet)?;
+        packets.push(packet);
+    }
+    Some(packets)
+}
+
+/// What identifies the fragments of one datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentKey {
+    pub src: [u8;4],
+    pub dst: [u8;4],
+    pub proto: u8,
+    pub id: u16,
+}
+
+impl FragmentKey {
+    pub fn of(header: &Ipv4Header) -> Self {
+        FragmentKey { src: header.src, dst: header.dst, proto: header.proto, id: header.identification }
+    }
+}
+
+struct Partial {
+    key: FragmentKey,
+    // header of the fragment at offset 0, once it has arrived
+    first: Option<Ipv4Header>,
+    data: Vec<u8>,
+    // received byte ranges, never overlapping
+    ranges: Vec<(usize, usize)>,
+    // payload length, known once the last fragment has arrived
+    total: Option<usize>,
+    started: u64,
+}
+
+impl Partial {
+    fn received(&self) -> usize {
+        self.ranges.iter().map(|(start, end)| end - start).sum()
+    }
+}
+
+pub struct Reassembler {
+    partials: Vec<Partial>,
+    memory_limit: usize,
+    timeout: u64,
+    used: usize,
+}
+
+impl Reassembler {
+    /// Reassembler buffering at most `memory_limit` bytes of fragment data
+    /// and giving up on a datagram `timeout` time units after its first
+    /// fragment. The unit is whatever the caller passes as `now`.
+    pub const fn new(memory_limit: usize, timeout: u64) -> Self {
+        Reassembler { partials: Vec::new(), memory_limit, timeout, used: 0 }
+    }
+
+    /// Datagrams waiting for fragments.
+    pub fn pending(&self) -> usize { self.partials.len() }
+
+    /// Bytes of fragment data buffered.
+    pub fn memory_used(&self) -> usize { self.used }
+
+    /// Drop partial datagrams older than the timeout. Returns how many.
+    pub fn expire(&mut self, now: u64) -> usize {
+        let timeout = self.timeout;
+        let before = self.partials.len();
+        self.partials.retain(|p| now.saturating_sub(p.started) < timeout);
+        self.recount();
+        before - self.partials.len()
+    }
+
+    fn recount(&mut self) {
+        self.used = self.partials.iter().map(|p| p.data.len()).sum();
+    }
+
+    fn drop_partial(&mut self, index: usize) {
+        self.partials.remove(index);
+        self.recount();
+    }
+
+    /// Drop the oldest partial datagram other than `keep`. Returns false
+    /// if there is none.
+    fn evict_oldest(&mut self, keep: Option<FragmentKey>) -> bool {
+        let oldest = self
+            .partials
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| keep != Some(p.key))
+            .min_by_key(|(_, p)| p.started)
+            .map(|(i, _)| i);
+        match oldest {
+            Some(i) => {
+                self.drop_partial(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Add a received packet at time `now`. Returns the whole datagram,
+    /// with the header of its first fragment (MF and offset cleared, total
+    /// length updated), once it is complete. An unfragmented packet is
+    /// returned as it is.
+    pub fn push(&mut self, header: &Ipv4Header, payload: &[u8], now: u64) -> Option<(Ipv4Header, Vec<u8>)> {
+        self.expire(now);
+        if !header.is_fragment() { return Some((*header, Vec::from(payload))); }
+        let start = header.fragment_offset as usize;
+        let end = start + payload.len();
+        // only the last fragment may end off an 8-byte boundary
+        if payload.is_empty() || end > MAX_PAYLOAD || (header.more_fragments && payload.len() & 7 != 0) {
+            return None;
+        }
+
+        let key = FragmentKey::of(header);
+        let index = match self.partials.iter().position(|p| p.key == key) {
+            Some(i) => i,
+            None => {
+                if self.partials.len() >= MAX_PARTIALS { self.evict_oldest(None); }
+                self.partials.push(Partial { key, first: None, data: Vec::new(), ranges: Vec::new(), total: None, started: now });
+                self.partials.len() - 1
+            }
+        };
+
+        let partial = &self.partials[index];
+        if partial.ranges.contains(&(start, end)) { return None; }
+        let overlaps = partial.ranges.iter().any(|&(s, e)| start < e && s < end);
+        let past_total = partial.total.is_some_and(|total| end > total);
+        // a second, different last fragment, or data beyond the last one
+        let bad_last = !header.more_fragments
+            && (partial.total.is_some() || partial.ranges.iter().any(|&(_, e)| e > end));
+        if overlaps || past_total || bad_last {
+            self.drop_partial(index);
+            return None;
+        }
+
+        let growth = end.saturating_sub(partial.data.len());
+        while self.used + growth > self.memory_limit {
+            if !self.evict_oldest(Some(key)) { break; }
+        }
+        let index = self.partials.iter().position(|p| p.key == key)?;
+        if self.used + growth > self.memory_limit {
+            self.drop_partial(index);
+            return None;
+        }
+
+        let partial = &mut self.partials[index];
+        if partial.data.len() < end { partial.data.resize(end, 0); }
+        partial.data[start..end].copy_from_slice(payload);
+        partial.ranges.push((start, end));
+        if start == 0 { partial.first = Some(*header); }
+        if !header.more_fragments { partial.total = Some(end); }
+        self.used += growth;
+
+        let partial = &self.partials[index];
+        let complete = partial.total.is_some_and(|total| partial.received() == total);
+        if !complete { return None; }
+        let partial = self.partials.remove(index);
+        self.recount();
+        let mut first = partial.first?;
+        first.more_fragments = false;
+        first.fragment_offset = 0;
+        first.total_len = (first.header_bytes() + partial.data.len()).min(u16::MAX as usize) as u16;
+        Some((first, partial.data))
+    }
+}
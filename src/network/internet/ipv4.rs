@@ -1,30 +1,186 @@
-extern crate alloc;
-use alloc::vec::Vec;
+// IPv4 headers (RFC 791).
+//
+// `parse_ipv4_header` accepts a packet only if it is well formed: version
+// 4, an IHL of at least five words that fits the buffer, a total length
+// that covers the header and fits the buffer, a valid header checksum, a
+// clear reserved flag and options whose lengths stay inside the header. The
+// payload it returns ends at the total length, so link-layer padding is
+// dropped. Options are checked and skipped, never interpreted.
+//
+// `build_ipv4_packet` writes a 20-byte header without options.
 
+use crate::network::internet::checksums::ipv4_checksum;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+pub const IPV4_MIN_HEADER_LEN: usize = 20;
+/// Largest header: IHL is four bits of 32-bit words.
+pub const IPV4_MAX_HEADER_LEN: usize = 60;
+pub const DEFAULT_TTL: u8 = 64;
+
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+// flags and fragment offset share the 16 bits at offset 6
+const FLAG_RESERVED: u16 = 0x8000;
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+
+static NEXT_IDENTIFICATION: AtomicU16 = AtomicU16::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Ipv4Header {
     pub src: [u8;4],
     pub dst: [u8;4],
     pub proto: u8,
+    /// IHL: header length in 32-bit words, options included.
     pub header_len: u8,
+    /// Header plus payload, in bytes.
     pub total_len: u16,
+    pub tos: u8,
+    pub identification: u16,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    /// Position of this fragment's payload in the original, in bytes
+    /// (always a multiple of 8).
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    pub checksum: u16,
 }
 
-pub fn parse_ipv4_header(_buf: &[u8]) -> Option<(Ipv4Header, &[u8])> {
-    // TODO: implement parsing
-    None
+impl Ipv4Header {
+    /// Header for an unfragmented packet with no payload yet, the default
+    /// TTL and identification 0.
+    pub const fn new(src: [u8;4], dst: [u8;4], proto: u8) -> Self {
+        Ipv4Header {
+            src,
+            dst,
+            proto,
+            header_len: 5,
+            total_len: IPV4_MIN_HEADER_LEN as u16,
+            tos: 0,
+            identification: 0,
+            dont_fragment: false,
+            more_fragments: false,
+            fragment_offset: 0,
+            ttl: DEFAULT_TTL,
+            checksum: 0,
+        }
+    }
+
+    /// Header length in bytes.
+    pub fn header_bytes(&self) -> usize { self.header_len as usize * 4 }
+
+    /// Payload length in bytes.
+    pub fn payload_len(&self) -> usize { (self.total_len as usize).saturating_sub(self.header_bytes()) }
+
+    /// True for any piece of a fragmented packet, the first included.
+    pub fn is_fragment(&self) -> bool { self.more_fragments || self.fragment_offset != 0 }
+
+    /// Write this header without options, followed by `payload`, into
+    /// `out`. `total_len`, `header_len` and the checksum are computed; the
+    /// other fields are taken as they are. Returns the packet length, or
+    /// `None` if `out` is too small, the packet would exceed 65535 bytes or
+    /// the fragment offset is not a multiple of 8.
+    pub fn write(&self, payload: &[u8], out: &mut [u8]) -> Option<usize> {
+        let total = IPV4_MIN_HEADER_LEN + payload.len();
+        if total > u16::MAX as usize || out.len() < total { return None; }
+        if self.fragment_offset & 7 != 0 { return None; }
+        let mut flags = self.fragment_offset / 8;
+        if self.dont_fragment { flags |= FLAG_DF; }
+        if self.more_fragments { flags |= FLAG_MF; }
+        let header = &mut out[..IPV4_MIN_HEADER_LEN];
+        header[0] = 0x45;
+        header[1] = self.tos;
+        header[2..4].copy_from_slice(&(total as u16).to_be_bytes());
+        header[4..6].copy_from_slice(&self.identification.to_be_bytes());
+        header[6..8].copy_from_slice(&flags.to_be_bytes());
+        header[8] = self.ttl;
+        header[9] = self.proto;
+        header[10..12].copy_from_slice(&[0, 0]);
+        header[12..16].copy_from_slice(&self.src);
+        header[16..20].copy_from_slice(&self.dst);
+        let checksum = ipv4_checksum(header);
+        header[10..12].copy_from_slice(&checksum.to_be_bytes());
+        out[IPV4_MIN_HEADER_LEN..total].copy_from_slice(payload);
+        Some(total)
+    }
 }
 
-pub fn build_ipv4_packet(_src: [u8;4], _dst: [u8;4], _proto: u8, _payload: &[u8], _out: &mut [u8]) -> Option<usize> {
-    // TODO: implement packet building (set checksum, etc.)
-    None
+/// Identification for the next packet sent; wraps at 65535.
+pub fn next_identification() -> u16 {
+    NEXT_IDENTIFICATION.fetch_add(1, Ordering::Relaxed)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn ipv4_compile() {
-        let b = [0u8; 64];
-        let _ = parse_ipv4_header(&b);
+/// Check that the option bytes of a header are well formed.
+fn options_valid(options: &[u8]) -> bool {
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            OPTION_END => return true,
+            OPTION_NOP => i += 1,
+            _ => {
+                // type, length (covering type and length), data
+                let len = match options.get(i + 1) {
+                    Some(&len) if len >= 2 => len as usize,
+                    _ => return false,
+                };
+                if i + len > options.len() { return false; }
+                i += len;
+            }
+        }
     }
+    true
+}
+
+/// Parse and validate the header of the packet in `buf` -> returns
+/// (Ipv4Header, payload_slice).
+pub fn parse_ipv4_header(buf: &[u8]) -> Option<(Ipv4Header, &[u8])> {
+    if buf.len() < IPV4_MIN_HEADER_LEN { return None; }
+    let version = buf[0] >> 4;
+    let ihl = buf[0] & 0x0F;
+    let header_bytes = ihl as usize * 4;
+    if version != 4 || ihl < 5 || header_bytes > buf.len() { return None; }
+    let total_len = u16::from_be_bytes([buf[2], buf[3]]);
+    if (total_len as usize) < header_bytes || total_len as usize > buf.len() { return None; }
+    if ipv4_checksum(&buf[..header_bytes]) != 0 { return None; }
+    let flags = u16::from_be_bytes([buf[6], buf[7]]);
+    if flags & FLAG_RESERVED != 0 { return None; }
+    // a fragment may not reach past the largest packet
+    let fragment_offset = (flags & FRAGMENT_OFFSET_MASK) as usize * 8;
+    if fragment_offset + total_len as usize - header_bytes > u16::MAX as usize { return None; }
+    if !options_valid(&buf[IPV4_MIN_HEADER_LEN..header_bytes]) { return None; }
+
+    let mut src = [0u8;4];
+    let mut dst = [0u8;4];
+    src.copy_from_slice(&buf[12..16]);
+    dst.copy_from_slice(&buf[16..20]);
+    let header = Ipv4Header {
+        src,
+        dst,
+        proto: buf[9],
+        header_len: ihl,
+        total_len,
+        tos: buf[1],
+        identification: u16::from_be_bytes([buf[4], buf[5]]),
+        dont_fragment: flags & FLAG_DF != 0,
+        more_fragments: flags & FLAG_MF != 0,
+        fragment_offset: fragment_offset as u16,
+        ttl: buf[8],
+        checksum: u16::from_be_bytes([buf[10], buf[11]]),
+    };
+    Some((header, &buf[header_bytes..total_len as usize]))
+}
+
+/// Serialize a 20-byte header + payload into `out` (caller provides the
+/// buffer), with the default TTL, a fresh identification and DF clear.
+/// Returns the packet length, or `None` if it does not fit.
+pub fn build_ipv4_packet(src: [u8;4], dst: [u8;4], proto: u8, payload: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut header = Ipv4Header::new(src, dst, proto);
+    header.identification = next_identification();
+    header.write(payload, out)
 }
@@ -0,0 +1,4 @@
+pub mod checksums;
+pub mod ipv4;
+pub mod fragment;
+pub mod icmp;
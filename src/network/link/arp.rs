@@ -1,5 +1,10 @@
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt;
 
+use crate::network::device::MacAddr;
+use crate::network::link::ethernet::{build_eth_frame, parse_eth_header, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETH_HEADER_LEN};
+
 /// ARP packet format constants
 pub const ARP_HDR_LEN: usize = 28; // Ethernet + IPv4
 
@@ -34,8 +39,7 @@ impl fmt::Display for ArpPacket {
 }
 
 fn hex_mac(m: &[u8;6]) -> String {
-    use alloc::string::String;
-    use alloc::fmt::Write;
+    use core::fmt::Write;
     let mut s = String::new();
     for (i, b) in m.iter().enumerate() {
         if i != 0 { let _ = write!(s, ":"); }
@@ -45,8 +49,7 @@ fn hex_mac(m: &[u8;6]) -> String {
 }
 
 fn format_ip(ip: &[u8;4]) -> String {
-    use alloc::string::String;
-    use alloc::fmt::Write;
+    use core::fmt::Write;
     let mut s = String::new();
     let _ = write!(s, "{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3]);
     s
@@ -66,17 +69,23 @@ struct ArpEntry {
     valid: bool,
 }
 
+impl ArpEntry {
+    const EMPTY: ArpEntry = ArpEntry { ip: [0;4], mac: [0;6], age: 0, valid: false };
+}
+
 impl Default for ArpEntry {
-    fn default() -> Self {
-        ArpEntry { ip: [0;4], mac: [0;6], age: 0, valid: false }
-    }
+    fn default() -> Self { ArpEntry::EMPTY }
+}
+
+impl Default for ArpCache {
+    fn default() -> Self { Self::new() }
 }
 
 impl ArpCache {
-    const CAPACITY: usize = 16;
+    pub const CAPACITY: usize = 16;
 
-    pub fn new() -> Self {
-        ArpCache { entries: [ArpEntry::default(); ArpCache::CAPACITY], clock: 1 }
+    pub const fn new() -> Self {
+        ArpCache { entries: [ArpEntry::EMPTY; ArpCache::CAPACITY], clock: 1 }
     }
 
     /// Lookup a MAC for an IPv4 address. Returns Some(mac) or None.
@@ -99,19 +108,102 @@ impl ArpCache {
                 return;
             }
         }
-        // find invalid entry
-        for e in self.entries.iter_mut() {
-            if e.ip == ip { e.mac = mac; return; }
-        }
-        self.entries.push(ArpEntry { ip, mac });
+        // otherwise take an empty slot, or evict the least recently used one
+        let slot = match self.entries.iter().position(|e| !e.valid) {
+            Some(free) => free,
+            None => {
+                let oldest = self.entries.iter().enumerate().min_by_key(|(_, e)| e.age);
+                oldest.map_or(0, |(i, _)| i)
+            }
+        };
+        self.entries[slot] = ArpEntry { ip, mac, age: self.clock, valid: true };
     }
 
     /// Remove a mapping (if present)
     pub fn remove(&mut self, ip: [u8;4]) {
-        self.entries.retain(|e| e.ip != ip);
+        for e in self.entries.iter_mut() {
+            if e.valid && e.ip == ip { *e = ArpEntry::EMPTY; }
+        }
+    }
+
+    /// Number of valid mappings.
+    pub fn len(&self) -> usize { self.entries.iter().filter(|e| e.valid).count() }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl ArpPacket {
+    /// Write the 28-byte ARP payload.
+    pub fn serialize(&self) -> [u8; ARP_HDR_LEN] {
+        let mut out = [0u8; ARP_HDR_LEN];
+        out[0..2].copy_from_slice(&self.htype.to_be_bytes());
+        out[2..4].copy_from_slice(&self.ptype.to_be_bytes());
+        out[4] = self.hlen;
+        out[5] = self.plen;
+        out[6..8].copy_from_slice(&self.opcode.to_be_bytes());
+        out[8..14].copy_from_slice(&self.sender_mac);
+        out[14..18].copy_from_slice(&self.sender_ip);
+        out[18..24].copy_from_slice(&self.target_mac);
+        out[24..28].copy_from_slice(&self.target_ip);
+        out
     }
 }
 
+/// Parse an ARP payload (the bytes after the Ethernet header). Only
+/// Ethernet/IPv4 ARP is accepted.
+pub fn parse_arp_packet(buf: &[u8]) -> Option<ArpPacket> {
+    if buf.len() < ARP_HDR_LEN { return None; }
+    let u16_at = |o: usize| u16::from_be_bytes([buf[o], buf[o + 1]]);
+    let mut pkt = ArpPacket {
+        htype: u16_at(0),
+        ptype: u16_at(2),
+        hlen: buf[4],
+        plen: buf[5],
+        opcode: u16_at(6),
+        sender_mac: [0; 6],
+        sender_ip: [0; 4],
+        target_mac: [0; 6],
+        target_ip: [0; 4],
+    };
+    if pkt.htype != 1 || pkt.ptype != ETHERTYPE_IPV4 || pkt.hlen != 6 || pkt.plen != 4 { return None; }
+    pkt.sender_mac.copy_from_slice(&buf[8..14]);
+    pkt.sender_ip.copy_from_slice(&buf[14..18]);
+    pkt.target_mac.copy_from_slice(&buf[18..24]);
+    pkt.target_ip.copy_from_slice(&buf[24..28]);
+    Some(pkt)
+}
+
+/// ARP payload answering `request` with `our_mac` for `our_ip`.
+pub fn build_arp_reply(request: &ArpPacket, our_mac: MacAddr, our_ip: [u8;4]) -> Vec<u8> {
+    let reply = ArpPacket {
+        opcode: ArpOp::Reply as u16,
+        sender_mac: our_mac,
+        sender_ip: our_ip,
+        target_mac: request.sender_mac,
+        target_ip: request.sender_ip,
+        ..*request
+    };
+    Vec::from(reply.serialize())
+}
+
+/// Broadcast Ethernet frame asking who has `target_ip`.
+pub fn build_arp_request(our_mac: MacAddr, our_ip: [u8;4], target_ip: [u8;4]) -> Vec<u8> {
+    let request = ArpPacket {
+        htype: 1,
+        ptype: ETHERTYPE_IPV4,
+        hlen: 6,
+        plen: 4,
+        opcode: ArpOp::Request as u16,
+        sender_mac: our_mac,
+        sender_ip: our_ip,
+        target_mac: [0; 6],
+        target_ip,
+    };
+    let mut out = alloc::vec![0u8; ETH_HEADER_LEN + ARP_HDR_LEN];
+    build_eth_frame([0xFF; 6], our_mac, ETHERTYPE_ARP, &request.serialize(), &mut out);
+    out
+}
+
 /// Parse an incoming ARP Ethernet frame and optionally build an ARP reply
 /// if `our_ip` and `our_mac` are provided and the packet is an ARP request
 /// directed at `our_ip`.
@@ -119,91 +211,13 @@ impl ArpCache {
 /// Returns Some(frame_bytes) containing a full Ethernet frame (eth header + arp)
 /// to transmit, or None if no reply should be sent or parse failed.
 pub fn handle_arp_packet(frame: &[u8], our_ip: Option<[u8;4]>, our_mac: Option<[u8;6]>) -> Option<Vec<u8>> {
-    // Minimum Ethernet + ARP packet size: 14 + 28 = 42
-    if frame.len() < 42 { return None; }
-
-    // Ethernet header
-    let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
-    if ethertype != 0x0806 { return None; } // not ARP
-
-    // ARP payload starts at offset 14
-    let arp = &frame[14..];
-    // hardware type (2), protocol type (2),
-    // hardware address length (1), protocol address length (1), operation code (2)
-    let opcode = u16::from_be_bytes([arp[6], arp[7]]);
-    let sender_hw = &arp[8..14];
-    let sender_proto = &arp[14..18];
-    let target_proto = &arp[24..28];
-
-    // Only handle Ethernet/IPv4 ARP (htype=1, ptype=0x0800)
-    if arp.len() < 28 { return None; }
-
-    if opcode == 1 {
-        // ARP Request
-        if let (Some(my_ip), Some(my_mac)) = (our_ip, our_mac) {
-            if target_proto == &my_ip {
-                // build ARP reply frame
-                let mut out: Vec<u8> = Vec::with_capacity(14 + 28);
-                // ethernet dst = sender_hw, src = my_mac, ethertype = 0x0806
-                out.extend_from_slice(sender_hw); // dst
-                out.extend_from_slice(&my_mac); // src
-                out.extend_from_slice(&0x0806u16.to_be_bytes()); // ethertype
-                // ARP payload
-                out.extend_from_slice(&1u16.to_be_bytes()); // htype = Ethernet
-                out.extend_from_slice(&0x0800u16.to_be_bytes()); // ptype = IPv4
-                out.push(6u8); // hlen
-                out.push(4u8); // plen
-                out.extend_from_slice(&2u16.to_be_bytes()); // opcode = reply
-                // sender hw addr (our_mac)
-                out.extend_from_slice(&my_mac);
-                // sender proto (our_ip)
-                out.extend_from_slice(&my_ip);
-                // target hw = sender_hw
-                out.extend_from_slice(sender_hw);
-                // target proto = sender_proto
-                out.extend_from_slice(sender_proto);
-                return Some(out);
-            }
-        }
-    }
-    // For ARP reply or other opcodes we do not craft responses in skeleton.
-    None
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn arp_cache_basic() {
-        let mut c = ArpCache::new();
-        assert!(c.lookup([0,0,0,0]).is_none());
-        c.insert([1,2,3,4], [5,6,7,8,9,10]);
-        assert_eq!(c.lookup([1,2,3,4]).unwrap(), [5,6,7,8,9,10]);
-    }
-
-    #[test]
-    fn parse_and_build() {
-        // build a request packet
-        let mut req = vec![];
-        req.extend_from_slice(&1u16.to_be_bytes()); // htype
-        req.extend_from_slice(&0x0800u16.to_be_bytes()); // ptype IPv4
-        req.push(6); req.push(4);
-        req.extend_from_slice(&1u16.to_be_bytes()); // request
-        let sender_mac = [1u8,2,3,4,5,6];
-        let sender_ip = [10u8,0,0,1];
-        let target_mac = [0u8;6];
-        let target_ip = [10u8,0,0,2];
-        req.extend_from_slice(&sender_mac);
-        req.extend_from_slice(&sender_ip);
-        req.extend_from_slice(&target_mac);
-        req.extend_from_slice(&target_ip);
-        let pkt = parse_arp_packet(&req).expect("parse");
-        assert_eq!(pkt.sender_ip, sender_ip);
-        let reply = build_arp_reply(&pkt, [9u8,9,9,9,9,9], [10u8,0,0,2]);
-        let parsed = parse_arp_packet(&reply).expect("parse reply");
-        assert_eq!(parsed.opcode, ArpOp::Reply as u16);
-        assert_eq!(parsed.sender_ip, [10u8,0,0,2]);
-        assert_eq!(parsed.target_ip, sender_ip);
-    }
+    let (eth, payload) = parse_eth_header(frame)?;
+    if eth.ethertype != ETHERTYPE_ARP { return None; }
+    let pkt = parse_arp_packet(payload)?;
+    let (my_ip, my_mac) = (our_ip?, our_mac?);
+    if pkt.opcode != ArpOp::Request as u16 || pkt.target_ip != my_ip { return None; }
+    let reply = build_arp_reply(&pkt, my_mac, my_ip);
+    let mut out = alloc::vec![0u8; ETH_HEADER_LEN + reply.len()];
+    build_eth_frame(pkt.sender_mac, my_mac, ETHERTYPE_ARP, &reply, &mut out)?;
+    Some(out)
 }
@@ -36,28 +36,3 @@ pub fn build_eth_frame(_dst: [u8;6], _src: [u8;6], _ethertype: u16, _payload: &[
     _out[14..14 + _payload.len()].copy_from_slice(_payload);
     Some(needed)
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn compile_parse() {
-        let b = [0u8; 64];
-        let _ = parse_eth_header(&b);
-    }
-
-    #[test]
-    fn build_and_parse_roundtrip() {
-        let dst = [1u8,2,3,4,5,6];
-        let src = [10u8,11,12,13,14,15];
-        let ethertype = ETHERTYPE_IPV4;
-        let payload = [0x45u8, 0, 0x00, 0x54];
-        let mut out = [0u8; 128];
-        let len = build_eth_frame(dst, src, ethertype, &payload, &mut out).expect("build failed");
-        let (hdr, pl) = parse_eth_header(&out[..len]).expect("parse failed");
-        assert_eq!(hdr.dst, dst);
-        assert_eq!(hdr.src, src);
-        assert_eq!(hdr.ethertype, ethertype);
-        assert_eq!(pl, &payload);
-    }
-}
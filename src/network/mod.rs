@@ -1,12 +1,7 @@
+pub mod config;
 pub mod device;
-pub mod e1000;
-pub mod buf;
-pub mod ethernet;
-pub mod arp;
-pub mod ipv4;
-pub mod icmp;
-pub mod udp;
-pub mod sockets;
-pub mod checksums;
-pub mod network;
-pub use self::network::*;
+pub mod link;
+pub mod internet;
+pub mod transport;
+pub mod stack;
+pub use self::stack::*;
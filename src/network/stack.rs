@@ -0,0 +1,217 @@
+// Network stack state and the receive loop.
+//
+// `init` hands the stack its device and interface configuration; `poll`
+// drains received frames and dispatches them by EtherType. ARP requests for
+// our address are answered and every ARP sender is learned into the cache.
+// IPv4 packets addressed to us are reassembled and queued for `recv_ipv4`.
+// `send_ipv4` fragments to the device MTU and resolves the next hop with ARP.
+//
+// The kernel runs `poll_task` on its executor. The NIC interrupt and the
+// timer tick call `wake`, so frames are handled soon after they arrive and
+// a card without a working interrupt line is still polled.
+
+use crate::network::config::{Ipv4Addr, NetConfig};
+use crate::network::device::{MacAddr, NetError, NetworkDevice, Result};
+use crate::network::internet::fragment::{self, Reassembler, DEFAULT_MEMORY_LIMIT, REASSEMBLY_TIMEOUT_SECS};
+use crate::network::internet::ipv4::{self, Ipv4Header};
+use crate::network::link::arp::{self, ArpCache};
+use crate::network::link::ethernet::{build_eth_frame, parse_eth_header, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETH_HEADER_LEN};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+/// Largest Ethernet frame without FCS: 1500 bytes of payload plus header
+/// and an 802.1Q tag.
+const MAX_FRAME_LEN: usize = 1518;
+/// Frames handled per `poll`, so a busy link cannot stall the caller.
+const POLL_BUDGET: usize = 32;
+/// Received datagrams kept for `recv_ipv4`; the oldest goes first.
+const MAX_QUEUED_DATAGRAMS: usize = 16;
+
+struct Stack {
+    device: Box<dyn NetworkDevice + Send>,
+    config: Option<NetConfig>,
+    arp: ArpCache,
+    reassembly: Reassembler,
+    datagrams: VecDeque<(Ipv4Header, Vec<u8>)>,
+}
+
+static STACK: Mutex<Option<Stack>> = Mutex::new(None);
+
+/// Set by `wake`, cleared when `poll_task` runs `poll`.
+static POLL_PENDING: AtomicBool = AtomicBool::new(false);
+static POLL_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Initialize the network stack with a device and optional static configuration.
+/// Passing `None` for `config` indicates DHCP or runtime configuration (not implemented).
+/// Calling it again replaces the device and forgets learned addresses.
+pub fn init(device: Box<dyn NetworkDevice + Send>, config: Option<NetConfig>) {
+    *STACK.lock() = Some(Stack {
+        device,
+        config,
+        arp: ArpCache::new(),
+        reassembly: Reassembler::new(DEFAULT_MEMORY_LIMIT, REASSEMBLY_TIMEOUT_SECS * crate::interrupts::TIMER_HZ),
+        datagrams: VecDeque::new(),
+    });
+}
+
+/// True once `init` has been called.
+pub fn is_initialized() -> bool { STACK.lock().is_some() }
+
+/// The interface configuration, if the stack has one.
+pub fn config() -> Option<NetConfig> { STACK.lock().as_ref().and_then(|s| s.config) }
+
+/// MAC address of the stack's device.
+pub fn mac_addr() -> Option<MacAddr> { STACK.lock().as_ref().map(|s| s.device.mac_addr()) }
+
+/// MAC address learned for `ip`.
+pub fn arp_lookup(ip: [u8; 4]) -> Option<MacAddr> { STACK.lock().as_ref().and_then(|s| s.arp.lookup(ip)) }
+
+/// Run `f` on the stack's device; `None` before `init`.
+pub fn with_device<R>(f: impl FnOnce(&mut dyn NetworkDevice) -> R) -> Option<R> {
+    STACK.lock().as_mut().map(|s| f(s.device.as_mut()))
+}
+
+/// Datagrams still waiting for fragments.
+pub fn reassembly_pending() -> usize { STACK.lock().as_ref().map_or(0, |s| s.reassembly.pending()) }
+
+/// Process received frames. Returns how many were handled; frames that
+/// do not parse or are not for us count too.
+pub fn poll() -> usize {
+    let mut guard = STACK.lock();
+    let stack = match guard.as_mut() {
+        Some(s) => s,
+        None => return 0,
+    };
+    let mut frame = [0u8; MAX_FRAME_LEN];
+    let mut handled = 0;
+    while handled < POLL_BUDGET {
+        // WouldBlock, or a device error that the next poll may not repeat
+        let len = match stack.device.receive(&mut frame) {
+            Ok(len) => len.min(MAX_FRAME_LEN),
+            Err(_) => break,
+        };
+        stack.handle_frame(&frame[..len]);
+        handled += 1;
+    }
+    handled
+}
+
+/// Have `poll_task` run `poll` again. Called from interrupt handlers, so it
+/// must not block or allocate.
+pub(crate) fn wake() {
+    POLL_PENDING.store(true, Ordering::Release);
+    POLL_WAKER.wake();
+}
+
+/// Resolves once `wake` has been called since the last time it resolved.
+struct PollSignal;
+
+impl Future for PollSignal {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // fast path: no need to register the waker
+        if POLL_PENDING.swap(false, Ordering::AcqRel) { return Poll::Ready(()); }
+        POLL_WAKER.register(cx.waker());
+        if POLL_PENDING.swap(false, Ordering::AcqRel) {
+            POLL_WAKER.take();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Executor task that runs `poll` each time the NIC interrupts or the
+/// timer ticks.
+pub async fn poll_task() {
+    loop {
+        PollSignal.await;
+        poll();
+    }
+}
+
+/// Next complete IPv4 datagram addressed to us, reassembled if it came in
+/// fragments.
+pub fn recv_ipv4() -> Option<(Ipv4Header, Vec<u8>)> {
+    STACK.lock().as_mut().and_then(|s| s.datagrams.pop_front())
+}
+
+/// Send `payload` to `dst` as one IPv4 datagram, in as many fragments as
+/// the device MTU needs. If the next hop's MAC address is not known yet an
+/// ARP request goes out instead and `WouldBlock` is returned; try again
+/// after `poll` has seen the reply. `Unsupported` without a configuration
+/// or a route to `dst`.
+pub fn send_ipv4(dst: Ipv4Addr, proto: u8, payload: &[u8]) -> Result<()> {
+    let mut guard = STACK.lock();
+    let stack = guard.as_mut().ok_or(NetError::Unsupported)?;
+    let config = stack.config.ok_or(NetError::Unsupported)?;
+    let hop = config.next_hop(dst).ok_or(NetError::Unsupported)?;
+    let our_mac = stack.device.mac_addr();
+    let dst_mac = if hop == [255; 4] || hop == config.broadcast() {
+        [0xFF; 6]
+    } else {
+        match stack.arp.lookup(hop) {
+            Some(mac) => mac,
+            None => {
+                stack.device.transmit(&arp::build_arp_request(our_mac, config.ip, hop))?;
+                return Err(NetError::WouldBlock);
+            }
+        }
+    };
+    let mut header = Ipv4Header::new(config.ip, dst, proto);
+    header.identification = ipv4::next_identification();
+    let packets = fragment::fragment(&header, payload, stack.device.mtu()).ok_or(NetError::BufferTooSmall)?;
+    let mut frame = Vec::new();
+    for packet in packets {
+        frame.resize(ETH_HEADER_LEN + packet.len(), 0);
+        build_eth_frame(dst_mac, our_mac, ETHERTYPE_IPV4, &packet, &mut frame).ok_or(NetError::BufferTooSmall)?;
+        stack.device.transmit(&frame)?;
+    }
+    Ok(())
+}
+
+impl Stack {
+    fn handle_frame(&mut self, frame: &[u8]) {
+        let (eth, payload) = match parse_eth_header(frame) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        if eth.ethertype == ETHERTYPE_ARP {
+            if let Some(pkt) = arp::parse_arp_packet(payload) {
+                // 0.0.0.0 is an address probe, not a mapping
+                if pkt.sender_ip != [0; 4] { self.arp.insert(pkt.sender_ip, pkt.sender_mac); }
+            }
+            let our_ip = self.config.map(|c| c.ip);
+            if let Some(reply) = arp::handle_arp_packet(frame, our_ip, Some(self.device.mac_addr())) {
+                // a dropped reply is retried by the asker
+                let _ = self.device.transmit(&reply);
+            }
+        } else if eth.ethertype == ETHERTYPE_IPV4 {
+            self.handle_ipv4(payload);
+        }
+    }
+
+    fn handle_ipv4(&mut self, packet: &[u8]) {
+        let config = match self.config {
+            Some(c) => c,
+            None => return,
+        };
+        let (header, payload) = match ipv4::parse_ipv4_header(packet) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        if header.dst != config.ip && header.dst != config.broadcast() && header.dst != [255; 4] { return; }
+        if let Some(datagram) = self.reassembly.push(&header, payload, crate::interrupts::ticks()) {
+            if self.datagrams.len() >= MAX_QUEUED_DATAGRAMS { self.datagrams.pop_front(); }
+            self.datagrams.push_back(datagram);
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod udp;
+pub mod sockets;
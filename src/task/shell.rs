@@ -1,43 +1,18 @@
 use crate::{print, println};
 use alloc::{string::String, vec::Vec};
 use crate::task::keyboard::try_pop_key;
-use crate::fs::directory::DirectoryEntry;
-use crate::fs::fs::FileSystem;
-use crate::fs::mock_device::MockDevice;
+use crate::fs::directory::{ATTR_ARCHIVE, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
+use crate::fs::vfs::Vfs;
+use spin::Mutex;
 
-// formats file name to FAT 8.3 style
-fn format_8_3(name: &str) -> String {
-    let up = name.to_ascii_uppercase();
-    let mut parts = up.splitn(2, '.');
-    let base = parts.next().unwrap_or("");
-    let ext = parts.next().unwrap_or("");
-    let mut base_buf = [b' '; 8];
-    for (i, &b) in base.as_bytes().iter().take(8).enumerate() {
-        base_buf[i] = b;
-    }
-    let mut ext_buf = [b' '; 3];
-    for (i, &b) in ext.as_bytes().iter().take(3).enumerate() {
-        ext_buf[i] = b;
-    }
-    let mut s = String::new();
-    for &b in base_buf.iter() { s.push(b as char); }
-    for &b in ext_buf.iter() { s.push(b as char); }
-    s
-}
-
-
-// Converts DirectoryEntry back into name + extension string.
-fn display_entry_name(e: &DirectoryEntry) -> String {
-    let base = core::str::from_utf8(&e.name).unwrap_or("").trim_end_matches(' ');
-    let ext = core::str::from_utf8(&e.ext).unwrap_or("").trim_end_matches(' ');
-    if ext.is_empty() {
-        String::from(base)
-    } else {
-        let mut s = String::new();
-        s.push_str(base);
-        s.push('.');
-        s.push_str(ext);
-        s
+// Maps an `attrib` flag letter to its attribute bit.
+fn attr_bit(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        'r' => Some(ATTR_READ_ONLY),
+        'h' => Some(ATTR_HIDDEN),
+        's' => Some(ATTR_SYSTEM),
+        'a' => Some(ATTR_ARCHIVE),
+        _ => None,
     }
 }
 
@@ -56,25 +31,27 @@ pub fn flush_keypresses() {
     shell_input(&s);
 }
 
-// A plain global pointer to the registered FileSystem.
-static mut SHELL_FS_PTR: *mut FileSystem<'static, MockDevice<'static>> = core::ptr::null_mut();
+// The mount table the shell works on. Paths typed at the prompt are
+// resolved from its root.
+static SHELL_VFS: Mutex<Vfs<'static>> = Mutex::new(Vfs::new());
 
-/// Register a 'static FileSystem for the shell to use. Call this once during
-/// early boot after you have created/mounted a FileSystem with a 'static
-/// lifetime.
-///
-/// Raw pointer here so callers (tests or init code) don't create
-/// overlapping mutable borrows when they keep a local `&'static mut` handle.
-pub fn new(fs: *mut FileSystem<'static, MockDevice<'static>>) {
-    unsafe {
-        SHELL_FS_PTR = fs;
-    }
+/// Hand the shell its mount table. Call this during early boot once the
+/// root file system (and anything else) is mounted; it replaces whatever
+/// table the shell had before.
+pub fn new(vfs: Vfs<'static>) {
+    *SHELL_VFS.lock() = vfs;
     // print initial prompt
     print!("$ ");
 }
 
-/// Execute a single input line against the registered FileSystem. If no
-/// FileSystem was registered, this prints an error.
+/// Run `f` on the shell's mount table, e.g. to mount a disk later or to
+/// look at what a command did.
+pub fn with_vfs<R>(f: impl FnOnce(&mut Vfs<'static>) -> R) -> R {
+    f(&mut SHELL_VFS.lock())
+}
+
+/// Execute a single input line against the shell's mount table. If nothing
+/// is mounted, this prints an error.
 pub fn shell_input(s: &str) -> () {
     if (s.len() == 0) {
         print!("$ ");
@@ -84,27 +61,47 @@ pub fn shell_input(s: &str) -> () {
     if line_trim.is_empty() { return; }
     let mut parts = line_trim.split_whitespace();
     let cmd = parts.next().unwrap_or("").to_ascii_lowercase();
-    unsafe {
-        if SHELL_FS_PTR.is_null() {
-            println!("shell: no filesystem registered");
+    {
+        let mut guard = SHELL_VFS.lock();
+        if guard.mounts().is_empty() {
+            println!("shell: no filesystem mounted");
             return;
         }
-        let fs: &mut FileSystem<'static, MockDevice<'static>> = &mut *SHELL_FS_PTR;
+        let vfs = &mut *guard;
         match cmd.as_str() {
             "help" => {
-                println!("Commands: help, ls, read <name>, write <name> <text>, delete <name>");
+                println!("Commands: help, ls [-l] [dir], read <name>, write <name> <text>, delete <name>, rename <old> <new>, mkdir <dir>, rmdir <dir>, attrib <name> [+r|-r|+h|-h|+s|-s|+a|-a], fsck [mount] [repair], df, mount");
             }
             "ls" => {
-                let list = fs.list_root();
-                for e in list.iter() {
-                    let name = display_entry_name(e);
-                    println!("{}\t{} bytes", name, e.file_size);
+                let mut arg = parts.next();
+                let long = arg == Some("-l");
+                if long { arg = parts.next(); }
+                let path = arg.unwrap_or("/");
+                match vfs.list_dir(path) {
+                    Ok(list) => {
+                        for e in list.iter() {
+                            let name = e.display_name();
+                            if long {
+                                // attributes, size, modification time, name
+                                let flag = |set: bool, c: char| if set { c } else { '-' };
+                                println!(
+                                    "{}{}{}{}{} {:>10} {} {}{}",
+                                    flag(e.is_dir(), 'd'), flag(e.is_read_only(), 'r'), flag(e.is_hidden(), 'h'),
+                                    flag(e.is_system(), 's'), flag(e.is_archive(), 'a'),
+                                    e.file_size, e.modified, name, if e.is_dir() { "/" } else { "" });
+                            } else if e.is_dir() {
+                                println!("{}/\t<dir>", name);
+                            } else {
+                                println!("{}\t{} bytes", name, e.file_size);
+                            }
+                        }
+                    }
+                    Err(e) => println!("ls error: {:?}", e),
                 }
             }
             "read" => {
                 if let Some(name) = parts.next() {
-                    let name11 = format_8_3(name);
-                    match fs.read_file(&name11) {
+                    match vfs.read_file(name) {
                         Ok(data) => {
                             if let Ok(s) = core::str::from_utf8(&data) {
                                 println!("{}", s);
@@ -124,8 +121,7 @@ pub fn shell_input(s: &str) -> () {
                 if let Some(name) = parts.next() {
                     let rest: Vec<&str> = parts.collect();
                     let data = rest.join(" ");
-                    let name11 = format_8_3(name);
-                    match fs.write_file(&name11, data.as_bytes()) {
+                    match vfs.write_file(name, data.as_bytes()) {
                         Ok(()) => println!("wrote {} bytes", data.len()),
                         Err(e) => println!("write error: {:?}", e),
                     }
@@ -135,8 +131,7 @@ pub fn shell_input(s: &str) -> () {
             }
             "delete" => {
                 if let Some(name) = parts.next() {
-                    let name11 = format_8_3(name);
-                    match fs.delete(&name11) {
+                    match vfs.delete(name) {
                         Ok(()) => println!("deleted {}", name),
                         Err(e) => println!("delete error: {:?}", e),
                     }
@@ -144,6 +139,111 @@ pub fn shell_input(s: &str) -> () {
                     println!("usage: delete <NAME>");
                 }
             }
+            "rename" => {
+                match (parts.next(), parts.next()) {
+                    (Some(old), Some(new)) => match vfs.rename(old, new) {
+                        Ok(()) => println!("renamed {} to {}", old, new),
+                        Err(e) => println!("rename error: {:?}", e),
+                    },
+                    _ => println!("usage: rename <OLD> <NEW>"),
+                }
+            }
+            "mkdir" => {
+                if let Some(name) = parts.next() {
+                    match vfs.mkdir(name) {
+                        Ok(()) => println!("created {}", name),
+                        Err(e) => println!("mkdir error: {:?}", e),
+                    }
+                } else {
+                    println!("usage: mkdir <DIR>");
+                }
+            }
+            "rmdir" => {
+                if let Some(name) = parts.next() {
+                    match vfs.rmdir(name) {
+                        Ok(()) => println!("removed {}", name),
+                        Err(e) => println!("rmdir error: {:?}", e),
+                    }
+                } else {
+                    println!("usage: rmdir <DIR>");
+                }
+            }
+            "attrib" => {
+                if let Some(name) = parts.next() {
+                    let mut attr = match vfs.attributes(name) {
+                        Ok(a) => a,
+                        Err(e) => {
+                            println!("attrib error: {:?}", e);
+                            print!("$ ");
+                            return;
+                        }
+                    };
+                    for flag in parts {
+                        let mut chars = flag.chars();
+                        let (op, bit) = (chars.next(), chars.next().and_then(attr_bit));
+                        match (op, bit) {
+                            (Some('+'), Some(b)) => attr |= b,
+                            (Some('-'), Some(b)) => attr &= !b,
+                            _ => println!("attrib: ignoring flag {}", flag),
+                        }
+                    }
+                    if let Err(e) = vfs.set_attributes(name, attr) {
+                        println!("attrib error: {:?}", e);
+                    } else {
+                        let mut flags = String::new();
+                        for (bit, c) in [(ATTR_READ_ONLY, 'R'), (ATTR_HIDDEN, 'H'), (ATTR_SYSTEM, 'S'), (ATTR_ARCHIVE, 'A')] {
+                            flags.push(if attr & bit != 0 { c } else { '-' });
+                        }
+                        println!("{}\t{}", flags, name);
+                    }
+                } else {
+                    println!("usage: attrib <NAME> [+r|-r|+h|-h|+s|-s|+a|-a]");
+                }
+            }
+            "fsck" => {
+                let mut arg = parts.next();
+                let path = match arg {
+                    Some(p) if p != "repair" => { arg = parts.next(); p }
+                    _ => "/",
+                };
+                let repair = arg == Some("repair");
+                match vfs.check(path, repair) {
+                    Ok(report) => {
+                        for problem in report.problems.iter() {
+                            println!("{}", problem);
+                        }
+                        println!(
+                            "{} files, {} directories, {} free clusters, {} problems{}",
+                            report.files,
+                            report.directories,
+                            report.free_clusters,
+                            report.problems.len(),
+                            if repair && !report.is_clean() { " (repaired)" } else { "" });
+                    }
+                    Err(e) => println!("fsck error: {:?}", e),
+                }
+            }
+            "df" => {
+                for m in vfs.mounts() {
+                    match vfs.stat(&m.path) {
+                        Ok(st) => {
+                            println!(
+                                "{}: {} bytes total, {} used, {} free ({} of {} clusters of {} bytes free)",
+                                m.path, st.total_bytes, st.used_bytes, st.free_bytes,
+                                st.free_clusters, st.total_clusters, st.cluster_size);
+                            println!(
+                                "{}: {} files, {} directories, {}% fragmented",
+                                m.path, st.files, st.directories, st.fragmentation());
+                        }
+                        Err(e) => println!("df error: {}: {:?}", m.path, e),
+                    }
+                }
+            }
+            "mount" => {
+                for m in vfs.mounts() {
+                    println!("{}\t{}", m.path, m.fs_type);
+                }
+            }
             other => {
                 println!("unknown command: {}", other);
             }
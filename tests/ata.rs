@@ -0,0 +1,136 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rz_rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use rz_rust_os::allocator;
+use rz_rust_os::memory::{self, BootInfoFrameAllocator};
+use x86_64::VirtAddr;
+
+use rz_rust_os::drivers::ata::{self, AtaDrive, Channel};
+use rz_rust_os::fs::block_device::{BlockDevice, IoError};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rz_rust_os::init();
+    // Initialize memory and heap so tests can use `alloc` (Vec, Box, etc.).
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization in tests failed");
+
+    test_main();
+    loop {}
+}
+
+/// QEMU boots the test kernel from the bootimage attached as the primary
+/// master IDE disk, so that drive is always present.
+fn boot_disk() -> AtaDrive {
+    AtaDrive::identify(Channel::Primary, false)
+        .expect("identify failed")
+        .expect("no primary master")
+}
+
+/// Scratch disks attached by tools/qemu-runner.sh. QEMU runs them with
+/// `snapshot=on`, so they read as zeros until a test writes them.
+const SLAVE_SECTORS: u64 = 2048;
+const LBA48_SECTORS: u64 = 129 * 1024 * 1024 * 2;
+
+fn scratch_disk(channel: Channel, slave: bool) -> AtaDrive {
+    AtaDrive::identify(channel, slave)
+        .expect("identify failed")
+        .expect("scratch disk missing")
+}
+
+fn pattern(seed: u8) -> [u8; 512] {
+    let mut buf = [0u8; 512];
+    for (i, b) in buf.iter_mut().enumerate() { *b = i as u8 ^ seed; }
+    buf
+}
+
+#[test_case]
+fn ata_identify_boot_disk() {
+    let disk = boot_disk();
+    assert!(disk.sector_count() > 0);
+    assert!(!disk.model().is_empty());
+    assert!(ata::probe().iter().any(|d| d.channel() == Channel::Primary && !d.is_slave()));
+}
+
+#[test_case]
+fn ata_probe_finds_scratch_disks() {
+    let drives = ata::probe();
+    assert_eq!(drives.len(), 3);
+    let slave = drives.iter().find(|d| d.channel() == Channel::Primary && d.is_slave()).expect("no primary slave");
+    assert_eq!(slave.sector_count(), SLAVE_SECTORS);
+    let big = drives.iter().find(|d| d.channel() == Channel::Secondary && !d.is_slave()).expect("no secondary master");
+    assert_eq!(big.sector_count(), LBA48_SECTORS);
+    assert!(big.supports_lba48());
+}
+
+#[test_case]
+fn ata_read_boot_sector() {
+    let mut disk = boot_disk();
+    let mut buf = [0u8; 512];
+    disk.read_sector(0, &mut buf).expect("read failed");
+    assert_eq!(&buf[510..512], &[0x55, 0xAA]);
+    let last = disk.sector_count() - 1;
+    disk.read_sector(last, &mut buf).expect("read failed");
+    assert_eq!(disk.read_sector(last + 1, &mut buf), Err(IoError::OutOfRange));
+    assert_eq!(disk.read_sector(0, &mut buf[..256]), Err(IoError::BadBuffer));
+}
+
+#[test_case]
+fn ata_write_roundtrip() {
+    let mut disk = scratch_disk(Channel::Primary, true);
+    let mut back = [0xFFu8; 512];
+    disk.read_sector(0, &mut back).expect("read failed");
+    assert_eq!(back, [0u8; 512]);
+    for lba in [0, 1, SLAVE_SECTORS - 1] {
+        disk.write_sector(lba, &pattern(lba as u8)).expect("write failed");
+    }
+    disk.flush().expect("flush failed");
+    for lba in [0, 1, SLAVE_SECTORS - 1] {
+        disk.read_sector(lba, &mut back).expect("read failed");
+        assert_eq!(back, pattern(lba as u8));
+    }
+    // the boot disk on the same channel is untouched
+    boot_disk().read_sector(0, &mut back).expect("read failed");
+    assert_eq!(&back[510..512], &[0x55, 0xAA]);
+    assert_eq!(disk.write_sector(SLAVE_SECTORS, &back), Err(IoError::OutOfRange));
+    // each command completes with an interrupt on IRQ 14
+    assert!(ata::irq_count(Channel::Primary) > 0);
+}
+
+#[test_case]
+fn ata_lba48_roundtrip_on_secondary() {
+    let mut disk = scratch_disk(Channel::Secondary, false);
+    let irqs = ata::irq_count(Channel::Secondary);
+    // the first sector LBA28 cannot reach, and the last one on the disk
+    let high = [1 << 28, LBA48_SECTORS - 1];
+    for (i, &lba) in high.iter().enumerate() {
+        disk.write_sector(lba, &pattern(0xA0 + i as u8)).expect("write failed");
+    }
+    disk.flush().expect("flush failed");
+    let mut back = [0u8; 512];
+    for (i, &lba) in high.iter().enumerate() {
+        disk.read_sector(lba, &mut back).expect("read failed");
+        assert_eq!(back, pattern(0xA0 + i as u8));
+    }
+    // a truncated LBA would have landed at the start of the disk
+    disk.read_sector(0, &mut back).expect("read failed");
+    assert_eq!(back, [0u8; 512]);
+    // the secondary channel interrupts on IRQ 15
+    assert!(ata::irq_count(Channel::Secondary) > irqs);
+}
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rz_rust_os::test_panic_handler(info)
+}
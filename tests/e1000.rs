@@ -0,0 +1,140 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rz_rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+use alloc::boxed::Box;
+
+use bootloader::{entry_point, BootInfo};
+use rz_rust_os::allocator;
+use rz_rust_os::memory::{self, BootInfoFrameAllocator};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+use rz_rust_os::network;
+use rz_rust_os::network::config::NetConfig;
+use rz_rust_os::network::device::e1000::{self, E1000};
+use rz_rust_os::network::device::{MacAddr, NetError, NetworkDevice};
+use rz_rust_os::network::link::arp::{self, ArpOp};
+use rz_rust_os::network::link::ethernet::{parse_eth_header, ETHERTYPE_ARP};
+
+/// QEMU's default MAC for the first NIC, and the one its user-mode network
+/// gives the router at 10.0.2.2.
+const QEMU_MAC: MacAddr = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+const ROUTER_MAC: MacAddr = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+const OUR_IP: [u8; 4] = [10, 0, 2, 15];
+const ROUTER_IP: [u8; 4] = [10, 0, 2, 2];
+
+/// Receive attempts before a reply is considered lost.
+const RECEIVE_SPINS: u32 = 50_000_000;
+
+static NIC: Mutex<Option<E1000>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rz_rust_os::init();
+    // Initialize memory and heap so tests can use `alloc` (Vec, Box, etc.).
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization in tests failed");
+    // registers are mapped and the rings allocated here, where the mapper
+    // and frame allocator are available
+    let nic = E1000::probe(&mut mapper, &mut frame_allocator, phys_mem_offset)
+        .expect("e1000 init failed")
+        .expect("no e1000 device");
+    *NIC.lock() = Some(nic);
+
+    test_main();
+    loop {}
+}
+
+/// Receive frames until one is an ARP reply, returning it.
+fn wait_for_arp_reply(nic: &mut E1000) -> Option<arp::ArpPacket> {
+    let mut frame = [0u8; 1518];
+    for _ in 0..RECEIVE_SPINS {
+        match nic.receive(&mut frame) {
+            Ok(len) => {
+                let (eth, payload) = match parse_eth_header(&frame[..len]) {
+                    Some(parsed) => parsed,
+                    None => continue,
+                };
+                if eth.ethertype != ETHERTYPE_ARP { continue; }
+                let pkt = arp::parse_arp_packet(payload);
+                if pkt.as_ref().is_some_and(|p| p.opcode == ArpOp::Reply as u16) { return pkt; }
+            }
+            Err(_) => core::hint::spin_loop(),
+        }
+    }
+    None
+}
+
+#[test_case]
+fn e1000_reads_mac_from_eeprom() {
+    let guard = NIC.lock();
+    let nic = guard.as_ref().expect("no nic");
+    assert_eq!(nic.mac_addr(), QEMU_MAC);
+    assert_eq!(nic.mtu(), 1500);
+    assert!(nic.mmio_base() as u64 >= memory::MMIO_START);
+}
+
+#[test_case]
+fn e1000_link_is_up() {
+    let guard = NIC.lock();
+    let nic = guard.as_ref().expect("no nic");
+    assert!(nic.link_up());
+    assert_eq!(nic.link_speed(), 1000);
+}
+
+#[test_case]
+fn e1000_rejects_oversized_frames() {
+    let mut guard = NIC.lock();
+    let nic = guard.as_mut().expect("no nic");
+    assert_eq!(nic.transmit(&[0u8; 1515]), Err(NetError::BufferTooSmall));
+    let mut buf = [0u8; 64];
+    // nothing has been sent, so nothing has come back
+    assert_eq!(nic.receive(&mut buf), Err(NetError::WouldBlock));
+}
+
+#[test_case]
+fn e1000_arp_roundtrip_with_router() {
+    let mut guard = NIC.lock();
+    let nic = guard.as_mut().expect("no nic");
+    let irqs = e1000::irq_count();
+    // more requests than transmit slots, so the ring wraps
+    for _ in 0..40 {
+        let request = arp::build_arp_request(QEMU_MAC, OUR_IP, ROUTER_IP);
+        nic.transmit(&request).expect("transmit failed");
+        let reply = wait_for_arp_reply(nic).expect("no ARP reply from the router");
+        assert_eq!((reply.sender_mac, reply.sender_ip), (ROUTER_MAC, ROUTER_IP));
+        assert_eq!((reply.target_mac, reply.target_ip), (QEMU_MAC, OUR_IP));
+    }
+    if nic.is_interrupt_driven() {
+        assert!(e1000::irq_count() > irqs);
+        // the replies raised receive interrupts, which the handler kept
+        assert_ne!(nic.interrupt_handler() & e1000::ICR_RXT0, 0);
+    }
+}
+
+#[test_case]
+fn e1000_drives_the_stack() {
+    let nic = NIC.lock().take().expect("no nic");
+    network::init(Box::new(nic), Some(NetConfig::qemu_user()));
+    let request = arp::build_arp_request(QEMU_MAC, OUR_IP, ROUTER_IP);
+    network::with_device(|d| d.transmit(&request)).expect("stack has no device").expect("transmit failed");
+    for _ in 0..RECEIVE_SPINS {
+        if network::arp_lookup(ROUTER_IP).is_some() { break; }
+        network::poll();
+    }
+    assert_eq!(network::arp_lookup(ROUTER_IP), Some(ROUTER_MAC));
+}
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rz_rust_os::test_panic_handler(info)
+}
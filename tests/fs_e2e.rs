@@ -5,14 +5,30 @@
 #![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use bootloader::{entry_point, BootInfo};
 use rz_rust_os::allocator;
 use rz_rust_os::memory::{self, BootInfoFrameAllocator};
 use x86_64::VirtAddr;
 
-use rz_rust_os::fs::mock_device::MockDevice;
-use rz_rust_os::fs::fs::FileSystem;
+use rz_rust_os::fs::mock_device::{FaultyDevice, MockDevice, MockDeviceFixed};
+use rz_rust_os::fs::journal;
+use rz_rust_os::fs::cached_device::CachedDevice;
+use rz_rust_os::fs::partition::{self, Partition, PartitionError, PartitionKind, GUID_BASIC_DATA};
+use rz_rust_os::fs::fs::{FileSystem, FsError, FsStat, MountOptions};
+use rz_rust_os::fs::format::FormatOptions;
+use rz_rust_os::fs::file::SeekFrom;
+use rz_rust_os::fs::block_device::{BlockDevice, IoError};
+use rz_rust_os::fs::boot_sector::{BootSector, FatType};
+use rz_rust_os::fs::fat_table::FatTable;
+use rz_rust_os::fs::fs_info::FsInfo;
+use rz_rust_os::fs::check::{self, Problem};
+use rz_rust_os::fs::time::{self, DateTime};
+use rz_rust_os::fs::vfs::{FileSystemOps, Vfs};
+use rz_rust_os::fs::tmpfs::Tmpfs;
+use rz_rust_os::fs::directory::{Directory, DirectoryEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
 
 entry_point!(main);
 
@@ -35,7 +51,7 @@ fn e2e_format_write_read_list_delete() {
         let buf = &mut BUF[..];
         let mut dev = MockDevice::new(buf);
         // format device
-        FileSystem::format(&mut dev, 2880).expect("format failed");
+        FileSystem::format(&mut dev).expect("format failed");
         // mount
         let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
         // write a small file
@@ -45,7 +61,7 @@ fn e2e_format_write_read_list_delete() {
         let read = fs.read_file("HELLO   TXT").expect("read failed");
         assert_eq!(&read[..], &data[..]);
         // list
-        let list = fs.list_root();
+        let list = fs.list_root().expect("list failed");
         assert!(list.len() >= 1);
         let mut found = false;
         for e in list.iter() {
@@ -57,7 +73,7 @@ fn e2e_format_write_read_list_delete() {
         assert!(found, "file not found in root listing");
         // delete
         fs.delete("HELLO   TXT").expect("delete failed");
-        let list2 = fs.list_root();
+        let list2 = fs.list_root().expect("list failed");
         // file should no longer be present
         let mut still = false;
         for e in list2.iter() {
@@ -70,6 +86,1216 @@ fn e2e_format_write_read_list_delete() {
     }
 }
 
+#[test_case]
+fn e2e_nested_directories() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.mkdir("/A").expect("mkdir A failed");
+        fs.mkdir("/A/B").expect("mkdir A/B failed");
+        fs.write_file("/A/B/C.TXT", b"nested").expect("nested write failed");
+        assert_eq!(&fs.read_file("/A/B/C.TXT").expect("nested read failed")[..], b"nested");
+        assert_eq!(&fs.read_file("A/./B/../B/C.TXT").expect("dot path read failed")[..], b"nested");
+
+        // root only shows the directory, flagged with the directory attribute
+        let root = fs.list_root().expect("list failed");
+        assert_eq!(root.len(), 1);
+        assert!(root[0].is_dir());
+
+        // subdirectories start with . and .. entries
+        let b = fs.list_dir("/A/B").expect("list_dir failed");
+        assert_eq!(b.len(), 3);
+        assert_eq!(&b[0].name, b".       ");
+        assert_eq!(&b[1].name, b"..      ");
+        assert_eq!(b[1].start_cluster, root[0].start_cluster);
+
+        assert!(matches!(fs.read_file("/A/B"), Err(FsError::IsADirectory)));
+        assert!(matches!(fs.list_dir("/A/B/C.TXT"), Err(FsError::NotADirectory)));
+        assert!(matches!(fs.mkdir("/A/B"), Err(FsError::FileAlreadyExists)));
+        assert!(matches!(fs.mkdir("/X/Y"), Err(FsError::FileNotFound)));
+    }
+}
+
+#[test_case]
+fn e2e_rmdir() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.mkdir("/DIR").expect("mkdir failed");
+        fs.write_file("/DIR/F.TXT", b"x").expect("write failed");
+        assert!(matches!(fs.rmdir("/DIR"), Err(FsError::DirectoryNotEmpty)));
+        assert!(matches!(fs.delete("/DIR"), Err(FsError::IsADirectory)));
+        assert!(matches!(fs.rmdir("/DIR/F.TXT"), Err(FsError::NotADirectory)));
+        fs.delete("/DIR/F.TXT").expect("delete failed");
+        fs.rmdir("/DIR").expect("rmdir failed");
+        assert_eq!(fs.list_root().expect("list failed").len(), 0);
+        assert!(matches!(fs.list_dir("/DIR"), Err(FsError::FileNotFound)));
+    }
+}
+
+#[test_case]
+fn e2e_directory_grows_past_one_cluster() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.mkdir("/LOGS").expect("mkdir failed");
+        // one 512-byte cluster holds 16 entries, two of which are . and ..
+        let names = [
+            "/LOGS/F0.TXT", "/LOGS/F1.TXT", "/LOGS/F2.TXT", "/LOGS/F3.TXT", "/LOGS/F4.TXT",
+            "/LOGS/F5.TXT", "/LOGS/F6.TXT", "/LOGS/F7.TXT", "/LOGS/F8.TXT", "/LOGS/F9.TXT",
+            "/LOGS/FA.TXT", "/LOGS/FB.TXT", "/LOGS/FC.TXT", "/LOGS/FD.TXT", "/LOGS/FE.TXT",
+            "/LOGS/FF.TXT", "/LOGS/FG.TXT", "/LOGS/FH.TXT",
+        ];
+        for n in names.iter() {
+            fs.write_file(n, n.as_bytes()).expect("write failed");
+        }
+        assert_eq!(fs.list_dir("/LOGS").expect("list failed").len(), names.len() + 2);
+        for n in names.iter() {
+            assert_eq!(&fs.read_file(n).expect("read failed")[..], n.as_bytes());
+        }
+    }
+}
+
+#[test_case]
+fn e2e_arbitrary_names() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file("KERNEL.BIN", &[0xde, 0xad, 0xbe, 0xef]).expect("bin write failed");
+        fs.write_file("config.cfg", b"key=value").expect("cfg write failed");
+        fs.write_file("README", b"no extension").expect("extensionless write failed");
+        fs.write_file("$LOG_1~.{A}", b"specials").expect("special chars write failed");
+        assert_eq!(&fs.read_file("kernel.bin").expect("read failed")[..], &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(&fs.read_file("CONFIG.CFG").expect("read failed")[..], b"key=value");
+        assert_eq!(&fs.read_file("README     ").expect("read failed")[..], b"no extension");
+
+        for bad in ["A*B.TXT", "Q?.TXT", "PIPE|.TXT", "ANGLE<.TXT", ""].iter() {
+            assert!(matches!(fs.write_file(bad, b"x"), Err(FsError::InvalidName)), "accepted {:?}", bad);
+        }
+        assert!(matches!(fs.mkdir("/BAD:DIR"), Err(FsError::InvalidName)));
+    }
+}
+
+#[test_case]
+fn e2e_attributes() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file("LOG.TXT", b"log").expect("write failed");
+        assert_eq!(fs.attributes("LOG.TXT").expect("attr failed"), ATTR_ARCHIVE);
+
+        fs.write_file_with_attr("SYS.BIN", b"sys", ATTR_SYSTEM | ATTR_HIDDEN | ATTR_DIRECTORY)
+            .expect("write with attr failed");
+        let sys = fs.list_root().expect("list failed").into_iter().find(|e| &e.name == b"SYS     ").expect("SYS missing");
+        assert!(sys.is_system() && sys.is_hidden() && !sys.is_dir());
+
+        // read-only files cannot be deleted or replaced
+        fs.set_attributes("LOG.TXT", ATTR_READ_ONLY).expect("set attr failed");
+        assert_eq!(fs.attributes("LOG.TXT").expect("attr failed"), ATTR_READ_ONLY);
+        assert!(matches!(fs.delete("LOG.TXT"), Err(FsError::ReadOnly)));
+        assert!(matches!(fs.write_file("LOG.TXT", b"new"), Err(FsError::ReadOnly)));
+        assert_eq!(&fs.read_file("LOG.TXT").expect("read failed")[..], b"log");
+        fs.set_attributes("LOG.TXT", 0).expect("clear attr failed");
+        fs.delete("LOG.TXT").expect("delete failed");
+
+        // the directory bit survives attribute changes
+        fs.mkdir("/D").expect("mkdir failed");
+        fs.set_attributes("/D", ATTR_HIDDEN | ATTR_READ_ONLY).expect("set dir attr failed");
+        assert_eq!(fs.attributes("/D").expect("attr failed"), ATTR_DIRECTORY | ATTR_HIDDEN | ATTR_READ_ONLY);
+        assert!(matches!(fs.rmdir("/D"), Err(FsError::ReadOnly)));
+        fs.set_attributes("/D", 0).expect("clear dir attr failed");
+        fs.rmdir("/D").expect("rmdir failed");
+    }
+}
+
+#[test_case]
+fn e2e_long_file_names() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file("Quarterly Report.txt", b"q1").expect("long write failed");
+        fs.write_file("Quarterly Results.txt", b"q2").expect("second long write failed");
+        fs.mkdir("/Project Files").expect("long mkdir failed");
+        fs.write_file("/Project Files/notes for later.md", b"nested").expect("nested long write failed");
+
+        let root = fs.list_root().expect("list failed");
+        assert_eq!(root.len(), 3);
+        assert_eq!(root[0].long_name.as_deref(), Some("Quarterly Report.txt"));
+        assert_eq!(&root[0].short_name(), b"QUARTE~1TXT");
+        assert_eq!(&root[1].short_name(), b"QUARTE~2TXT");
+        assert_eq!(root[2].display_name(), "Project Files");
+
+        // lookups work by long name (any case) and by generated short name
+        assert_eq!(&fs.read_file("quarterly results.TXT").expect("read failed")[..], b"q2");
+        assert_eq!(&fs.read_file("QUARTE~1.TXT").expect("read failed")[..], b"q1");
+        assert_eq!(&fs.read_file("/project files/Notes For Later.md").expect("read failed")[..], b"nested");
+        assert!(matches!(fs.write_file("QUARTERLY REPORT.TXT", b"dup"), Err(FsError::FileAlreadyExists)));
+
+        // deleting removes the LFN fragments too, so the slots are reused
+        fs.delete("Quarterly Report.txt").expect("delete failed");
+        assert_eq!(fs.list_root().expect("list failed").len(), 2);
+        fs.write_file("Quarterly Report.txt", b"again").expect("rewrite failed");
+        assert_eq!(&fs.read_file("Quarterly Report.txt").expect("read failed")[..], b"again");
+    }
+}
+
+#[test_case]
+fn e2e_long_name_spanning_clusters() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.mkdir("/D").expect("mkdir failed");
+        // 200 characters need 16 LFN entries + 1 short entry, more than one
+        // 16-slot cluster can hold
+        let mut name = alloc::string::String::new();
+        for _ in 0..20 { name.push_str("abcdefghij"); }
+        let mut path = alloc::string::String::from("/D/");
+        path.push_str(&name);
+        fs.write_file(&path, b"long").expect("write failed");
+        let list = fs.list_dir("/D").expect("list failed");
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[2].long_name.as_deref(), Some(name.as_str()));
+        assert_eq!(&fs.read_file(&path).expect("read failed")[..], b"long");
+    }
+}
+
+#[test_case]
+fn e2e_file_handle_read_write_seek() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+
+        let data: alloc::vec::Vec<u8> = (0..1300u32).map(|i| (i % 253) as u8).collect();
+        {
+            let mut f = fs.create("LOG.TXT").expect("create failed");
+            assert!(f.is_empty());
+            // odd-sized chunks straddle sector and cluster boundaries
+            for chunk in data.chunks(97) {
+                assert_eq!(f.write(chunk).expect("write failed"), chunk.len());
+            }
+            assert_eq!(f.len(), 1300);
+        }
+        assert_eq!(fs.read_file("LOG.TXT").expect("read failed"), data);
+
+        let mut f = fs.open("LOG.TXT").expect("open failed");
+        let mut part = [0u8; 40];
+        assert_eq!(f.seek(SeekFrom::Start(500)).expect("seek failed"), 500);
+        assert_eq!(f.read(&mut part).expect("read failed"), 40);
+        assert_eq!(&part[..], &data[500..540]);
+        assert_eq!(f.position(), 540);
+        assert_eq!(f.seek(SeekFrom::Current(-540)).expect("seek failed"), 0);
+        assert_eq!(f.seek(SeekFrom::End(-10)).expect("seek failed"), 1290);
+        assert_eq!(f.read(&mut part).expect("read failed"), 10);
+        assert_eq!(f.read(&mut part).expect("read failed"), 0);
+        assert!(matches!(f.seek(SeekFrom::Current(-2000)), Err(FsError::InvalidSeek)));
+
+        // overwrite in place across a sector boundary, then append
+        f.seek(SeekFrom::Start(510)).expect("seek failed");
+        f.write(b"XYZW").expect("overwrite failed");
+        assert_eq!(f.len(), 1300);
+        f.append(b"tail").expect("append failed");
+        assert_eq!(f.len(), 1304);
+        assert_eq!(f.position(), 1304);
+        f.flush().expect("flush failed");
+        drop(f);
+        let read = fs.read_file("LOG.TXT").expect("read failed");
+        assert_eq!(&read[..510], &data[..510]);
+        assert_eq!(&read[510..514], b"XYZW");
+        assert_eq!(&read[514..1300], &data[514..]);
+        assert_eq!(&read[1300..], b"tail");
+    }
+}
+
+#[test_case]
+fn e2e_file_handle_set_len_and_holes() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file("A.BIN", &[0xAAu8; 2000]).expect("write failed");
+        drop(fs);
+        let free_before = free_clusters(&mut dev);
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        {
+            let mut f = fs.open("A.BIN").expect("open failed");
+            // shrink to part of one cluster: the other three are released
+            f.set_len(100).expect("truncate failed");
+            assert_eq!(f.len(), 100);
+        }
+        drop(fs);
+        assert_eq!(free_clusters(&mut dev), free_before + 3);
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert_eq!(fs.read_file("A.BIN").expect("read failed"), alloc::vec![0xAAu8; 100]);
+        {
+            let mut f = fs.open("A.BIN").expect("open failed");
+            // stale bytes beyond the old end must not reappear
+            f.set_len(300).expect("extend failed");
+            // writing past the end leaves a zero-filled hole
+            f.seek(SeekFrom::Start(1000)).expect("seek failed");
+            f.write(b"end").expect("write failed");
+            assert_eq!(f.len(), 1003);
+        }
+        let read = fs.read_file("A.BIN").expect("read failed");
+        assert_eq!(&read[..100], &[0xAAu8; 100][..]);
+        assert!(read[100..1000].iter().all(|&b| b == 0));
+        assert_eq!(&read[1000..], b"end");
+
+        {
+            let mut f = fs.open("A.BIN").expect("open failed");
+            f.set_len(0).expect("truncate failed");
+        }
+        let entry = fs.list_root().expect("list failed").into_iter().find(|e| e.matches("A.BIN")).expect("missing");
+        assert_eq!((entry.start_cluster, entry.file_size), (0, 0));
+        drop(fs);
+        assert_eq!(free_clusters(&mut dev), free_before + 4);
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        // create truncates an existing file; open refuses missing files
+        fs.write_file("B.BIN", b"old contents").expect("write failed");
+        drop(fs.create("B.BIN").expect("create failed"));
+        assert!(fs.read_file("B.BIN").expect("read failed").is_empty());
+        assert!(matches!(fs.open("NOPE.BIN"), Err(FsError::FileNotFound)));
+        fs.mkdir("D").expect("mkdir failed");
+        assert!(matches!(fs.open("D"), Err(FsError::IsADirectory)));
+
+        // read-only files can be read but not written
+        fs.write_file_with_attr("RO.TXT", b"keep", ATTR_READ_ONLY).expect("write failed");
+        let mut f = fs.open("RO.TXT").expect("open failed");
+        let mut out = [0u8; 8];
+        assert_eq!(f.read(&mut out).expect("read failed"), 4);
+        assert!(matches!(f.write(b"x"), Err(FsError::ReadOnly)));
+        assert!(matches!(f.set_len(0), Err(FsError::ReadOnly)));
+        drop(f);
+        assert!(matches!(fs.create("RO.TXT"), Err(FsError::ReadOnly)));
+    }
+}
+
+#[test_case]
+fn e2e_overwrite() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+
+        // creates the file when it is missing
+        fs.overwrite("NOTES.TXT", &[1u8; 1100]).expect("overwrite failed");
+        assert_eq!(fs.read_file("NOTES.TXT").expect("read failed"), alloc::vec![1u8; 1100]);
+        fs.overwrite("NOTES.TXT", b"short").expect("overwrite failed");
+        assert_eq!(fs.read_file("NOTES.TXT").expect("read failed"), b"short");
+        fs.overwrite("NOTES.TXT", b"").expect("overwrite failed");
+        assert!(fs.read_file("NOTES.TXT").expect("read failed").is_empty());
+        assert_eq!(fs.list_root().expect("list failed").len(), 1);
+        drop(fs);
+        // the three clusters of the first version were released
+        let free = free_clusters(&mut dev);
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.overwrite("NOTES.TXT", b"v4").expect("overwrite failed");
+        fs.mkdir("DIR").expect("mkdir failed");
+        assert!(matches!(fs.overwrite("DIR", b"x"), Err(FsError::IsADirectory)));
+        fs.write_file_with_attr("RO.TXT", b"ro", ATTR_READ_ONLY).expect("write failed");
+        assert!(matches!(fs.overwrite("RO.TXT", b"x"), Err(FsError::ReadOnly)));
+        drop(fs);
+        assert_eq!(free_clusters(&mut dev), free - 3);
+    }
+}
+
+#[test_case]
+fn e2e_overwrite_keeps_old_data_when_full() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        // a small volume, so that it can be filled up
+        let bs = make_volume(&mut dev, geometry(FatType::Fat12, 64, 1, 16, 1));
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        let clusters = bs.cluster_count() as usize;
+        fs.write_file("OLD.BIN", &[7u8; 512 * 4]).expect("write failed");
+        fs.write_file("FILL.BIN", &alloc::vec![0u8; 512 * (clusters - 6)]).expect("fill failed");
+        // two clusters are free, the new contents need three
+        assert!(matches!(fs.overwrite("OLD.BIN", &[9u8; 512 * 3]), Err(FsError::NoSpace)));
+        assert_eq!(fs.read_file("OLD.BIN").expect("read failed"), alloc::vec![7u8; 512 * 4]);
+        // a smaller replacement fits and then frees the old chain
+        fs.overwrite("OLD.BIN", &[9u8; 512 * 2]).expect("overwrite failed");
+        assert_eq!(fs.read_file("OLD.BIN").expect("read failed"), alloc::vec![9u8; 512 * 2]);
+        fs.write_file("MORE.BIN", &[1u8; 512 * 4]).expect("old chain was not freed");
+    }
+}
+
+#[test_case]
+fn e2e_rename_and_move() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file_with_attr("A.TXT", b"alpha", ATTR_HIDDEN).expect("write failed");
+        fs.write_file("B.TXT", b"beta").expect("write failed");
+
+        // same directory, to a long name; attributes come along
+        fs.rename("A.TXT", "Alpha Release Notes.txt").expect("rename failed");
+        assert!(matches!(fs.read_file("A.TXT"), Err(FsError::FileNotFound)));
+        assert_eq!(fs.read_file("alpha release notes.TXT").expect("read failed"), b"alpha");
+        assert_eq!(fs.attributes("Alpha Release Notes.txt").expect("attr failed") & ATTR_HIDDEN, ATTR_HIDDEN);
+        // only the case changes
+        fs.rename("alpha release notes.txt", "ALPHA RELEASE NOTES.TXT").expect("case rename failed");
+        assert!(fs.list_root().expect("list failed").iter().any(|e| e.display_name() == "ALPHA RELEASE NOTES.TXT"));
+        assert_eq!(fs.list_root().expect("list failed").len(), 2);
+
+        // target exists, bad name, missing source
+        assert!(matches!(fs.rename("B.TXT", "ALPHA RELEASE NOTES.TXT"), Err(FsError::FileAlreadyExists)));
+        assert!(matches!(fs.rename("B.TXT", "B?.TXT"), Err(FsError::InvalidName)));
+        assert!(matches!(fs.rename("NOPE.TXT", "C.TXT"), Err(FsError::FileNotFound)));
+
+        // move a file and then a whole directory
+        fs.mkdir("SRC").expect("mkdir failed");
+        fs.mkdir("DST").expect("mkdir failed");
+        fs.rename("B.TXT", "SRC/B.TXT").expect("move failed");
+        fs.mkdir("SRC/INNER").expect("mkdir failed");
+        assert!(matches!(fs.rename("SRC", "SRC/INNER/SRC"), Err(FsError::InvalidName)));
+        assert!(matches!(fs.rename("SRC", "SRC/SELF"), Err(FsError::InvalidName)));
+        fs.rename("SRC", "DST/MOVED").expect("directory move failed");
+        assert!(matches!(fs.list_dir("SRC"), Err(FsError::FileNotFound)));
+        assert_eq!(fs.read_file("DST/MOVED/B.TXT").expect("read failed"), b"beta");
+        // `..` of the moved directory now leads to its new parent
+        let up = fs.list_dir("DST/MOVED/..").expect("list failed");
+        assert!(up.iter().any(|e| e.matches("MOVED")));
+        assert!(fs.list_dir("DST/MOVED/INNER/../..").expect("list failed").iter().any(|e| e.matches("MOVED")));
+        // and back to the root
+        fs.rename("DST/MOVED/B.TXT", "/B.TXT").expect("move to root failed");
+        assert_eq!(fs.read_file("B.TXT").expect("read failed"), b"beta");
+    }
+}
+
+#[test_case]
+fn e2e_fat_mirrors_stay_in_sync() {
+    static mut BUF: [u8; 512 * 96] = [0u8; 512 * 96];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut g = geometry(FatType::Fat16, 4200, 1, 512, 17);
+        g.num_fats = 2;
+        let bs = make_volume(&mut dev, g);
+        assert_eq!(bs.root_dir_start_lba, 1 + 2 * 17);
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file("A.BIN", &[1u8; 3000]).expect("write failed");
+        fs.mkdir("DIR").expect("mkdir failed");
+        fs.delete("A.BIN").expect("delete failed");
+        fs.write_file("DIR/B.BIN", &[2u8; 700]).expect("write failed");
+        drop(fs);
+        let fat_bytes = 17 * 512;
+        let (first, second) = BUF[512..512 + 2 * fat_bytes].split_at(fat_bytes);
+        assert_eq!(first, second);
+
+        // damage the second copy; a plain check reports it but leaves it
+        BUF[512 + fat_bytes + 8] ^= 0xFF;
+        BUF[512 + fat_bytes + 600] ^= 0xFF;
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let check = MountOptions { check_fat_mirrors: true, ..MountOptions::default() };
+        let fs = FileSystem::mount_with_options(&mut dev, check).expect("mount failed");
+        assert_eq!(fs.fat_mismatches, 2);
+        drop(fs);
+        let fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert_eq!(fs.fat_mismatches, 0); // not checked
+        drop(fs);
+
+        let repair = MountOptions { repair_fat_mirrors: true, ..MountOptions::default() };
+        let mut fs = FileSystem::mount_with_options(&mut dev, repair).expect("mount failed");
+        assert_eq!(fs.fat_mismatches, 2);
+        assert_eq!(fs.read_file("DIR/B.BIN").expect("read failed"), alloc::vec![2u8; 700]);
+        drop(fs);
+        let fs = FileSystem::mount_with_options(&mut dev, check).expect("mount failed");
+        assert_eq!(fs.fat_mismatches, 0);
+    }
+}
+
+#[test_case]
+fn e2e_check_and_repair() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let bs = make_volume(&mut dev, geometry(FatType::Fat12, 64, 1, 16, 1));
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        for (name, len) in [("A.BIN", 1024), ("B.BIN", 1024), ("C.BIN", 1500), ("D.BIN", 100), ("E.BIN", 10)] {
+            fs.write_file(name, &alloc::vec![0x5Au8; len]).expect("write failed");
+        }
+        fs.write_file("EMPTY.TXT", b"").expect("write failed");
+        fs.mkdir("SUB").expect("mkdir failed");
+        fs.write_file("SUB/F.TXT", b"nested").expect("write failed");
+        let report = check::check(&mut fs, false).expect("check failed");
+        assert!(report.is_clean(), "{:?}", report.problems);
+        assert_eq!((report.files, report.directories), (7, 1));
+        let free = report.free_clusters;
+
+        let start = |fs: &mut FileSystem<MockDevice>, path: &str| {
+            fs.list_dir("/").expect("list failed").into_iter()
+                .chain(fs.list_dir("SUB").expect("list failed"))
+                .find(|e| e.matches(path)).expect("missing").start_cluster
+        };
+        let (a, b, c, d) = (start(&mut fs, "A.BIN"), start(&mut fs, "B.BIN"), start(&mut fs, "C.BIN"), start(&mut fs, "D.BIN"));
+        let sub = start(&mut fs, "SUB");
+        drop(fs);
+
+        // damage the volume behind the file system's back
+        let a1 = {
+            let mut fat = FatTable::for_volume(&mut dev, &bs);
+            let (a1, b1) = (fat.read_entry(a).expect("read failed"), fat.read_entry(b).expect("read failed"));
+            assert!(b1 != a1);
+            fat.write_entry(b, a1).expect("write failed"); // B runs into A's second cluster
+            fat.write_entry(c, 0xFFF).expect("write failed"); // C loses two of its three clusters
+            let spare = fat.alloc_cluster().expect("alloc failed").expect("no space");
+            fat.write_entry(d, spare).expect("write failed"); // D gains a cluster it does not need
+            fat.write_entry(60, 0xFFF).expect("write failed"); // allocated but unreachable
+            fat.flush().expect("flush failed");
+            a1
+        };
+        Directory::new(&mut dev, bs.root_dir_start_lba as u64, 16).set_cluster_and_size("E.BIN", 0x700, 10).expect("update failed");
+        let sub_lba = bs.data_start_lba as u64 + (sub as u64 - 2);
+        Directory::from_sectors(&mut dev, alloc::vec![sub_lba]).set_cluster_and_size("..", 9, 0).expect("update failed");
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        let report = check::check(&mut fs, false).expect("check failed");
+        let p = &report.problems;
+        assert!(p.contains(&Problem::CrossLinked { path: "/B.BIN".into(), cluster: a1 }), "{:?}", p);
+        assert!(p.contains(&Problem::ChainTooShort { path: "/B.BIN".into(), clusters: 1, size: 1024 }));
+        assert!(p.contains(&Problem::ChainTooShort { path: "/C.BIN".into(), clusters: 1, size: 1500 }));
+        assert!(p.contains(&Problem::ChainTooLong { path: "/D.BIN".into(), clusters: 2, size: 100 }));
+        assert!(p.contains(&Problem::InvalidCluster { path: "/E.BIN".into(), cluster: 0x700 }));
+        assert!(p.contains(&Problem::BadDotEntry { path: "/SUB".into() }));
+        // B's own second cluster, C's last two, E's old cluster and cluster 60
+        assert!(p.contains(&Problem::LostClusters { count: 5 }));
+        assert_eq!(p.len(), 7);
+        // without repair nothing changed
+        assert_eq!(check::check(&mut fs, false).expect("check failed").problems.len(), 7);
+
+        let report = check::check(&mut fs, true).expect("check failed");
+        assert!(report.repaired);
+        assert_eq!(report.problems.len(), 7);
+        let report = check::check(&mut fs, false).expect("check failed");
+        assert!(report.is_clean(), "{:?}", report.problems);
+        // the clusters cut off B, C and E are free again
+        assert_eq!(report.free_clusters, free + 4);
+        assert_eq!(fs.read_file("A.BIN").expect("read failed"), alloc::vec![0x5Au8; 1024]);
+        assert_eq!(fs.read_file("B.BIN").expect("read failed").len(), 512);
+        assert_eq!(fs.read_file("C.BIN").expect("read failed").len(), 512);
+        assert_eq!(fs.read_file("D.BIN").expect("read failed"), alloc::vec![0x5Au8; 100]);
+        assert!(fs.read_file("E.BIN").expect("read failed").is_empty());
+        assert_eq!(fs.read_file("SUB/../SUB/F.TXT").expect("read failed"), b"nested");
+    }
+}
+
+#[test_case]
+fn e2e_check_fat32_free_count_and_boot_sector() {
+    static mut BUF: [u8; 512 * 640] = [0u8; 512 * 640];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let bs = make_volume(&mut dev, geometry(FatType::Fat32, 70_000, 32, 0, 547));
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file("X.TXT", b"x").expect("write failed");
+        drop(fs);
+        let mut info = FsInfo::parse(&BUF[512..1024]).expect("fsinfo parse failed");
+        let actual = info.free_count;
+        info.free_count = 5;
+        info.serialize(&mut BUF[512..1024]).expect("fsinfo serialize failed");
+
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        let report = check::check(&mut fs, true).expect("check failed");
+        // the BPB describes 70000 sectors on a 640-sector device
+        assert_eq!(report.problems, alloc::vec![
+            Problem::BadBootSector("volume extends past the end of the device"),
+            Problem::FreeCountMismatch { recorded: 5, actual },
+        ]);
+        assert_eq!(report.free_clusters, actual);
+        assert_eq!(fs.fs_info.expect("fsinfo missing").free_count, actual);
+        drop(fs);
+        assert_eq!(FsInfo::parse(&BUF[512..1024]).expect("fsinfo parse failed").free_count, actual);
+        assert_eq!(bs.cluster_count() - 2, actual);
+    }
+}
+
+#[test_case]
+fn e2e_device_errors_propagate() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        fs.write_file("A.BIN", &[7u8; 512 * 24]).expect("write failed");
+    }
+    unsafe {
+        // the same volume on a device cut short: the file's tail is missing
+        let buf = &mut BUF[..512 * 32];
+        let mut dev = MockDevice::new(buf);
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert!(matches!(fs.read_file("A.BIN"), Err(FsError::Io(IoError::OutOfRange))));
+        assert!(matches!(fs.list_root(), Ok(ref l) if l.len() == 1));
+    }
+}
+
+#[test_case]
+fn e2e_read_only_device() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        {
+            let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+            fs.write_file("A.TXT", b"kept").expect("write failed");
+        }
+        dev.set_read_only(true);
+        assert!(matches!(FileSystem::format(&mut dev), Err(FsError::ReadOnly)));
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert_eq!(fs.read_file("A.TXT").expect("read failed"), b"kept");
+        assert!(matches!(fs.write_file("B.TXT", b"x"), Err(FsError::ReadOnly)));
+        assert!(matches!(fs.overwrite("A.TXT", b"x"), Err(FsError::ReadOnly)));
+        assert!(matches!(fs.delete("A.TXT"), Err(FsError::ReadOnly)));
+        assert!(matches!(fs.mkdir("DIR"), Err(FsError::ReadOnly)));
+        assert!(matches!(fs.rename("A.TXT", "C.TXT"), Err(FsError::ReadOnly)));
+        assert!(matches!(fs.create("C.TXT"), Err(FsError::ReadOnly)));
+        let mut f = fs.open("A.TXT").expect("open failed");
+        assert!(matches!(f.write(b"x"), Err(FsError::ReadOnly)));
+        let mut out = [0u8; 4];
+        assert_eq!(f.read(&mut out).expect("read failed"), 4);
+        assert_eq!(&out, b"kept");
+    }
+}
+
+#[test_case]
+fn e2e_cached_device() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = CachedDevice::new(MockDevice::new(buf), 16);
+        FileSystem::format(&mut dev).expect("format failed");
+        {
+            let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+            fs.mkdir("DIR").expect("mkdir failed");
+            for i in 0..8 {
+                let name = alloc::format!("/DIR/F{}.TXT", i);
+                fs.write_file(&name, name.as_bytes()).expect("write failed");
+            }
+            assert_eq!(fs.list_dir("/DIR").expect("list failed").len(), 10);
+            fs.flush().expect("flush failed");
+        }
+        // directory scans and FAT walks are served from the cache
+        let stats = dev.stats();
+        assert!(stats.hits > stats.misses);
+        assert_eq!(dev.dirty_count(), 0);
+    }
+    unsafe {
+        // the synced image mounts without the cache
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert_eq!(fs.read_file("/DIR/F7.TXT").expect("read failed"), b"/DIR/F7.TXT");
+    }
+}
+
+/// Write a GPT header at `lba` whose single-sector entry array at
+/// `entries_lba` describes one basic data partition over LBAs 8..=119.
+fn write_gpt(dev: &mut MockDevice, lba: u64, alternate: u64, entries_lba: u64) {
+    let mut entries = [0u8; 512];
+    entries[0..16].copy_from_slice(&GUID_BASIC_DATA);
+    entries[16] = 0x42;
+    entries[32..40].copy_from_slice(&8u64.to_le_bytes());
+    entries[40..48].copy_from_slice(&119u64.to_le_bytes());
+    for (i, c) in "DATA".encode_utf16().enumerate() {
+        entries[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+    dev.write_sector(entries_lba, &entries).expect("write failed");
+    let mut header = [0u8; 512];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate.to_le_bytes());
+    header[40..48].copy_from_slice(&3u64.to_le_bytes());
+    header[48..56].copy_from_slice(&125u64.to_le_bytes());
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&4u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&partition::crc32(&entries).to_le_bytes());
+    let crc = partition::crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    dev.write_sector(lba, &header).expect("write failed");
+}
+
+#[test_case]
+fn e2e_mount_gpt_partition() {
+    static mut BUF: [u8; 512 * 128] = [0u8; 512 * 128];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        // protective MBR, primary GPT at LBA 1, backup at the last LBA
+        let mut mbr = [0u8; 512];
+        mbr[446 + 4] = 0xEE;
+        mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+        mbr[446 + 12..446 + 16].copy_from_slice(&127u32.to_le_bytes());
+        mbr[510] = 0x55;
+        mbr[511] = 0xAA;
+        dev.write_sector(0, &mbr).expect("write failed");
+        write_gpt(&mut dev, 1, 127, 2);
+        write_gpt(&mut dev, 127, 1, 126);
+
+        let info = partition::find_fat_partition(&mut dev).expect("read partitions failed").expect("no FAT partition");
+        assert_eq!((info.start_lba, info.sector_count), (8, 112));
+        assert!(matches!(info.kind, PartitionKind::Gpt { ref name, .. } if name == "DATA"));
+        {
+            let mut part = Partition::new(&mut dev, &info).expect("partition failed");
+            FileSystem::format(&mut part).expect("format failed");
+            let mut fs = FileSystem::mount(&mut part).expect("mount failed");
+            fs.write_file("HELLO.TXT", b"inside a partition").expect("write failed");
+        }
+        // the volume starts at the partition, not at LBA 0
+        let mut sector = [0u8; 512];
+        dev.read_sector(8, &mut sector).expect("read failed");
+        assert!(BootSector::parse(&sector).is_ok());
+        dev.read_sector(1, &mut sector).expect("read failed");
+        assert_eq!(&sector[0..8], b"EFI PART");
+
+        // a damaged primary header falls back to the backup
+        dev.buf[512 + 40] ^= 0xFF;
+        let info = partition::find_fat_partition(&mut dev).expect("backup not used").expect("no FAT partition");
+        let mut part = Partition::new(&mut dev, &info).expect("partition failed");
+        let mut fs = FileSystem::mount(&mut part).expect("mount failed");
+        assert_eq!(fs.read_file("HELLO.TXT").expect("read failed"), b"inside a partition");
+
+        // with both copies damaged the table is rejected
+        dev.buf[126 * 512] ^= 0xFF;
+        assert_eq!(
+            partition::read_partitions(&mut dev),
+            Err(PartitionError::BadGpt("header CRC mismatch"))
+        );
+    }
+}
+
+#[test_case]
+fn e2e_format_with_options() {
+    static mut FLOPPY: MockDeviceFixed = MockDeviceFixed::new();
+    static mut BUF: [u8; 512 * 4400] = [0u8; 512 * 4400];
+    unsafe {
+        let dev = &mut *core::ptr::addr_of_mut!(FLOPPY);
+        let options = FormatOptions::new()
+            .num_fats(2)
+            .serial(0xDEAD_BEEF)
+            .volume_label("rz disk")
+            .expect("bad label");
+        FileSystem::format_with_options(dev, &options).expect("format failed");
+        let mut buf = [0u8; 512];
+        dev.read_sector(0, &mut buf).expect("read failed");
+        assert_eq!(buf[0], 0xEB);
+        assert_eq!(&buf[3..11], b"RZOS    ");
+        let bs = BootSector::parse(&buf).expect("parse failed");
+        // the standard 1.44 MB floppy layout
+        assert_eq!((bs.fat_type, bs.sectors_per_cluster, bs.sectors_per_fat), (FatType::Fat12, 1, 9));
+        assert_eq!((bs.max_root_dir_entries, bs.data_start_lba, bs.media), (224, 33, 0xF0));
+        assert_eq!((bs.volume_id, bs.volume_label), (0xDEAD_BEEF, *b"RZ DISK    "));
+        let mut fs = FileSystem::mount(dev).expect("mount failed");
+        fs.write_file("A.TXT", b"floppy").expect("write failed");
+        let report = check::check(&mut fs, false).expect("check failed");
+        assert!(report.problems.is_empty());
+    }
+    unsafe {
+        // too few clusters for FAT16 unless they are small
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let fat16 = FormatOptions::new().fat_type(FatType::Fat16);
+        assert!(matches!(
+            FileSystem::format_with_options(&mut dev, &fat16.sectors_per_cluster(2)),
+            Err(FsError::Boot(_))
+        ));
+        FileSystem::format_with_options(&mut dev, &fat16).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert_eq!(fs.boot_sector.fat_type, FatType::Fat16);
+        assert_eq!(fs.boot_sector.total_sectors, 4400);
+        fs.write_file("B.TXT", b"sixteen").expect("write failed");
+        assert!(check::check(&mut fs, false).expect("check failed").problems.is_empty());
+    }
+}
+
+#[test_case]
+fn e2e_stat_and_free_map() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        let st = fs.stat().expect("stat failed");
+        assert_eq!((st.total_clusters, st.free_clusters, st.used_clusters), (48, 48, 0));
+        assert_eq!((st.cluster_size, st.total_bytes, st.files), (512, 48 * 512, 0));
+
+        fs.write_file("A.BIN", &[1u8; 512]).expect("write failed");
+        fs.write_file("B.BIN", &[2u8; 512 * 2]).expect("write failed");
+        fs.mkdir("DIR").expect("mkdir failed");
+        fs.write_file("DIR/C.BIN", &[3u8; 512]).expect("write failed");
+        // growing A puts its second cluster after everything else
+        fs.open("A.BIN").expect("open failed").append(&[1u8; 512]).expect("append failed");
+        let st = fs.stat().expect("stat failed");
+        assert_eq!((st.files, st.directories, st.fragmented_files), (3, 1, 1));
+        assert_eq!(st.fragmentation(), 33);
+        assert_eq!((st.used_clusters, st.free_clusters), (6, 42));
+        assert_eq!(st.used_bytes + st.free_bytes, st.total_bytes);
+
+        // freed clusters are found again, and the volume fills up exactly
+        fs.delete("B.BIN").expect("delete failed");
+        assert_eq!(fs.stat().expect("stat failed").free_clusters, 44);
+        fs.write_file("FILL.BIN", &alloc::vec![0u8; 512 * 44]).expect("fill failed");
+        assert_eq!(fs.stat().expect("stat failed").free_clusters, 0);
+        assert!(matches!(fs.write_file("MORE.BIN", &[0u8; 1]), Err(FsError::NoSpace)));
+        drop(fs);
+        assert_eq!(free_clusters(&mut dev), 0);
+    }
+}
+
+static CLOCK_MINUTE: core::sync::atomic::AtomicU8 = core::sync::atomic::AtomicU8::new(0);
+
+fn test_clock() -> DateTime {
+    let minute = CLOCK_MINUTE.load(core::sync::atomic::Ordering::Relaxed);
+    DateTime { year: 2025, month: 6, day: 1, hour: 12, minute, second: 31 }
+}
+
+fn at_minute(minute: u8) -> DateTime {
+    CLOCK_MINUTE.store(minute, core::sync::atomic::Ordering::Relaxed);
+    test_clock()
+}
+
+#[test_case]
+fn e2e_timestamps() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        FileSystem::format(&mut dev).expect("format failed");
+        time::set_clock(test_clock);
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        let t0 = at_minute(0);
+        fs.write_file("A.TXT", b"first").expect("write failed");
+        fs.mkdir("DIR").expect("mkdir failed");
+        let a = fs.list_dir("/").expect("list failed").into_iter().find(|e| e.matches("A.TXT")).expect("missing");
+        assert_eq!((a.created, a.modified, a.accessed), (t0, DateTime { second: 30, ..t0 }, t0.date_only()));
+        let dot = fs.list_dir("/DIR").expect("list failed").remove(0);
+        assert_eq!(dot.created, t0);
+
+        // new contents move the modification time, not the creation time
+        let t1 = at_minute(1);
+        fs.overwrite("A.TXT", b"second").expect("overwrite failed");
+        let a = fs.list_dir("/").expect("list failed").into_iter().find(|e| e.matches("A.TXT")).expect("missing");
+        assert_eq!((a.created, a.modified.minute), (t0, t1.minute));
+
+        // so does writing in place through a handle, without changing size
+        let t2 = at_minute(2);
+        {
+            let mut f = fs.open("A.TXT").expect("open failed");
+            f.write(b"S").expect("write failed");
+            f.flush().expect("flush failed");
+        }
+        // and a rename keeps every stamp
+        at_minute(3);
+        fs.rename("A.TXT", "DIR/B.TXT").expect("rename failed");
+        let b = fs.list_dir("/DIR").expect("list failed").into_iter().find(|e| e.matches("B.TXT")).expect("missing");
+        assert_eq!((b.created, b.modified.minute), (t0, t2.minute));
+        assert_eq!(fs.read_file("DIR/B.TXT").expect("read failed"), b"Second");
+        time::set_clock(|| DateTime::FAT_EPOCH);
+    }
+}
+
+#[test_case]
+fn e2e_vfs_mount_table() {
+    static mut ROOT: [u8; 512 * 64] = [0u8; 512 * 64];
+    static mut DISK: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let mut root_dev = MockDevice::new(&mut ROOT[..]);
+        let mut disk_dev = MockDevice::new(&mut DISK[..]);
+        FileSystem::format(&mut root_dev).expect("format failed");
+        FileSystem::format(&mut disk_dev).expect("format failed");
+        let mut vfs = Vfs::new();
+        vfs.mount("/", Box::new(FileSystem::mount(&mut root_dev).expect("mount failed"))).expect("mount failed");
+        vfs.mount("/mnt/disk1/", Box::new(FileSystem::mount(&mut disk_dev).expect("mount failed")))
+            .expect("mount failed");
+        assert!(matches!(vfs.mount("/mnt//disk1", Box::new(NoFs)), Err(FsError::Busy)));
+        let paths: Vec<_> = vfs.mounts().into_iter().map(|m| m.path).collect();
+        assert_eq!(paths, ["/", "/mnt/disk1"]);
+
+        // `/mnt` only exists to hold the mount point
+        let mnt = vfs.list_dir("/").expect("list failed");
+        assert_eq!(mnt.len(), 1);
+        assert!(mnt[0].is_dir() && mnt[0].display_name() == "mnt");
+        assert_eq!(vfs.list_dir("/mnt").expect("list failed")[0].display_name(), "disk1");
+
+        vfs.mkdir("/mnt/disk1/logs").expect("mkdir failed");
+        vfs.write_file("/mnt/disk1/logs/../boot.log", b"up").expect("write failed");
+        vfs.write_file("/mnt/./disk1/logs/a.log", b"a").expect("write failed");
+        assert_eq!(vfs.read_file("/mnt/disk1/logs/../../disk1/BOOT.LOG").expect("read failed"), b"up");
+        assert!(matches!(vfs.read_file("/boot.log"), Err(FsError::FileNotFound)));
+
+        // moving a file between volumes copies it; directories cannot move
+        vfs.rename("/mnt/disk1/boot.log", "/boot.log").expect("rename failed");
+        assert_eq!(vfs.read_file("/boot.log").expect("read failed"), b"up");
+        assert!(matches!(vfs.read_file("/mnt/disk1/boot.log"), Err(FsError::FileNotFound)));
+        assert!(matches!(vfs.rename("/mnt/disk1/logs", "/logs"), Err(FsError::CrossDevice)));
+        vfs.rename("/mnt/disk1/logs/a.log", "/mnt/disk1/b.log").expect("rename failed");
+
+        assert!(matches!(vfs.rmdir("/mnt/disk1"), Err(FsError::Busy)));
+        assert!(matches!(vfs.rmdir("/mnt"), Err(FsError::Busy)));
+        assert!(matches!(vfs.unmount("/"), Err(FsError::Busy)));
+        assert!(vfs.check("/mnt/disk1", false).expect("check failed").is_clean());
+        assert_eq!(vfs.stat("/mnt/disk1").expect("stat failed").files, 1);
+
+        vfs.unmount("/mnt/disk1").expect("unmount failed");
+        assert!(vfs.list_dir("/").expect("list failed").iter().all(|e| !e.is_dir()));
+        assert!(matches!(vfs.read_file("/mnt/disk1/b.log"), Err(FsError::FileNotFound)));
+        drop(vfs);
+        // the unmounted volume kept its files
+        let mut fs = FileSystem::mount(&mut disk_dev).expect("remount failed");
+        assert_eq!(fs.read_file("B.LOG").expect("read failed"), b"a");
+    }
+}
+
+#[test_case]
+fn e2e_tmpfs_tree() {
+    let mut fs = Tmpfs::new(1024);
+    fs.mkdir("/home").expect("mkdir failed");
+    fs.mkdir("/home/user").expect("mkdir failed");
+    // names are arbitrary and case-sensitive
+    fs.write_file("/home/user/Notes about the kernel.md", b"# notes").expect("write failed");
+    fs.write_file("/home/user/notes about the kernel.md", b"other").expect("write failed");
+    assert!(matches!(fs.write_file("/home/user/Notes about the kernel.md", b"x"), Err(FsError::FileAlreadyExists)));
+    assert!(matches!(fs.write_file("/home/a/b", b"x"), Err(FsError::FileNotFound)));
+    assert!(matches!(fs.write_file("/home/..", b"x"), Err(FsError::InvalidName)));
+    assert_eq!(fs.read_file("home/user/../user/Notes about the kernel.md").expect("read failed"), b"# notes");
+    let list = fs.list_dir("/home/user").expect("list failed");
+    let names: Vec<_> = list.iter().map(|e| e.display_name()).collect();
+    assert_eq!(names, ["Notes about the kernel.md", "notes about the kernel.md"]);
+    assert_eq!(list[0].file_size, 7);
+    assert!(fs.list_dir("/").expect("list failed")[0].is_dir());
+
+    fs.overwrite("/home/user/notes about the kernel.md", b"grown to more bytes").expect("overwrite failed");
+    assert_eq!(fs.read_file("/home/user/notes about the kernel.md").expect("read failed"), b"grown to more bytes");
+    assert!(matches!(fs.read_file("/home"), Err(FsError::IsADirectory)));
+    assert!(matches!(fs.list_dir("/home/user/Notes about the kernel.md"), Err(FsError::NotADirectory)));
+
+    // rename moves whole subtrees, but not into themselves
+    fs.rename("/home/user", "/user").expect("rename failed");
+    assert!(matches!(fs.rename("/user", "/user/inner"), Err(FsError::InvalidName)));
+    assert!(matches!(fs.rmdir("/user"), Err(FsError::DirectoryNotEmpty)));
+    // the directory bit is reported like FAT does, and cannot be set
+    fs.set_attributes("/user", ATTR_HIDDEN).expect("attrib failed");
+    assert_eq!(fs.attributes("/user").expect("attr failed"), ATTR_DIRECTORY | ATTR_HIDDEN);
+    fs.set_attributes("/user/Notes about the kernel.md", ATTR_READ_ONLY).expect("attrib failed");
+    assert!(matches!(fs.delete("/user/Notes about the kernel.md"), Err(FsError::ReadOnly)));
+    fs.set_attributes("/user/Notes about the kernel.md", 0).expect("attrib failed");
+    fs.delete("/user/Notes about the kernel.md").expect("delete failed");
+    fs.delete("/user/notes about the kernel.md").expect("delete failed");
+    fs.rmdir("/user").expect("rmdir failed");
+    let st = fs.stat().expect("stat failed");
+    assert_eq!((st.files, st.directories, st.used_bytes), (0, 1, 0));
+}
+
+#[test_case]
+fn e2e_tmpfs_capacity_and_mounts() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        let mut dev = MockDevice::new(&mut BUF[..]);
+        FileSystem::format(&mut dev).expect("format failed");
+        let mut vfs = Vfs::new();
+        vfs.mount("/", Box::new(Tmpfs::new(16))).expect("mount failed");
+        vfs.mount("/mnt/disk1", Box::new(FileSystem::mount(&mut dev).expect("mount failed"))).expect("mount failed");
+        vfs.write_file("/a", b"0123456789").expect("write failed");
+        assert!(matches!(vfs.write_file("/b", b"0123456789"), Err(FsError::NoSpace)));
+        assert!(matches!(vfs.overwrite("/a", &[0u8; 17]), Err(FsError::NoSpace)));
+        vfs.overwrite("/a", b"0123456789abcdef").expect("overwrite failed");
+        let st = vfs.stat("/").expect("stat failed");
+        assert_eq!((st.used_bytes, st.free_bytes), (16, 0));
+        // the failed writes left nothing behind
+        assert_eq!(vfs.list_dir("/").expect("list failed").len(), 2);
+        // files move freely between the RAM root and the FAT disk
+        vfs.rename("/a", "/mnt/disk1/a.bin").expect("rename failed");
+        assert_eq!(vfs.stat("/").expect("stat failed").used_bytes, 0);
+        vfs.mkdir("/dir").expect("mkdir failed");
+        assert!(matches!(vfs.rename("/dir", "/mnt/disk1/dir"), Err(FsError::CrossDevice)));
+        vfs.rename("/mnt/disk1/a.bin", "/scratch").expect("rename failed");
+        assert_eq!(vfs.read_file("/scratch").expect("read failed"), b"0123456789abcdef");
+        assert!(matches!(vfs.check("/", false), Err(FsError::Unsupported)));
+        assert_eq!(vfs.mounts()[0].fs_type, "tmpfs");
+    }
+}
+
+#[test_case]
+fn e2e_journal_survives_power_loss() {
+    static mut IMAGE: [u8; 512 * 128] = [0u8; 512 * 128];
+    static mut WORK: [u8; 512 * 128] = [0u8; 512 * 128];
+    unsafe {
+        let mut dev = MockDevice::new(&mut IMAGE[..]);
+        let options = FormatOptions::new().journal(journal::JOURNAL_DEFAULT_SECTORS);
+        FileSystem::format_with_options(&mut dev, &options).expect("format failed");
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert!(fs.is_journaled());
+        fs.write_file("OLD.TXT", &[1u8; 1100]).expect("write failed");
+        fs.mkdir("DIR").expect("mkdir failed");
+        fs.write_file("DIR/A.TXT", b"a").expect("write failed");
+        drop(fs);
+        let image = &IMAGE[..];
+        let work = &mut WORK[..];
+
+        let replayed = crash_at_every_write(image, work, |fs| fs.overwrite("OLD.TXT", &[2u8; 1600]), |fs, done| {
+            let data = fs.read_file("OLD.TXT").expect("read failed");
+            assert!(data == [2u8; 1600] || (!done && data == [1u8; 1100]));
+        });
+        // some cut fell between the commit point and the home writes
+        assert!(replayed > 0);
+        crash_at_every_write(image, work, |fs| fs.rename("OLD.TXT", "DIR/MOVED.TXT"), |fs, done| {
+            let old = fs.read_file("OLD.TXT");
+            let moved = fs.read_file("DIR/MOVED.TXT");
+            assert!(old.is_ok() != moved.is_ok() && (!done || moved.is_ok()));
+            assert_eq!(old.or(moved).expect("file lost"), alloc::vec![1u8; 1100]);
+        });
+        crash_at_every_write(image, work, |fs| fs.delete("OLD.TXT"), |fs, done| {
+            match fs.read_file("OLD.TXT") {
+                Ok(data) => assert!(!done && data == [1u8; 1100]),
+                Err(e) => assert!(matches!(e, FsError::FileNotFound)),
+            }
+        });
+    }
+}
+
+/// Run `op` on a fresh copy of `image` once for every write it makes,
+/// cutting the power after 0, 1, 2, ... writes. Each result is mounted
+/// read-only (replaying into memory) and then read-write (replaying to
+/// disk), must pass `check::check`, and is handed to `verify`, which is
+/// told whether `op` ran to completion. Returns how many runs needed a
+/// replay.
+fn crash_at_every_write(
+    image: &[u8],
+    work: &mut [u8],
+    op: impl Fn(&mut FileSystem<'_, FaultyDevice<MockDevice<'_>>>) -> Result<(), FsError>,
+    verify: impl Fn(&mut FileSystem<'_, MockDevice<'_>>, bool),
+) -> u32 {
+    let mut replayed = 0;
+    for cut in 0..1000 {
+        work.copy_from_slice(image);
+        let mut dev = FaultyDevice::new(MockDevice::new(&mut *work));
+        dev.fail_after(cut);
+        let done = {
+            let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+            op(&mut fs).is_ok()
+        };
+        for read_only in [true, false] {
+            let mut dev = MockDevice::new(&mut *work);
+            dev.set_read_only(read_only);
+            let mut fs = FileSystem::mount(&mut dev).expect("remount failed");
+            if !read_only && fs.journal_replayed > 0 { replayed += 1; }
+            assert!(check::check(&mut fs, false).expect("check failed").is_clean());
+            verify(&mut fs, done);
+        }
+        if done { return replayed; }
+    }
+    panic!("operation never completed");
+}
+
+/// A backend with nothing in it, for mount table tests.
+struct NoFs;
+
+impl FileSystemOps for NoFs {
+    fn fs_type(&self) -> &'static str { "none" }
+    fn list_dir(&mut self, _: &str) -> Result<Vec<DirectoryEntry>, FsError> { Ok(Vec::new()) }
+    fn read_file(&mut self, _: &str) -> Result<Vec<u8>, FsError> { Err(FsError::FileNotFound) }
+    fn write_file(&mut self, _: &str, _: &[u8]) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+    fn overwrite(&mut self, _: &str, _: &[u8]) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+    fn delete(&mut self, _: &str) -> Result<(), FsError> { Err(FsError::FileNotFound) }
+    fn rename(&mut self, _: &str, _: &str) -> Result<(), FsError> { Err(FsError::FileNotFound) }
+    fn mkdir(&mut self, _: &str) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+    fn rmdir(&mut self, _: &str) -> Result<(), FsError> { Err(FsError::FileNotFound) }
+    fn attributes(&mut self, _: &str) -> Result<u8, FsError> { Err(FsError::FileNotFound) }
+    fn set_attributes(&mut self, _: &str, _: u8) -> Result<(), FsError> { Err(FsError::FileNotFound) }
+    fn stat(&mut self) -> Result<FsStat, FsError> { Ok(FsStat::default()) }
+}
+
+/// Count free clusters by scanning the FAT directly.
+fn free_clusters(dev: &mut MockDevice) -> u32 {
+    let mut buf = [0u8; 512];
+    dev.read_sector(0, &mut buf).expect("read failed");
+    let bs = BootSector::parse(&buf).expect("parse failed");
+    let mut fat = FatTable::with_type(dev, bs.fat_start_lba as u64, bs.sectors_per_fat, bs.fat_type, bs.cluster_count());
+    (2..=fat.max_cluster()).filter(|&c| fat.read_entry(c).expect("read failed") == 0).count() as u32
+}
+
+/// Lay out an empty volume with the given BPB geometry by hand, since
+/// `FileSystem::format` sizes the volume to the device and real FAT16/FAT32
+/// volumes are larger than these test buffers. `total_sectors` may be larger
+/// than the device; the tests only touch the first few clusters.
+fn make_volume(dev: &mut MockDevice, geometry: BootSector) -> BootSector {
+    let mut buf = [0u8; 512];
+    geometry.serialize(&mut buf).expect("serialize failed");
+    dev.write_sector(0, &buf).expect("write failed");
+    let bs = BootSector::parse(&buf).expect("parse failed");
+    let mut fat = FatTable::for_volume(dev, &bs);
+    fat.write_entry(0, 0x0FFF_FFF8).expect("write failed");
+    fat.write_entry(1, bs.fat_type.eoc()).expect("write failed");
+    if bs.fat_type == FatType::Fat32 {
+        // root directory chain, one cluster long
+        fat.write_entry(bs.root_cluster, bs.fat_type.eoc()).expect("write failed");
+    }
+    fat.flush().expect("flush failed");
+    if bs.fat_type == FatType::Fat32 {
+        let mut info = [0u8; 512];
+        FsInfo { free_count: bs.cluster_count() - 1, next_free: bs.root_cluster + 1 }
+            .serialize(&mut info)
+            .expect("fsinfo serialize failed");
+        dev.write_sector(bs.fs_info_sector as u64, &info).expect("write failed");
+    }
+    bs
+}
+
+fn geometry(fat_type: FatType, total_sectors: u32, reserved: u16, root_entries: u16, sectors_per_fat: u32) -> BootSector {
+    let fat32 = fat_type == FatType::Fat32;
+    BootSector {
+        bytes_per_sector: 512,
+        sectors_per_cluster: 1,
+        reserved_sectors: reserved,
+        num_fats: 1,
+        max_root_dir_entries: root_entries,
+        total_sectors,
+        sectors_per_fat,
+        fat_type,
+        root_cluster: if fat32 { 2 } else { 0 },
+        fs_info_sector: if fat32 { 1 } else { 0 },
+        // derived fields are recomputed by parse
+        fat_start_lba: 0,
+        root_dir_start_lba: 0,
+        data_start_lba: 0,
+        oem_name: *b"RZOS    ",
+        media: 0xF8,
+        volume_id: 0,
+        volume_label: *b"NO NAME    ",
+    }
+}
+
+#[test_case]
+fn e2e_fat16_volume() {
+    static mut BUF: [u8; 512 * 96] = [0u8; 512 * 96];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        // 4200 sectors, 1 sector per cluster: ~4150 clusters, too many for FAT12
+        let bs = make_volume(&mut dev, geometry(FatType::Fat16, 4200, 1, 512, 17));
+        assert_eq!(bs.fat_type, FatType::Fat16);
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert_eq!(fs.boot_sector.fat_type, FatType::Fat16);
+        assert!(fs.fs_info.is_none());
+        let data: alloc::vec::Vec<u8> = (0..1500u32).map(|i| (i % 251) as u8).collect();
+        fs.write_file("BIG.BIN", &data).expect("write failed");
+        fs.mkdir("DIR").expect("mkdir failed");
+        fs.write_file("DIR/INNER.TXT", b"fat16").expect("nested write failed");
+        assert_eq!(fs.read_file("BIG.BIN").expect("read failed"), data);
+
+        let start = fs.list_root().expect("list failed").iter().find(|e| e.matches("BIG.BIN")).expect("missing").start_cluster;
+        drop(fs);
+        // 16-bit entries: BIG.BIN spans three clusters, linked in the FAT
+        let fat_off = 512 + start as usize * 2;
+        assert_eq!(u16::from_le_bytes([BUF[fat_off], BUF[fat_off + 1]]) as u32, start + 1);
+        let last = 512 + (start as usize + 2) * 2;
+        assert_eq!(&BUF[last..last + 2], &[0xFF, 0xFF]);
+
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fs = FileSystem::mount(&mut dev).expect("remount failed");
+        assert_eq!(fs.read_file("DIR/INNER.TXT").expect("read failed"), b"fat16");
+    }
+}
+
+#[test_case]
+fn e2e_fat32_volume() {
+    static mut BUF: [u8; 512 * 640] = [0u8; 512 * 640];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        // 70000 sectors, 1 sector per cluster: ~69400 clusters, too many for FAT16
+        let bs = make_volume(&mut dev, geometry(FatType::Fat32, 70_000, 32, 0, 547));
+        assert_eq!(bs.fat_type, FatType::Fat32);
+        assert_eq!(bs.data_start_lba, 32 + 547);
+        let clusters = bs.cluster_count();
+
+        let mut fs = FileSystem::mount(&mut dev).expect("mount failed");
+        assert_eq!(fs.boot_sector.fat_type, FatType::Fat32);
+        assert_eq!(fs.fs_info, Some(FsInfo { free_count: clusters - 1, next_free: 3 }));
+        assert!(fs.list_root().expect("list failed").is_empty());
+
+        // 16 entries fit in the one-cluster root; more files make it grow
+        for i in 0..20u8 {
+            let name = alloc::format!("F{}.TXT", i);
+            fs.write_file(&name, &[i]).expect("write failed");
+        }
+        assert_eq!(fs.list_root().expect("list failed").len(), 20);
+        for i in 0..20u8 {
+            let name = alloc::format!("F{}.TXT", i);
+            assert_eq!(fs.read_file(&name).expect("read failed"), alloc::vec![i]);
+        }
+        // 20 file clusters plus one extra root cluster
+        let info = fs.fs_info.expect("fsinfo missing");
+        assert_eq!(info.free_count, clusters - 1 - 21);
+
+        fs.mkdir("SUB").expect("mkdir failed");
+        fs.write_file("SUB/A LONG NAME.TXT", b"thirty-two").expect("nested write failed");
+        fs.delete("F0.TXT").expect("delete failed");
+        let info = fs.fs_info.expect("fsinfo missing");
+        assert_eq!(info.free_count, clusters - 1 - 21 - 2 + 1);
+        drop(fs);
+
+        // FSInfo on disk matches what the mounted volume reported
+        let on_disk = FsInfo::parse(&BUF[512..1024]).expect("fsinfo parse failed");
+        assert_eq!(on_disk, info);
+
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fs = FileSystem::mount(&mut dev).expect("remount failed");
+        assert_eq!(fs.fs_info, Some(info));
+        assert_eq!(fs.list_root().expect("list failed").len(), 20);
+        assert_eq!(fs.read_file("SUB/A LONG NAME.TXT").expect("read failed"), b"thirty-two");
+        assert_eq!(fs.list_dir("SUB/..").expect("list failed").len(), 20);
+    }
+}
+
 use core::panic::PanicInfo;
 
 #[panic_handler]
@@ -11,11 +11,20 @@ use rz_rust_os::allocator;
 use rz_rust_os::memory::{self, BootInfoFrameAllocator};
 use x86_64::VirtAddr;
 
-use rz_rust_os::fs::boot_sector::BootSector;
+use rz_rust_os::fs::boot_sector::{BootSector, FatError, FatType};
 use rz_rust_os::fs::fat_constants::{FAT12_MAX_ROOT_DIR_ENTRIES, BOOT_SIG_LEAD, BOOT_SIG_TRAIL};
-use rz_rust_os::fs::mock_device::MockDevice;
+use rz_rust_os::fs::block_device::{BlockDevice, IoError};
+use rz_rust_os::fs::mock_device::{FaultyDevice, MockDevice};
+use rz_rust_os::fs::cached_device::{CacheStats, CachedDevice};
+use rz_rust_os::fs::partition::{self, Partition, PartitionError, PartitionKind};
 use rz_rust_os::fs::fat_table::FatTable;
-use rz_rust_os::fs::directory::Directory;
+use rz_rust_os::fs::free_map::FreeMap;
+use rz_rust_os::fs::fs_info::FsInfo;
+use rz_rust_os::fs::directory::{to_short_name, Directory};
+use rz_rust_os::fs::lfn;
+use rz_rust_os::fs::time::DateTime;
+use rz_rust_os::fs::format::FormatOptions;
+use rz_rust_os::fs::journal::{self, JournalDevice};
 
 entry_point!(main);
 
@@ -70,15 +79,26 @@ fn serialize_and_parse_roundtrip() {
         max_root_dir_entries: FAT12_MAX_ROOT_DIR_ENTRIES,
         total_sectors: 2880,
         sectors_per_fat: 9,
+        fat_type: FatType::Fat12,
+        root_cluster: 0,
+        fs_info_sector: 0,
         fat_start_lba: 1,
         root_dir_start_lba: 10,
         data_start_lba: 20,
+        oem_name: *b"MSWIN4.1",
+        media: 0xF0,
+        volume_id: 0x1234_5678,
+        volume_label: *b"FLOPPY     ",
     };
     let mut buf = [0u8; 512];
     bs.serialize(&mut buf).expect("serialize failed");
     let bs2 = BootSector::parse(&buf).expect("parse failed");
     assert_eq!(bs2.bytes_per_sector, 512);
     assert_eq!(bs2.total_sectors, 2880);
+    assert_eq!(&buf[0..3], &[0xEB, 0x3C, 0x90]);
+    assert_eq!(bs2.oem_name, *b"MSWIN4.1");
+    assert_eq!((bs2.media, bs2.volume_id), (0xF0, 0x1234_5678));
+    assert_eq!(bs2.volume_label, *b"FLOPPY     ");
 }
 
 #[test_case]
@@ -89,14 +109,14 @@ fn fat12_read_write_simple() {
         let mut dev = MockDevice::new(buf);
         let mut fat = FatTable::new(&mut dev, 0, 9);
         // write cluster 2->3, 3->EOF
-        fat.write_entry(2, 3);
-        fat.write_entry(3, 0xFFF);
-        let v2 = fat.read_entry(2);
-        let v3 = fat.read_entry(3);
+        fat.write_entry(2, 3).expect("write failed");
+        fat.write_entry(3, 0xFFF).expect("write failed");
+        let v2 = fat.read_entry(2).expect("read failed");
+        let v3 = fat.read_entry(3).expect("read failed");
         assert_eq!(v2, 3);
         assert_eq!(v3, 0xFFF);
-        let mut out = [0u16; 16];
-        let len = fat.get_chain_nonalloc(2, &mut out);
+        let mut out = [0u32; 16];
+        let len = fat.get_chain_nonalloc(2, &mut out).expect("chain failed");
         assert_eq!(len, 2);
         assert_eq!(out[0], 2);
         assert_eq!(out[1], 3);
@@ -110,9 +130,9 @@ fn dir_create_serialize() {
         let buf = &mut BUF[..];
         let mut dev = MockDevice::new(buf);
         let mut dir = Directory::new(&mut dev, 0, 32);
-        dir.create("FOO     TXT", 2, 12);
-        dir.create("BAR     TXT", 3, 7);
-        let list = dir.list();
+        dir.create("FOO     TXT", 2, 12).expect("create failed");
+        dir.create("BAR     TXT", 3, 7).expect("create failed");
+        let list = dir.list().expect("list failed");
         assert_eq!(list.len(), 2);
         assert_eq!(list[0].start_cluster, 2);
         assert_eq!(list[0].file_size, 12);
@@ -120,12 +140,532 @@ fn dir_create_serialize() {
         assert_eq!(list[1].file_size, 7);
         // serialize into a buffer and verify first entry bytes
         let mut out = [0u8; 32 * 32];
-        dir.serialize(&mut out);
+        dir.serialize(&mut out).expect("serialize failed");
         assert_eq!(&out[0..11], b"FOO     TXT");
         assert_eq!(&out[32..43], b"BAR     TXT");
     }
 }
 
+#[test_case]
+fn short_name_conversion() {
+    assert_eq!(&to_short_name("foo.txt").expect("foo.txt"), b"FOO     TXT");
+    assert_eq!(&to_short_name("FOO     TXT").expect("padded"), b"FOO     TXT");
+    assert_eq!(&to_short_name("Makefile").expect("no ext"), b"MAKEFILE   ");
+    assert_eq!(&to_short_name("..").expect("dotdot"), b"..         ");
+    assert!(to_short_name("NINECHARS.TXT").is_none());
+    assert!(to_short_name("A.LONG").is_none());
+    assert!(to_short_name("A+B.TXT").is_none());
+    assert!(to_short_name("A.B.C").is_none());
+    assert!(to_short_name("FO O    TXT").is_none());
+}
+
+#[test_case]
+fn lfn_entries_roundtrip() {
+    let short = *b"LONGFI~1TXT";
+    let entries = lfn::build_entries("Long file name.txt", &short);
+    assert_eq!(entries.len(), 2);
+    // last fragment comes first on disk and carries the 0x40 flag
+    assert_eq!(entries[0][0], 0x42);
+    assert_eq!(entries[1][0], 0x01);
+    assert_eq!(entries[0][11], lfn::ATTR_LONG_NAME);
+    assert_eq!(entries[0][13], lfn::checksum(&short));
+    let mut acc = lfn::LfnAccumulator::new();
+    for e in entries.iter() { acc.push(e); }
+    assert_eq!(acc.take(&short).as_deref(), Some("Long file name.txt"));
+    // a checksum mismatch orphans the fragments
+    for e in entries.iter() { acc.push(e); }
+    assert!(acc.take(b"OTHER   TXT").is_none());
+    // a fragment numbered 0 after a complete run discards it
+    for e in entries.iter() { acc.push(e); }
+    let mut zero = entries[1];
+    zero[0] = 0x00;
+    acc.push(&zero);
+    assert!(!acc.is_active());
+    assert!(acc.take(&short).is_none());
+}
+
+#[test_case]
+fn lfn_short_name_generation() {
+    let first = lfn::generate_short_name("My Document.backup", |_| false).expect("gen failed");
+    assert_eq!(&first, b"MYDOCU~1BAC");
+    let taken = [*b"MYDOCU~1BAC", *b"MYDOCU~2BAC"];
+    let third = lfn::generate_short_name("My Document.backup", |c| taken.contains(c)).expect("gen failed");
+    assert_eq!(&third, b"MYDOCU~3BAC");
+    assert_eq!(&lfn::generate_short_name(".bashrc", |_| false).expect("gen failed"), b"BASHRC~1   ");
+    assert_eq!(&lfn::generate_short_name("a+b=c.txt", |_| false).expect("gen failed"), b"A_B_C~1 TXT");
+}
+
+#[test_case]
+fn format_layout_fits_the_device() {
+    // 64 sectors: 1 reserved + 1 FAT + 14 root sectors leaves 48 clusters
+    let bs = FormatOptions::new().layout(64).expect("layout failed");
+    assert_eq!((bs.fat_type, bs.sectors_per_fat, bs.data_start_lba), (FatType::Fat12, 1, 16));
+    assert_eq!(bs.cluster_count(), 48);
+
+    // FAT16 by default above 4 MiB; small clusters while they fit
+    let bs = FormatOptions::new().layout(64 * 1024).expect("layout failed");
+    assert_eq!((bs.fat_type, bs.sectors_per_cluster, bs.max_root_dir_entries), (FatType::Fat16, 1, 512));
+    assert!(bs.sectors_per_fat as u64 * 256 >= bs.cluster_count() as u64 + 2);
+
+    // 1 GiB: FAT32 with 4 KiB clusters and a FAT covering every cluster
+    let bs = FormatOptions::new().layout(1 << 21).expect("layout failed");
+    assert_eq!((bs.fat_type, bs.sectors_per_cluster, bs.reserved_sectors), (FatType::Fat32, 8, 32));
+    assert_eq!((bs.root_cluster, bs.fs_info_sector, bs.max_root_dir_entries), (2, 1, 0));
+    assert!(bs.sectors_per_fat as u64 * 128 >= bs.cluster_count() as u64 + 2);
+    let mut buf = [0u8; 512];
+    bs.serialize(&mut buf).expect("serialize failed");
+    assert_eq!(&buf[0..3], &[0xEB, 0x58, 0x90]);
+    assert_eq!(BootSector::parse(&buf).expect("parse failed"), bs);
+
+    // the requested type has to suit the size
+    assert!(FormatOptions::new().fat_type(FatType::Fat32).layout(64 * 1024).is_err());
+    assert!(FormatOptions::new().sectors_per_cluster(3).layout(64).is_err());
+    assert!(FormatOptions::new().num_fats(0).layout(64).is_err());
+    assert!(FormatOptions::new().layout(8).is_err());
+    assert!(FormatOptions::new().volume_label("TOO LONG LABEL").is_err());
+    assert!(FormatOptions::new().volume_label("A/B").is_err());
+}
+
+#[test_case]
+fn detect_fat_type_from_cluster_count() {
+    assert_eq!(FatType::from_cluster_count(4084), FatType::Fat12);
+    assert_eq!(FatType::from_cluster_count(4085), FatType::Fat16);
+    assert_eq!(FatType::from_cluster_count(65524), FatType::Fat16);
+    assert_eq!(FatType::from_cluster_count(65525), FatType::Fat32);
+
+    // FAT32 BPB: 16-bit sizes are zero, the 32-bit fields are used instead
+    let mut buf = [0u8; 512];
+    buf[11..13].copy_from_slice(&512u16.to_le_bytes());
+    buf[13] = 8; // sectors per cluster
+    buf[14..16].copy_from_slice(&32u16.to_le_bytes()); // reserved
+    buf[16] = 2; // num_fats
+    buf[32..36].copy_from_slice(&1_048_576u32.to_le_bytes()); // 512 MiB
+    buf[36..40].copy_from_slice(&1016u32.to_le_bytes()); // sectors per FAT
+    buf[44..48].copy_from_slice(&2u32.to_le_bytes()); // root cluster
+    buf[48..50].copy_from_slice(&1u16.to_le_bytes()); // FSInfo sector
+    buf[510] = BOOT_SIG_LEAD;
+    buf[511] = BOOT_SIG_TRAIL;
+    let bs = BootSector::parse(&buf).expect("parse failed");
+    assert_eq!(bs.fat_type, FatType::Fat32);
+    assert_eq!(bs.total_sectors, 1_048_576);
+    assert_eq!(bs.sectors_per_fat, 1016);
+    assert_eq!(bs.root_cluster, 2);
+    assert_eq!(bs.fs_info_sector, 1);
+    assert_eq!(bs.data_start_lba, 32 + 2 * 1016);
+    assert_eq!(bs.cluster_count(), (1_048_576 - (32 + 2 * 1016)) / 8);
+
+    // serialize keeps the 32-bit fields
+    let mut out = [0u8; 512];
+    bs.serialize(&mut out).expect("serialize failed");
+    assert_eq!(BootSector::parse(&out).expect("reparse failed"), bs);
+
+    // only 512-byte sectors can be mounted
+    buf[11..13].copy_from_slice(&4096u16.to_le_bytes());
+    assert_eq!(BootSector::parse(&buf), Err(FatError::InvalidGeometry));
+    buf[11..13].copy_from_slice(&512u16.to_le_bytes());
+
+    // FAT sizes that overflow the sector count are rejected, not a panic
+    buf[36..40].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    assert_eq!(BootSector::parse(&buf), Err(FatError::InvalidGeometry));
+    buf[16] = 1;
+    buf[36..40].copy_from_slice(&(u32::MAX - 16).to_le_bytes());
+    assert_eq!(BootSector::parse(&buf), Err(FatError::InvalidGeometry));
+    buf[16] = 2;
+    buf[36..40].copy_from_slice(&1016u32.to_le_bytes());
+
+    // a zero sectors-per-cluster BPB is rejected instead of dividing by zero
+    buf[13] = 0;
+    assert!(BootSector::parse(&buf).is_err());
+}
+
+#[test_case]
+fn fat16_and_fat32_entries() {
+    static mut BUF: [u8; 512 * 4] = [0u8; 512 * 4];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        {
+            let mut fat = FatTable::with_type(&mut dev, 0, 2, FatType::Fat16, 250);
+            fat.write_entry(2, 3).expect("write failed");
+            fat.write_entry(3, FatType::Fat16.eoc()).expect("write failed");
+            fat.write_entry(255, 0xABCD).expect("write failed");
+            assert_eq!(fat.read_entry(255).expect("read failed"), 0xABCD);
+            assert_eq!(fat.get_chain(2).expect("chain failed"), alloc::vec![2, 3]);
+            fat.flush().expect("flush failed");
+        }
+        assert_eq!(&BUF[4..8], &[3, 0, 0xFF, 0xFF]);
+
+        // reserved high nibble of cluster 5's entry must survive writes
+        BUF[2 * 512 + 23] = 0xF0;
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fat = FatTable::with_type(&mut dev, 2, 2, FatType::Fat32, 200);
+        fat.write_entry(5, 0x0123_4567).expect("write failed");
+        assert_eq!(fat.read_entry(5).expect("read failed"), 0x0123_4567);
+        fat.write_entry(6, FatType::Fat32.eoc()).expect("write failed");
+        let v = fat.read_entry(6).expect("read failed");
+        assert!(fat.is_eoc(v));
+        fat.flush().expect("flush failed");
+        assert_eq!(&BUF[2 * 512 + 20..2 * 512 + 24], &[0x67, 0x45, 0x23, 0xF1]);
+    }
+}
+
+#[test_case]
+fn fat_alloc_stays_in_bounds_and_survives_cycles() {
+    static mut BUF: [u8; 512] = [0u8; 512];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fat = FatTable::with_type(&mut dev, 0, 1, FatType::Fat16, 4);
+        // clusters 2..=5 exist
+        for expected in 2..=5u32 {
+            assert_eq!(fat.alloc_cluster().expect("alloc failed"), Some(expected));
+        }
+        assert_eq!(fat.alloc_cluster().expect("alloc failed"), None);
+        assert_eq!(fat.allocated, 4);
+        // a corrupted chain that loops back on itself terminates
+        fat.write_entry(2, 3).expect("write failed");
+        fat.write_entry(3, 2).expect("write failed");
+        assert!(fat.get_chain(2).expect("chain failed").len() <= 5);
+        fat.free_cluster(2).expect("free failed");
+        assert_eq!(fat.read_entry(2).expect("read failed"), 0);
+        assert_eq!(fat.read_entry(3).expect("read failed"), 0);
+        assert_eq!(fat.alloc_cluster().expect("alloc failed"), Some(2));
+    }
+}
+
+#[test_case]
+fn fat_writes_reach_every_copy() {
+    static mut BUF: [u8; 512 * 7] = [0u8; 512 * 7];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut buf = [0u8; 512];
+        // FAT12, 2 copies of 2 sectors each starting at LBA 1
+        buf[11..13].copy_from_slice(&512u16.to_le_bytes());
+        buf[13] = 1;
+        buf[14..16].copy_from_slice(&1u16.to_le_bytes());
+        buf[16] = 2;
+        buf[17..19].copy_from_slice(&16u16.to_le_bytes());
+        buf[19..21].copy_from_slice(&600u16.to_le_bytes());
+        buf[22..24].copy_from_slice(&2u16.to_le_bytes());
+        buf[510] = BOOT_SIG_LEAD;
+        buf[511] = BOOT_SIG_TRAIL;
+        let bs = BootSector::parse(&buf).expect("parse failed");
+        let mut fat = FatTable::for_volume(&mut dev, &bs);
+        assert_eq!(fat.num_fats(), 2);
+        fat.write_entry(2, 0xFFF).expect("write failed");
+        // cluster 341's entry straddles the two FAT sectors
+        fat.write_entry(341, 0xABC).expect("write failed");
+        fat.flush().expect("flush failed");
+        assert_eq!(fat.sync_mirrors(false).expect("sync failed"), 0);
+        assert_eq!(fat.read_entry(341).expect("read failed"), 0xABC);
+    }
+    unsafe {
+        let (first, second) = BUF[512..512 * 5].split_at(1024);
+        assert_eq!(first, second);
+        BUF[512 * 4 + 3] = 0x55;
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fat = FatTable::with_type(&mut dev, 1, 2, FatType::Fat12, 500);
+        // a single-copy table never looks at the mirror
+        assert_eq!(fat.sync_mirrors(true).expect("sync failed"), 0);
+        assert_eq!(BUF[512 * 4 + 3], 0x55);
+    }
+}
+
+#[test_case]
+fn fs_info_roundtrip() {
+    let info = FsInfo { free_count: 1234, next_free: 77 };
+    let mut buf = [0u8; 512];
+    info.serialize(&mut buf).expect("serialize failed");
+    assert_eq!(&buf[0..4], b"RRaA");
+    assert_eq!(&buf[484..488], b"rrAa");
+    assert_eq!(FsInfo::parse(&buf).expect("parse failed"), info);
+    buf[0] = 0;
+    assert!(FsInfo::parse(&buf).is_err());
+}
+
+#[test_case]
+fn dir_entry_keeps_high_cluster_word() {
+    static mut BUF: [u8; 512] = [0u8; 512];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut dir = Directory::new(&mut dev, 0, 16);
+        dir.create("BIG     DAT", 0x0012_3456, 1).expect("create failed");
+        assert_eq!(dir.find("BIG.DAT").expect("find failed").expect("missing").start_cluster, 0x0012_3456);
+    }
+    unsafe {
+        assert_eq!(&BUF[20..22], &[0x12, 0x00]);
+        assert_eq!(&BUF[26..28], &[0x56, 0x34]);
+    }
+}
+
+#[test_case]
+fn mock_device_reports_io_errors() {
+    static mut BUF: [u8; 512 * 2] = [0u8; 512 * 2];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut sector = [0u8; 512];
+        assert_eq!(dev.read_sector(2, &mut sector), Err(IoError::OutOfRange));
+        assert_eq!(dev.write_sector(2, &sector), Err(IoError::OutOfRange));
+        assert_eq!(dev.read_sector(0, &mut sector[..100]), Err(IoError::BadBuffer));
+        sector[0] = 0xAA;
+        dev.write_sector(1, &sector).expect("write failed");
+        dev.set_read_only(true);
+        assert!(dev.is_read_only());
+        assert_eq!(dev.write_sector(1, &[0u8; 512]), Err(IoError::ReadOnly));
+        let mut back = [0u8; 512];
+        dev.read_sector(1, &mut back).expect("read failed");
+        assert_eq!(back[0], 0xAA);
+        // a FAT entry past the end of the device surfaces as an error
+        let mut fat = FatTable::new(&mut dev, 1, 9);
+        assert_eq!(fat.read_entry(1000), Err(IoError::OutOfRange));
+    }
+}
+
+#[test_case]
+fn cached_device_lru_write_back() {
+    static mut BUF: [u8; 512 * 8] = [0u8; 512 * 8];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = CachedDevice::new(MockDevice::new(buf), 2);
+        let mut sector = [0u8; 512];
+        dev.write_sector(0, &[0xAA; 512]).expect("write failed");
+        // the write stays in the cache until it is evicted or synced
+        assert_eq!(dev.inner().buf[0], 0);
+        dev.read_sector(0, &mut sector).expect("read failed");
+        assert_eq!(sector[0], 0xAA);
+        dev.read_sector(1, &mut sector).expect("read failed");
+        dev.read_sector(0, &mut sector).expect("read failed");
+        // sector 1 is now least recently used, so reading 2 evicts it
+        dev.read_sector(2, &mut sector).expect("read failed");
+        assert_eq!(dev.dirty_count(), 1);
+        assert_eq!(dev.stats(), CacheStats { hits: 2, misses: 3, writebacks: 0 });
+        // sector 0 goes next and is written back on the way out
+        dev.read_sector(3, &mut sector).expect("read failed");
+        dev.read_sector(4, &mut sector).expect("read failed");
+        assert_eq!(dev.inner().buf[0], 0xAA);
+        assert_eq!(dev.stats().writebacks, 1);
+
+        dev.write_sector(5, &[0xBB; 512]).expect("write failed");
+        dev.sync().expect("sync failed");
+        assert_eq!(dev.dirty_count(), 0);
+        assert_eq!(dev.inner().buf[5 * 512], 0xBB);
+        assert_eq!(dev.read_sector(8, &mut sector), Err(IoError::OutOfRange));
+        dev.inner_mut().set_read_only(true);
+        assert_eq!(dev.write_sector(1, &sector), Err(IoError::ReadOnly));
+    }
+}
+
+#[test_case]
+fn mbr_partitions_and_bounds() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    fn entry(sector: &mut [u8], slot: usize, kind: u8, start: u32, count: u32) {
+        let e = &mut sector[446 + slot * 16..446 + (slot + 1) * 16];
+        e[4] = kind;
+        e[8..12].copy_from_slice(&start.to_le_bytes());
+        e[12..16].copy_from_slice(&count.to_le_bytes());
+    }
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut sector = [0u8; 512];
+        sector[510] = BOOT_SIG_LEAD;
+        sector[511] = BOOT_SIG_TRAIL;
+        let signed = sector;
+        // one FAT16 primary and an extended partition holding two logicals
+        entry(&mut sector, 0, 0x06, 2, 10);
+        entry(&mut sector, 1, 0x05, 20, 40);
+        dev.write_sector(0, &sector).expect("write failed");
+        sector = signed;
+        entry(&mut sector, 0, 0x0B, 1, 5);
+        entry(&mut sector, 1, 0x05, 10, 10);
+        dev.write_sector(20, &sector).expect("write failed");
+        sector = signed;
+        entry(&mut sector, 0, 0x83, 2, 4);
+        dev.write_sector(30, &sector).expect("write failed");
+
+        let parts = partition::read_partitions(&mut dev).expect("read partitions failed");
+        assert_eq!(parts.len(), 3);
+        assert_eq!((parts[0].index, parts[0].start_lba, parts[0].sector_count), (0, 2, 10));
+        assert_eq!((parts[1].index, parts[1].start_lba, parts[1].sector_count), (4, 21, 5));
+        assert_eq!((parts[2].index, parts[2].start_lba, parts[2].sector_count), (5, 32, 4));
+        assert_eq!(parts[1].kind, PartitionKind::Mbr(0x0B));
+        assert!(parts[0].is_fat() && parts[1].is_fat() && !parts[2].is_fat());
+
+        // the view is offset by the start LBA and stops at the partition end
+        let mut part = Partition::new(&mut dev, &parts[0]).expect("partition failed");
+        assert_eq!(part.sector_count(), 10);
+        part.write_sector(0, &[0x5A; 512]).expect("write failed");
+        part.read_sector(9, &mut sector).expect("read failed");
+        assert_eq!(part.write_sector(10, &sector), Err(IoError::OutOfRange));
+        assert_eq!(part.read_sector(10, &mut sector), Err(IoError::OutOfRange));
+        assert_eq!(dev.buf[2 * 512], 0x5A);
+        assert_eq!(dev.buf[12 * 512], 0);
+
+        let mut too_big = parts[2].clone();
+        too_big.sector_count = 40;
+        assert!(matches!(Partition::new(&mut dev, &too_big), Err(PartitionError::OutOfBounds)));
+
+        // MBR boot code fills the bytes a BPB would use; the table still counts
+        sector = signed;
+        let boot_code = [0x33, 0xC0, 0x8E, 0xD0, 0xBC, 0x00, 0x7C, 0x8E, 0xC0, 0x8E, 0xD8, 0xBE, 0x00, 0x7C, 0xBF, 0x00, 0x06, 0xB9, 0x00, 0x02, 0xFC, 0xF3, 0xA4];
+        sector[..boot_code.len()].copy_from_slice(&boot_code);
+        entry(&mut sector, 0, 0x0C, 2, 10);
+        dev.write_sector(0, &sector).expect("write failed");
+        let parts = partition::read_partitions(&mut dev).expect("read partitions failed");
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].start_lba, 2);
+        assert_eq!(parts[0].kind, PartitionKind::Mbr(0x0C));
+
+        // a bare FAT volume has no partition table
+        sector = [0u8; 512];
+        FormatOptions::new().layout(64).expect("layout failed").serialize(&mut sector).expect("serialize failed");
+        dev.write_sector(0, &sector).expect("write failed");
+        assert_eq!(partition::read_partitions(&mut dev), Err(PartitionError::NoTable));
+        dev.write_sector(0, &[0u8; 512]).expect("write failed");
+        assert_eq!(partition::read_partitions(&mut dev), Err(PartitionError::NoTable));
+        assert_eq!(partition::crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
+
+#[test_case]
+fn free_map_tracks_fat_writes() {
+    static mut BUF: [u8; 512 * 4] = [0u8; 512 * 4];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        // FAT16, 200 clusters: entries 0-1 reserved, 2-69 in use
+        let mut fat = FatTable::with_type(&mut dev, 0, 2, FatType::Fat16, 200);
+        for c in 0..70 { fat.write_entry(c, 0xFFFF).expect("write failed"); }
+        fat.flush().expect("flush failed");
+        let mut map = FreeMap::build(&mut fat).expect("build failed");
+        assert_eq!(map.free_count(), 200 + 2 - 70);
+        assert_eq!(map.find_free(2), Some(70));
+        assert_eq!(map.find_free(150), Some(150));
+        assert!(!map.is_free(69) && map.is_free(201) && !map.is_free(202));
+
+        let mut fat = FatTable::with_type(&mut dev, 0, 2, FatType::Fat16, 200).with_free_map(&mut map);
+        fat.set_next_free(201);
+        assert_eq!(fat.alloc_cluster().expect("alloc failed"), Some(201));
+        // the search wraps around to the start of the volume
+        assert_eq!(fat.alloc_cluster().expect("alloc failed"), Some(70));
+        fat.free_cluster(5).expect("free failed");
+        assert_eq!(fat.free_count(), Some(200 + 2 - 70 - 2 + 1));
+        // an entry changed behind the map's back is skipped, not reused
+        fat.flush().expect("flush failed");
+        drop(fat);
+        let mut behind = FatTable::with_type(&mut dev, 0, 2, FatType::Fat16, 200);
+        behind.write_entry(71, 0xFFFF).expect("write failed");
+        behind.flush().expect("flush failed");
+        drop(behind);
+        let mut fat = FatTable::with_type(&mut dev, 0, 2, FatType::Fat16, 200).with_free_map(&mut map);
+        fat.set_next_free(71);
+        assert_eq!(fat.alloc_cluster().expect("alloc failed"), Some(72));
+        assert!(!map.is_free(71));
+    }
+}
+
+#[test_case]
+fn free_map_covers_large_volumes() {
+    // a FAT32 volume of 600 MiB in 4 KiB clusters has about 153000 clusters
+    const CLUSTERS: u32 = 160_000;
+    static mut BUF: [u8; 512 * 1251] = [0u8; 512 * 1251];
+    unsafe {
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let mut fat = FatTable::with_type(&mut dev, 0, 1251, FatType::Fat32, CLUSTERS);
+        for c in [0, 1, 2, CLUSTERS] { fat.write_entry(c, 0x0FFF_FFFF).expect("write failed"); }
+        fat.flush().expect("flush failed");
+        let map = FreeMap::build(&mut fat).expect("build failed");
+        assert_eq!(map.max_cluster(), CLUSTERS + 1);
+        assert_eq!(map.free_count(), CLUSTERS - 2);
+        assert_eq!(map.find_free(CLUSTERS - 1), Some(CLUSTERS - 1));
+        assert_eq!(map.find_free(CLUSTERS), Some(CLUSTERS + 1));
+        assert!(!map.is_free(CLUSTERS) && map.is_free(3));
+    }
+}
+
+#[test_case]
+fn fat_timestamp_encoding() {
+    let t = DateTime { year: 2024, month: 2, day: 29, hour: 23, minute: 59, second: 59 };
+    assert_eq!(t.fat_date(), (44 << 9) | (2 << 5) | 29);
+    assert_eq!(t.fat_time(), (23 << 11) | (59 << 5) | 29);
+    assert_eq!(t.fat_hundredths(), 100);
+    // the odd second survives only with the creation hundredths
+    assert_eq!(DateTime::from_fat(t.fat_date(), t.fat_time(), t.fat_hundredths()), t);
+    assert_eq!(DateTime::from_fat(t.fat_date(), t.fat_time(), 0).second, 58);
+    // a zero date is "not set" and prints as dashes
+    let unset = DateTime::from_fat(0, 0, 0);
+    assert!(!unset.is_set());
+    assert_eq!(unset.fat_date(), 0);
+    assert_eq!(alloc::format!("{}", DateTime::FAT_EPOCH), "1980-01-01 00:00:00");
+    // years FAT cannot store are clamped
+    let old = DateTime { year: 1970, ..t };
+    assert_eq!(DateTime::from_fat(old.fat_date(), 0, 0).year, 1980);
+}
+
+#[test_case]
+fn journal_commit_and_replay() {
+    static mut BUF: [u8; 512 * 64] = [0u8; 512 * 64];
+    unsafe {
+        // the journal grows the reserved region
+        let options = FormatOptions::new().journal(journal::JOURNAL_DEFAULT_SECTORS);
+        assert_eq!(options.layout(64).expect("layout failed").reserved_sectors, 17);
+        let fat32 = options.fat_type(FatType::Fat32).journal(40).layout(600_000).expect("layout failed");
+        assert_eq!(fat32.reserved_sectors, 48);
+        assert!(FormatOptions::new().journal(1).layout(64).is_err());
+
+        let buf = &mut BUF[..];
+        let mut dev = MockDevice::new(buf);
+        let bs = options.layout(64).expect("layout failed");
+        let mut raw = [0u8; 512];
+        bs.serialize(&mut raw).expect("serialize failed");
+        dev.write_sector(0, &raw).expect("write failed");
+        journal::write_empty(&mut dev, 1, journal::JOURNAL_DEFAULT_SECTORS).expect("write failed");
+
+        // held writes are visible through the journal but not below it
+        let mut jd = JournalDevice::new(&mut dev);
+        assert_eq!(jd.open(&bs).expect("open failed"), 0);
+        assert!(jd.is_journaled());
+        jd.begin();
+        jd.write_sector(40, &[0xAB; 512]).expect("write failed");
+        jd.write_sector(41, &[0xCD; 512]).expect("write failed");
+        let mut out = [0u8; 512];
+        jd.read_sector(40, &mut out).expect("read failed");
+        assert_eq!(out, [0xAB; 512]);
+        drop(jd);
+        dev.read_sector(40, &mut out).expect("read failed");
+        assert_eq!(out, [0u8; 512]);
+
+        // a commit cut off after its header (2 records, then the header)
+        // is finished by the next open
+        let mut faulty = FaultyDevice::new(MockDevice::new(&mut BUF[..]));
+        faulty.fail_after(3);
+        let mut jd = JournalDevice::new(&mut faulty);
+        jd.open(&bs).expect("open failed");
+        jd.begin();
+        jd.write_sector(40, &[0xAB; 512]).expect("write failed");
+        jd.write_sector(41, &[0xCD; 512]).expect("write failed");
+        assert_eq!(jd.commit(), Err(IoError::Device));
+        drop(jd);
+        let mut dev = MockDevice::new(&mut BUF[..]);
+        dev.read_sector(41, &mut out).expect("read failed");
+        assert_eq!(out, [0u8; 512]);
+        let mut jd = JournalDevice::new(&mut dev);
+        assert_eq!(jd.open(&bs).expect("open failed"), 2);
+        drop(jd);
+        dev.read_sector(41, &mut out).expect("read failed");
+        assert_eq!(out, [0xCD; 512]);
+        // and the header is clear again
+        let mut jd = JournalDevice::new(&mut dev);
+        assert_eq!(jd.open(&bs).expect("open failed"), 0);
+    }
+}
+
 use core::panic::PanicInfo;
 
 #[panic_handler]
@@ -0,0 +1,586 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rz_rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use bootloader::{entry_point, BootInfo};
+use rz_rust_os::allocator;
+use rz_rust_os::memory::{self, BootInfoFrameAllocator};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+use rz_rust_os::network;
+use rz_rust_os::network::config::{self, NetConfig};
+use rz_rust_os::network::device::buf::PacketBuf;
+use rz_rust_os::network::device::{MacAddr, NetError, NetworkDevice, Result};
+use rz_rust_os::network::internet::checksums;
+use rz_rust_os::network::internet::fragment::{self, Reassembler};
+use rz_rust_os::network::internet::ipv4::{self, Ipv4Header};
+use rz_rust_os::network::link::arp::{self, ArpCache, ArpOp};
+use rz_rust_os::network::link::ethernet::{build_eth_frame, parse_eth_header, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use rz_rust_os::network::transport::sockets::SocketWaker;
+use rz_rust_os::network::transport::udp::UdpSocket;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rz_rust_os::init();
+    // Initialize memory and heap so tests can use `alloc` (Vec, Box, etc.).
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization in tests failed");
+
+    test_main();
+    loop {}
+}
+
+const OUR_MAC: MacAddr = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+const PEER_MAC: MacAddr = [1, 2, 3, 4, 5, 6];
+
+// frames waiting to be received, and frames the stack transmitted
+static RX: Mutex<VecDeque<Vec<u8>>> = Mutex::new(VecDeque::new());
+static TX: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+/// A device whose wire is the `RX` and `TX` queues.
+struct QueueDevice;
+
+impl NetworkDevice for QueueDevice {
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        TX.lock().push(Vec::from(frame));
+        Ok(())
+    }
+    fn receive(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let frame = RX.lock().pop_front().ok_or(NetError::WouldBlock)?;
+        if frame.len() > buf.len() { return Err(NetError::BufferTooSmall); }
+        buf[..frame.len()].copy_from_slice(&frame);
+        Ok(frame.len())
+    }
+    fn mac_addr(&self) -> MacAddr { OUR_MAC }
+    fn mtu(&self) -> usize { 1500 }
+    fn handle_interrupt(&mut self) {}
+}
+
+#[test_case]
+fn device_trait_shape() {
+    let mut d = QueueDevice;
+    let mut buf = [0u8; 64];
+    assert_eq!(d.receive(&mut buf), Err(NetError::WouldBlock));
+    assert_eq!(d.mtu(), 1500);
+    assert_eq!(d.mac_addr(), OUR_MAC);
+}
+
+#[test_case]
+fn packetbuf_basic() {
+    let mut p = PacketBuf::with_capacity(128);
+    assert!(p.is_empty());
+    p.push_bytes(&[1, 2, 3]);
+    assert_eq!(p.len(), 3);
+    assert_eq!(p.as_slice(), &[1, 2, 3]);
+}
+
+#[test_case]
+fn ethernet_rejects_short_frames() {
+    assert!(parse_eth_header(&[0u8; 13]).is_none());
+    assert!(parse_eth_header(&[0u8; 64]).is_some());
+    let mut out = [0u8; 15];
+    assert!(build_eth_frame([0; 6], [0; 6], ETHERTYPE_IPV4, &[1, 2], &mut out).is_none());
+}
+
+#[test_case]
+fn build_and_parse_roundtrip() {
+    let dst = [1u8, 2, 3, 4, 5, 6];
+    let src = [10u8, 11, 12, 13, 14, 15];
+    let ethertype = ETHERTYPE_IPV4;
+    let payload = [0x45u8, 0, 0x00, 0x54];
+    let mut out = [0u8; 128];
+    let len = build_eth_frame(dst, src, ethertype, &payload, &mut out).expect("build failed");
+    let (hdr, pl) = parse_eth_header(&out[..len]).expect("parse failed");
+    assert_eq!(hdr.dst, dst);
+    assert_eq!(hdr.src, src);
+    assert_eq!(hdr.ethertype, ethertype);
+    assert_eq!(pl, &payload);
+}
+
+#[test_case]
+fn arp_cache_basic() {
+    let mut c = ArpCache::new();
+    assert!(c.lookup([0, 0, 0, 0]).is_none());
+    c.insert([1, 2, 3, 4], [5, 6, 7, 8, 9, 10]);
+    assert_eq!(c.lookup([1, 2, 3, 4]), Some([5, 6, 7, 8, 9, 10]));
+    c.insert([1, 2, 3, 4], [5; 6]);
+    assert_eq!(c.lookup([1, 2, 3, 4]), Some([5; 6]));
+    assert_eq!(c.len(), 1);
+    c.remove([1, 2, 3, 4]);
+    assert!(c.lookup([1, 2, 3, 4]).is_none() && c.is_empty());
+}
+
+#[test_case]
+fn arp_cache_evicts_least_recently_used() {
+    let mut c = ArpCache::new();
+    for i in 0..ArpCache::CAPACITY as u8 {
+        c.insert([10, 0, 0, i], [i; 6]);
+    }
+    // refresh the oldest entry, so the second oldest goes
+    c.insert([10, 0, 0, 0], [0; 6]);
+    c.insert([10, 0, 1, 0], [0xEE; 6]);
+    assert_eq!(c.len(), ArpCache::CAPACITY);
+    assert!(c.lookup([10, 0, 0, 0]).is_some());
+    assert!(c.lookup([10, 0, 0, 1]).is_none());
+    assert_eq!(c.lookup([10, 0, 1, 0]), Some([0xEE; 6]));
+}
+
+#[test_case]
+fn arp_parse_and_build() {
+    // build a request packet
+    let mut req = Vec::new();
+    req.extend_from_slice(&1u16.to_be_bytes()); // htype
+    req.extend_from_slice(&0x0800u16.to_be_bytes()); // ptype IPv4
+    req.push(6);
+    req.push(4);
+    req.extend_from_slice(&1u16.to_be_bytes()); // request
+    let sender_mac = [1u8, 2, 3, 4, 5, 6];
+    let sender_ip = [10u8, 0, 0, 1];
+    let target_mac = [0u8; 6];
+    let target_ip = [10u8, 0, 0, 2];
+    req.extend_from_slice(&sender_mac);
+    req.extend_from_slice(&sender_ip);
+    req.extend_from_slice(&target_mac);
+    req.extend_from_slice(&target_ip);
+    let pkt = arp::parse_arp_packet(&req).expect("parse");
+    assert_eq!(pkt.sender_ip, sender_ip);
+    let reply = arp::build_arp_reply(&pkt, [9u8, 9, 9, 9, 9, 9], [10u8, 0, 0, 2]);
+    let parsed = arp::parse_arp_packet(&reply).expect("parse reply");
+    assert_eq!(parsed.opcode, ArpOp::Reply as u16);
+    assert_eq!(parsed.sender_ip, [10u8, 0, 0, 2]);
+    assert_eq!(parsed.target_ip, sender_ip);
+    assert_eq!(parsed.target_mac, sender_mac);
+    // anything but Ethernet/IPv4 is refused
+    req[1] = 6;
+    assert!(arp::parse_arp_packet(&req).is_none());
+    assert!(arp::parse_arp_packet(&reply[..27]).is_none());
+}
+
+/// The header from RFC 1071's usual worked example: 192.168.0.1 ->
+/// 192.168.0.199, UDP, DF, checksum 0xB861.
+const SAMPLE_HEADER: [u8; 20] = [
+    0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+    0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+];
+
+#[test_case]
+fn checksums_match_known_values() {
+    let mut header = SAMPLE_HEADER;
+    assert_eq!(checksums::ipv4_checksum(&header), 0);
+    header[10..12].copy_from_slice(&[0, 0]);
+    assert_eq!(checksums::ipv4_checksum(&header), 0xb861);
+    // an odd trailing byte counts as the high half of a word
+    assert_eq!(checksums::internet_checksum(&[0x12, 0x34, 0x56]), !(0x1234u16 + 0x5600));
+    assert_eq!(checksums::internet_checksum(&[]), 0xFFFF);
+    // carries wrap around
+    assert_eq!(checksums::internet_checksum(&[0xFF, 0xFF, 0x00, 0x02]), !0x0002u16);
+
+    // UDP 10.0.2.15:1234 -> 10.0.2.2:53 carrying "hi!"
+    let (src, dst) = ([10, 0, 2, 15], [10, 0, 2, 2]);
+    let mut udp = [0x04, 0xd2, 0x00, 0x35, 0x00, 0x0b, 0x00, 0x00, b'h', b'i', b'!'];
+    let sum = checksums::udp_checksum(src, dst, &udp);
+    let expected = !(0x0a00u16 + 0x020f + 0x0a00 + 0x0202 + 17 + 11 + 0x04d2 + 0x0035 + 11 + 0x6869 + 0x2100);
+    assert_eq!(sum, expected);
+    udp[6..8].copy_from_slice(&sum.to_be_bytes());
+    assert_eq!(checksums::udp_checksum(src, dst, &udp), 0);
+    // the pseudo-header covers the addresses
+    assert_ne!(checksums::udp_checksum(src, [10, 0, 2, 3], &udp), 0);
+}
+
+#[test_case]
+fn ipv4_parses_known_header() {
+    let mut packet = [0u8; 0x73];
+    packet[..20].copy_from_slice(&SAMPLE_HEADER);
+    let (hdr, payload) = ipv4::parse_ipv4_header(&packet).expect("parse failed");
+    assert_eq!((hdr.src, hdr.dst), ([192, 168, 0, 1], [192, 168, 0, 199]));
+    assert_eq!((hdr.proto, hdr.ttl, hdr.header_len, hdr.total_len), (ipv4::PROTO_UDP, 64, 5, 0x73));
+    assert!(hdr.dont_fragment && !hdr.more_fragments && !hdr.is_fragment());
+    assert_eq!((hdr.identification, hdr.fragment_offset, hdr.checksum), (0, 0, 0xb861));
+    assert_eq!(payload.len(), 0x73 - 20);
+}
+
+#[test_case]
+fn ipv4_build_and_parse_roundtrip() {
+    let payload = *b"ping payload";
+    let mut out = [0u8; 64];
+    let len = ipv4::build_ipv4_packet([10, 0, 2, 15], [10, 0, 2, 2], ipv4::PROTO_ICMP, &payload, &mut out).expect("build failed");
+    assert_eq!(len, 20 + payload.len());
+    let (hdr, pl) = ipv4::parse_ipv4_header(&out[..len]).expect("parse failed");
+    assert_eq!((hdr.src, hdr.dst, hdr.proto), ([10, 0, 2, 15], [10, 0, 2, 2], ipv4::PROTO_ICMP));
+    assert_eq!((hdr.ttl, hdr.total_len as usize), (ipv4::DEFAULT_TTL, len));
+    assert!(!hdr.is_fragment());
+    assert_eq!(pl, &payload);
+    // every packet gets its own identification
+    let (first, _) = ipv4::parse_ipv4_header(&out[..len]).expect("parse failed");
+    ipv4::build_ipv4_packet([10, 0, 2, 15], [10, 0, 2, 2], ipv4::PROTO_ICMP, &payload, &mut out).expect("build failed");
+    let (second, _) = ipv4::parse_ipv4_header(&out[..len]).expect("parse failed");
+    assert_ne!(first.identification, second.identification);
+
+    // all header fields survive, fragments included
+    let mut hdr = Ipv4Header::new([1, 2, 3, 4], [5, 6, 7, 8], ipv4::PROTO_UDP);
+    hdr.tos = 0x10;
+    hdr.ttl = 1;
+    hdr.identification = 0xBEEF;
+    hdr.more_fragments = true;
+    hdr.fragment_offset = 1480;
+    let len = hdr.write(&payload, &mut out).expect("write failed");
+    let (parsed, _) = ipv4::parse_ipv4_header(&out[..len]).expect("parse failed");
+    assert_eq!((parsed.tos, parsed.ttl, parsed.identification), (0x10, 1, 0xBEEF));
+    assert!(parsed.more_fragments && !parsed.dont_fragment && parsed.is_fragment());
+    assert_eq!(parsed.fragment_offset, 1480);
+    hdr.fragment_offset = 1481;
+    assert!(hdr.write(&payload, &mut out).is_none());
+    // and the result must fit the buffer
+    assert!(ipv4::build_ipv4_packet([0; 4], [0; 4], 0, &payload, &mut out[..31]).is_none());
+}
+
+/// Recompute the checksum of the header at the front of `packet`.
+fn fix_checksum(packet: &mut [u8]) {
+    let len = (packet[0] & 0x0F) as usize * 4;
+    packet[10..12].copy_from_slice(&[0, 0]);
+    let sum = checksums::ipv4_checksum(&packet[..len]);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+}
+
+#[test_case]
+fn ipv4_skips_options_and_padding() {
+    // 8 option bytes: NOP, a 4-byte option, NOP, end of list, padding
+    let options = [1, 0x94, 4, 0, 1, 0, 0, 0];
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&SAMPLE_HEADER);
+    packet[0] = 0x47;
+    packet[2..4].copy_from_slice(&(28u16 + 3).to_be_bytes());
+    packet.extend_from_slice(&options);
+    packet.extend_from_slice(b"abc");
+    // Ethernet pads short frames; the padding is not payload
+    packet.extend_from_slice(&[0; 10]);
+    fix_checksum(&mut packet);
+    let (hdr, payload) = ipv4::parse_ipv4_header(&packet).expect("parse failed");
+    assert_eq!((hdr.header_len, hdr.header_bytes(), hdr.payload_len()), (7, 28, 3));
+    assert_eq!(payload, b"abc");
+}
+
+#[test_case]
+fn ipv4_rejects_malformed_packets() {
+    let mut good = Vec::from(&SAMPLE_HEADER[..]);
+    good.resize(0x73, 0);
+    assert!(ipv4::parse_ipv4_header(&good).is_some());
+    assert!(ipv4::parse_ipv4_header(&good[..19]).is_none());
+    // shorter than its total length
+    assert!(ipv4::parse_ipv4_header(&good[..0x72]).is_none());
+
+    let broken = |at: usize, value: u8, fix: bool| {
+        let mut p = good.clone();
+        p[at] = value;
+        if fix { fix_checksum(&mut p); }
+        ipv4::parse_ipv4_header(&p).is_none()
+    };
+    assert!(broken(0, 0x65, true), "version 6");
+    assert!(broken(0, 0x44, true), "IHL below 5");
+    assert!(broken(3, 0x10, true), "total length inside the header");
+    assert!(broken(6, 0xC0, true), "reserved flag");
+    assert!(broken(8, 63, false), "bad checksum");
+
+    // an IHL reaching past the buffer
+    let mut p = good.clone();
+    p.truncate(28);
+    p[0] = 0x47;
+    p[2..4].copy_from_slice(&28u16.to_be_bytes());
+    fix_checksum(&mut p);
+    assert!(ipv4::parse_ipv4_header(&p).is_some());
+    p.truncate(24);
+    assert!(ipv4::parse_ipv4_header(&p).is_none());
+
+    // an option whose length runs out of the header, and one of length 0
+    for options in [[0x94u8, 8, 0, 0], [0x94, 0, 0, 0]] {
+        let mut p = Vec::from(&SAMPLE_HEADER[..]);
+        p[0] = 0x46;
+        p[2..4].copy_from_slice(&24u16.to_be_bytes());
+        p.extend_from_slice(&options);
+        fix_checksum(&mut p);
+        assert!(ipv4::parse_ipv4_header(&p).is_none());
+    }
+
+    // a last fragment that would end past 65535 bytes
+    let mut p = good.clone();
+    p[6..8].copy_from_slice(&0x1FFFu16.to_be_bytes());
+    fix_checksum(&mut p);
+    assert!(ipv4::parse_ipv4_header(&p).is_none());
+}
+
+/// A datagram of `len` bytes whose content says where each byte was.
+fn numbered_payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Split `packets` into (header, payload) pairs.
+fn parse_all(packets: &[Vec<u8>]) -> Vec<(Ipv4Header, Vec<u8>)> {
+    packets
+        .iter()
+        .map(|p| ipv4::parse_ipv4_header(p).map(|(h, pl)| (h, Vec::from(pl))).expect("fragment does not parse"))
+        .collect()
+}
+
+#[test_case]
+fn ipv4_fragments_to_mtu() {
+    let mut hdr = Ipv4Header::new([10, 0, 2, 15], [10, 0, 2, 2], ipv4::PROTO_UDP);
+    hdr.identification = 77;
+    let payload = numbered_payload(3000);
+    let packets = fragment::fragment(&hdr, &payload, 1500).expect("fragment failed");
+    assert_eq!(packets.iter().map(|p| p.len()).collect::<Vec<_>>(), [1500, 1500, 60]);
+    let parsed = parse_all(&packets);
+    let offsets: Vec<_> = parsed.iter().map(|(h, _)| (h.fragment_offset, h.more_fragments)).collect();
+    assert_eq!(offsets, [(0, true), (1480, true), (2960, false)]);
+    assert!(parsed.iter().all(|(h, _)| h.identification == 77 && h.proto == ipv4::PROTO_UDP));
+    let joined: Vec<u8> = parsed.iter().flat_map(|(_, pl)| pl.iter().copied()).collect();
+    assert_eq!(joined, payload);
+
+    // an MTU that is not 20 + a multiple of 8 rounds the pieces down
+    let packets = fragment::fragment(&hdr, &payload, 1000).expect("fragment failed");
+    assert!(parse_all(&packets).iter().all(|(h, pl)| !h.more_fragments || pl.len() == 976));
+    // small enough: one ordinary packet
+    let packets = fragment::fragment(&hdr, &payload[..100], 1500).expect("fragment failed");
+    assert_eq!(packets.len(), 1);
+    assert!(!parse_all(&packets)[0].0.is_fragment());
+    // DF forbids it, and an MTU must carry at least 8 bytes
+    hdr.dont_fragment = true;
+    assert!(fragment::fragment(&hdr, &payload, 1500).is_none());
+    hdr.dont_fragment = false;
+    assert!(fragment::fragment(&hdr, &payload, 27).is_none());
+}
+
+#[test_case]
+fn reassembly_out_of_order_with_duplicates() {
+    let mut hdr = Ipv4Header::new([10, 0, 2, 2], [10, 0, 2, 15], ipv4::PROTO_UDP);
+    hdr.identification = 9;
+    let payload = numbered_payload(4000);
+    let mut pieces = parse_all(&fragment::fragment(&hdr, &payload, 1500).expect("fragment failed"));
+    assert_eq!(pieces.len(), 3);
+    pieces.swap(0, 2);
+    let mut r = Reassembler::new(16 * 1024, 100);
+    assert!(r.push(&pieces[0].0, &pieces[0].1, 0).is_none());
+    assert!(r.push(&pieces[0].0, &pieces[0].1, 0).is_none());
+    assert!(r.push(&pieces[1].0, &pieces[1].1, 1).is_none());
+    assert_eq!((r.pending(), r.memory_used()), (1, 4000));
+    let (whole, data) = r.push(&pieces[2].0, &pieces[2].1, 2).expect("not reassembled");
+    assert_eq!(data, payload);
+    assert!(!whole.is_fragment());
+    assert_eq!((whole.src, whole.identification, whole.total_len), ([10, 0, 2, 2], 9, 4020));
+    assert_eq!((r.pending(), r.memory_used()), (0, 0));
+
+    // unfragmented packets pass straight through
+    let (h, pl) = r.push(&hdr, b"small", 3).expect("not passed through");
+    assert_eq!((h, pl.as_slice()), (hdr, &b"small"[..]));
+    // the same id from another source is another datagram
+    let mut other = pieces[1].0;
+    other.src = [10, 0, 2, 3];
+    assert!(r.push(&pieces[1].0, &pieces[1].1, 4).is_none());
+    assert!(r.push(&other, &pieces[1].1, 4).is_none());
+    assert_eq!(r.pending(), 2);
+}
+
+#[test_case]
+fn reassembly_drops_bad_fragments_and_times_out() {
+    let mut hdr = Ipv4Header::new([10, 0, 2, 2], [10, 0, 2, 15], ipv4::PROTO_UDP);
+    hdr.identification = 10;
+    let payload = numbered_payload(3000);
+    let pieces = parse_all(&fragment::fragment(&hdr, &payload, 1500).expect("fragment failed"));
+    let mut r = Reassembler::new(16 * 1024, 100);
+
+    // a fragment overlapping received data drops the datagram
+    assert!(r.push(&pieces[0].0, &pieces[0].1, 0).is_none());
+    let mut overlap = pieces[1].0;
+    overlap.fragment_offset -= 8;
+    assert!(r.push(&overlap, &pieces[1].1, 0).is_none());
+    assert_eq!(r.pending(), 0);
+    // so does a second last fragment at another place
+    assert!(r.push(&pieces[2].0, &pieces[2].1, 0).is_none());
+    let mut early_end = pieces[1].0;
+    early_end.more_fragments = false;
+    assert!(r.push(&early_end, &pieces[1].1[..100], 0).is_none());
+    assert_eq!(r.pending(), 0);
+    // a middle fragment whose length is not a multiple of 8 is ignored
+    assert!(r.push(&pieces[1].0, &pieces[1].1[..100], 0).is_none());
+    assert_eq!(r.pending(), 0);
+
+    // fragments of one datagram spread past the timeout never complete
+    assert!(r.push(&pieces[0].0, &pieces[0].1, 0).is_none());
+    assert!(r.push(&pieces[1].0, &pieces[1].1, 50).is_none());
+    assert!(r.push(&pieces[2].0, &pieces[2].1, 100).is_none());
+    assert_eq!(r.pending(), 1, "only the last fragment is left");
+    assert_eq!(r.expire(199), 0);
+    assert_eq!(r.expire(200), 1);
+    assert_eq!((r.pending(), r.memory_used()), (0, 0));
+}
+
+#[test_case]
+fn reassembly_respects_memory_limit() {
+    let mut r = Reassembler::new(5000, 100);
+    let payload = numbered_payload(3000);
+    let datagram = |id: u16| {
+        let mut hdr = Ipv4Header::new([10, 0, 2, 2], [10, 0, 2, 15], ipv4::PROTO_UDP);
+        hdr.identification = id;
+        parse_all(&fragment::fragment(&hdr, &payload, 1500).expect("fragment failed"))
+    };
+    let (a, b) = (datagram(1), datagram(2));
+    // the last fragments size both buffers to 3000 bytes: 6000 > 5000, so
+    // the older datagram makes room
+    assert!(r.push(&a[2].0, &a[2].1, 0).is_none());
+    assert!(r.push(&b[2].0, &b[2].1, 1).is_none());
+    assert_eq!((r.pending(), r.memory_used()), (1, 3000));
+    assert!(r.push(&b[0].0, &b[0].1, 2).is_none());
+    assert_eq!(r.push(&b[1].0, &b[1].1, 2).expect("not reassembled").1, payload);
+    // the evicted datagram starts over and can still complete on its own
+    for piece in &a[..2] {
+        assert!(r.push(&piece.0, &piece.1, 3).is_none());
+    }
+    assert!(r.push(&a[2].0, &a[2].1, 3).is_some());
+    // a datagram larger than the whole limit is never buffered
+    let big = {
+        let mut hdr = Ipv4Header::new([10, 0, 2, 2], [10, 0, 2, 15], ipv4::PROTO_UDP);
+        hdr.identification = 3;
+        parse_all(&fragment::fragment(&hdr, &numbered_payload(6000), 1500).expect("fragment failed"))
+    };
+    let last = big.last().expect("no fragments");
+    assert!(r.push(&last.0, &last.1, 4).is_none());
+    assert_eq!((r.pending(), r.memory_used()), (0, 0));
+}
+
+#[test_case]
+fn udp_socket_queue() {
+    let mut s = UdpSocket::bind(1234);
+    assert_eq!(s.port(), 1234);
+    assert!(s.recv_from().is_none());
+    s.deliver(Vec::from(&b"one"[..]), ([10, 0, 0, 1], 53));
+    s.deliver(Vec::from(&b"two"[..]), ([10, 0, 0, 2], 53));
+    let (data, from) = s.recv_from().expect("nothing queued");
+    assert_eq!((data.as_slice(), from), (&b"one"[..], ([10, 0, 0, 1], 53)));
+    assert_eq!(s.recv_from().expect("nothing queued").0, b"two");
+}
+
+#[test_case]
+fn socket_waker_new() {
+    let w = SocketWaker::new();
+    w.wake();
+}
+
+#[test_case]
+fn net_config_routing() {
+    let c = NetConfig::qemu_user();
+    assert_eq!(c.netmask, [255, 255, 255, 0]);
+    assert_eq!(c.prefix_len(), 24);
+    assert_eq!(c.broadcast(), [10, 0, 2, 255]);
+    assert!(c.is_local([10, 0, 2, 3]) && !c.is_local([10, 0, 3, 1]));
+    assert_eq!(c.next_hop([10, 0, 2, 3]), Some([10, 0, 2, 3]));
+    assert_eq!(c.next_hop([1, 1, 1, 1]), Some([10, 0, 2, 2]));
+    assert_eq!(NetConfig::new([192, 168, 1, 5], 16).next_hop([8, 8, 8, 8]), None);
+    assert_eq!(config::netmask(0), [0; 4]);
+    assert_eq!(config::netmask(32), [255; 4]);
+    assert_eq!(alloc::format!("{}", c), "10.0.2.15/24 via 10.0.2.2");
+}
+
+#[test_case]
+fn stack_answers_arp_and_learns_senders() {
+    network::init(Box::new(QueueDevice), Some(NetConfig::qemu_user()));
+    assert!(network::is_initialized());
+    assert_eq!(network::mac_addr(), Some(OUR_MAC));
+    assert_eq!(network::poll(), 0);
+
+    // who has 10.0.2.15? (us) and who has 10.0.2.99? (not us)
+    RX.lock().push_back(arp::build_arp_request(PEER_MAC, [10, 0, 2, 2], [10, 0, 2, 15]));
+    RX.lock().push_back(arp::build_arp_request([7; 6], [10, 0, 2, 7], [10, 0, 2, 99]));
+    // and a frame that is too short to be anything
+    RX.lock().push_back(Vec::from(&[0u8; 10][..]));
+    assert_eq!(network::poll(), 3);
+
+    let sent = core::mem::take(&mut *TX.lock());
+    assert_eq!(sent.len(), 1);
+    let (eth, payload) = parse_eth_header(&sent[0]).expect("reply does not parse");
+    assert_eq!((eth.dst, eth.src, eth.ethertype), (PEER_MAC, OUR_MAC, ETHERTYPE_ARP));
+    let reply = arp::parse_arp_packet(payload).expect("reply does not parse");
+    assert_eq!(reply.opcode, ArpOp::Reply as u16);
+    assert_eq!((reply.sender_mac, reply.sender_ip), (OUR_MAC, [10, 0, 2, 15]));
+    assert_eq!(reply.target_ip, [10, 0, 2, 2]);
+    // both senders were learned
+    assert_eq!(network::arp_lookup([10, 0, 2, 2]), Some(PEER_MAC));
+    assert_eq!(network::arp_lookup([10, 0, 2, 7]), Some([7; 6]));
+}
+
+#[test_case]
+fn stack_exchanges_large_datagrams() {
+    network::init(Box::new(QueueDevice), Some(NetConfig::qemu_user()));
+    TX.lock().clear();
+    let payload = numbered_payload(4000);
+
+    // the router is not known yet: it is asked for first
+    assert_eq!(network::send_ipv4([10, 0, 2, 2], ipv4::PROTO_UDP, &payload), Err(NetError::WouldBlock));
+    let sent = core::mem::take(&mut *TX.lock());
+    let (_, arp_payload) = parse_eth_header(&sent[0]).expect("request does not parse");
+    assert_eq!(arp::parse_arp_packet(arp_payload).expect("not ARP").target_ip, [10, 0, 2, 2]);
+    RX.lock().push_back(arp::build_arp_request(PEER_MAC, [10, 0, 2, 2], [10, 0, 2, 15]));
+    network::poll();
+    TX.lock().clear();
+
+    // out: three frames within the MTU, sent via the router's MAC even for
+    // a destination beyond it
+    network::send_ipv4([8, 8, 8, 8], ipv4::PROTO_UDP, &payload).expect("send failed");
+    let sent = core::mem::take(&mut *TX.lock());
+    assert_eq!(sent.len(), 3);
+    let mut r = Reassembler::new(16 * 1024, 100);
+    let mut whole = None;
+    for frame in &sent {
+        assert!(frame.len() <= 14 + 1500);
+        let (eth, packet) = parse_eth_header(frame).expect("frame does not parse");
+        assert_eq!((eth.dst, eth.src, eth.ethertype), (PEER_MAC, OUR_MAC, ETHERTYPE_IPV4));
+        let (hdr, pl) = ipv4::parse_ipv4_header(packet).expect("packet does not parse");
+        assert_eq!((hdr.src, hdr.dst), ([10, 0, 2, 15], [8, 8, 8, 8]));
+        whole = r.push(&hdr, pl, 0).or(whole);
+    }
+    assert_eq!(whole.expect("not reassembled").1, payload);
+
+    // in: fragments arrive out of order, mixed with one for someone else
+    let mut hdr = Ipv4Header::new([8, 8, 8, 8], [10, 0, 2, 15], ipv4::PROTO_UDP);
+    hdr.identification = 4242;
+    let mut frames: Vec<Vec<u8>> = fragment::fragment(&hdr, &payload, 1500)
+        .expect("fragment failed")
+        .iter()
+        .map(|p| {
+            let mut f = alloc::vec![0u8; 14 + p.len()];
+            build_eth_frame(OUR_MAC, PEER_MAC, ETHERTYPE_IPV4, p, &mut f).expect("build failed");
+            f
+        })
+        .collect();
+    frames.reverse();
+    let mut stranger = [0u8; 64];
+    let len = ipv4::build_ipv4_packet([8, 8, 8, 8], [10, 0, 2, 99], ipv4::PROTO_UDP, b"not ours", &mut stranger[14..]).expect("build failed");
+    stranger[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    frames.insert(1, Vec::from(&stranger[..14 + len]));
+    for f in frames {
+        RX.lock().push_back(f);
+    }
+    assert!(network::recv_ipv4().is_none());
+    assert_eq!(network::poll(), 4);
+    assert_eq!(network::reassembly_pending(), 0);
+    let (got, data) = network::recv_ipv4().expect("nothing received");
+    assert_eq!((got.src, got.identification, data.len()), ([8, 8, 8, 8], 4242, 4000));
+    assert_eq!(data, payload);
+    assert!(network::recv_ipv4().is_none());
+}
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rz_rust_os::test_panic_handler(info)
+}
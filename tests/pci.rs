@@ -0,0 +1,159 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rz_rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bootloader::{entry_point, BootInfo};
+use rz_rust_os::allocator;
+use rz_rust_os::memory::{self, BootInfoFrameAllocator};
+use x86_64::VirtAddr;
+
+use rz_rust_os::drivers::pci::{self, Bar, PciAddress, PciDevice, PciDriver, PciId};
+use rz_rust_os::drivers::virtio_blk;
+use rz_rust_os::network::device::e1000;
+
+/// The i440FX host bridge QEMU's `pc` machine puts at 00:00.0.
+const HOST_BRIDGE: PciId = PciId::new(0x8086, 0x1237);
+const E1000: PciId = PciId::new(0x8086, 0x100E);
+const VIRTIO_BLK: PciId = PciId::new(0x1AF4, 0x1001);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rz_rust_os::init();
+    // Initialize memory and heap so tests can use `alloc` (Vec, Box, etc.).
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization in tests failed");
+
+    test_main();
+    loop {}
+}
+
+fn find(id: PciId) -> PciDevice {
+    pci::find_device(id.vendor_id, id.device_id).expect("device not on the bus")
+}
+
+#[test_case]
+fn pci_enumerates_qemu_devices() {
+    let devices = pci::enumerate();
+    let host = devices[0];
+    assert_eq!(host.address, PciAddress { bus: 0, device: 0, function: 0 });
+    assert_eq!((host.vendor_id, host.device_id), (HOST_BRIDGE.vendor_id, HOST_BRIDGE.device_id));
+    assert_eq!((host.class, host.subclass), (0x06, 0x00));
+    assert!(!host.is_pci_bridge());
+    // the ISA bridge is a multi-function device; its IDE function is found too
+    assert!(devices.iter().any(|d| d.class == 0x01 && d.subclass == 0x01 && d.address.function == 1));
+    for id in [E1000, VIRTIO_BLK] {
+        assert_eq!(devices.iter().filter(|d| d.vendor_id == id.vendor_id && d.device_id == id.device_id).count(), 1);
+    }
+    // no function is listed twice
+    for (i, d) in devices.iter().enumerate() {
+        assert!(devices[i + 1..].iter().all(|o| o.address != d.address));
+    }
+}
+
+#[test_case]
+fn pci_decodes_e1000_header() {
+    let nic = find(E1000);
+    assert_eq!((nic.class, nic.subclass, nic.prog_if), (0x02, 0x00, 0x00));
+    assert_eq!(nic.header_type, 0);
+    assert_eq!(nic.interrupt_pin, 1);
+    assert!(nic.interrupt_line != 0 && nic.interrupt_line < 16);
+    // 128 KiB of registers in BAR 0 and an I/O window in BAR 1
+    match nic.decode_bar(0) {
+        Some(Bar::Memory { addr, size, prefetchable, is_64bit }) => {
+            assert_eq!(size, 128 * 1024);
+            assert_eq!(addr & (size - 1), 0);
+            assert_eq!(Some(addr), nic.mmio_bar(0));
+            assert!(!prefetchable && !is_64bit);
+        }
+        other => panic!("BAR 0 is {:?}", other),
+    }
+    match nic.decode_bar(1) {
+        Some(Bar::Io { port, size }) => {
+            assert_eq!(size, 0x40);
+            assert_eq!(Some(port), nic.io_bar(1));
+        }
+        other => panic!("BAR 1 is {:?}", other),
+    }
+    assert_eq!(nic.decode_bar(6), None);
+}
+
+#[test_case]
+fn pci_bar_sizing_leaves_device_intact() {
+    let disk = find(VIRTIO_BLK);
+    let raw: Vec<u32> = (0..6).map(|i| disk.bar(i)).collect();
+    let bars = disk.bars();
+    assert!(bars.iter().any(|(i, b)| *i == 0 && matches!(b, Bar::Io { .. })));
+    for (_, bar) in &bars {
+        let size = match *bar {
+            Bar::Io { size, .. } => size as u64,
+            Bar::Memory { size, .. } => size,
+        };
+        assert!(size.is_power_of_two());
+    }
+    // a 64-bit BAR's upper half is not listed on its own
+    for (i, bar) in &bars {
+        if matches!(bar, Bar::Memory { is_64bit: true, .. }) {
+            assert!(bars.iter().all(|(j, _)| *j != i + 1));
+        }
+    }
+    let after: Vec<u32> = (0..6).map(|i| disk.bar(i)).collect();
+    assert_eq!(raw, after);
+}
+
+#[test_case]
+fn pci_walks_capabilities() {
+    // transitional virtio devices describe their modern layout with
+    // vendor-specific capabilities
+    let disk = find(VIRTIO_BLK);
+    let caps = disk.capabilities();
+    assert!(caps.iter().any(|c| c.id == pci::CAP_VENDOR_SPECIFIC));
+    assert!(caps.iter().all(|c| c.offset >= 0x40 && c.offset & 3 == 0));
+    let first_vendor = caps.iter().find(|c| c.id == pci::CAP_VENDOR_SPECIFIC).map(|c| c.offset);
+    assert_eq!(disk.find_capability(pci::CAP_VENDOR_SPECIFIC), first_vendor);
+    assert_eq!(find(HOST_BRIDGE).find_capability(pci::CAP_PCI_EXPRESS), None);
+}
+
+#[test_case]
+fn pci_enables_bus_mastering() {
+    let nic = find(E1000);
+    nic.enable_bus_master();
+    assert!(nic.is_bus_master());
+}
+
+static TEST_DRIVER: PciDriver = PciDriver { name: "test-e1000", ids: &[E1000] };
+
+#[test_case]
+fn pci_registry_binds_by_id() {
+    // `init` registers the built-in drivers
+    let nic = find(E1000);
+    assert_eq!(pci::driver_for(&nic).map(|d| d.name), Some(e1000::PCI_DRIVER.name));
+    assert_eq!(pci::driver_for(&find(VIRTIO_BLK)).map(|d| d.name), Some(virtio_blk::PCI_DRIVER.name));
+    assert!(pci::driver_for(&find(HOST_BRIDGE)).is_none());
+    assert!(pci::register_driver(&TEST_DRIVER));
+
+    // there is one e1000, so only the first claim gets it
+    assert_eq!(pci::claim(&TEST_DRIVER).map(|d| d.address), Some(nic.address));
+    assert_eq!(pci::bound_driver(nic.address), Some("test-e1000"));
+    assert!(pci::claim(&e1000::PCI_DRIVER).is_none());
+    assert!(pci::bound_devices().iter().any(|(d, _, bound)| d.address == nic.address && *bound));
+
+    pci::release(nic.address);
+    assert_eq!(pci::bound_driver(nic.address), None);
+    assert_eq!(pci::claim(&e1000::PCI_DRIVER).map(|d| d.address), Some(nic.address));
+    pci::release(nic.address);
+}
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rz_rust_os::test_panic_handler(info)
+}
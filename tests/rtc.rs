@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rz_rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use rz_rust_os::drivers::rtc;
+use rz_rust_os::fs::time;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    rz_rust_os::init();
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn rtc_reads_plausible_date() {
+    let t = rtc::read();
+    assert!(t.year >= 2020 && t.year < 2100);
+    assert!((1..=12).contains(&t.month));
+    assert!((1..=31).contains(&t.day));
+    assert!(t.hour < 24 && t.minute < 60 && t.second < 60);
+}
+
+#[test_case]
+fn rtc_is_the_fs_clock() {
+    // `init` installs the RTC, so stamps are no longer the FAT epoch
+    let before = rtc::read();
+    let now = time::now();
+    assert!(now.year >= 2020);
+    assert!(now >= before);
+}
+
+#[test_case]
+fn rtc_advances() {
+    let start = rtc::read();
+    // the RTC updates once a second; give it a few
+    for _ in 0..100_000_000u64 {
+        if rtc::read() > start { return; }
+        core::hint::spin_loop();
+    }
+    panic!("RTC did not advance");
+}
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rz_rust_os::test_panic_handler(info)
+}
@@ -14,9 +14,12 @@ use x86_64::VirtAddr;
 
 use rz_rust_os::fs::mock_device::MockDevice;
 use rz_rust_os::fs::fs::FileSystem;
+use rz_rust_os::fs::vfs::Vfs;
+use rz_rust_os::fs::tmpfs::Tmpfs;
 use rz_rust_os::task::shell;
-use rz_rust_os::task::shell::shell_input;
+use rz_rust_os::task::shell::{shell_input, with_vfs};
 use rz_rust_os::fs::fs::FsError;
+use rz_rust_os::fs::directory::ATTR_READ_ONLY;
 
 entry_point!(main);
 
@@ -32,7 +35,7 @@ fn main(boot_info: &'static BootInfo) -> ! {
     loop {}
 }
 
-fn make_leaked_fs() -> &'static mut FileSystem<'static, MockDevice<'static>> {
+fn leaked_fs() -> FileSystem<'static, MockDevice<'static>> {
     // allocate buffer on heap and leak to get 'static slice
     let boxed_buf = Box::new([0u8; 512 * 64]);
     let leaked_buf: &'static mut [u8; 512 * 64] = Box::leak(boxed_buf);
@@ -41,23 +44,25 @@ fn make_leaked_fs() -> &'static mut FileSystem<'static, MockDevice<'static>> {
     let dev_box = Box::new(MockDevice::new(leaked_slice));
     let shell_dev: &'static mut MockDevice = Box::leak(dev_box);
     // format and mount
-    let sectors = shell_dev.sector_count() as u16;
-    FileSystem::format(shell_dev, sectors).expect("format failed");
-    let fs_box = Box::new(FileSystem::mount(shell_dev).expect("mount failed"));
-    let shell_fs: &'static mut FileSystem<'static, MockDevice<'static>> = Box::leak(fs_box);
-    shell_fs
+    FileSystem::format(shell_dev).expect("format failed");
+    FileSystem::mount(shell_dev).expect("mount failed")
+}
+
+/// Give the shell a fresh mount table with a RAM disk at `/`.
+fn mount_ram_root() {
+    let mut vfs = Vfs::new();
+    vfs.mount("/", Box::new(leaked_fs())).expect("mount failed");
+    shell::new(vfs);
 }
 
 #[test_case]
 fn shell_write_and_read() {
-    let fs = make_leaked_fs();
-    // register (use raw pointer to avoid borrow conflicts)
-    shell::new(fs as *mut _);
+    mount_ram_root();
     // write using shell
     shell_input("write test.txt hello_from_shell");
     // verify via fs
     let name11 = "TEST    TXT"; // 8 + 3
-    match fs.read_file(name11) {
+    match with_vfs(|fs| fs.read_file(name11)) {
         Ok(data) => {
             let s = core::str::from_utf8(&data).unwrap_or("");
             assert!(s.contains("hello_from_shell"));
@@ -68,11 +73,10 @@ fn shell_write_and_read() {
 
 #[test_case]
 fn shell_list_directories() {
-    let fs = make_leaked_fs();
-    shell::new(fs as *mut _);
+    mount_ram_root();
     shell_input("write a.txt a");
     shell_input("write b.txt b");
-    let list = fs.list_root();
+    let list = with_vfs(|fs| fs.list_dir("/")).expect("list failed");
     // Expect at least two entries
     assert!(list.len() >= 2);
     // Check presence of A and B files
@@ -90,19 +94,18 @@ fn shell_list_directories() {
 
 #[test_case]
 fn shell_write_read_delete_read_again() {
-    let fs = make_leaked_fs();
-    shell::new(fs as *mut _);
+    mount_ram_root();
     // write
     shell_input("write temp.txt secret");
     // read
     let name11 = "TEMP    TXT";
-    let data = fs.read_file(name11).expect("read failed");
+    let data = with_vfs(|fs| fs.read_file(name11)).expect("read failed");
     let s = core::str::from_utf8(&data).unwrap_or("");
     assert!(s.contains("secret"));
     // delete
     shell_input("delete temp.txt");
     // reading again should fail
-    match fs.read_file(name11) {
+    match with_vfs(|fs| fs.read_file(name11)) {
         Ok(_) => panic!("file still present after delete"),
         Err(e) => match e {
             FsError::FileNotFound => {},
@@ -111,6 +114,124 @@ fn shell_write_read_delete_read_again() {
     }
 }
 
+#[test_case]
+fn shell_mkdir_write_into_subdirectory() {
+    mount_ram_root();
+    shell_input("mkdir docs");
+    shell_input("write docs/note.txt nested_note");
+    let data = with_vfs(|fs| fs.read_file("/DOCS/NOTE.TXT")).expect("read failed");
+    assert_eq!(core::str::from_utf8(&data).unwrap_or(""), "nested_note");
+    let list = with_vfs(|fs| fs.list_dir("/")).expect("list failed");
+    assert_eq!(list.len(), 1);
+    assert!(list[0].is_dir());
+    // rmdir refuses a non-empty directory
+    shell_input("rmdir docs");
+    assert_eq!(with_vfs(|fs| fs.list_dir("/")).expect("list failed").len(), 1);
+    shell_input("delete docs/note.txt");
+    shell_input("rmdir docs");
+    assert_eq!(with_vfs(|fs| fs.list_dir("/")).expect("list failed").len(), 0);
+}
+
+#[test_case]
+fn shell_write_binary_name_and_attrib() {
+    mount_ram_root();
+    shell_input("write boot.cfg timeout=5");
+    let data = with_vfs(|fs| fs.read_file("BOOT.CFG")).expect("read failed");
+    assert_eq!(core::str::from_utf8(&data).unwrap_or(""), "timeout=5");
+    shell_input("attrib boot.cfg +r -a");
+    assert_eq!(with_vfs(|fs| fs.attributes("BOOT.CFG")).expect("attr failed"), ATTR_READ_ONLY);
+    shell_input("delete boot.cfg");
+    assert!(with_vfs(|fs| fs.read_file("BOOT.CFG")).is_ok());
+}
+
+#[test_case]
+fn shell_long_names_are_kept() {
+    mount_ram_root();
+    shell_input("write release-notes.markdown v1");
+    let list = with_vfs(|fs| fs.list_dir("/")).expect("list failed");
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].display_name(), "release-notes.markdown");
+    assert_eq!(&list[0].short_name(), b"RELEAS~1MAR");
+    let data = with_vfs(|fs| fs.read_file("release-notes.markdown")).expect("read failed");
+    assert_eq!(core::str::from_utf8(&data).unwrap_or(""), "v1");
+}
+
+#[test_case]
+fn shell_rename_moves_file() {
+    mount_ram_root();
+    shell_input("mkdir docs");
+    shell_input("write draft.txt text");
+    shell_input("rename draft.txt docs/final.txt");
+    assert!(matches!(with_vfs(|fs| fs.read_file("draft.txt")), Err(FsError::FileNotFound)));
+    assert_eq!(with_vfs(|fs| fs.read_file("docs/final.txt")).expect("read failed"), b"text");
+}
+
+#[test_case]
+fn shell_fsck_runs_clean() {
+    mount_ram_root();
+    shell_input("mkdir d");
+    shell_input("write d/x.txt data");
+    shell_input("fsck");
+    shell_input("fsck repair");
+    // the shell made no changes to a consistent volume
+    assert_eq!(with_vfs(|fs| fs.read_file("d/x.txt")).expect("read failed"), b"data");
+    assert_eq!(with_vfs(|fs| fs.list_dir("d")).expect("list failed").len(), 3);
+}
+
+#[test_case]
+fn shell_ls_long_lists_timestamps() {
+    mount_ram_root();
+    shell_input("write t.txt stamped");
+    shell_input("ls -l");
+    shell_input("ls -l /");
+    let list = with_vfs(|fs| fs.list_dir("/")).expect("list failed");
+    assert!(list[0].modified.is_set());
+}
+
+#[test_case]
+fn shell_df_reports_usage() {
+    mount_ram_root();
+    shell_input("write a.txt hello");
+    shell_input("df");
+    let st = with_vfs(|fs| fs.stat("/")).expect("stat failed");
+    assert_eq!((st.files, st.used_clusters), (1, 1));
+}
+
+#[test_case]
+fn shell_works_across_mounts() {
+    mount_ram_root();
+    with_vfs(|fs| fs.mount("/mnt/disk1", Box::new(leaked_fs()))).expect("mount failed");
+    shell_input("mount");
+    shell_input("write /mnt/disk1/saved.txt persistent");
+    shell_input("write scratch.txt temporary");
+    assert_eq!(with_vfs(|fs| fs.read_file("/mnt/disk1/SAVED.TXT")).expect("read failed"), b"persistent");
+    // each file landed on its own volume
+    assert!(matches!(with_vfs(|fs| fs.read_file("/saved.txt")), Err(FsError::FileNotFound)));
+    shell_input("rename scratch.txt /mnt/disk1/kept.txt");
+    assert_eq!(with_vfs(|fs| fs.read_file("/mnt/disk1/kept.txt")).expect("read failed"), b"temporary");
+    assert_eq!(with_vfs(|fs| fs.stat("/")).expect("stat failed").files, 0);
+    // the mount point cannot be removed from under the disk
+    shell_input("rmdir /mnt/disk1");
+    assert_eq!(with_vfs(|fs| fs.stat("/mnt/disk1")).expect("stat failed").files, 2);
+    shell_input("df");
+    shell_input("fsck /mnt/disk1");
+}
+
+#[test_case]
+fn shell_on_tmpfs_root() {
+    let mut vfs = Vfs::new();
+    vfs.mount("/", Box::new(Tmpfs::new(4096))).expect("mount failed");
+    shell::new(vfs);
+    shell_input("mkdir scratch");
+    shell_input("write scratch/Build Log.txt ok");
+    shell_input("ls -l scratch");
+    shell_input("df");
+    assert_eq!(with_vfs(|fs| fs.read_file("/scratch/Build")).expect("read failed"), b"Log.txt ok");
+    shell_input("delete scratch/Build");
+    shell_input("rmdir scratch");
+    assert!(with_vfs(|fs| fs.list_dir("/")).expect("list failed").is_empty());
+}
+
 use core::panic::PanicInfo;
 
 #[panic_handler]
@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rz_rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bootloader::{entry_point, BootInfo};
+use rz_rust_os::allocator;
+use rz_rust_os::memory::{self, BootInfoFrameAllocator};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+use rz_rust_os::drivers::virtio_blk::{self, VirtioBlk};
+use rz_rust_os::fs::block_device::{BlockDevice, IoError};
+use rz_rust_os::fs::fs::FileSystem;
+
+/// tools/qemu-runner.sh attaches a 1 MiB scratch image with
+/// `snapshot=on`: it reads as zeros until a test writes it, and writes last
+/// until QEMU exits.
+const DISK_SECTORS: u64 = 2048;
+
+static DISK: Mutex<Option<VirtioBlk>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rz_rust_os::init();
+    // Initialize memory and heap so tests can use `alloc` (Vec, Box, etc.).
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization in tests failed");
+    // the virtqueue lives in DMA frames, so the device is set up here where
+    // the frame allocator is available
+    let disk = VirtioBlk::probe(&mut frame_allocator, phys_mem_offset)
+        .expect("virtio-blk init failed")
+        .expect("no virtio-blk device");
+    *DISK.lock() = Some(disk);
+
+    test_main();
+    loop {}
+}
+
+#[test_case]
+fn virtio_blk_capacity() {
+    let mut guard = DISK.lock();
+    let disk = guard.as_mut().expect("no disk");
+    assert_eq!(disk.sector_count(), DISK_SECTORS);
+    assert!(!disk.is_read_only());
+}
+
+#[test_case]
+fn virtio_blk_read_sectors() {
+    let mut guard = DISK.lock();
+    let disk = guard.as_mut().expect("no disk");
+    let mut buf = [0xFFu8; 512];
+    disk.read_sector(0, &mut buf).expect("read failed");
+    assert!(buf.iter().all(|&b| b == 0));
+    disk.read_sector(DISK_SECTORS - 1, &mut buf).expect("read failed");
+    assert_eq!(disk.read_sector(DISK_SECTORS, &mut buf), Err(IoError::OutOfRange));
+    assert_eq!(disk.read_sector(0, &mut buf[..100]), Err(IoError::BadBuffer));
+}
+
+#[test_case]
+fn virtio_blk_write_and_flush_complete() {
+    let mut guard = DISK.lock();
+    let disk = guard.as_mut().expect("no disk");
+    // enough requests to wrap the available ring index past the queue size
+    for lba in 0..300 {
+        disk.write_sector(lba, &[lba as u8; 512]).expect("write failed");
+    }
+    disk.flush().expect("flush failed");
+    // completions are signalled on the device's PCI interrupt line
+    assert!(virtio_blk::irq_count() > 0);
+    assert!(!disk.is_failed());
+}
+
+#[test_case]
+fn virtio_blk_writes_read_back() {
+    let mut guard = DISK.lock();
+    let disk = guard.as_mut().expect("no disk");
+    let lbas = [0, 7, 1000, DISK_SECTORS - 1];
+    for &lba in &lbas {
+        let mut sector = [0u8; 512];
+        for (i, b) in sector.iter_mut().enumerate() { *b = (i as u64 ^ lba) as u8; }
+        disk.write_sector(lba, &sector).expect("write failed");
+    }
+    disk.flush().expect("flush failed");
+    let mut back = [0u8; 512];
+    for &lba in &lbas {
+        disk.read_sector(lba, &mut back).expect("read failed");
+        assert!(back.iter().enumerate().all(|(i, &b)| b == (i as u64 ^ lba) as u8));
+    }
+    // a neighbour of a written sector keeps what it had
+    disk.read_sector(DISK_SECTORS - 2, &mut back).expect("read failed");
+    assert!(back.iter().all(|&b| b == 0));
+}
+
+#[test_case]
+fn virtio_blk_hosts_a_file_system() {
+    let mut guard = DISK.lock();
+    let disk = guard.as_mut().expect("no disk");
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    FileSystem::format(disk).expect("format failed");
+    {
+        let mut fs = FileSystem::mount(disk).expect("mount failed");
+        fs.mkdir("/DOCS").expect("mkdir failed");
+        fs.write_file("/DOCS/NOTES.TXT", &data).expect("write failed");
+        fs.flush().expect("flush failed");
+    }
+    // a fresh mount only sees what reached the disk
+    let mut fs = FileSystem::mount(disk).expect("remount failed");
+    assert_eq!(fs.read_file("/DOCS/NOTES.TXT").expect("read failed"), data);
+    assert!(fs.list_dir("/DOCS").expect("list failed").iter().any(|e| e.display_name() == "NOTES.TXT"));
+}
+
+use core::panic::PanicInfo;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rz_rust_os::test_panic_handler(info)
+}
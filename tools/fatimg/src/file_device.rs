@@ -0,0 +1,52 @@
+// A disk image file as a `BlockDevice`.
+
+use crate::fs::block_device::{sector_offset, BlockDevice, IoError};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub struct FileDevice {
+    file: File,
+    sectors: u64,
+    read_only: bool,
+}
+
+impl FileDevice {
+    /// Open an existing image. Its size is rounded down to whole sectors.
+    pub fn open(path: &Path, read_only: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+        let sectors = file.metadata()?.len() / 512;
+        Ok(FileDevice { file, sectors, read_only })
+    }
+
+    /// Create (or truncate) an image of `sectors` zeroed sectors.
+    pub fn create(path: &Path, sectors: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len(sectors * 512)?;
+        Ok(FileDevice { file, sectors, read_only: false })
+    }
+}
+
+impl BlockDevice for FileDevice {
+    fn read_sector(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), IoError> {
+        let offset = sector_offset(lba, buf.len(), self.sectors)?;
+        self.file.seek(SeekFrom::Start(offset as u64)).map_err(|_| IoError::Device)?;
+        self.file.read_exact(buf).map_err(|_| IoError::Device)
+    }
+
+    fn write_sector(&mut self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        if self.read_only { return Err(IoError::ReadOnly); }
+        let offset = sector_offset(lba, data.len(), self.sectors)?;
+        self.file.seek(SeekFrom::Start(offset as u64)).map_err(|_| IoError::Device)?;
+        self.file.write_all(data).map_err(|_| IoError::Device)
+    }
+
+    fn sector_count(&self) -> u64 { self.sectors }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        if self.read_only { return Ok(()); }
+        self.file.sync_data().map_err(|_| IoError::Device)
+    }
+
+    fn is_read_only(&self) -> bool { self.read_only }
+}
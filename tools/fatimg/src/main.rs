@@ -0,0 +1,241 @@
+// fatimg: build and inspect rz_rust_os FAT images from the host.
+//
+// The kernel's `fs` modules are compiled in unchanged (they only need
+// `alloc`, `spin` and a `println!`), on top of a file-backed BlockDevice. An
+// image with an MBR or GPT is used through its first FAT partition;
+// anything else is treated as a bare volume.
+
+extern crate alloc;
+
+#[macro_export]
+macro_rules! println {
+    ($($t:tt)*) => { std::println!($($t)*) };
+}
+
+// linted as part of the kernel
+#[allow(dead_code, unused_imports, clippy::all)]
+#[path = "../../../src/fs/mod.rs"]
+mod fs;
+mod file_device;
+
+use crate::file_device::FileDevice;
+use crate::fs::block_device::BlockDevice;
+use crate::fs::boot_sector::FatType;
+use crate::fs::check;
+use crate::fs::format::FormatOptions;
+use crate::fs::journal;
+use crate::fs::fs::{FileSystem, FsError};
+use crate::fs::partition::{self, Partition};
+use crate::fs::time::{self, DateTime};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+usage: fatimg <command> <image> [args]
+
+commands:
+  create <image> <size>[K|M|G] [--fat12|--fat16|--fat32] [--cluster SECTORS] [--label NAME] [--journal]
+  info   <image>
+  ls     <image> [path] [-r]
+  put    <image> <host-file> <path>     copy a host file in (replacing an existing file)
+  get    <image> <path> <host-file>     copy a file out
+  mkdir  <image> <path>
+  rm     <image> <path>                 delete a file or an empty directory
+  check  <image> [--repair]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    }
+    time::set_clock(host_clock);
+    match run(&args[0], Path::new(&args[1]), &args[2..]) {
+        Ok(code) => code,
+        Err(msg) => {
+            eprintln!("fatimg: {}", msg);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cmd: &str, image: &Path, args: &[String]) -> Result<ExitCode, String> {
+    if cmd == "create" {
+        return create(image, args).map(|_| ExitCode::SUCCESS);
+    }
+    let read_only = matches!(cmd, "info" | "ls" | "get") || (cmd == "check" && !args.iter().any(|a| a == "--repair"));
+    let mut device = FileDevice::open(image, read_only).map_err(|e| format!("{}: {}", image.display(), e))?;
+    let found = partition::find_fat_partition(&mut device).ok().flatten();
+    match found {
+        Some(info) => {
+            let mut part = Partition::new(&mut device, &info).map_err(|e| format!("partition: {}", e))?;
+            println!("using partition {} at LBA {}", info.index, info.start_lba);
+            volume_command(&mut part, cmd, args)
+        }
+        None => volume_command(&mut device, cmd, args),
+    }
+}
+
+fn volume_command<D: BlockDevice>(device: &mut D, cmd: &str, args: &[String]) -> Result<ExitCode, String> {
+    let mut fs = FileSystem::mount(device).map_err(|e| format!("mount: {:?}", e))?;
+    if fs.journal_replayed != 0 {
+        println!("replayed {} journal records", fs.journal_replayed);
+    }
+    let arg = |i: usize, what: &str| args.get(i).map(String::as_str).ok_or(format!("missing {}\n{}", what, USAGE));
+    match cmd {
+        "info" => {
+            let bs = fs.boot_sector;
+            let st = fs.stat().map_err(fs_err)?;
+            let label = String::from_utf8_lossy(&bs.volume_label);
+            println!("type:      {:?}", bs.fat_type);
+            println!("label:     {}", label.trim_end());
+            println!("serial:    {:08X}", bs.volume_id);
+            println!("sectors:   {} ({} per cluster, {} per FAT, {} FATs)",
+                bs.total_sectors, bs.sectors_per_cluster, bs.sectors_per_fat, bs.num_fats);
+            println!("clusters:  {} total, {} free", st.total_clusters, st.free_clusters);
+            println!("usage:     {} of {} bytes", st.used_bytes, st.total_bytes);
+            println!("journal:   {}", if fs.is_journaled() { "yes" } else { "no" });
+            println!("contents:  {} files, {} directories, {}% fragmented",
+                st.files, st.directories, st.fragmentation());
+        }
+        "ls" => {
+            let recursive = args.iter().any(|a| a == "-r");
+            let path = args.iter().find(|a| *a != "-r").map_or("/", String::as_str);
+            list(&mut fs, path, recursive)?;
+        }
+        "put" => {
+            let (host, path) = (arg(0, "host file")?, arg(1, "image path")?);
+            let data = std::fs::read(host).map_err(|e| format!("{}: {}", host, e))?;
+            match fs.write_file(path, &data) {
+                Err(FsError::FileAlreadyExists) => fs.overwrite(path, &data).map_err(fs_err)?,
+                other => other.map_err(fs_err)?,
+            }
+            println!("{} -> {} ({} bytes)", host, path, data.len());
+        }
+        "get" => {
+            let (path, host) = (arg(0, "image path")?, arg(1, "host file")?);
+            let data = fs.read_file(path).map_err(fs_err)?;
+            std::fs::write(host, &data).map_err(|e| format!("{}: {}", host, e))?;
+            println!("{} -> {} ({} bytes)", path, host, data.len());
+        }
+        "mkdir" => fs.mkdir(arg(0, "path")?).map_err(fs_err)?,
+        "rm" => {
+            let path = arg(0, "path")?;
+            match fs.delete(path) {
+                Err(FsError::IsADirectory) => fs.rmdir(path).map_err(fs_err)?,
+                other => other.map_err(fs_err)?,
+            }
+        }
+        "check" => {
+            let repair = args.iter().any(|a| a == "--repair");
+            let report = check::check(&mut fs, repair).map_err(fs_err)?;
+            for problem in report.problems.iter() {
+                println!("{}", problem);
+            }
+            println!(
+                "{} files, {} directories, {} free clusters, {} problems{}",
+                report.files,
+                report.directories,
+                report.free_clusters,
+                report.problems.len(),
+                if repair && !report.is_clean() { " (repaired)" } else { "" });
+            if !report.is_clean() && !repair {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        other => return Err(format!("unknown command: {}\n{}", other, USAGE)),
+    }
+    fs.flush().map_err(fs_err)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn create(image: &Path, args: &[String]) -> Result<(), String> {
+    let size = args.first().ok_or(format!("missing size\n{}", USAGE))?;
+    let sectors = parse_size(size).ok_or(format!("bad size: {}", size))?.div_ceil(512);
+    let mut options = FormatOptions::new().serial(host_serial());
+    let mut rest = args[1..].iter();
+    while let Some(flag) = rest.next() {
+        options = match flag.as_str() {
+            "--fat12" => options.fat_type(FatType::Fat12),
+            "--fat16" => options.fat_type(FatType::Fat16),
+            "--fat32" => options.fat_type(FatType::Fat32),
+            "--cluster" => {
+                let n = rest.next().and_then(|s| s.parse().ok()).ok_or("--cluster needs a sector count")?;
+                options.sectors_per_cluster(n)
+            }
+            "--label" => options.volume_label(rest.next().ok_or("--label needs a name")?).map_err(fs_err)?,
+            "--journal" => options.journal(journal::JOURNAL_DEFAULT_SECTORS),
+            other => return Err(format!("unknown option: {}", other)),
+        };
+    }
+    let mut device = FileDevice::create(image, sectors).map_err(|e| format!("{}: {}", image.display(), e))?;
+    FileSystem::format_with_options(&mut device, &options).map_err(fs_err)?;
+    let bs = options.layout(sectors).map_err(fs_err)?;
+    println!("{}: {:?}, {} sectors, {} clusters", image.display(), bs.fat_type, sectors, bs.cluster_count());
+    Ok(())
+}
+
+fn list<D: BlockDevice>(fs: &mut FileSystem<'_, D>, path: &str, recursive: bool) -> Result<(), String> {
+    let entries = fs.list_dir(path).map_err(fs_err)?;
+    for e in entries.iter().filter(|e| !e.is_dot()) {
+        let name = e.display_name();
+        let full = if path.ends_with('/') { format!("{}{}", path, name) } else { format!("{}/{}", path, name) };
+        if e.is_dir() {
+            println!("{:>10}  {}  {}/", "<dir>", e.modified, full);
+            if recursive { list(fs, &full, true)?; }
+        } else {
+            println!("{:>10}  {}  {}", e.file_size, e.modified, full);
+        }
+    }
+    Ok(())
+}
+
+fn fs_err(e: FsError) -> String { format!("{:?}", e) }
+
+/// Bytes in a size such as "1440K" or "64M".
+fn parse_size(s: &str) -> Option<u64> {
+    let (digits, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Volume serial from the creation time, as DOS does.
+fn host_serial() -> u32 { unix_seconds() as u32 }
+
+/// The host's clock in UTC, for directory entry timestamps.
+fn host_clock() -> DateTime {
+    let secs = unix_seconds();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = (yoe + era * 400 + (month <= 2) as i64) as u16;
+    DateTime {
+        year,
+        month,
+        day,
+        hour: (rem / 3600) as u8,
+        minute: (rem / 60 % 60) as u8,
+        second: (rem % 60) as u8,
+    }
+}
@@ -0,0 +1,64 @@
+// Runs the fatimg binary against scratch images in the target directory.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let path = dir.join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn fatimg(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fatimg")).args(args).output().expect("failed to run fatimg")
+}
+
+fn ok(args: &[&str]) -> String {
+    let out = fatimg(args);
+    assert!(out.status.success(), "fatimg {:?} failed: {}", args, String::from_utf8_lossy(&out.stderr));
+    String::from_utf8(out.stdout).expect("non-utf8 output")
+}
+
+#[test]
+fn copy_in_list_and_copy_out() {
+    let image = scratch("roundtrip.img");
+    let host_in = scratch("roundtrip.in");
+    let host_out = scratch("roundtrip.out");
+    let img = image.to_str().unwrap();
+    let data: Vec<u8> = (0..5000u32).map(|i| (i * 7) as u8).collect();
+    std::fs::write(&host_in, &data).unwrap();
+
+    assert!(ok(&["create", img, "1440K", "--label", "boot disk"]).contains("Fat12"));
+    ok(&["mkdir", img, "/etc"]);
+    ok(&["put", img, host_in.to_str().unwrap(), "/etc/kernel config.bin"]);
+    let listing = ok(&["ls", img, "-r"]);
+    assert!(listing.contains("/ETC/kernel config.bin"), "{}", listing);
+    assert!(listing.contains("5000"), "{}", listing);
+    ok(&["get", img, "/etc/kernel config.bin", host_out.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&host_out).unwrap(), data);
+    assert!(ok(&["check", img]).contains("0 problems"));
+    assert!(ok(&["info", img]).contains("BOOT DISK"));
+
+    // put replaces an existing file; rm refuses a non-empty directory
+    std::fs::write(&host_in, b"short").unwrap();
+    ok(&["put", img, host_in.to_str().unwrap(), "/etc/kernel config.bin"]);
+    ok(&["get", img, "/etc/kernel config.bin", host_out.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&host_out).unwrap(), b"short");
+    assert!(!fatimg(&["rm", img, "/etc"]).status.success());
+    ok(&["rm", img, "/etc/kernel config.bin"]);
+    ok(&["rm", img, "/etc"]);
+    assert_eq!(ok(&["ls", img]), "");
+}
+
+#[test]
+fn create_picks_type_and_rejects_bad_input() {
+    let image = scratch("fat32.img");
+    let img = image.to_str().unwrap();
+    assert!(ok(&["create", img, "64M", "--fat32"]).contains("Fat32"));
+    assert_eq!(std::fs::metadata(&image).unwrap().len(), 64 << 20);
+    assert!(ok(&["check", img]).contains("0 problems"));
+    assert!(!fatimg(&["create", img, "64X"]).status.success());
+    assert!(!fatimg(&["create", img, "1M", "--fat32"]).status.success());
+    assert!(!fatimg(&["ls", scratch("missing.img").to_str().unwrap()]).status.success());
+}
@@ -0,0 +1,30 @@
+#!/bin/sh
+# Cargo runner for the kernel: hands the binary to `bootimage runner`.
+#
+# Test binaries (bootimage tells them apart by their `deps` directory) also
+# get the scratch disks tests/ata.rs and tests/virtio_blk.rs use. The images
+# are sparse raw files created with qemu-img next to the test binaries, so
+# they follow CARGO_TARGET_DIR. QEMU opens them with `snapshot=on`: writes
+# land in a temporary overlay and every run starts from an all-zero disk.
+set -e
+
+kernel="$1"
+shift
+
+case "$kernel" in
+*/deps/*) ;;
+*) exec bootimage runner "$kernel" "$@" ;;
+esac
+
+scratch="$(dirname "$kernel")/../scratch"
+mkdir -p "$scratch"
+# name and size; the secondary master reaches past the 128 GiB LBA28 limit
+for image in ata-slave.img:1M ata-lba48.img:129G virtio.img:1M; do
+    file="$scratch/${image%%:*}"
+    [ -f "$file" ] || qemu-img create -q -f raw "$file" "${image##*:}"
+done
+
+exec bootimage runner "$kernel" "$@" \
+    -drive "if=ide,index=1,format=raw,file=$scratch/ata-slave.img,snapshot=on" \
+    -drive "if=ide,index=2,format=raw,file=$scratch/ata-lba48.img,snapshot=on" \
+    -drive "if=virtio,format=raw,file=$scratch/virtio.img,snapshot=on"